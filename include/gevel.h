@@ -0,0 +1,52 @@
+/*
+ * C entry points of gevel, for other extensions reading statistics of GiST indexes without
+ * going through SQL. Look them up with load_external_function("$libdir/gevel", ...).
+ *
+ * GevelIndexStats is versioned: fields are only ever appended to it, and every addition bumps
+ * GEVEL_ABI_VERSION. Pass sizeof(GevelIndexStats) as out_size, so that newer builds of gevel
+ * only fill the fields known to the caller.
+ */
+#ifndef GEVEL_H
+#define GEVEL_H
+
+#include "postgres.h"
+
+#define GEVEL_ABI_VERSION 1
+
+#define GEVEL_OK 0
+/* the buffer is NULL or smaller than the first version of GevelIndexStats */
+#define GEVEL_ERR_BUFFER (-1)
+/* there's no relation with a given oid */
+#define GEVEL_ERR_INVALID_RELATION (-2)
+/* the relation is not a GiST index */
+#define GEVEL_ERR_NOT_GIST_INDEX (-3)
+/* the index couldn't be locked within gevel.lock_timeout_ms */
+#define GEVEL_ERR_LOCK_TIMEOUT (-4)
+
+typedef struct GevelIndexStats
+{
+	uint32		abi_version;	/* layout the struct has been filled with */
+	uint32		size;			/* number of bytes written */
+	bool		is_valid;
+	uint32		levels;
+	uint64		pages;
+	uint64		leaf_pages;
+	uint64		new_pages;
+	uint64		tuples;
+	uint64		leaf_tuples;
+	uint64		invalid_tuples;
+	uint64		dead_tuples;
+	uint64		null_tuples;
+	uint64		tuple_bytes;
+	uint64		leaf_tuple_bytes;
+	uint64		total_bytes;
+	uint64		free_bytes;
+} GevelIndexStats;
+
+/* returns GEVEL_ABI_VERSION of the loaded build */
+typedef uint32 (*gevel_abi_version_fn) (void);
+
+/* walks an index and writes at most out_size bytes of its statistics into out */
+typedef int (*gevel_index_stats_fn) (Oid index_oid, GevelIndexStats *out, size_t out_size);
+
+#endif							/* GEVEL_H */
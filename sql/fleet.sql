@@ -0,0 +1,16 @@
+-- Summaries of GiST indexes of all databases stored by gevel.scan_all_databases. Extensions
+-- can't create shared tables, so the summaries are kept in the database the scans are run in.
+CREATE TABLE gevel.fleet_stats (
+    database name NOT NULL,
+    -- schema-qualified names, as oids of other databases can't be resolved here
+    index_name text NOT NULL,
+    table_name text NOT NULL,
+    verdict text NOT NULL,
+    health_score float8 NOT NULL,
+    bloat_pct float8 NOT NULL,
+    pages bigint NOT NULL,
+    invalid_tuples bigint NOT NULL,
+    maintenance_score float8 NOT NULL,
+    scanned_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (database, index_name)
+);
@@ -0,0 +1,11 @@
+-- Functions which only report aggregated numbers and never expose values of indexed keys
+-- don't require SELECT on the table of an inspected index, so instead of being executable by
+-- everyone (the default for new functions) they are only granted to monitoring roles. All other
+-- functions check SELECT on the table before reading any page.
+REVOKE EXECUTE ON FUNCTION gist_summary(oid) FROM PUBLIC;
+REVOKE EXECUTE ON FUNCTION gist_stat_record(oid) FROM PUBLIC;
+REVOKE EXECUTE ON FUNCTION gevel.prioritized_maintenance(integer) FROM PUBLIC;
+GRANT USAGE ON SCHEMA gevel TO pg_monitor;
+GRANT EXECUTE ON FUNCTION gist_summary(oid) TO pg_monitor;
+GRANT EXECUTE ON FUNCTION gist_stat_record(oid) TO pg_monitor;
+GRANT EXECUTE ON FUNCTION gevel.prioritized_maintenance(integer) TO pg_monitor;
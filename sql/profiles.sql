@@ -0,0 +1,13 @@
+-- Named inspection profiles saved by gevel.save_profile and run by gevel.run_profile. They
+-- are included in dumps, apart from the predefined ones created with the extension.
+CREATE TABLE gevel.profiles (
+    name text PRIMARY KEY,
+    profile jsonb NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+
+INSERT INTO gevel.profiles(name, profile) VALUES
+    ('health_ping', '{"analyses": ["summary"], "max_level": null, "sample_size": 100, "format": "json"}'),
+    ('deep_check', '{"analyses": ["stats", "levels", "histogram", "health", "duplicates", "worst_pages"], "max_level": null, "sample_size": 1000, "format": "json"}');
+
+SELECT pg_catalog.pg_extension_config_dump('gevel.profiles', 'WHERE name NOT IN (''health_ping'', ''deep_check'')');
@@ -0,0 +1,10 @@
+-- Statistics of subtrees under root downlinks stored by gist_stat_incremental, so that
+-- subsequent runs can skip subtrees which have not changed.
+CREATE TABLE gevel.stat_snapshots (
+    index_oid oid NOT NULL,
+    subtree_blkno bigint NOT NULL,
+    -- relfilenode and size of the index and LSN of its root page
+    fingerprint text NOT NULL,
+    snapshot jsonb NOT NULL,
+    PRIMARY KEY (index_oid, subtree_blkno)
+);
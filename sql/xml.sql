@@ -0,0 +1,5 @@
+-- pgx can't return values of the xml type, so the document is built as text and parsed here.
+-- Parsing requires PostgreSQL built with libxml.
+CREATE FUNCTION gist_tree_xml(rel regclass, include_items boolean DEFAULT false) RETURNS xml
+    LANGUAGE sql STABLE PARALLEL SAFE
+    AS $$ SELECT xmlparse(DOCUMENT gevel.tree_xml(rel, include_items)) $$;
@@ -0,0 +1,218 @@
+use crate::error::{raise, Error, ErrorClass, OrRaise};
+use crate::gist::{IndexInspector, Summary};
+use memoffset::offset_of;
+use pgx::pg_sys::{IndexTupleData, ItemIdData, PageHeaderData, BLCKSZ, MAXIMUM_ALIGNOF};
+use pgx::{JsonB, Spi};
+use serde_json::json;
+use std::mem::size_of;
+
+/// Size of `BTPageOpaqueData`, the special space of B-tree pages.
+const BT_SPECIAL_SIZE: usize = 16;
+/// Default fillfactor of B-tree leaf pages, used by `CREATE INDEX`.
+const BT_DEFAULT_FILLFACTOR: usize = 90;
+/// Fillfactor of B-tree internal pages, which can't be changed.
+const BT_NONLEAF_FILLFACTOR: usize = 70;
+/// Converting is recommended when B-tree would take at least this share of pages less than a
+/// freshly built GiST index, in percents.
+const MIN_SAVINGS_PCT: f64 = 20.0;
+
+fn maxalign(len: usize) -> usize {
+    let align = MAXIMUM_ALIGNOF as usize;
+    (len + align - 1) & !(align - 1)
+}
+
+/// Number of pages and levels of an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeShape {
+    pub pages: u64,
+    pub levels: usize,
+}
+
+/// Result of [IndexInspector::index_advice].
+#[derive(Debug)]
+pub struct Advice {
+    /// Type of the indexed column.
+    pub key_type: String,
+    /// Average size of a single key in bytes.
+    pub key_width: f64,
+    pub leaf_tuples: u64,
+    /// The index as it is now.
+    pub gist: TreeShape,
+    /// The index right after `REINDEX`, see [Summary::optimal_pages].
+    pub gist_reindexed: TreeShape,
+    /// Estimated B-tree index on the same column, right after `CREATE INDEX`.
+    pub btree: TreeShape,
+    /// Name of the exclusion constraint enforced by the index, if any.
+    pub exclusion_constraint: Option<String>,
+    /// True if the index should be replaced with a B-tree.
+    pub convert: bool,
+    /// Numbers justifying the recommendation.
+    pub reason: String,
+}
+
+impl Advice {
+    pub fn to_json(&self) -> serde_json::Value {
+        let shape = |s: &TreeShape| json!({ "pages": s.pages, "levels": s.levels });
+        json!({
+            "key_type": self.key_type,
+            "key_width": self.key_width,
+            "leaf_tuples": self.leaf_tuples,
+            "gist": shape(&self.gist),
+            "gist_reindexed": shape(&self.gist_reindexed),
+            "btree": shape(&self.btree),
+            "exclusion_constraint": self.exclusion_constraint,
+            "recommendation": if self.convert { "convert" } else { "keep" },
+            "reason": self.reason,
+        })
+    }
+}
+
+/// Estimates the shape of a B-tree holding `tuples` keys of a given average width, built by
+/// `CREATE INDEX` on a server with pages of `block_size` bytes. Pivot tuples of internal pages
+/// are assumed to be as wide as leaf keys, and deduplication is not accounted for, so indexes
+/// with many duplicates get even smaller.
+pub(crate) fn btree_shape(block_size: usize, tuples: u64, key_width: f64) -> TreeShape {
+    let page_space = block_size - offset_of!(PageHeaderData, pd_linp) - maxalign(BT_SPECIAL_SIZE);
+    let tuple =
+        maxalign(size_of::<IndexTupleData>() + key_width.ceil() as usize) + size_of::<ItemIdData>();
+    let per_leaf = (page_space * BT_DEFAULT_FILLFACTOR / 100 / tuple).max(1) as u64;
+    let fanout = (page_space * BT_NONLEAF_FILLFACTOR / 100 / tuple).max(2) as u64;
+    let mut pages = tuples.div_ceil(per_leaf).max(1);
+    let (mut total, mut levels) = (pages, 1);
+    while pages > 1 {
+        pages = pages.div_ceil(fanout);
+        total += pages;
+        levels += 1;
+    }
+    // metapage
+    TreeShape {
+        pages: total + 1,
+        levels,
+    }
+}
+
+impl IndexInspector {
+    /// Compares a single-column index using a btree_gist opclass with an equivalent B-tree and
+    /// recommends whether to convert it. Such indexes are often created for scalar columns out
+    /// of habit, while B-tree stores the same keys more densely: btree_gist keeps both bounds
+    /// of a range even on leaf pages for fixed-size types, and GiST pages split in halves.
+    ///
+    /// Sizes are compared against the GiST index right after `REINDEX`, so that bloat which
+    /// can be fixed without converting doesn't count. Indexes enforcing exclusion constraints
+    /// are never recommended for conversion. The advisor can't tell if queries rely on
+    /// operators B-tree doesn't support, like `<>` or the `<->` distance ordering.
+    pub fn index_advice(&self) -> Advice {
+        let query = format!(
+            "SELECT jsonb_build_object(\
+                 'key_columns', i.indnkeyatts, \
+                 'opclass', c.opcname::text, \
+                 'extension', e.extname::text, \
+                 'key_type', format_type(c.opcintype, NULL), \
+                 'typlen', t.typlen, \
+                 'has_btree', EXISTS (SELECT 1 FROM pg_opclass b JOIN pg_am a ON a.oid = b.opcmethod \
+                     WHERE a.amname = 'btree' AND b.opcdefault AND b.opcintype = c.opcintype), \
+                 'exclusion_constraint', (SELECT conname::text FROM pg_constraint \
+                     WHERE conindid = i.indexrelid AND contype = 'x' LIMIT 1)) \
+             FROM pg_index i JOIN pg_opclass c ON c.oid = i.indclass[0] \
+             JOIN pg_type t ON t.oid = c.opcintype \
+             LEFT JOIN pg_depend d ON d.classid = 'pg_opclass'::regclass AND d.objid = c.oid AND d.deptype = 'e' \
+             LEFT JOIN pg_extension e ON e.oid = d.refobjid \
+             WHERE i.indexrelid = {}",
+            self.oid()
+        );
+        // the index might have been dropped in between
+        let info = Spi::get_one::<JsonB>(&query)
+            .ok_or(Error::InvalidRelation { oid: self.oid() })
+            .or_raise()
+            .0;
+        let key_columns = info["key_columns"].as_u64().unwrap_or(0);
+        if key_columns != 1 {
+            raise(
+                ErrorClass::Unsupported,
+                &format!(
+                    "index advisor only supports single-column indexes, but the index has {} key columns",
+                    key_columns
+                ),
+            );
+        }
+        let opclass = info["opclass"].as_str().unwrap_or_default();
+        if info["extension"].as_str() != Some("btree_gist") {
+            raise(
+                ErrorClass::Unsupported,
+                &format!(
+                    "index advisor only supports btree_gist opclasses, but the index uses {}",
+                    opclass
+                ),
+            );
+        }
+        let key_type = info["key_type"].as_str().unwrap_or_default().to_string();
+        if info["has_btree"].as_bool() != Some(true) {
+            raise(
+                ErrorClass::Unsupported,
+                &format!("type {} has no default B-tree opclass", key_type),
+            );
+        }
+
+        let stats = self.stats(None);
+        let summary = Summary::from_stats(&stats, self.fillfactor());
+        let typlen = info["typlen"].as_i64().unwrap_or(-1);
+        let key_width = if typlen > 0 {
+            typlen as f64
+        } else if stats.num_leaf_tuple == 0 {
+            0.0
+        } else {
+            // btree_gist stores a single value in leaf keys of variable-length types, so B-tree
+            // leaf tuples would be as large as GiST ones
+            let tuple = stats.leaf_tuple_size as f64 / stats.num_leaf_tuple as f64;
+            (tuple - (size_of::<IndexTupleData>() + size_of::<ItemIdData>()) as f64).max(0.0)
+        };
+        let gist = TreeShape {
+            pages: stats.num_pages,
+            levels: stats.level + 1,
+        };
+        let gist_reindexed = TreeShape {
+            pages: summary.optimal_pages,
+            levels: summary.optimal_levels,
+        };
+        let btree = btree_shape(BLCKSZ as usize, stats.num_leaf_tuple, key_width);
+        let exclusion_constraint = info["exclusion_constraint"].as_str().map(|c| c.to_string());
+
+        let savings_pct = 100.0 - btree.pages as f64 * 100.0 / gist_reindexed.pages.max(1) as f64;
+        let (convert, reason) = if let Some(constraint) = exclusion_constraint.as_ref() {
+            (
+                false,
+                format!(
+                    "the index enforces exclusion constraint {}, which B-tree can't enforce",
+                    constraint
+                ),
+            )
+        } else if savings_pct >= MIN_SAVINGS_PCT || btree.levels < gist_reindexed.levels {
+            (
+                true,
+                format!(
+                    "B-tree would take about {} pages and {} levels, {:.0}% less pages than {} pages and {} levels of GiST even right after REINDEX (it takes {} pages now)",
+                    btree.pages, btree.levels, savings_pct, gist_reindexed.pages, gist_reindexed.levels, gist.pages
+                ),
+            )
+        } else {
+            (
+                false,
+                format!(
+                    "B-tree would take about {} pages and {} levels, which is not much less than {} pages and {} levels of GiST right after REINDEX",
+                    btree.pages, btree.levels, gist_reindexed.pages, gist_reindexed.levels
+                ),
+            )
+        };
+        Advice {
+            key_type,
+            key_width,
+            leaf_tuples: stats.num_leaf_tuple,
+            gist,
+            gist_reindexed,
+            btree,
+            exclusion_constraint,
+            convert,
+            reason,
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use crate::gist::IndexInspector;
+use crate::key::KeyDecoder;
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys;
+use pgx::pg_sys::{
+    index_getprocinfo, Datum, FmgrInfo, FunctionCall3Coll, OffsetNumber, Oid, Relation, GISTENTRY,
+    GIST_PENALTY_PROC, INDEX_MAX_KEYS,
+};
+use std::ptr::null_mut;
+
+/// Space usage and influence on insertions of a single key attribute of a multicolumn index.
+#[derive(Debug, Default)]
+pub struct ColumnAttribution {
+    pub name: String,
+    /// Total size of non-NULL keys of this attribute stored on leaf pages.
+    pub leaf_bytes: u64,
+    /// Total size of non-NULL keys of this attribute stored on internal pages.
+    pub internal_bytes: u64,
+    /// Sum and number of finite penalties computed between keys of internal pages.
+    penalty_sum: f64,
+    penalties: u64,
+    /// Number of simulated subtree choices decided by this attribute.
+    pub decisive_choices: u64,
+}
+
+impl ColumnAttribution {
+    /// Average penalty of extending internal keys of this attribute to cover their siblings,
+    /// `None` if no penalty has been computed (ie. for single-page indexes).
+    pub fn avg_penalty(&self) -> Option<f64> {
+        if self.penalties == 0 {
+            None
+        } else {
+            Some(self.penalty_sum / self.penalties as f64)
+        }
+    }
+}
+
+/// Result of [IndexInspector::column_attribution].
+#[derive(Debug, Default)]
+pub struct Attribution {
+    pub columns: Vec<ColumnAttribution>,
+    /// Number of simulated subtree choices, including ties not decided by any attribute.
+    pub choices: u64,
+}
+
+impl Attribution {
+    /// Share of all key bytes occupied by a given attribute, in percents.
+    pub fn bytes_pct(&self, attno: usize) -> f64 {
+        let total: u64 = self
+            .columns
+            .iter()
+            .map(|c| c.leaf_bytes + c.internal_bytes)
+            .sum();
+        if total == 0 {
+            0.0
+        } else {
+            let c = &self.columns[attno];
+            (c.leaf_bytes + c.internal_bytes) as f64 * 100.0 / total as f64
+        }
+    }
+
+    /// Share of simulated subtree choices decided by a given attribute, in percents.
+    pub fn decisive_pct(&self, attno: usize) -> Option<f64> {
+        if self.choices == 0 {
+            None
+        } else {
+            Some(self.columns[attno].decisive_choices as f64 * 100.0 / self.choices as f64)
+        }
+    }
+}
+
+/// Penalty support function of a single key attribute.
+pub(crate) struct Penalty {
+    relation: Relation,
+    proc: *mut FmgrInfo,
+    collation: Oid,
+}
+
+impl Penalty {
+    /// Looks up the penalty function of the key attribute at a given (0-based) position.
+    pub(crate) fn new(relation: Relation, attno: usize) -> Self {
+        unsafe {
+            Penalty {
+                relation,
+                proc: index_getprocinfo(relation, (attno + 1) as i16, GIST_PENALTY_PROC as u16),
+                collation: *(*relation).rd_indcollation.add(attno),
+            }
+        }
+    }
+
+    /// Computes penalty of extending `orig` key to cover `new` one, both given together with
+    /// their offsets or as `None` if NULL. NULLs are handled the same way as by `gistpenalty`:
+    /// they are never mixed with non-NULL keys.
+    pub(crate) fn compute(
+        &self,
+        page: &PageVisit,
+        orig: Option<(Datum, OffsetNumber)>,
+        new: Option<(Datum, OffsetNumber)>,
+    ) -> f64 {
+        self.compute_on(page.page.0, orig, new)
+    }
+
+    /// Same as [Penalty::compute], but for keys which are not stored on a page (ie. copied
+    /// out of it), which are passed to the opclass with a NULL page.
+    pub(crate) fn compute_detached(&self, orig: Option<Datum>, new: Option<Datum>) -> f64 {
+        self.compute_on(null_mut(), orig.map(|k| (k, 0)), new.map(|k| (k, 0)))
+    }
+
+    fn compute_on(
+        &self,
+        page: pg_sys::Page,
+        orig: Option<(Datum, OffsetNumber)>,
+        new: Option<(Datum, OffsetNumber)>,
+    ) -> f64 {
+        match (orig, new) {
+            (None, None) => 0.0,
+            (Some(orig), Some(new)) => {
+                let entry = |(key, offset)| GISTENTRY {
+                    key,
+                    rel: self.relation,
+                    page,
+                    offset,
+                    leafkey: false,
+                };
+                let (orig, new) = (entry(orig), entry(new));
+                let mut penalty: f32 = 0.0;
+                unsafe {
+                    FunctionCall3Coll(
+                        self.proc,
+                        self.collation,
+                        &orig as *const GISTENTRY as Datum,
+                        &new as *const GISTENTRY as Datum,
+                        &mut penalty as *mut f32 as Datum,
+                    )
+                };
+                penalty as f64
+            }
+            _ => f64::INFINITY,
+        }
+    }
+}
+
+type Keys = (
+    [Datum; INDEX_MAX_KEYS as usize],
+    [bool; INDEX_MAX_KEYS as usize],
+);
+
+struct AttributionCollector {
+    decoder: KeyDecoder,
+    /// Penalty functions of key attributes. Non-key (INCLUDE) attributes have none.
+    penalties: Vec<Penalty>,
+    /// Keys of internal pages on the path from the root to the currently visited page, together
+    /// with their offsets. Datums point into the pages, which stay in memory until they are left.
+    keys: Vec<Vec<(Keys, OffsetNumber)>>,
+    result: Attribution,
+}
+
+/// Returns the number of key attributes of an index, which excludes INCLUDE columns.
+#[cfg(not(feature = "pg10"))]
+fn key_attributes(relation: Relation) -> usize {
+    unsafe { (*(*relation).rd_index).indnkeyatts as usize }
+}
+
+#[cfg(feature = "pg10")]
+fn key_attributes(relation: Relation) -> usize {
+    unsafe { (*(*relation).rd_index).indnatts as usize }
+}
+
+impl AttributionCollector {
+    fn new(relation: Relation) -> Self {
+        let decoder = KeyDecoder::new(relation);
+        let penalties = (0..key_attributes(relation))
+            .map(|i| Penalty::new(relation, i))
+            .collect();
+        let columns = decoder
+            .attr_names()
+            .into_iter()
+            .map(|name| ColumnAttribution {
+                name,
+                ..ColumnAttribution::default()
+            })
+            .collect();
+        AttributionCollector {
+            decoder,
+            penalties,
+            keys: Vec::new(),
+            result: Attribution {
+                columns,
+                choices: 0,
+            },
+        }
+    }
+
+    /// Computes penalty of extending `orig` key of a given attribute to cover `new` one.
+    fn penalty(
+        &self,
+        page: &PageVisit,
+        attno: usize,
+        orig: &(Keys, OffsetNumber),
+        new: &(Keys, OffsetNumber),
+    ) -> f64 {
+        let key = |((values, is_null), offset): &(Keys, OffsetNumber)| {
+            Some((values[attno], *offset)).filter(|_| !is_null[attno])
+        };
+        self.penalties[attno].compute(page, key(orig), key(new))
+    }
+}
+
+impl Visitor for AttributionCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if !page.is_leaf() {
+            self.keys.push(Vec::new());
+        }
+        true
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        if tuple.is_invalid() {
+            return;
+        }
+        let keys = self.decoder.deform(tuple);
+        let (values, is_null) = &keys;
+        let is_leaf = page.is_leaf();
+        for (attno, column) in self.result.columns.iter_mut().enumerate() {
+            if !is_null[attno] {
+                let size = self.decoder.datum_size(attno, values[attno]) as u64;
+                if is_leaf {
+                    column.leaf_bytes += size;
+                } else {
+                    column.internal_bytes += size;
+                }
+            }
+        }
+        if let Some(page_keys) = self.keys.last_mut().filter(|_| !is_leaf) {
+            page_keys.push((keys, offset));
+        }
+    }
+
+    fn on_page_end(&mut self, page: &PageVisit) {
+        if page.is_leaf() {
+            return;
+        }
+        let keys = self.keys.pop().unwrap_or_default();
+        let n = keys.len();
+        if n < 3 {
+            return;
+        }
+        // Simulates gistchoose picking one of two downlinks for a key of their sibling, which
+        // stands for a newly inserted key: attributes are compared in order, and the first one
+        // whose penalties differ decides the choice.
+        for i in 0..n {
+            let new = &keys[i];
+            let a = &keys[(i + 1) % n];
+            let b = &keys[(i + 1 + n / 2) % n];
+            let mut decided_by = None;
+            for attno in 0..self.penalties.len() {
+                let pa = self.penalty(page, attno, a, new);
+                let pb = self.penalty(page, attno, b, new);
+                let column = &mut self.result.columns[attno];
+                for p in [pa, pb].iter().filter(|p| p.is_finite()) {
+                    column.penalty_sum += p;
+                    column.penalties += 1;
+                }
+                if decided_by.is_none() && pa != pb {
+                    decided_by = Some(attno);
+                }
+            }
+            if let Some(attno) = decided_by {
+                self.result.columns[attno].decisive_choices += 1;
+            }
+            self.result.choices += 1;
+        }
+    }
+}
+
+impl IndexInspector {
+    /// Attributes space usage and influence on insertions to individual key attributes.
+    ///
+    /// GiST picks the subtree for a new tuple by comparing penalties attribute by attribute,
+    /// so later attributes only matter when earlier ones tie. To estimate how often each of
+    /// them decides, keys of every internal page are used in turns as a new key, choosing
+    /// between two of its siblings.
+    pub fn column_attribution(&self) -> Attribution {
+        let mut collector = AttributionCollector::new(self.relation);
+        self.walk(&mut collector);
+        collector.result
+    }
+}
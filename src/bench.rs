@@ -0,0 +1,118 @@
+use crate::error::OrRaise;
+use crate::gist::{GistPage, IndexInspector};
+use crate::walker::{PageVisit, Visitor};
+use crate::{Buffer, Page, GIST_ROOT_BLKNO};
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Traversal strategies measured by [run].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Depth-first walk following downlinks, as done by [IndexInspector::walk].
+    DepthFirst,
+    /// Level by level walk following downlinks, with a queue of downlinks left to visit.
+    BreadthFirst,
+    /// Sequential read of all blocks in their physical order, ignoring tree structure.
+    PhysicalOrder,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 3] = [
+        Strategy::DepthFirst,
+        Strategy::BreadthFirst,
+        Strategy::PhysicalOrder,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::DepthFirst => "depth-first",
+            Strategy::BreadthFirst => "breadth-first",
+            Strategy::PhysicalOrder => "physical-order",
+        }
+    }
+}
+
+/// Result of a single benchmark run.
+#[derive(Debug)]
+pub struct BenchResult {
+    pub rows: i32,
+    pub strategy: Strategy,
+    pub pages: u64,
+    pub elapsed_ms: f64,
+}
+
+impl BenchResult {
+    pub fn pages_per_sec(&self) -> f64 {
+        if self.elapsed_ms == 0.0 {
+            0.0
+        } else {
+            self.pages as f64 / self.elapsed_ms * 1000.0
+        }
+    }
+}
+
+struct PageCounter(u64);
+
+impl Visitor for PageCounter {
+    fn on_page(&mut self, _page: &PageVisit) -> bool {
+        self.0 += 1;
+        true
+    }
+}
+
+/// Counts pages reachable from the root, visiting them level by level. Rightlinks are not
+/// followed, since demo indexes are not modified during the benchmark.
+fn breadth_first(index: &IndexInspector) -> u64 {
+    let mut queue = VecDeque::new();
+    queue.push_back(GIST_ROOT_BLKNO);
+    let mut pages = 0;
+    while let Some(blk) = queue.pop_front() {
+        let page = index.cache.get(blk);
+        if page.is_new() {
+            continue;
+        }
+        pages += 1;
+        if !GistPage::new(&page).is_leaf() {
+            queue.extend(page.tuples().map(|(_, tuple)| tuple.block_num()));
+        }
+    }
+    pages
+}
+
+/// Builds demo indexes of given sizes and measures throughput of every traversal strategy
+/// over each of them.
+pub fn run(sizes: &[i32]) -> Vec<BenchResult> {
+    let mut results = Vec::with_capacity(sizes.len() * Strategy::ALL.len());
+    for &rows in sizes {
+        let oid = crate::demo::setup(rows);
+        let index = IndexInspector::open(oid).or_raise();
+        for &strategy in Strategy::ALL.iter() {
+            let start = Instant::now();
+            let pages = match strategy {
+                Strategy::DepthFirst => {
+                    let mut counter = PageCounter(0);
+                    index.walk(&mut counter);
+                    counter.0
+                }
+                Strategy::BreadthFirst => breadth_first(&index),
+                Strategy::PhysicalOrder => {
+                    let mut pages = 0;
+                    for blk in 0..index.num_blocks() {
+                        let page = Page::new(Buffer::new(index.relation, blk));
+                        if page.max_offset() > 0 {
+                            pages += 1;
+                        }
+                    }
+                    pages
+                }
+            };
+            results.push(BenchResult {
+                rows,
+                strategy,
+                pages,
+                elapsed_ms: start.elapsed().as_secs_f64() * 1000.0,
+            });
+        }
+    }
+    results
+}
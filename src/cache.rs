@@ -0,0 +1,75 @@
+use crate::{Buffer, Page};
+use pgx::pg_sys::{work_mem, BlockNumber, Relation, BLCKSZ};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Small LRU cache of pages read during a single inspection call. Cached pages are local copies
+/// of their buffers, so they are not read through the buffer manager again when several
+/// analyses visit the same (usually internal) pages.
+pub struct PageCache {
+    relation: Relation,
+    capacity: usize,
+    /// Cached pages, most recently used first.
+    pages: RefCell<VecDeque<(BlockNumber, Rc<Page>)>>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl PageCache {
+    /// Min number of pages kept in cache, regardless of `work_mem`.
+    const MIN_CAPACITY: usize = 8;
+    /// Max number of pages kept in cache. Lookups are linear, so it must stay small.
+    const MAX_CAPACITY: usize = 256;
+
+    /// Creates a cache for pages of a given relation, which uses up to a quarter of
+    /// `work_mem`.
+    pub fn new(relation: Relation) -> Self {
+        let budget = unsafe { work_mem } as usize * 1024 / 4;
+        let capacity = (budget / BLCKSZ as usize).clamp(Self::MIN_CAPACITY, Self::MAX_CAPACITY);
+        PageCache {
+            relation,
+            capacity,
+            pages: RefCell::new(VecDeque::with_capacity(capacity)),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    /// Returns a page with a given block number, reading it only if it's not already cached.
+    pub fn get(&self, blk: BlockNumber) -> Rc<Page> {
+        let mut pages = self.pages.borrow_mut();
+        if let Some(i) = pages.iter().position(|(b, _)| *b == blk) {
+            self.hits.set(self.hits.get() + 1);
+            let entry = pages.remove(i).unwrap();
+            let page = entry.1.clone();
+            pages.push_front(entry);
+            return page;
+        }
+
+        self.misses.set(self.misses.get() + 1);
+        let page = Rc::new(Page::new(Buffer::new(self.relation, blk)));
+        if pages.len() == self.capacity {
+            pages.pop_back();
+        }
+        pages.push_front((blk, page.clone()));
+        page
+    }
+
+    /// Number of page requests served from cache.
+    #[cfg(any(test, feature = "pg_test"))]
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    /// Number of page requests which had to be read through the buffer manager.
+    #[cfg(any(test, feature = "pg_test"))]
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+
+    /// Releases all cached pages.
+    pub fn clear(&self) {
+        self.pages.borrow_mut().clear();
+    }
+}
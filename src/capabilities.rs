@@ -0,0 +1,79 @@
+use pgx::*;
+use serde_json::json;
+
+/// Output formats of the index tree, by the functions producing them.
+const FORMATS: [(&str, &str); 10] = [
+    ("text", "gist_tree"),
+    ("text_lines", "gist_tree_lines"),
+    ("json", "gist_tree_json"),
+    ("json_stream", "gist_tree_json_stream"),
+    ("rows", "gist_tree_rows"),
+    ("markdown", "gist_tree_markdown"),
+    ("csv", "gist_pages_csv"),
+    ("dot", "gist_tree_dot"),
+    ("mermaid", "gist_tree_mermaid"),
+    ("xml", "gist_tree_xml"),
+];
+
+/// Returns names of all functions installed by the extension, qualified with the `gevel`
+/// schema for the ones living there.
+fn installed_functions() -> serde_json::Value {
+    let query = "SELECT coalesce(jsonb_agg(DISTINCT f.name ORDER BY f.name), '[]') FROM ( \
+                 SELECT CASE WHEN n.nspname = 'gevel' THEN 'gevel.' || p.proname ELSE p.proname::text END AS name \
+                 FROM pg_proc p \
+                 JOIN pg_namespace n ON n.oid = p.pronamespace \
+                 JOIN pg_depend d ON d.classid = 'pg_proc'::regclass AND d.objid = p.oid AND d.deptype = 'e' \
+                 JOIN pg_extension e ON e.oid = d.refobjid \
+                 WHERE e.extname = 'gevel') f";
+    Spi::get_one::<JsonB>(query).map_or(json!([]), |f| f.0)
+}
+
+/// Describes what this build of gevel supports on the running server, so that client tooling
+/// can adapt instead of failing on functions missing from older builds.
+pub fn capabilities() -> serde_json::Value {
+    let functions = installed_functions();
+    let installed = |name: &str| {
+        functions
+            .as_array()
+            .is_some_and(|f| f.iter().any(|f| f == name))
+    };
+    let formats: Vec<&str> = FORMATS
+        .iter()
+        .filter(|(_, function)| installed(function))
+        .map(|(format, _)| *format)
+        .collect();
+    json!({
+        "gevel_version": env!("CARGO_PKG_VERSION"),
+        "server_version_num": Spi::get_one::<String>("SELECT current_setting('server_version_num')")
+            .and_then(|v| v.parse::<u64>().ok()),
+        "access_methods": ["gist"],
+        "formats": formats,
+        "features": {
+            "parallel_traversal": false,
+            "background_monitor": false,
+            "wal_correlation": false,
+            "incremental_stats": installed("gist_stat_incremental"),
+            // the hook is installed by _PG_init, which libraries don't have
+            "explain_annotations": !cfg!(feature = "testing"),
+            "scan_stats": scan_stats_enabled(),
+            "memory_context_accounting": cfg!(feature = "pg13"),
+            "bench": cfg!(feature = "bench"),
+            "testing": cfg!(feature = "testing"),
+        },
+        "functions": functions,
+        "c_abi_version": crate::ffi::GEVEL_ABI_VERSION,
+        "block_size": pg_sys::BLCKSZ,
+    })
+}
+
+/// Returns true if scans of GiST indexes are being tracked, which requires gevel to be loaded
+/// via `shared_preload_libraries`.
+#[cfg(not(feature = "testing"))]
+fn scan_stats_enabled() -> bool {
+    crate::scanstats::is_enabled()
+}
+
+#[cfg(feature = "testing")]
+fn scan_stats_enabled() -> bool {
+    false
+}
@@ -0,0 +1,295 @@
+use crate::gist::{GistPage, IndexInspector};
+use crate::walker::{check_for_interrupts, PageVisit, Visitor};
+use crate::IndexTuple;
+use crate::Page;
+use pgx::pg_sys::{
+    wal_level, BlockNumber, InvalidBlockNumber, OffsetNumber, WalLevel_WAL_LEVEL_REPLICA,
+    F_DELETED, F_FOLLOW_RIGHT, F_HAS_GARBAGE, F_LEAF, F_TUPLES_DELETED, GIST_PAGE_ID,
+    RELPERSISTENCE_PERMANENT,
+};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// LSN assigned to pages written by an index build, before they are WAL-logged at its end.
+const GIST_BUILD_LSN: u64 = 1;
+
+/// All page flags known to GiST.
+const KNOWN_FLAGS: u32 = F_LEAF | F_DELETED | F_TUPLES_DELETED | F_FOLLOW_RIGHT | F_HAS_GARBAGE;
+
+/// Signatures of pages left behind by an interrupted index build.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildLeftover {
+    /// Page of a WAL-logged index still carries the LSN assigned during the build, so the
+    /// build never reached the point of WAL-logging the index.
+    BuildLsn,
+    /// Page has flags which GiST never sets.
+    UnknownFlags(u16),
+    /// Initialized page which is not a GiST page, ie. a temporary page written by a build.
+    ForeignPage(u16),
+    /// Deleted or never initialized page is still referenced by a downlink.
+    ReachableUnused,
+}
+
+impl BuildLeftover {
+    /// Classifies a page, returning all leftover signatures it matches. `reachable` tells
+    /// whether the page is referenced by a downlink.
+    pub fn classify(page: &Page, reachable: bool, logged: bool) -> Vec<BuildLeftover> {
+        let mut signatures = Vec::new();
+        if page.is_new() {
+            // new page, left by extending the relation
+            if reachable {
+                signatures.push(BuildLeftover::ReachableUnused);
+            }
+            return signatures;
+        }
+        let gist_page = GistPage::new(page);
+        if gist_page.page_id() as u32 != GIST_PAGE_ID {
+            signatures.push(BuildLeftover::ForeignPage(gist_page.page_id()));
+            return signatures;
+        }
+        if logged && page.lsn() == GIST_BUILD_LSN {
+            signatures.push(BuildLeftover::BuildLsn);
+        }
+        if gist_page.flags() as u32 & !KNOWN_FLAGS != 0 {
+            signatures.push(BuildLeftover::UnknownFlags(gist_page.flags()));
+        }
+        if reachable && gist_page.is_deleted() {
+            signatures.push(BuildLeftover::ReachableUnused);
+        }
+        signatures
+    }
+}
+
+impl Display for BuildLeftover {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildLeftover::BuildLsn => write!(f, "page was written by an unfinished index build"),
+            BuildLeftover::UnknownFlags(flags) => write!(f, "unknown page flags 0x{:04x}", flags),
+            BuildLeftover::ForeignPage(id) => write!(f, "not a GiST page (page id 0x{:04x})", id),
+            BuildLeftover::ReachableUnused => {
+                write!(f, "unused page is referenced by a downlink")
+            }
+        }
+    }
+}
+
+/// Structural inconsistency found by [IndexInspector::check].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// Name of the check which reported the finding.
+    pub check: &'static str,
+    /// Level of the page, None for pages not reachable from the root.
+    pub level: Option<usize>,
+    pub block_num: BlockNumber,
+    pub message: String,
+}
+
+impl Finding {
+    /// Returns JSON representation of the finding, with the same fields as rows of `gist_check`.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "check": self.check,
+            "level": self.level,
+            "blkno": self.block_num,
+            "message": self.message,
+        })
+    }
+}
+
+/// Page reached by a downlink, as seen by [LinkCollector].
+struct LinkedPage {
+    block_num: BlockNumber,
+    right_link: BlockNumber,
+    /// Page was split, but the downlink to its right sibling hasn't been inserted yet.
+    follow_right: bool,
+}
+
+/// Downlink pointing past the end of the index.
+struct DanglingDownlink {
+    level: usize,
+    parent: BlockNumber,
+    offset: OffsetNumber,
+    child: BlockNumber,
+}
+
+/// Collects downlinks and rightlinks of all pages reachable from the root.
+#[derive(Default)]
+struct LinkCollector {
+    /// Size of the index when the walk started.
+    num_blocks: BlockNumber,
+    /// Downlinks pointing to blocks past `num_blocks`, which are not followed.
+    dangling: Vec<DanglingDownlink>,
+    /// Level of every page reached by a downlink.
+    level_of: HashMap<BlockNumber, usize>,
+    /// Number of downlinks pointing to every non-root page.
+    downlinks: HashMap<BlockNumber, u32>,
+    /// Pages of each level in depth-first order.
+    levels: Vec<Vec<LinkedPage>>,
+}
+
+impl Visitor for LinkCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if self.level_of.insert(page.block_num, page.level).is_some() {
+            // page referenced by a duplicate downlink, its subtree has been visited already
+            return false;
+        }
+        if self.levels.len() <= page.level {
+            self.levels.resize_with(page.level + 1, Vec::new);
+        }
+        self.levels[page.level].push(LinkedPage {
+            block_num: page.block_num,
+            right_link: page.gist_page.right_link(),
+            follow_right: page.gist_page.is_follow_right(),
+        });
+        true
+    }
+
+    fn descend(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) -> bool {
+        let child = tuple.block_num();
+        if child >= self.num_blocks {
+            self.dangling.push(DanglingDownlink {
+                level: page.level,
+                parent: page.block_num,
+                offset,
+                child,
+            });
+            return false;
+        }
+        *self.downlinks.entry(child).or_default() += 1;
+        true
+    }
+}
+
+impl IndexInspector {
+    /// Runs structural checks of the index and returns everything they found. Healthy index
+    /// produces no findings.
+    pub fn check(&self) -> Vec<Finding> {
+        let mut collector = LinkCollector {
+            num_blocks: self.num_blocks(),
+            ..LinkCollector::default()
+        };
+        self.walk(&mut collector);
+        let mut findings = self.check_downlinks(&collector);
+        findings.extend(self.check_rightlinks(&collector));
+        findings.extend(self.check_build_leftovers(&collector));
+        findings.sort_by_key(|f| (f.level, f.block_num));
+        findings
+    }
+
+    /// Reports downlinks pointing past the end of the index. Their subtrees can't be visited.
+    fn check_downlinks(&self, links: &LinkCollector) -> Vec<Finding> {
+        // pages may have been added by splits since the walk started, and their right halves
+        // reached by downlinks read afterwards
+        let num_blocks = self.num_blocks();
+        links
+            .dangling
+            .iter()
+            .filter(|d| d.child >= num_blocks)
+            .map(|d| Finding {
+                check: "downlinks",
+                level: Some(d.level),
+                block_num: d.parent,
+                message: format!(
+                    "downlink at offset {} points to block {} past the end of the index ({} blocks)",
+                    d.offset, d.child, num_blocks
+                ),
+            })
+            .collect()
+    }
+
+    /// Looks for artifacts of interrupted index builds in all pages of the index, including
+    /// the ones not reachable from the root.
+    fn check_build_leftovers(&self, links: &LinkCollector) -> Vec<Finding> {
+        // unlogged and temporary indexes never get their pages WAL-logged, and neither do
+        // indexes built with wal_level = minimal, assuming that it hasn't been changed since
+        let persistence = unsafe { (*(*self.relation).rd_rel).relpersistence } as u8;
+        let logged = persistence == RELPERSISTENCE_PERMANENT
+            && unsafe { wal_level } >= WalLevel_WAL_LEVEL_REPLICA as i32;
+        let mut findings = Vec::new();
+        for blk in 0..self.num_blocks() {
+            check_for_interrupts!();
+            let page = self.cache.get(blk);
+            let level = links.level_of.get(&blk);
+            // the walk doesn't enter deleted pages, but still counts downlinks pointing to them
+            let reachable = level.is_some() || links.downlinks.contains_key(&blk);
+            for signature in BuildLeftover::classify(&page, reachable, logged) {
+                findings.push(Finding {
+                    check: "build",
+                    level: level.copied(),
+                    block_num: blk,
+                    message: signature.to_string(),
+                });
+            }
+        }
+        findings
+    }
+
+    /// Cross-checks rightlinks against downlinks on every level. Every page reachable via
+    /// rightlink chains of a level should also be reachable by exactly one downlink from the
+    /// level above, so pages reachable only via rightlinks indicate lost downlinks. The other
+    /// direction can't be checked: unlike B-tree, GiST doesn't link all pages of a level (pages
+    /// created by a root split are not linked with each other), so pages which are not
+    /// reachable via any rightlink are perfectly normal.
+    fn check_rightlinks(&self, links: &LinkCollector) -> Vec<Finding> {
+        let num_blocks = self.num_blocks();
+        let mut findings = Vec::new();
+        let mut report = |level, block_num, message| {
+            findings.push(Finding {
+                check: "rightlinks",
+                level,
+                block_num,
+                message,
+            })
+        };
+
+        for (level, pages) in links.levels.iter().enumerate().skip(1) {
+            let mut linked = HashSet::new();
+            for page in pages.iter() {
+                let (mut left, mut right) = (page.block_num, page.right_link);
+                let mut follow_right = page.follow_right;
+                // follow the chain through pages which have no downlinks
+                while right != InvalidBlockNumber && right < num_blocks && linked.insert(right) {
+                    if links.level_of.contains_key(&right) {
+                        // rightlinks of recycled pages may be stale and point to a page on
+                        // another level, but such page is reachable anyway
+                        break;
+                    }
+                    let page = self.cache.get(right);
+                    if page.is_new() {
+                        // new page, which was never initialized
+                        break;
+                    }
+                    let gist_page = GistPage::new(&page);
+                    // deleted pages stay in the chain, and the downlink of a right half of an
+                    // incomplete split is expected to be missing
+                    if !gist_page.is_deleted() && !follow_right {
+                        report(
+                            Some(level),
+                            right,
+                            format!(
+                                "reachable via rightlink of block {}, but no downlink points to it",
+                                left
+                            ),
+                        );
+                    }
+                    left = right;
+                    right = gist_page.right_link();
+                    follow_right = gist_page.is_follow_right();
+                }
+            }
+        }
+
+        for (&block_num, &count) in links.downlinks.iter() {
+            if count > 1 {
+                // deleted and never initialized pages are not entered by the walk
+                report(
+                    links.level_of.get(&block_num).copied(),
+                    block_num,
+                    format!("referenced by {} downlinks", count),
+                );
+            }
+        }
+        findings
+    }
+}
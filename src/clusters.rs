@@ -0,0 +1,298 @@
+use crate::attribution::Penalty;
+use crate::error::{raise, ErrorClass};
+use crate::gist::{GistPage, IndexInspector};
+use crate::key::KeyDecoder;
+use crate::sys::{datumCopy, TupleDescAttr};
+use crate::walker::{check_for_interrupts, PageVisit, Visitor};
+use crate::{IndexTuple, GIST_ROOT_BLKNO};
+use pgx::pg_sys::{BlockNumber, Datum, OffsetNumber, Relation};
+use std::ptr::null_mut;
+
+/// Max number of clusters which can be requested.
+pub const MAX_CLUSTERS: usize = 100;
+
+/// Max number of sampled keys. Updating medoids costs a number of penalty calls quadratic in
+/// the size of clusters, so the sample must stay small.
+pub const MAX_SAMPLE: usize = 2000;
+
+/// Max number of k-medoids iterations. They usually converge in a few.
+const MAX_ITERATIONS: usize = 20;
+
+/// A group of sampled leaf keys, see [IndexInspector::key_clusters].
+#[derive(Debug)]
+pub struct KeyCluster {
+    /// Number of sampled keys belonging to the cluster.
+    pub keys: usize,
+    /// The most central key of the cluster, rendered as text.
+    pub medoid: String,
+    /// Union of all keys of the cluster computed by the opclass, rendered as text.
+    pub extent: String,
+    /// Average distance between keys of the cluster and its medoid.
+    pub avg_distance: f64,
+    /// Distance between the medoid and the nearest medoid of another cluster, `None` if there
+    /// is only one cluster. Clusters much further apart than their average distances are well
+    /// separated.
+    pub nearest_distance: Option<f64>,
+}
+
+/// Result of [IndexInspector::key_clusters].
+#[derive(Debug)]
+pub struct KeyClusters {
+    /// Number of sampled keys, which may be less than requested for small indexes.
+    pub sampled: usize,
+    /// Clusters ordered by size, largest first.
+    pub clusters: Vec<KeyCluster>,
+}
+
+impl KeyClusters {
+    /// Share of sampled keys belonging to a given cluster, in percents.
+    pub fn keys_pct(&self, cluster: usize) -> f64 {
+        if self.sampled == 0 {
+            0.0
+        } else {
+            self.clusters[cluster].keys as f64 * 100.0 / self.sampled as f64
+        }
+    }
+}
+
+/// Collects block numbers of all leaf pages without reading them.
+struct LeafBlocks {
+    leaf_level: usize,
+    blocks: Vec<BlockNumber>,
+}
+
+impl Visitor for LeafBlocks {
+    fn descend(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) -> bool {
+        if page.level + 1 == self.leaf_level {
+            self.blocks.push(tuple.block_num());
+            false
+        } else {
+            true
+        }
+    }
+}
+
+/// Copies a datum of the first key attribute, so that it outlives the page it's stored on.
+fn copy_key(relation: Relation, datum: Datum) -> Datum {
+    unsafe {
+        let attr = &*TupleDescAttr((*relation).rd_att, 0);
+        datumCopy(datum, attr.attbyval, attr.attlen as i32)
+    }
+}
+
+/// Symmetric dissimilarity of two keys: the sum of penalties of extending each of them to
+/// cover the other one.
+fn distance(penalty: &Penalty, a: Datum, b: Datum) -> f64 {
+    let d = penalty.compute_detached(Some(a), Some(b)) + penalty.compute_detached(Some(b), Some(a));
+    if d.is_nan() {
+        f64::INFINITY
+    } else {
+        d
+    }
+}
+
+/// Returns position of the nearest medoid for every key.
+fn assign(penalty: &Penalty, keys: &[Datum], medoids: &[usize]) -> Vec<usize> {
+    keys.iter()
+        .map(|&key| {
+            let distances = medoids.iter().map(|&m| distance(penalty, keys[m], key));
+            distances
+                .enumerate()
+                .fold(
+                    (0, f64::INFINITY),
+                    |best, (i, d)| if d < best.1 { (i, d) } else { best },
+                )
+                .0
+        })
+        .collect()
+}
+
+impl IndexInspector {
+    /// Returns up to `sample` non-NULL keys of the first key attribute, copied out of leaf
+    /// pages picked at even intervals, with keys picked at even intervals from each of them.
+    fn sample_leaf_keys(&self, sample: usize) -> Vec<Datum> {
+        let leaf_level = match self.leaf_level() {
+            Some(level) => level,
+            None => return Vec::new(),
+        };
+        let mut collector = LeafBlocks {
+            leaf_level,
+            blocks: Vec::new(),
+        };
+        if collector.leaf_level == 0 {
+            collector.blocks.push(GIST_ROOT_BLKNO);
+        } else {
+            self.walk(&mut collector);
+        }
+        let leaves = collector.blocks;
+        if leaves.is_empty() {
+            return Vec::new();
+        }
+        let pages = leaves.len().min(sample);
+        let per_page = sample.div_ceil(pages);
+        let step = leaves.len() as f64 / pages as f64;
+        let decoder = KeyDecoder::new(self.relation);
+        let mut keys = Vec::with_capacity(sample);
+        for i in 0..pages {
+            check_for_interrupts!();
+            let page = self.cache.get(leaves[(i as f64 * step) as usize]);
+            if page.is_new() || !GistPage::new(&page).is_leaf() {
+                continue;
+            }
+            let tuples: Vec<_> = page.tuples().map(|(_, tuple)| tuple).collect();
+            let take = per_page.min(tuples.len());
+            for j in 0..take {
+                let tuple = &tuples[j * tuples.len() / take];
+                if tuple.is_invalid() {
+                    continue;
+                }
+                let (values, is_null) = decoder.deform(tuple);
+                if !is_null[0] {
+                    keys.push(copy_key(self.relation, values[0]));
+                }
+            }
+            if keys.len() >= sample {
+                break;
+            }
+        }
+        keys.truncate(sample);
+        keys
+    }
+
+    /// Groups a sample of leaf keys of the first key attribute into (up to) `k` clusters.
+    ///
+    /// Keys are clustered with k-medoids, using the sum of penalties of extending each of two
+    /// keys to cover the other one as a distance, so it works for any opclass. Initial medoids
+    /// are picked farthest-first, starting from the first sampled key, which makes results
+    /// deterministic for a given index. Indexes whose keys fall into a few well separated
+    /// clusters are likely to be served better by partitioning or partial indexes along them.
+    pub fn key_clusters(&self, k: usize, sample: usize) -> KeyClusters {
+        if k == 0 || k > MAX_CLUSTERS {
+            raise(
+                ErrorClass::InvalidArgument,
+                &format!("k must be between 1 and {}, got {}", MAX_CLUSTERS, k),
+            );
+        }
+        if sample < k || sample > MAX_SAMPLE {
+            raise(
+                ErrorClass::InvalidArgument,
+                &format!(
+                    "sample must be between k ({}) and {}, got {}",
+                    k, MAX_SAMPLE, sample
+                ),
+            );
+        }
+        let penalty = Penalty::new(self.relation, 0);
+        let keys = self.sample_leaf_keys(sample);
+        if keys.is_empty() {
+            return KeyClusters {
+                sampled: 0,
+                clusters: Vec::new(),
+            };
+        }
+
+        // farthest-first initialization
+        let mut medoids = vec![0];
+        let mut nearest: Vec<f64> = keys
+            .iter()
+            .map(|&key| distance(&penalty, keys[0], key))
+            .collect();
+        while medoids.len() < k {
+            let (next, d) = nearest
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !medoids.contains(i))
+                .fold(
+                    (None, -1.0),
+                    |best, (i, &d)| {
+                        if d > best.1 {
+                            (Some(i), d)
+                        } else {
+                            best
+                        }
+                    },
+                );
+            // remaining keys are identical to one of medoids
+            match next.filter(|_| d > 0.0) {
+                Some(next) => {
+                    medoids.push(next);
+                    for (i, &key) in keys.iter().enumerate() {
+                        nearest[i] = nearest[i].min(distance(&penalty, keys[next], key));
+                    }
+                }
+                None => break,
+            }
+        }
+
+        let mut assignment = assign(&penalty, &keys, &medoids);
+        for _ in 0..MAX_ITERATIONS {
+            check_for_interrupts!();
+            let mut changed = false;
+            for (cluster, medoid) in medoids.iter_mut().enumerate() {
+                let members: Vec<usize> = (0..keys.len())
+                    .filter(|&i| assignment[i] == cluster)
+                    .collect();
+                let cost = |candidate: usize| -> f64 {
+                    members
+                        .iter()
+                        .map(|&m| distance(&penalty, keys[candidate], keys[m]))
+                        .sum()
+                };
+                let mut best = (*medoid, cost(*medoid));
+                for &candidate in members.iter().filter(|&&m| m != *medoid) {
+                    let c = cost(candidate);
+                    if c < best.1 {
+                        best = (candidate, c);
+                    }
+                }
+                if best.0 != *medoid {
+                    *medoid = best.0;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+            assignment = assign(&penalty, &keys, &medoids);
+        }
+
+        let decoder = KeyDecoder::new(self.relation);
+        let mut clusters: Vec<KeyCluster> = medoids
+            .iter()
+            .enumerate()
+            .filter_map(|(cluster, &medoid)| {
+                let members: Vec<(Datum, OffsetNumber)> = (0..keys.len())
+                    .filter(|&i| assignment[i] == cluster)
+                    .map(|i| (keys[i], 0))
+                    .collect();
+                // medoids identical to another one may end up with no keys
+                if members.is_empty() {
+                    return None;
+                }
+                let total: f64 = members
+                    .iter()
+                    .map(|&(key, _)| distance(&penalty, keys[medoid], key))
+                    .sum();
+                let nearest_distance = medoids
+                    .iter()
+                    .filter(|&&other| other != medoid)
+                    .map(|&other| distance(&penalty, keys[medoid], keys[other]))
+                    .fold(None, |min: Option<f64>, d| {
+                        Some(min.map_or(d, |m| m.min(d)))
+                    });
+                Some(KeyCluster {
+                    keys: members.len(),
+                    medoid: decoder.render_datum(0, keys[medoid]),
+                    extent: decoder.render_datum(0, self.union_keys(&members, null_mut())),
+                    avg_distance: total / members.len() as f64,
+                    nearest_distance,
+                })
+            })
+            .collect();
+        clusters.sort_by_key(|c| std::cmp::Reverse(c.keys));
+        KeyClusters {
+            sampled: keys.len(),
+            clusters,
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::error::{raise, Error, ErrorClass, OrRaise};
+use pgx::pg_sys::Oid;
+use pgx::*;
+use std::str::FromStr;
+
+/// Name of the table created by [setup].
+pub const DEMO_TABLE: &str = "gevel.demo_points";
+/// Name of the GiST index created by [setup].
+pub const DEMO_INDEX: &str = "gevel.demo_points_p_idx";
+
+/// Distributions of points generated by [setup_dataset]. Apart from the random one, all of
+/// them are adversarial and provoke a known pathology of GiST indexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dataset {
+    /// Points spread uniformly over a 1000x1000 square.
+    Random,
+    /// All points are the same, so keys can't be told apart by any split.
+    Identical,
+    /// All points fall into a tiny area, so keys barely differ from each other.
+    Clustered,
+    /// Random points with every 1000th one placed very far away, which blows up the union
+    /// keys of every page it lands on.
+    Outliers,
+    /// Points inserted in increasing order along a line, which leaves pages half-empty after
+    /// splits.
+    Sequential,
+}
+
+impl Dataset {
+    /// SQL expression producing a point for the `i`-th row.
+    fn point_expr(&self) -> &'static str {
+        match self {
+            Dataset::Random => "point(random() * 1000, random() * 1000)",
+            Dataset::Identical => "point(500, 500)",
+            Dataset::Clustered => "point(500 + random() * 0.001, 500 + random() * 0.001)",
+            Dataset::Outliers => {
+                "CASE WHEN i % 1000 = 0 THEN point(1e9 * random(), 1e9 * random()) \
+                 ELSE point(random() * 1000, random() * 1000) END"
+            }
+            Dataset::Sequential => "point(i, i)",
+        }
+    }
+}
+
+impl FromStr for Dataset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Dataset::Random),
+            "identical" => Ok(Dataset::Identical),
+            "clustered" => Ok(Dataset::Clustered),
+            "outliers" => Ok(Dataset::Outliers),
+            "sequential" => Ok(Dataset::Sequential),
+            other => Err(format!(
+                "unknown demo dataset: \"{}\" (expected one of: random, identical, clustered, outliers, sequential)",
+                other
+            )),
+        }
+    }
+}
+
+/// (Re)creates a demo table filled with `rows` random points and a GiST index over them.
+/// Returns the oid of the created index.
+#[cfg(any(test, feature = "pg_test", feature = "bench"))]
+pub fn setup(rows: i32) -> Oid {
+    setup_dataset(rows, Dataset::Random)
+}
+
+/// Seed of `random()` used to generate points, so that the same demo index (and the same
+/// page and level counts) is built every time.
+const SEED: f64 = 0.42;
+
+/// (Re)creates a demo table filled with `rows` points of a given distribution and a GiST
+/// index over them. Returns the oid of the created index.
+pub fn setup_dataset(rows: i32, dataset: Dataset) -> Oid {
+    Spi::run(&format!("DROP TABLE IF EXISTS {}", DEMO_TABLE));
+    Spi::run(&format!(
+        "CREATE TABLE {}(id serial primary key, p point)",
+        DEMO_TABLE
+    ));
+    Spi::run(&format!("SELECT setseed({})", SEED));
+    Spi::run(&format!(
+        "INSERT INTO {}(p) SELECT {} FROM generate_series(1, {}) AS i",
+        DEMO_TABLE,
+        dataset.point_expr(),
+        rows.max(0)
+    ));
+    Spi::run(&format!(
+        "CREATE INDEX demo_points_p_idx ON {} USING gist(p)",
+        DEMO_TABLE
+    ));
+    let query = format!("SELECT '{}'::regclass::oid::bigint", DEMO_INDEX);
+    Spi::get_one::<i64>(&query)
+        .ok_or_else(|| Error::InvalidArgument(format!("demo index {} was not created", DEMO_INDEX)))
+        .or_raise() as Oid
+}
+
+/// Parses a name of a [Dataset], raising an error for unknown ones.
+pub fn parse_dataset(name: &str) -> Dataset {
+    name.trim()
+        .to_lowercase()
+        .parse()
+        .unwrap_or_else(|e: String| raise(ErrorClass::InvalidArgument, &e))
+}
@@ -0,0 +1,226 @@
+use crate::error::{Error, Result};
+use crate::gist::{item_state, IndexInspector};
+use crate::OffsetRange;
+use memoffset::offset_of;
+use pgx::pg_sys::{
+    BlockNumber, FirstOffsetNumber, GISTPageOpaqueData, ItemIdData, OffsetNumber, PageHeaderData,
+    PageXLogRecPtr, BLCKSZ, MAXIMUM_ALIGNOF,
+};
+use std::collections::HashMap;
+use std::mem::size_of;
+
+/// A single difference between two images of the same page, reported by
+/// [IndexInspector::page_diff].
+#[derive(Debug, PartialEq, Eq)]
+pub struct PageDifference {
+    /// One of: `header` (header field changed), `item_added`, `item_removed` or
+    /// `item_changed` (line pointer state changed).
+    pub kind: &'static str,
+    /// Name of the header field or an offset of the item.
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Copy of a raw page image, detached from the buffer manager.
+struct PageImage {
+    /// Page bytes, stored as words so that page structures can be read with proper alignment.
+    words: Vec<u64>,
+}
+
+/// Line pointer of a [PageImage] together with the tuple it points to.
+struct ImageItem<'a> {
+    offset: OffsetNumber,
+    state: &'static str,
+    data: &'a [u8],
+}
+
+impl<'a> ImageItem<'a> {
+    /// Describes the item by its item pointer (heap tuple for leaf keys and child page for
+    /// internal ones) and size.
+    fn describe(&self) -> String {
+        if self.data.len() < 6 {
+            return format!("{}, {} bytes", self.state, self.data.len());
+        }
+        let read = |i: usize| u16::from_ne_bytes([self.data[i], self.data[i + 1]]);
+        let block_num = ((read(0) as BlockNumber) << 16) | read(2) as BlockNumber;
+        format!(
+            "{}, tid ({},{}), {} bytes",
+            self.state,
+            block_num,
+            read(4),
+            self.data.len()
+        )
+    }
+}
+
+impl PageImage {
+    fn new(bytes: &[u8]) -> Result<Self, String> {
+        let block_size = BLCKSZ as usize;
+        if bytes.len() != block_size {
+            return Err(format!(
+                "page image must be exactly {} bytes long, got {}",
+                block_size,
+                bytes.len()
+            ));
+        }
+        let mut words = vec![0u64; bytes.len() / size_of::<u64>()];
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                words.as_mut_ptr() as *mut u8,
+                bytes.len(),
+            )
+        };
+        let image = PageImage { words };
+        let header = image.header();
+        let is_valid = header.pd_lower as usize >= offset_of!(PageHeaderData, pd_linp)
+            && header.pd_lower <= header.pd_upper
+            && header.pd_upper <= header.pd_special
+            && (header.pd_special as u32).is_multiple_of(MAXIMUM_ALIGNOF)
+            && header.pd_special as usize + size_of::<GISTPageOpaqueData>() <= block_size;
+        if is_valid {
+            Ok(image)
+        } else {
+            Err("page image has an invalid header".to_string())
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.words.as_ptr() as *const u8,
+                self.words.len() * size_of::<u64>(),
+            )
+        }
+    }
+
+    fn header(&self) -> &PageHeaderData {
+        unsafe { &*(self.words.as_ptr() as *const PageHeaderData) }
+    }
+
+    fn opaque(&self) -> &GISTPageOpaqueData {
+        let special = self.header().pd_special as usize;
+        unsafe { &*(self.bytes().as_ptr().add(special) as *const GISTPageOpaqueData) }
+    }
+
+    /// Returns header fields of the page and its GiST special space as text, in a fixed order.
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let header = self.header();
+        let opaque = self.opaque();
+        vec![
+            ("lsn", lsn(&header.pd_lsn)),
+            ("checksum", header.pd_checksum.to_string()),
+            ("flags", header.pd_flags.to_string()),
+            ("lower", header.pd_lower.to_string()),
+            ("upper", header.pd_upper.to_string()),
+            ("special", header.pd_special.to_string()),
+            ("pagesize_version", header.pd_pagesize_version.to_string()),
+            ("prune_xid", header.pd_prune_xid.to_string()),
+            ("nsn", lsn(&opaque.nsn)),
+            ("rightlink", opaque.rightlink.to_string()),
+            ("gist_flags", opaque.flags.to_string()),
+        ]
+    }
+
+    fn items(&self) -> Vec<ImageItem<'_>> {
+        let linp = offset_of!(PageHeaderData, pd_linp);
+        let count =
+            (self.header().pd_lower as usize).saturating_sub(linp) / size_of::<ItemIdData>();
+        let bytes = self.bytes();
+        OffsetRange::new(count as OffsetNumber, bytes.len())
+            .map(|offset| {
+                let i = (offset - FirstOffsetNumber) as usize;
+                let iid = unsafe {
+                    *(bytes.as_ptr().add(linp + i * size_of::<ItemIdData>()) as *const ItemIdData)
+                };
+                let (start, end) = (
+                    iid.lp_off() as usize,
+                    (iid.lp_off() + iid.lp_len()) as usize,
+                );
+                ImageItem {
+                    offset,
+                    state: item_state(&iid),
+                    data: if iid.lp_len() > 0 && end <= bytes.len() {
+                        &bytes[start..end]
+                    } else {
+                        &[]
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+fn lsn(ptr: &PageXLogRecPtr) -> String {
+    format!("{:X}/{:X}", ptr.xlogid, ptr.xrecoff)
+}
+
+/// Compares two images of the same page. Items are matched by their contents rather than
+/// offsets, since offsets of all following items shift whenever a tuple is added or removed.
+fn diff(before: &PageImage, after: &PageImage) -> Vec<PageDifference> {
+    let mut diffs = Vec::new();
+    for ((field, old), (_, new)) in before.fields().into_iter().zip(after.fields()) {
+        if old != new {
+            diffs.push(PageDifference {
+                kind: "header",
+                field: field.to_string(),
+                before: Some(old),
+                after: Some(new),
+            });
+        }
+    }
+
+    let mut remaining: HashMap<&[u8], Vec<ImageItem>> = HashMap::new();
+    for item in before.items() {
+        remaining.entry(item.data).or_default().push(item);
+    }
+    for item in after.items() {
+        let matched = remaining
+            .get_mut(item.data)
+            .filter(|items| !items.is_empty())
+            .map(|items| items.remove(0));
+        match matched {
+            Some(old) if old.state != item.state => diffs.push(PageDifference {
+                kind: "item_changed",
+                field: format!("offset {}", item.offset),
+                before: Some(old.describe()),
+                after: Some(item.describe()),
+            }),
+            Some(_) => {}
+            None => diffs.push(PageDifference {
+                kind: "item_added",
+                field: format!("offset {}", item.offset),
+                before: None,
+                after: Some(item.describe()),
+            }),
+        }
+    }
+    let mut removed: Vec<_> = remaining.into_values().flatten().collect();
+    removed.sort_by_key(|item| item.offset);
+    for item in removed {
+        diffs.push(PageDifference {
+            kind: "item_removed",
+            field: format!("offset {}", item.offset),
+            before: Some(item.describe()),
+            after: None,
+        });
+    }
+    diffs
+}
+
+impl IndexInspector {
+    /// Compares a previously saved raw image of a page (ie. obtained with pageinspect's
+    /// `get_raw_page`) with its current contents and returns all structural differences.
+    pub fn page_diff(&self, blkno: i64, before: &[u8]) -> Result<Vec<PageDifference>> {
+        let blk = self.check_block(blkno)?;
+        let before = PageImage::new(before).map_err(Error::InvalidArgument)?;
+        let page = self.cache.get(blk);
+        let after = PageImage::new(page.as_bytes()).map_err(|message| Error::CorruptPage {
+            index: self.name(),
+            blkno: blk,
+            message,
+        })?;
+        Ok(diff(&before, &after))
+    }
+}
@@ -0,0 +1,148 @@
+use crate::attribution::Penalty;
+use crate::gist::IndexInspector;
+use crate::key::KeyDecoder;
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys::{BlockNumber, Datum, OffsetNumber, Relation};
+use serde_json::json;
+
+/// Keys of two siblings are considered near-identical when extending any of them to cover the
+/// other costs less than this fraction of the average penalty between adjacent siblings.
+const NEAR_DUPLICATE_RATIO: f64 = 0.01;
+
+/// Groups of sibling pages with identical or near-identical keys found on a single level.
+#[derive(Debug, Default, Clone)]
+pub struct DuplicateLevel {
+    /// Block numbers of pages of each group, in the order of their downlinks.
+    pub groups: Vec<Vec<BlockNumber>>,
+}
+
+impl DuplicateLevel {
+    /// Total number of pages belonging to any of the groups.
+    pub fn pages(&self) -> usize {
+        self.groups.iter().map(|g| g.len()).sum()
+    }
+
+    pub fn to_json(&self, level: usize) -> serde_json::Value {
+        json!({
+            "level": level,
+            "groups": self.groups.len(),
+            "pages": self.pages(),
+            "blocks": self.groups,
+        })
+    }
+}
+
+/// Downlink of the currently visited internal page.
+struct Downlink {
+    /// Key of the first attribute, `None` if NULL.
+    key: Option<(Datum, OffsetNumber)>,
+    child: BlockNumber,
+}
+
+/// Looks for sibling pages - children of the same parent - whose keys are identical or
+/// near-identical. Searches for any key covered by one of them have to descend into all of
+/// them, which is common after repeated splits of skewed data. Only the first key attribute
+/// is compared, using the opclass penalty function.
+pub(crate) struct DuplicateCollector {
+    decoder: KeyDecoder,
+    penalty: Penalty,
+    /// Downlinks of internal pages on the path from the root to the currently visited page.
+    /// Datums point into the pages, which stay in memory until they are left.
+    downlinks: Vec<Vec<Downlink>>,
+    /// Groups found on each level, indexed by the level of sibling pages. The root level has
+    /// no siblings, so it's always empty.
+    pub(crate) levels: Vec<DuplicateLevel>,
+}
+
+impl DuplicateCollector {
+    pub(crate) fn new(relation: Relation) -> Self {
+        DuplicateCollector {
+            decoder: KeyDecoder::new(relation),
+            penalty: Penalty::new(relation, 0),
+            downlinks: Vec::new(),
+            levels: Vec::new(),
+        }
+    }
+
+    fn is_duplicate(&self, page: &PageVisit, a: &Downlink, b: &Downlink, tolerance: f64) -> bool {
+        self.penalty.compute(page, a.key, b.key) <= tolerance
+            && self.penalty.compute(page, b.key, a.key) <= tolerance
+    }
+}
+
+impl Visitor for DuplicateCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if !page.is_leaf() {
+            self.downlinks.push(Vec::new());
+            if self.levels.len() < page.level + 2 {
+                self.levels
+                    .resize(page.level + 2, DuplicateLevel::default());
+            }
+        }
+        true
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        if page.is_leaf() || tuple.is_invalid() {
+            return;
+        }
+        let (values, is_null) = self.decoder.deform(tuple);
+        if let Some(downlinks) = self.downlinks.last_mut() {
+            downlinks.push(Downlink {
+                key: Some((values[0], offset)).filter(|_| !is_null[0]),
+                child: tuple.block_num(),
+            });
+        }
+    }
+
+    fn on_page_end(&mut self, page: &PageVisit) {
+        if page.is_leaf() {
+            return;
+        }
+        let downlinks = self.downlinks.pop().unwrap_or_default();
+        if downlinks.len() < 2 {
+            return;
+        }
+        // penalties of adjacent siblings tell how far apart keys of the page usually are
+        let penalties: Vec<f64> = downlinks
+            .windows(2)
+            .map(|w| self.penalty.compute(page, w[0].key, w[1].key))
+            .filter(|p| p.is_finite())
+            .collect();
+        let average = if penalties.is_empty() {
+            0.0
+        } else {
+            penalties.iter().sum::<f64>() / penalties.len() as f64
+        };
+        let tolerance = average * NEAR_DUPLICATE_RATIO;
+
+        // every group is represented by its first member
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for i in 0..downlinks.len() {
+            let group = groups
+                .iter_mut()
+                .find(|g| self.is_duplicate(page, &downlinks[g[0]], &downlinks[i], tolerance));
+            match group {
+                Some(group) => group.push(i),
+                None => groups.push(vec![i]),
+            }
+        }
+        let level = &mut self.levels[page.level + 1];
+        for group in groups.into_iter().filter(|g| g.len() > 1) {
+            level
+                .groups
+                .push(group.into_iter().map(|i| downlinks[i].child).collect());
+        }
+    }
+}
+
+impl IndexInspector {
+    /// Returns groups of sibling pages with identical or near-identical keys, per level.
+    #[cfg(any(test, feature = "pg_test"))]
+    pub fn sibling_duplicates(&self) -> Vec<DuplicateLevel> {
+        let mut collector = DuplicateCollector::new(self.relation);
+        self.walk(&mut collector);
+        collector.levels
+    }
+}
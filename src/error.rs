@@ -0,0 +1,162 @@
+use pgx::pg_sys::{BlockNumber, Oid};
+use pgx::{ereport, PgLogLevel, PgSqlErrorCode};
+use std::fmt::{Display, Formatter};
+
+/// Classes of failures reported by gevel. Each class is raised with its own SQLSTATE, so that
+/// monitoring systems can tell corrupted indexes apart from operational problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Inspected index structure is corrupted (`XX002 index_corrupted`).
+    Corruption,
+    /// Relation is not an index of a type or version supported by gevel
+    /// (`0A000 feature_not_supported`).
+    Unsupported,
+    /// Inspection hit one of the resource limits (`54000 program_limit_exceeded`).
+    ResourceLimit,
+    /// Inspection couldn't proceed due to concurrent activity (`55P03 lock_not_available`).
+    Conflict,
+    /// Function has been called with an invalid argument (`22023 invalid_parameter_value`).
+    InvalidArgument,
+    /// Current user lacks privileges needed for the inspection (`42501 insufficient_privilege`).
+    PermissionDenied,
+}
+
+impl ErrorClass {
+    pub fn sql_state(&self) -> PgSqlErrorCode {
+        match self {
+            ErrorClass::Corruption => PgSqlErrorCode::ERRCODE_INDEX_CORRUPTED,
+            ErrorClass::Unsupported => PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            ErrorClass::ResourceLimit => PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED,
+            ErrorClass::Conflict => PgSqlErrorCode::ERRCODE_LOCK_NOT_AVAILABLE,
+            ErrorClass::InvalidArgument => PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            ErrorClass::PermissionDenied => PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+        }
+    }
+}
+
+/// Raises a PostgreSQL ERROR of a given class. Control never returns to the caller.
+pub fn raise(class: ErrorClass, message: &str) -> ! {
+    ereport(
+        PgLogLevel::ERROR,
+        class.sql_state(),
+        message,
+        file!(),
+        line!(),
+        column!(),
+    );
+    unreachable!("ereport(ERROR) returned")
+}
+
+/// Failures of the inspection library, returned by fallible APIs of [crate::gist] and turned
+/// into PostgreSQL errors with [OrRaise::or_raise] by SQL functions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// There's no relation with a given oid.
+    InvalidRelation { oid: Oid },
+    /// Relation is not a GiST index. `what` tells what it is instead, ie. `a table`.
+    NotGistIndex { name: String, what: String },
+    /// Page of an index failed validation.
+    CorruptPage {
+        index: String,
+        blkno: BlockNumber,
+        message: String,
+    },
+    /// Page image passed by a caller (ie. obtained with pageinspect) failed validation.
+    CorruptPageImage { message: String },
+    /// A downlink or a rightlink, described by `link`, points past the end of the index.
+    OutOfRangeDownlink {
+        link: String,
+        blkno: BlockNumber,
+        num_blocks: BlockNumber,
+    },
+    /// Block number passed by a caller is not within the main fork of the index.
+    BlockOutOfRange { blkno: i64 },
+    /// Relation couldn't be locked within `gevel.lock_timeout_ms`.
+    LockTimeout { oid: Oid, timeout_ms: i32 },
+    /// Function has been called with an invalid argument.
+    InvalidArgument(String),
+    /// Current user can't read the table of an index, so it can't see its keys either.
+    PermissionDenied { table: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Error::InvalidRelation { .. }
+            | Error::BlockOutOfRange { .. }
+            | Error::InvalidArgument(_) => ErrorClass::InvalidArgument,
+            Error::NotGistIndex { .. } => ErrorClass::Unsupported,
+            Error::CorruptPage { .. }
+            | Error::CorruptPageImage { .. }
+            | Error::OutOfRangeDownlink { .. } => ErrorClass::Corruption,
+            Error::LockTimeout { .. } => ErrorClass::Conflict,
+            Error::PermissionDenied { .. } => ErrorClass::PermissionDenied,
+        }
+    }
+
+    /// Raises the error as a PostgreSQL ERROR of its class.
+    pub fn raise(&self) -> ! {
+        raise(self.class(), &self.to_string())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidRelation { oid } => write!(f, "invalid index oid: {}", oid),
+            Error::NotGistIndex { name, what } => {
+                write!(f, "relation \"{}\" is not a GiST index, but {}", name, what)
+            }
+            Error::CorruptPage {
+                index,
+                blkno,
+                message,
+            } => write!(
+                f,
+                "block {} of index \"{}\" is corrupted: {}",
+                blkno, index, message
+            ),
+            Error::CorruptPageImage { message } => {
+                write!(f, "page image is corrupted: {}", message)
+            }
+            Error::OutOfRangeDownlink {
+                link,
+                blkno,
+                num_blocks,
+            } => write!(
+                f,
+                "{} points to block {} past the end of the index ({} blocks)",
+                link, blkno, num_blocks
+            ),
+            Error::BlockOutOfRange { blkno } => {
+                write!(f, "block number {} is out of range", blkno)
+            }
+            Error::LockTimeout { oid, timeout_ms } => {
+                write!(f, "could not lock relation {} within {}ms", oid, timeout_ms)
+            }
+            Error::InvalidArgument(message) => f.write_str(message),
+            Error::PermissionDenied { table } => write!(
+                f,
+                "permission denied for table {}, inspecting its indexes requires SELECT privilege",
+                table
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Translates errors of the inspection library into PostgreSQL errors, at the boundary of SQL
+/// functions.
+pub trait OrRaise<T> {
+    /// Returns the value, or raises the error as a PostgreSQL ERROR of its class.
+    fn or_raise(self) -> T;
+}
+
+impl<T> OrRaise<T> for Result<T> {
+    fn or_raise(self) -> T {
+        self.unwrap_or_else(|e| e.raise())
+    }
+}
@@ -0,0 +1,322 @@
+use crate::gist::{IndexInspector, Summary};
+use crate::guc;
+use crate::SUMMARY_SAMPLE_SIZE;
+use pgx::pg_sys::{
+    CmdType_CMD_UTILITY, ExplainFormat_EXPLAIN_FORMAT_TEXT, ExplainOneQuery_hook_type,
+    ExplainOneUtility, ExplainPropertyText, ExplainState, IntoClause, NodeTag, Oid, ParamListInfo,
+    Plan, PlannedStmt, Query, QueryEnvironment,
+};
+use pgx::*;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::time::Instant;
+
+static mut PREV_EXPLAIN_ONE_QUERY: ExplainOneQuery_hook_type = None;
+
+/// Installs the EXPLAIN hook. It only annotates plans when `gevel.explain_annotations` is on.
+pub fn init() {
+    unsafe {
+        PREV_EXPLAIN_ONE_QUERY = pg_sys::ExplainOneQuery_hook;
+        pg_sys::ExplainOneQuery_hook = Some(explain_one_query);
+    }
+}
+
+/// Collects oids of indexes scanned by a plan and all its subplans, without duplicates.
+unsafe fn scanned_indexes(plan: *mut Plan, indexes: &mut Vec<Oid>) {
+    if plan.is_null() {
+        return;
+    }
+    let index = match (*plan).type_ as NodeTag {
+        pg_sys::NodeTag_T_IndexScan => Some((*(plan as *mut pg_sys::IndexScan)).indexid),
+        pg_sys::NodeTag_T_IndexOnlyScan => Some((*(plan as *mut pg_sys::IndexOnlyScan)).indexid),
+        pg_sys::NodeTag_T_BitmapIndexScan => {
+            Some((*(plan as *mut pg_sys::BitmapIndexScan)).indexid)
+        }
+        _ => None,
+    };
+    if let Some(index) = index.filter(|oid| !indexes.contains(oid)) {
+        indexes.push(index);
+    }
+    let children = match (*plan).type_ as NodeTag {
+        pg_sys::NodeTag_T_Append => (*(plan as *mut pg_sys::Append)).appendplans,
+        pg_sys::NodeTag_T_MergeAppend => (*(plan as *mut pg_sys::MergeAppend)).mergeplans,
+        pg_sys::NodeTag_T_BitmapAnd => (*(plan as *mut pg_sys::BitmapAnd)).bitmapplans,
+        pg_sys::NodeTag_T_BitmapOr => (*(plan as *mut pg_sys::BitmapOr)).bitmapplans,
+        pg_sys::NodeTag_T_ModifyTable => (*(plan as *mut pg_sys::ModifyTable)).plans,
+        pg_sys::NodeTag_T_CustomScan => (*(plan as *mut pg_sys::CustomScan)).custom_plans,
+        _ => std::ptr::null_mut(),
+    };
+    for child in PgList::<Plan>::from_pg(children).iter_ptr() {
+        scanned_indexes(child, indexes);
+    }
+    if (*plan).type_ as NodeTag == pg_sys::NodeTag_T_SubqueryScan {
+        scanned_indexes((*(plan as *mut pg_sys::SubqueryScan)).subplan, indexes);
+    }
+    scanned_indexes((*plan).lefttree, indexes);
+    scanned_indexes((*plan).righttree, indexes);
+}
+
+/// Returns the name of an index if it's a GiST index, `None` otherwise.
+fn gist_index_name(oid: Oid) -> Option<String> {
+    Spi::get_one::<String>(&format!(
+        "SELECT c.oid::regclass::text FROM pg_class c JOIN pg_am am ON am.oid = c.relam \
+         WHERE c.oid = {} AND am.amname = 'gist'",
+        oid
+    ))
+}
+
+/// Appends a summary of shape of every GiST index scanned by the plan to EXPLAIN output. Text
+/// format gets one compact line per index, other formats get a `GiST Indexes` group. Indexes
+/// which can't be inspected (ie. they can't be locked within `gevel.lock_timeout_ms` or the
+/// user can't `SELECT` from their table) are left out with a notice, so EXPLAIN never fails.
+unsafe fn annotate(stmt: *mut PlannedStmt, es: *mut ExplainState) {
+    let mut indexes = Vec::new();
+    scanned_indexes((*stmt).planTree, &mut indexes);
+    for subplan in PgList::<Plan>::from_pg((*stmt).subplans).iter_ptr() {
+        scanned_indexes(subplan, &mut indexes);
+    }
+    let summaries: Vec<_> = indexes
+        .into_iter()
+        .filter_map(|oid| gist_index_name(oid).map(|name| (oid, name)))
+        .filter_map(|(oid, name)| match IndexInspector::open(oid) {
+            Ok(index) => Some((name, index.summary(SUMMARY_SAMPLE_SIZE))),
+            Err(e) => {
+                notice!("{}, not annotating index {}", e, name);
+                None
+            }
+        })
+        .collect();
+    if summaries.is_empty() {
+        return;
+    }
+
+    if (*es).format == ExplainFormat_EXPLAIN_FORMAT_TEXT {
+        for (name, s) in summaries {
+            property(
+                es,
+                "GiST Index",
+                format!(
+                    "{} (levels={} leaf pages={} bloat={:.1}%)",
+                    name, s.levels, s.leaf_pages, s.bloat_pct
+                ),
+            );
+        }
+        return;
+    }
+    add_group(es, summaries);
+}
+
+/// Adds a text property to EXPLAIN output.
+unsafe fn property(es: *mut ExplainState, label: &str, value: String) {
+    let (label, value) = (CString::new(label).unwrap(), CString::new(value).unwrap());
+    ExplainPropertyText(label.as_ptr(), value.as_ptr(), es)
+}
+
+/// Adds the `GiST Indexes` group of EXPLAIN output in structured formats.
+#[cfg(not(feature = "pg10"))]
+unsafe fn add_group(es: *mut ExplainState, summaries: Vec<(String, Summary)>) {
+    use pgx::pg_sys::{ExplainCloseGroup, ExplainOpenGroup};
+    let text = |s: &str| CString::new(s).unwrap();
+    let (group, indexes, index) = (text("GiST Indexes"), text("Indexes"), text("Index"));
+    ExplainOpenGroup(group.as_ptr(), std::ptr::null(), true, es);
+    ExplainOpenGroup(indexes.as_ptr(), indexes.as_ptr(), false, es);
+    for (name, s) in summaries {
+        ExplainOpenGroup(index.as_ptr(), std::ptr::null(), true, es);
+        property(es, "Index Name", name);
+        property(es, "Levels", s.levels.to_string());
+        property(es, "Leaf Pages", s.leaf_pages.to_string());
+        property(es, "Bloat Percent", format!("{:.1}", s.bloat_pct));
+        ExplainCloseGroup(index.as_ptr(), std::ptr::null(), true, es);
+    }
+    ExplainCloseGroup(indexes.as_ptr(), indexes.as_ptr(), false, es);
+    ExplainCloseGroup(group.as_ptr(), std::ptr::null(), true, es);
+}
+
+// groups can't be opened before Postgres 11, where ExplainOpenGroup is static, so structured
+// formats are not annotated there
+#[cfg(feature = "pg10")]
+unsafe fn add_group(_es: *mut ExplainState, _summaries: Vec<(String, Summary)>) {}
+
+/// Returns time elapsed since `start` in the representation used by EXPLAIN.
+fn instr_time_since(start: Instant) -> pg_sys::instr_time {
+    let elapsed = start.elapsed();
+    let mut duration: pg_sys::instr_time = unsafe { std::mem::zeroed() };
+    duration.tv_sec = elapsed.as_secs() as _;
+    duration.tv_nsec = elapsed.subsec_nanos() as _;
+    duration
+}
+
+/// Plans a query and explains the plan, the same way `ExplainOneQuery` does by default.
+#[cfg(feature = "pg13")]
+unsafe fn plan_and_explain(
+    query: *mut Query,
+    cursor_options: c_int,
+    into: *mut IntoClause,
+    es: *mut ExplainState,
+    query_string: *const c_char,
+    params: ParamListInfo,
+    query_env: *mut QueryEnvironment,
+) -> *mut PlannedStmt {
+    let buffers_start = pg_sys::pgBufferUsage;
+    let start = Instant::now();
+    let stmt = pg_sys::pg_plan_query(query, query_string, cursor_options, params);
+    let duration = instr_time_since(start);
+    let mut buffers: pg_sys::BufferUsage = std::mem::zeroed();
+    pg_sys::BufferUsageAccumDiff(
+        &mut buffers,
+        std::ptr::addr_of!(pg_sys::pgBufferUsage),
+        &buffers_start,
+    );
+    let buffers = if (*es).buffers {
+        &mut buffers as *mut pg_sys::BufferUsage
+    } else {
+        std::ptr::null_mut()
+    };
+    pg_sys::ExplainOnePlan(
+        stmt,
+        into,
+        es,
+        query_string,
+        params,
+        query_env,
+        &duration,
+        buffers,
+    );
+    stmt
+}
+
+#[cfg(not(feature = "pg13"))]
+unsafe fn plan_and_explain(
+    query: *mut Query,
+    cursor_options: c_int,
+    into: *mut IntoClause,
+    es: *mut ExplainState,
+    query_string: *const c_char,
+    params: ParamListInfo,
+    query_env: *mut QueryEnvironment,
+) -> *mut PlannedStmt {
+    let start = Instant::now();
+    let stmt = pg_sys::pg_plan_query(query, cursor_options, params);
+    let duration = instr_time_since(start);
+    pg_sys::ExplainOnePlan(stmt, into, es, query_string, params, query_env, &duration);
+    stmt
+}
+
+/// Replacement of `ExplainOneQuery`. Hooks installed before gevel take precedence: they plan
+/// and explain the query, and the plan they printed is annotated afterwards.
+#[cfg(not(feature = "pg10"))]
+#[pg_guard]
+unsafe extern "C" fn explain_one_query(
+    query: *mut Query,
+    cursor_options: c_int,
+    into: *mut IntoClause,
+    es: *mut ExplainState,
+    query_string: *const c_char,
+    params: ParamListInfo,
+    query_env: *mut QueryEnvironment,
+) {
+    match PREV_EXPLAIN_ONE_QUERY {
+        Some(prev) => {
+            let printed = (*es).pstmt;
+            prev(
+                query,
+                cursor_options,
+                into,
+                es,
+                query_string,
+                params,
+                query_env,
+            );
+            annotate_chained(query, es, printed);
+        }
+        None => explain_and_annotate(
+            query,
+            cursor_options,
+            into,
+            es,
+            query_string,
+            params,
+            query_env,
+        ),
+    }
+}
+
+/// Same as above, but the hook doesn't get a query environment before Postgres 11.
+#[cfg(feature = "pg10")]
+#[pg_guard]
+unsafe extern "C" fn explain_one_query(
+    query: *mut Query,
+    cursor_options: c_int,
+    into: *mut IntoClause,
+    es: *mut ExplainState,
+    query_string: *const c_char,
+    params: ParamListInfo,
+) {
+    match PREV_EXPLAIN_ONE_QUERY {
+        Some(prev) => {
+            let printed = (*es).pstmt;
+            prev(query, cursor_options, into, es, query_string, params);
+            annotate_chained(query, es, printed);
+        }
+        None => explain_and_annotate(
+            query,
+            cursor_options,
+            into,
+            es,
+            query_string,
+            params,
+            std::ptr::null_mut(),
+        ),
+    }
+}
+
+/// Annotates the plan of a query explained by a hook installed before gevel. ExplainPrintPlan
+/// leaves the printed plan in the state, so `printed`, the plan which was there before the hook
+/// was called, is not the one of the query.
+unsafe fn annotate_chained(query: *mut Query, es: *mut ExplainState, printed: *mut PlannedStmt) {
+    // utility statements explaining queries (ie. CREATE TABLE AS) come back to this hook with
+    // the query they contain, which is annotated then
+    let stmt = (*es).pstmt;
+    if guc::EXPLAIN_ANNOTATIONS.get()
+        && (*query).commandType != CmdType_CMD_UTILITY
+        && !stmt.is_null()
+        && stmt != printed
+    {
+        annotate(stmt, es);
+    }
+}
+
+/// Explains a query the same way `ExplainOneQuery` does by default, and annotates its plan.
+unsafe fn explain_and_annotate(
+    query: *mut Query,
+    cursor_options: c_int,
+    into: *mut IntoClause,
+    es: *mut ExplainState,
+    query_string: *const c_char,
+    params: ParamListInfo,
+    query_env: *mut QueryEnvironment,
+) {
+    if (*query).commandType == CmdType_CMD_UTILITY {
+        ExplainOneUtility(
+            (*query).utilityStmt,
+            into,
+            es,
+            query_string,
+            params,
+            query_env,
+        );
+    } else {
+        let stmt = plan_and_explain(
+            query,
+            cursor_options,
+            into,
+            es,
+            query_string,
+            params,
+            query_env,
+        );
+        if guc::EXPLAIN_ANNOTATIONS.get() {
+            annotate(stmt, es);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! C-compatible entry points for other extensions (ie. monitoring agents loaded as shared
+//! libraries), which can look them up with `load_external_function("$libdir/gevel", ...)` and
+//! read statistics of an index without going through SQL. The layout of [GevelIndexStats] is
+//! versioned: fields are only ever appended to it, and every addition bumps
+//! [GEVEL_ABI_VERSION], so callers compiled against an older version keep working. See
+//! `include/gevel.h` for the C declarations.
+//!
+//! Functions must be called inside of a transaction, like any other code reading relations.
+//! Problems with the arguments are reported with return codes, while failures found during a
+//! walk (ie. corrupted pages) are raised with `ereport(ERROR)`, as usual in Postgres.
+
+// #[pg_guard] moves docs of a function, `# Safety` sections included, to the body it wraps
+#![allow(clippy::missing_safety_doc)]
+
+use crate::error::Error;
+use crate::gist::IndexInspector;
+use pgx::pg_guard;
+use pgx::pg_sys::{self, Oid};
+use std::mem::size_of;
+use std::os::raw::c_int;
+use std::ptr::copy_nonoverlapping;
+
+/// Version of the layout of [GevelIndexStats] filled by this build.
+pub const GEVEL_ABI_VERSION: u32 = 1;
+
+/// Size of [GevelIndexStats] as of its first version. Buffers smaller than that are rejected.
+pub const GEVEL_INDEX_STATS_V1_SIZE: usize = 112;
+
+/// Statistics were written into the buffer.
+pub const GEVEL_OK: c_int = 0;
+/// The buffer is NULL or smaller than [GEVEL_INDEX_STATS_V1_SIZE].
+pub const GEVEL_ERR_BUFFER: c_int = -1;
+/// There's no relation with a given oid.
+pub const GEVEL_ERR_INVALID_RELATION: c_int = -2;
+/// The relation is not a GiST index.
+pub const GEVEL_ERR_NOT_GIST_INDEX: c_int = -3;
+/// The index couldn't be locked within `gevel.lock_timeout_ms`.
+pub const GEVEL_ERR_LOCK_TIMEOUT: c_int = -4;
+
+/// Statistics of an index, the same as returned by `gist_stat`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GevelIndexStats {
+    /// Version of the layout the struct has been filled with, see [GEVEL_ABI_VERSION].
+    pub abi_version: u32,
+    /// Number of bytes written into the buffer.
+    pub size: u32,
+    /// False if the index has been marked as invalid, ie. after failed concurrent build.
+    pub is_valid: bool,
+    /// Number of levels of the tree, including the root.
+    pub levels: u32,
+    pub pages: u64,
+    pub leaf_pages: u64,
+    pub new_pages: u64,
+    pub tuples: u64,
+    pub leaf_tuples: u64,
+    pub invalid_tuples: u64,
+    pub dead_tuples: u64,
+    pub null_tuples: u64,
+    pub tuple_bytes: u64,
+    pub leaf_tuple_bytes: u64,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+}
+
+/// Returns the version of the layout of [GevelIndexStats] filled by this build.
+#[no_mangle]
+pub extern "C" fn gevel_abi_version() -> u32 {
+    GEVEL_ABI_VERSION
+}
+
+/// Walks the whole index with a given oid and writes its statistics into a caller-provided
+/// buffer of `out_size` bytes. Callers compiled against an older version pass a smaller
+/// buffer, which gets only the fields it knows about. Returns [GEVEL_OK] or one of the
+/// `GEVEL_ERR_*` codes, in which case the buffer is left untouched.
+///
+/// # Safety
+///
+/// `out` must be NULL or point to at least `out_size` writable bytes.
+#[pg_guard]
+pub unsafe extern "C" fn gevel_index_stats(
+    rel_oid: Oid,
+    out: *mut GevelIndexStats,
+    out_size: usize,
+) -> c_int {
+    if out.is_null() || out_size < GEVEL_INDEX_STATS_V1_SIZE {
+        return GEVEL_ERR_BUFFER;
+    }
+    // callers are C code of other extensions, which decide who gets to see the numbers
+    let index = match IndexInspector::open_aggregates(rel_oid) {
+        Ok(index) => index,
+        Err(Error::InvalidRelation { .. }) => return GEVEL_ERR_INVALID_RELATION,
+        Err(Error::NotGistIndex { .. }) => return GEVEL_ERR_NOT_GIST_INDEX,
+        Err(Error::LockTimeout { .. }) => return GEVEL_ERR_LOCK_TIMEOUT,
+        Err(e) => e.raise(),
+    };
+    let stats = index.stats(None);
+    let size = out_size.min(size_of::<GevelIndexStats>());
+    let result = GevelIndexStats {
+        abi_version: GEVEL_ABI_VERSION,
+        size: size as u32,
+        is_valid: stats.is_valid,
+        levels: (stats.level + 1) as u32,
+        pages: stats.num_pages,
+        leaf_pages: stats.num_leaf_pages,
+        new_pages: stats.num_new_pages,
+        tuples: stats.num_tuple,
+        leaf_tuples: stats.num_leaf_tuple,
+        invalid_tuples: stats.num_invalid_tuple,
+        dead_tuples: stats.num_dead_tuple,
+        null_tuples: stats.num_null_tuple,
+        tuple_bytes: stats.tuple_size,
+        leaf_tuple_bytes: stats.leaf_tuple_size,
+        total_bytes: stats.total_size,
+        free_bytes: stats.free_space,
+    };
+    copy_nonoverlapping(
+        &result as *const GevelIndexStats as *const u8,
+        out as *mut u8,
+        size,
+    );
+    GEVEL_OK
+}
@@ -0,0 +1,314 @@
+use crate::error::{raise, ErrorClass};
+use crate::walker::check_for_interrupts;
+use pgx::bgworkers::{BackgroundWorker, SignalWakeFlags};
+use pgx::pg_sys::{
+    dsm_attach, dsm_create, dsm_detach, dsm_segment_address, dsm_segment_handle, superuser,
+    BackgroundWorkerHandle, BgWorkerStartTime_BgWorkerStart_RecoveryFinished,
+    BgwHandleStatus_BGWH_POSTMASTER_DIED, BgwHandleStatus_BGWH_STOPPED, Datum, InvalidOid,
+    MyProcPid, Oid, RegisterDynamicBackgroundWorker, WaitForBackgroundWorkerShutdown,
+    BGWORKER_BACKEND_DATABASE_CONNECTION, BGWORKER_SHMEM_ACCESS, BGW_NEVER_RESTART,
+};
+use pgx::*;
+use serde_json::json;
+use std::os::raw::c_char;
+use std::ptr::null_mut;
+
+/// Table of the database `gevel.scan_all_databases` is called in, where summaries of indexes
+/// of all databases are stored. Postgres has no shared tables other than its own catalogs,
+/// so dashboards have to query the database the scans are run from.
+const FLEET_TABLE: &str = "gevel.fleet_stats";
+
+/// Channel alerts about indexes crossing thresholds of `gevel.alert_max_*` settings are sent on.
+const ALERT_CHANNEL: &str = "gevel_alerts";
+
+/// Size of the shared memory segment a worker passes summaries of a database back in. The
+/// JSON written there is prefixed by its length as 8 native-endian bytes.
+const RESULT_BYTES: usize = 8 * 1024 * 1024;
+const LEN_BYTES: usize = std::mem::size_of::<u64>();
+
+/// Summary of a single GiST index of any database of the cluster, as returned by
+/// `gevel.scan_all_databases`.
+#[derive(Debug)]
+pub struct FleetEntry {
+    pub database: String,
+    /// Schema-qualified name of the index.
+    pub index: String,
+    /// Schema-qualified name of the indexed table.
+    pub table: String,
+    pub verdict: String,
+    pub health_score: f64,
+    pub bloat_pct: f64,
+    pub pages: u64,
+    pub invalid_tuples: u64,
+    pub maintenance_score: f64,
+}
+
+impl FleetEntry {
+    fn from_json(database: &str, value: &serde_json::Value) -> Option<Self> {
+        Some(FleetEntry {
+            database: database.to_string(),
+            index: value["index"].as_str()?.to_string(),
+            table: value["table"].as_str()?.to_string(),
+            verdict: value["verdict"].as_str()?.to_string(),
+            health_score: value["health_score"].as_f64()?,
+            bloat_pct: value["bloat_pct"].as_f64()?,
+            pages: value["pages"].as_u64()?,
+            invalid_tuples: value["invalid_tuples"].as_u64()?,
+            maintenance_score: value["maintenance_score"].as_f64()?,
+        })
+    }
+}
+
+/// Returns JSON payloads of alerts about indexes whose bloat or number of invalid tuples exceeds
+/// `gevel.alert_max_bloat_pct` or `gevel.alert_max_invalid_tuples`. Every payload names the
+/// `exceeded` thresholds together with their values.
+pub fn alerts(entries: &[FleetEntry]) -> Vec<serde_json::Value> {
+    let max_bloat_pct = crate::guc::ALERT_MAX_BLOAT_PCT.get();
+    let max_invalid_tuples = crate::guc::ALERT_MAX_INVALID_TUPLES.get();
+    entries
+        .iter()
+        .filter_map(|e| {
+            let mut exceeded = serde_json::Map::new();
+            if max_bloat_pct >= 0.0 && e.bloat_pct > max_bloat_pct {
+                exceeded.insert("max_bloat_pct".into(), json!(max_bloat_pct));
+            }
+            if max_invalid_tuples >= 0 && e.invalid_tuples > max_invalid_tuples as u64 {
+                exceeded.insert("max_invalid_tuples".into(), json!(max_invalid_tuples));
+            }
+            if exceeded.is_empty() {
+                return None;
+            }
+            Some(json!({
+                "database": e.database,
+                "index": e.index,
+                "table": e.table,
+                "verdict": e.verdict,
+                "bloat_pct": e.bloat_pct,
+                "invalid_tuples": e.invalid_tuples,
+                "exceeded": exceeded,
+            }))
+        })
+        .collect()
+}
+
+/// Sends every alert as a separate notification, delivered when the transaction commits.
+fn notify(alerts: &[serde_json::Value]) {
+    for alert in alerts {
+        let payload = alert.to_string().replace('\'', "''");
+        Spi::run(&format!(
+            "SELECT pg_notify('{}', '{}')",
+            ALERT_CHANNEL, payload
+        ));
+    }
+}
+
+/// Summarizes GiST indexes of every database of the cluster which accepts connections, ranked
+/// the same way as by [crate::workload::prioritized_maintenance] from `sample_size` leaf pages
+/// per index. A backend can't switch databases, so every database is scanned by a background
+/// worker connected to it, one at a time. Summaries of each scanned database replace the ones
+/// stored before in [FLEET_TABLE], and indexes crossing alert thresholds are reported on
+/// [ALERT_CHANNEL], see [alerts]. Databases which can't be scanned are skipped with a
+/// warning. Only superusers may scan databases they may not even be allowed to connect to.
+pub fn scan_all_databases(sample_size: usize) -> Vec<FleetEntry> {
+    if !unsafe { superuser() } {
+        raise(
+            ErrorClass::PermissionDenied,
+            "must be superuser to scan all databases",
+        );
+    }
+    if sample_size == 0 {
+        raise(
+            ErrorClass::InvalidArgument,
+            "sample_size must be a positive number of leaf pages",
+        );
+    }
+    let databases = Spi::get_one::<JsonB>(
+        "SELECT jsonb_agg(jsonb_build_array(oid::bigint, datname) ORDER BY datname) \
+         FROM pg_database WHERE datallowconn AND NOT datistemplate",
+    )
+    .and_then(|databases| databases.0.as_array().cloned())
+    .unwrap_or_default();
+
+    let mut entries = Vec::new();
+    for database in databases.iter() {
+        check_for_interrupts!();
+        let oid = database[0].as_u64().unwrap_or(0) as Oid;
+        let name = database[1].as_str().unwrap_or_default();
+        match scan_database(oid, name, sample_size) {
+            Ok(scanned) => {
+                store(name, &scanned);
+                notify(&alerts(&scanned));
+                entries.extend(scanned);
+            }
+            Err(message) => warning!("{}, skipping database {}", message, name),
+        }
+    }
+    entries
+}
+
+/// Runs a background worker summarizing indexes of a given database and waits for it to exit.
+fn scan_database(oid: Oid, name: &str, sample_size: usize) -> Result<Vec<FleetEntry>, String> {
+    unsafe {
+        let segment = dsm_create(RESULT_BYTES, 0);
+        let result = dsm_segment_address(segment) as *mut u8;
+        std::ptr::write_bytes(result, 0, LEN_BYTES);
+
+        let mut worker = pg_sys::BackgroundWorker::default();
+        copy_str(&mut worker.bgw_name, &format!("gevel scan of {}", name));
+        #[cfg(not(feature = "pg10"))]
+        copy_str(&mut worker.bgw_type, "gevel scan");
+        copy_str(&mut worker.bgw_library_name, "gevel");
+        copy_str(&mut worker.bgw_function_name, "gevel_scan_worker");
+        copy_str(&mut worker.bgw_extra, &format!("{} {}", oid, sample_size));
+        worker.bgw_flags = (BGWORKER_SHMEM_ACCESS | BGWORKER_BACKEND_DATABASE_CONNECTION) as i32;
+        worker.bgw_start_time = BgWorkerStartTime_BgWorkerStart_RecoveryFinished;
+        worker.bgw_restart_time = BGW_NEVER_RESTART;
+        worker.bgw_main_arg = dsm_segment_handle(segment) as Datum;
+        worker.bgw_notify_pid = MyProcPid;
+
+        let mut handle: *mut BackgroundWorkerHandle = null_mut();
+        if !RegisterDynamicBackgroundWorker(&mut worker, &mut handle) {
+            dsm_detach(segment);
+            return Err("no background worker slot is free (see max_worker_processes)".into());
+        }
+        let status = WaitForBackgroundWorkerShutdown(handle);
+        if status == BgwHandleStatus_BGWH_POSTMASTER_DIED {
+            raise(
+                ErrorClass::Conflict,
+                "postmaster died while waiting for a background worker",
+            );
+        }
+        let mut len = [0u8; LEN_BYTES];
+        std::ptr::copy_nonoverlapping(result, len.as_mut_ptr(), len.len());
+        let len = u64::from_ne_bytes(len) as usize;
+        let payload =
+            std::slice::from_raw_parts(result.add(LEN_BYTES), len.min(RESULT_BYTES - LEN_BYTES));
+        let parsed = serde_json::from_slice::<serde_json::Value>(payload);
+        dsm_detach(segment);
+
+        match parsed {
+            _ if status != BgwHandleStatus_BGWH_STOPPED || len == 0 => {
+                Err("background worker failed, see the server log".into())
+            }
+            Ok(value) => match value["error"].as_str() {
+                Some(error) => Err(error.to_string()),
+                None => Ok(value["indexes"]
+                    .as_array()
+                    .map(|indexes| {
+                        indexes
+                            .iter()
+                            .filter_map(|index| FleetEntry::from_json(name, index))
+                            .collect()
+                    })
+                    .unwrap_or_default()),
+            },
+            Err(e) => Err(format!("invalid result of background worker: {}", e)),
+        }
+    }
+}
+
+/// Replaces summaries of a given database stored by previous scans.
+fn store(database: &str, entries: &[FleetEntry]) {
+    let quote = |s: &str| s.replace('\'', "''");
+    Spi::run(&format!(
+        "DELETE FROM {} WHERE database = '{}'",
+        FLEET_TABLE,
+        quote(database)
+    ));
+    if entries.is_empty() {
+        return;
+    }
+    let values: Vec<String> = entries
+        .iter()
+        .map(|e| {
+            format!(
+                "('{}', '{}', '{}', '{}', {}, {}, {}, {}, {})",
+                quote(&e.database),
+                quote(&e.index),
+                quote(&e.table),
+                e.verdict,
+                e.health_score,
+                e.bloat_pct,
+                e.pages,
+                e.invalid_tuples,
+                e.maintenance_score
+            )
+        })
+        .collect();
+    Spi::run(&format!(
+        "INSERT INTO {}(database, index_name, table_name, verdict, health_score, bloat_pct, pages, invalid_tuples, maintenance_score) VALUES {}",
+        FLEET_TABLE,
+        values.join(", ")
+    ));
+}
+
+/// Copies a string into a fixed-size C string field, truncating it if needed.
+fn copy_str(dest: &mut [c_char], value: &str) {
+    let max = dest.len() - 1;
+    for (dest, byte) in dest.iter_mut().zip(value.bytes().take(max)) {
+        *dest = byte as c_char;
+    }
+}
+
+/// Main function of background workers started by [scan_all_databases]. Connects to the
+/// database given in `bgw_extra` together with the sample size, summarizes its GiST indexes
+/// and writes them as JSON into the shared memory segment given by `arg`.
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn gevel_scan_worker(arg: Datum) {
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGTERM);
+    let mut extra = BackgroundWorker::get_extra().split(' ');
+    let database: Oid = extra
+        .next()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(InvalidOid);
+    let sample_size: usize = extra.next().and_then(|s| s.parse().ok()).unwrap_or(1);
+    connect(database);
+
+    BackgroundWorker::transaction(move || unsafe {
+        // attached within the transaction, so that the mapping is released if it fails
+        let segment = dsm_attach(arg as pg_sys::dsm_handle);
+        if segment.is_null() {
+            // the scan has been cancelled and its segment is gone
+            return;
+        }
+        let indexes: Vec<_> = crate::workload::prioritized_maintenance(sample_size)
+            .into_iter()
+            .map(|item| {
+                json!({
+                    "index": item.index,
+                    "table": item.table,
+                    "verdict": item.summary.verdict(),
+                    "health_score": item.summary.health_score,
+                    "bloat_pct": item.summary.bloat_pct,
+                    "pages": item.summary.pages,
+                    "invalid_tuples": item.summary.invalid_tuples,
+                    "maintenance_score": item.score,
+                })
+            })
+            .collect();
+        let mut payload = json!({ "indexes": indexes }).to_string();
+        if payload.len() > RESULT_BYTES - LEN_BYTES {
+            payload = json!({
+                "error": format!("summaries of {} indexes don't fit into {} bytes", indexes.len(), RESULT_BYTES)
+            })
+            .to_string();
+        }
+        let result = dsm_segment_address(segment) as *mut u8;
+        std::ptr::copy_nonoverlapping(payload.as_ptr(), result.add(LEN_BYTES), payload.len());
+        let len = (payload.len() as u64).to_ne_bytes();
+        std::ptr::copy_nonoverlapping(len.as_ptr(), result, len.len());
+        dsm_detach(segment);
+    });
+}
+
+// flags were added in Postgres 11
+#[cfg(feature = "pg10")]
+fn connect(database: Oid) {
+    unsafe { pg_sys::BackgroundWorkerInitializeConnectionByOid(database, InvalidOid) }
+}
+
+#[cfg(not(feature = "pg10"))]
+fn connect(database: Oid) {
+    unsafe { pg_sys::BackgroundWorkerInitializeConnectionByOid(database, InvalidOid, 0) }
+}
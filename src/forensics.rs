@@ -0,0 +1,185 @@
+use crate::gist::{IndexInspector, TupleState};
+use crate::walker::{PageVisit, Visitor};
+use crate::{IndexTuple, Page};
+use pgx::pg_sys::{BlockNumber, IndexTupleData, InvalidBlockNumber, OffsetNumber, BLCKSZ};
+use pgx::Spi;
+use serde_json::json;
+use std::fmt::Write;
+use std::mem::size_of;
+
+/// Max number of suspicious pages dumped into a forensics bundle.
+const MAX_DUMPED_PAGES: usize = 16;
+
+/// Page which looks suspicious, together with reasons why.
+struct Suspect {
+    block_num: BlockNumber,
+    reasons: Vec<String>,
+}
+
+/// Finds pages which show signs of corruption.
+struct SuspectCollector {
+    num_blocks: BlockNumber,
+    /// Level of the first visited leaf page. All other leaves should be on the same level.
+    leaf_level: Option<usize>,
+    suspects: Vec<Suspect>,
+    /// Reasons found for the currently visited page.
+    reasons: Vec<String>,
+}
+
+impl Visitor for SuspectCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if page.is_leaf() {
+            let leaf_level = *self.leaf_level.get_or_insert(page.level);
+            if leaf_level != page.level {
+                self.reasons.push(format!(
+                    "leaf page on level {}, while other leaves are on level {}",
+                    page.level, leaf_level
+                ));
+            }
+        }
+        let right_link = page.gist_page.right_link();
+        if right_link != InvalidBlockNumber && right_link >= self.num_blocks {
+            self.reasons.push(format!(
+                "rightlink {} beyond the end of the index",
+                right_link
+            ));
+        }
+        true
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, _tuple: &IndexTuple) {
+        if TupleState::of(page.page, offset) == TupleState::Invalid {
+            self.reasons
+                .push(format!("invalid tuple at offset {}", offset));
+        }
+    }
+
+    fn on_page_end(&mut self, page: &PageVisit) {
+        if !self.reasons.is_empty() {
+            self.suspects.push(Suspect {
+                block_num: page.block_num,
+                reasons: std::mem::take(&mut self.reasons),
+            });
+        }
+    }
+}
+
+/// Returns a copy of the page with key data of all tuples zeroed. Page header, line pointers,
+/// tuple headers (item pointers and sizes) and the special space are preserved, so the
+/// structure of the page can still be analyzed.
+pub fn redact_page(page: &Page) -> Vec<u8> {
+    let mut bytes = page.as_bytes().to_vec();
+    for i in page.offsets() {
+        let iid = page.item_id(i as usize);
+        let start = iid.lp_off() as usize + size_of::<IndexTupleData>();
+        let end = (iid.lp_off() + iid.lp_len()) as usize;
+        if iid.lp_len() as usize > size_of::<IndexTupleData>() && end <= bytes.len() {
+            for b in bytes[start..end].iter_mut() {
+                *b = 0;
+            }
+        }
+    }
+    bytes
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(hex, "{:02x}", b).unwrap();
+    }
+    hex
+}
+
+fn setting(name: &str) -> Option<String> {
+    Spi::get_one::<String>(&format!("SELECT current_setting('{}')", name))
+}
+
+impl IndexInspector {
+    /// Collects everything needed to investigate a possibly corrupted index into a single JSON
+    /// bundle: findings of all available checks, statistics, dumps of suspicious pages and
+    /// server metadata. With `redact` enabled, key data is zeroed in page dumps.
+    pub fn forensics(&self, redact: bool) -> serde_json::Value {
+        let stats = self.stats(None);
+        let count_check = self.count_check();
+        let mut collector = SuspectCollector {
+            num_blocks: self.num_blocks(),
+            leaf_level: None,
+            suspects: Vec::new(),
+            reasons: Vec::new(),
+        };
+        self.walk(&mut collector);
+
+        let mut findings = Vec::new();
+        if !stats.is_valid {
+            findings.push("index is marked as invalid".to_string());
+        }
+        if stats.num_invalid_tuple > 0 {
+            findings.push(format!("{} invalid tuples", stats.num_invalid_tuple));
+        }
+        if stats.depth_imbalance > 0 {
+            findings.push(format!(
+                "leaves found at different depths (imbalance: {})",
+                stats.depth_imbalance
+            ));
+        }
+        if !count_check.is_consistent() {
+            findings.push(format!(
+                "{} live leaf tuples, but a full index scan returned {}",
+                count_check.walker_count, count_check.scan_count
+            ));
+        }
+        for finding in self.check() {
+            findings.push(format!("block {}: {}", finding.block_num, finding.message));
+        }
+        for suspect in collector.suspects.iter() {
+            for reason in suspect.reasons.iter() {
+                findings.push(format!("block {}: {}", suspect.block_num, reason));
+            }
+        }
+
+        let pages: Vec<_> = collector
+            .suspects
+            .iter()
+            .take(MAX_DUMPED_PAGES)
+            .map(|suspect| {
+                let page = self.cache.get(suspect.block_num);
+                let bytes = if redact {
+                    redact_page(&page)
+                } else {
+                    page.as_bytes().to_vec()
+                };
+                json!({
+                    "blkno": suspect.block_num,
+                    "reasons": suspect.reasons,
+                    "hex": to_hex(&bytes),
+                    "base64": base64::encode(&bytes),
+                })
+            })
+            .collect();
+
+        let rel_oid = self.oid();
+        json!({
+            "index": Spi::get_one::<String>(&format!("SELECT {}::regclass::text", rel_oid)),
+            "table": Spi::get_one::<String>(&format!(
+                "SELECT indrelid::regclass::text FROM pg_index WHERE indexrelid = {}",
+                rel_oid
+            )),
+            "redacted": redact,
+            "findings": findings,
+            "stats": stats.to_json(),
+            "count_check": {
+                "walker_count": count_check.walker_count,
+                "scan_count": count_check.scan_count,
+            },
+            "suspicious_pages": pages,
+            "server": {
+                "version": Spi::get_one::<String>("SELECT version()"),
+                "server_version_num": setting("server_version_num"),
+                "block_size": BLCKSZ,
+                "data_checksums": setting("data_checksums"),
+                "gevel_version": env!("CARGO_PKG_VERSION"),
+                "generated_at": Spi::get_one::<String>("SELECT now()::text"),
+            },
+        })
+    }
+}
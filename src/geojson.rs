@@ -0,0 +1,81 @@
+use crate::error::{raise, ErrorClass};
+use crate::gist::IndexInspector;
+use crate::heatmap::{box_format, BoundingBox, BoxFormat};
+use crate::key::KeyDecoder;
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys::OffsetNumber;
+use serde_json::json;
+
+/// Returns a GeoJSON polygon feature outlining a bounding box key.
+fn feature(key: &BoundingBox, page: &PageVisit, offset: OffsetNumber) -> serde_json::Value {
+    json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "Polygon",
+            "coordinates": [[
+                [key.xmin, key.ymin],
+                [key.xmax, key.ymin],
+                [key.xmax, key.ymax],
+                [key.xmin, key.ymax],
+                [key.xmin, key.ymin],
+            ]],
+        },
+        "properties": {
+            "level": page.level,
+            "blkno": page.block_num,
+            "offset": offset,
+            "is_leaf": page.is_leaf(),
+        },
+    })
+}
+
+struct GeoJsonCollector {
+    decoder: KeyDecoder,
+    format: BoxFormat,
+    level: usize,
+    features: Vec<serde_json::Value>,
+}
+
+impl Visitor for GeoJsonCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        page.level < self.level
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        if page.level != self.level || tuple.is_invalid() {
+            return;
+        }
+        let (values, is_null) = self.decoder.deform(tuple);
+        if !is_null[0] {
+            let key = self.format.decode(values[0]);
+            self.features.push(feature(&key, page, offset));
+        }
+    }
+}
+
+impl IndexInspector {
+    /// Returns bounding boxes of the first key attribute stored on pages of a given level (the
+    /// root is at level 0) as a GeoJSON FeatureCollection of polygons. Keys of internal pages
+    /// outline subtrees of their children, so they show how the index partitions the space.
+    /// Levels deeper than the tree yield an empty collection, pages below `level` are not read.
+    pub fn keys_geojson(&self, level: i32) -> serde_json::Value {
+        if level < 0 {
+            raise(
+                ErrorClass::InvalidArgument,
+                &format!("level must not be negative, got {}", level),
+            );
+        }
+        let mut collector = GeoJsonCollector {
+            decoder: KeyDecoder::new(self.relation),
+            format: box_format(self.relation, "GeoJSON exports"),
+            level: level as usize,
+            features: Vec::new(),
+        };
+        self.walk(&mut collector);
+        json!({
+            "type": "FeatureCollection",
+            "features": collector.features,
+        })
+    }
+}
@@ -1,134 +1,1392 @@
-use crate::{Buffer, Page, GIST_ROOT_BLKNO, PAGE_SIZE};
+use crate::cache::PageCache;
+use crate::error::{Error, Result};
+use crate::guc;
+use crate::key::{KeyDecoder, VarlenaKind};
+use crate::memory::InspectionMemory;
+use crate::readonly::ReadOnlyCheck;
+use crate::rows::TreeRows;
+use crate::signature::Signature;
+use crate::sys::{
+    pg_class_aclcheck, AclResult_ACLCHECK_OK, ConditionalLockRelationOid, GetRecordedFreeSpace,
+    LockRelationOid, TupleDescAttr, UnlockRelationOid, GIST_AM_OID,
+};
+use crate::walker::{
+    check_for_interrupts, Link, PageVisit, TopK, VisitedBlocks, Visitor, TOP_K_MAX_BYTES,
+};
+use crate::{fork_size, Buffer, IndexTuple, Page, GIST_ROOT_BLKNO};
+use memoffset::offset_of;
 use pgx::pg_sys::{
-    index_close, index_open, AccessExclusiveLock, BlockNumber, FirstOffsetNumber,
-    GISTPageOpaqueData, InvalidBlockNumber, OffsetNumber, Oid, Relation, BLCKSZ, F_LEAF,
+    get_am_name, get_rel_name, get_rel_relkind, index_beginscan_bitmap, index_close, index_endscan,
+    index_getbitmap, index_getprocid, index_getprocinfo, index_open, index_rescan, palloc0,
+    pg_usleep, tbm_create, tbm_free, work_mem, AccessShareLock, AclMode, BlockNumber, Datum,
+    ForkNumber_MAIN_FORKNUM, FunctionCall1Coll, FunctionCall2Coll, GISTPageOpaqueData,
+    GetActiveSnapshot, GetUserId, GistEntryVector, IndexGetRelation, InvalidBlockNumber,
+    InvalidOid, ItemIdData, NoLock, OffsetNumber, Oid, Relation, RelationGetNumberOfBlocksInFork,
+    ShareLock, ACL_SELECT, BLCKSZ, F_DELETED, F_FOLLOW_RIGHT, F_HAS_GARBAGE, F_LEAF,
+    F_TUPLES_DELETED, GISTENTRY, GIST_DECOMPRESS_PROC, GIST_UNION_PROC, LP_DEAD, LP_NORMAL,
+    LP_REDIRECT, MAXIMUM_ALIGNOF, RELKIND_INDEX,
 };
-use std::fmt::{Display, Formatter};
+use pgx::{pg_sys, warning, Spi};
+use serde_json::json;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fmt::{Display, Formatter, Write};
+use std::mem::size_of;
+use std::os::raw::c_long;
+use std::ptr::null_mut;
+use std::time::{Duration, Instant};
+
+/// Max number of characters of a decoded key used in previews.
+const KEY_PREVIEW_LEN: usize = 64;
+/// Lock acquired on inspected indexes for the lifetime of [IndexInspector]. It only conflicts
+/// with DDL, so regular reads and writes proceed while an index is inspected. Page splits
+/// running concurrently with a walk are handled by [IndexInspector::walk] the same way GiST
+/// scans handle them.
+const INDEX_LOCK_MODE: u32 = AccessShareLock;
+/// Lock taken on the table of an index by [IndexInspector::with_table_locked]. Same as the one
+/// taken by `CREATE INDEX`, it conflicts with all writes and `VACUUM`, so the index can't change
+/// while it's held.
+const TABLE_LOCK_MODE: u32 = ShareLock;
+/// Max delay between consecutive attempts to lock an index.
+const MAX_LOCK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Fillfactor used by GiST when it's not set explicitly on the index.
+const GIST_DEFAULT_FILLFACTOR: u32 = 90;
+
+/// Returns a name of a relation kind stored in `pg_class.relkind`, as used in error messages.
+fn relkind_name(relkind: u8) -> &'static str {
+    match relkind {
+        b'r' => "table",
+        b'i' => "index",
+        b'S' => "sequence",
+        b't' => "TOAST table",
+        b'v' => "view",
+        b'm' => "materialized view",
+        b'c' => "composite type",
+        b'f' => "foreign table",
+        b'p' => "partitioned table",
+        b'I' => "partitioned index",
+        _ => "relation",
+    }
+}
+
+/// Returns an error describing what a relation is, if it's not a GiST index.
+fn not_gist_index(rel_oid: Oid, what: &str) -> Error {
+    let name = unsafe { CStr::from_ptr(get_rel_name(rel_oid)) };
+    Error::NotGistIndex {
+        name: name.to_string_lossy().into_owned(),
+        what: what.to_string(),
+    }
+}
+
+/// Fails if the current user can't `SELECT` from the table of an index.
+fn check_table_privileges(rel_oid: Oid) -> Result<()> {
+    unsafe {
+        let table_oid = IndexGetRelation(rel_oid, false);
+        if pg_class_aclcheck(table_oid, GetUserId(), ACL_SELECT as AclMode) == AclResult_ACLCHECK_OK
+        {
+            return Ok(());
+        }
+        Err(Error::PermissionDenied {
+            table: CStr::from_ptr(get_rel_name(table_oid))
+                .to_string_lossy()
+                .into_owned(),
+        })
+    }
+}
+
+/// Locks a relation in a given mode, ie. an index with [INDEX_LOCK_MODE]. With
+/// `gevel.lock_timeout_ms` set, the lock is only taken if it can be granted immediately and
+/// attempts are retried with a growing delay until the timeout passes. Such attempts never wait
+/// in the lock queue, so they don't block DDL issued after them.
+pub(crate) fn lock_relation(rel_oid: Oid, mode: u32) -> Result<()> {
+    let timeout = guc::LOCK_TIMEOUT_MS.get();
+    if timeout <= 0 {
+        unsafe { LockRelationOid(rel_oid, mode as i32) };
+        return Ok(());
+    }
+    let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+    let mut delay = Duration::from_millis(1);
+    while !unsafe { ConditionalLockRelationOid(rel_oid, mode as i32) } {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::LockTimeout {
+                oid: rel_oid,
+                timeout_ms: timeout,
+            });
+        }
+        check_for_interrupts!();
+        unsafe { pg_usleep(delay.min(deadline - now).as_micros() as c_long) };
+        delay = (delay * 2).min(MAX_LOCK_RETRY_DELAY);
+    }
+    Ok(())
+}
 
 pub struct IndexInspector {
-    relation: Relation,
+    pub(crate) relation: Relation,
+    /// Pages read during the lifetime of this inspector.
+    pub(crate) cache: PageCache,
+    /// Asserts that nothing is written during the inspection, unless explicitly allowed.
+    pub(crate) read_only: ReadOnlyCheck,
+    /// Accounts memory used by walks of the index. Declared last, so that it's dropped after
+    /// everything else is released. `None` for inspectors outliving a single function call.
+    memory: Option<InspectionMemory>,
 }
 
 impl IndexInspector {
-    pub fn open(rel_oid: Oid) -> Self {
-        let relation = unsafe { index_open(rel_oid, AccessExclusiveLock as i32) };
-        IndexInspector { relation }
+    /// Opens and locks a GiST index for inspection. Fails if the relation doesn't exist, is
+    /// not a GiST index, the current user can't `SELECT` from its table (keys stored in the
+    /// index are copies of the table's data) or it can't be locked within
+    /// `gevel.lock_timeout_ms`.
+    pub fn open(rel_oid: Oid) -> Result<Self> {
+        Self::open_with(rel_oid, true, true)
     }
 
-    pub fn get_tree(&self, max_level: Option<usize>) -> IndexTree {
-        let node = self.get_tree_node(0, max_level, GIST_ROOT_BLKNO, 0);
-        IndexTree(node)
+    /// Opens an index with [IndexInspector::open] while writes to its table are blocked, and
+    /// runs `f` with it, so that everything `f` reads describes the very same structure. The
+    /// table is locked before the index, same as by everything else touching both of them, and
+    /// it's unlocked as soon as `f` returns.
+    pub fn with_table_locked<R>(rel_oid: Oid, f: impl FnOnce(&IndexInspector) -> R) -> Result<R> {
+        // if there's no table, the relation is not an index and opening it fails below
+        let table = unsafe { IndexGetRelation(rel_oid, true) };
+        if table != InvalidOid {
+            lock_relation(table, TABLE_LOCK_MODE)?;
+        }
+        let result = IndexInspector::open(rel_oid).map(|index| f(&index));
+        if table != InvalidOid {
+            unsafe { UnlockRelationOid(table, TABLE_LOCK_MODE as i32) };
+        }
+        result
     }
 
-    fn get_tree_node(
-        &self,
-        level: usize,
-        max_level: Option<usize>,
-        blk: BlockNumber,
-        offset: OffsetNumber,
-    ) -> IndexTreeNode {
-        let buf = Buffer::new(self.relation, blk);
-        let page = Page::new(buf);
-        let max_offset = page.max_offset();
-        let free_space = page.free_space();
-        let gist_page = GistPage::new(&page);
-        let is_leaf = gist_page.is_leaf();
+    /// Same as [IndexInspector::open], but doesn't account memory used by the inspection.
+    /// Accounting allocates walks in a dedicated memory context deleted with the inspector,
+    /// which must not happen for inspectors kept between calls of a set-returning function.
+    pub fn open_streaming(rel_oid: Oid) -> Result<Self> {
+        Self::open_with(rel_oid, false, true)
+    }
 
-        let mut node = IndexTreeNode::new(
-            max_offset,
-            free_space,
-            offset,
-            blk,
-            gist_page.right_link(),
-            is_leaf,
+    /// Same as [IndexInspector::open], but doesn't require `SELECT` on the table. Only meant
+    /// for functions reporting aggregated numbers which never expose keys, like `gist_summary`,
+    /// whose use is restricted with `EXECUTE` privileges instead.
+    pub fn open_aggregates(rel_oid: Oid) -> Result<Self> {
+        Self::open_with(rel_oid, true, false)
+    }
+
+    fn open_with(rel_oid: Oid, account_memory: bool, check_select: bool) -> Result<Self> {
+        // get_rel_relkind returns '\0' for relations that don't exist
+        let relkind = if rel_oid == InvalidOid {
+            0
+        } else {
+            unsafe { get_rel_relkind(rel_oid) as u8 }
+        };
+        if relkind == 0 {
+            return Err(Error::InvalidRelation { oid: rel_oid });
+        }
+        // pages of other relations would be misinterpreted, so they are rejected before any of
+        // them is read
+        if relkind != RELKIND_INDEX {
+            let kind = relkind_name(relkind);
+            if relkind == b'I' {
+                return Err(not_gist_index(
+                    rel_oid,
+                    "a partitioned index, which has no pages of its own (inspect indexes of its partitions instead)",
+                ));
+            }
+            return Err(not_gist_index(rel_oid, &format!("a {}", kind)));
+        }
+        if check_select {
+            check_table_privileges(rel_oid)?;
+        }
+        let memory = if account_memory {
+            Some(InspectionMemory::start())
+        } else {
+            None
+        };
+        let read_only = ReadOnlyCheck::start();
+        lock_relation(rel_oid, INDEX_LOCK_MODE)?;
+        // lock is already held, it's released by index_close
+        let relation = unsafe { index_open(rel_oid, NoLock as i32) };
+        let relam = unsafe { (*(*relation).rd_rel).relam };
+        if relam != GIST_AM_OID {
+            let am = unsafe { CStr::from_ptr(get_am_name(relam)) }
+                .to_string_lossy()
+                .into_owned();
+            unsafe { index_close(relation, INDEX_LOCK_MODE as i32) };
+            return Err(not_gist_index(rel_oid, &format!("a {} index", am)));
+        }
+        let inspector = IndexInspector {
+            relation,
+            cache: PageCache::new(relation),
+            read_only,
+            memory,
+        };
+        if !inspector.is_valid() {
+            match crate::reindex::reindex_pair(rel_oid).filter(|pair| pair.new == rel_oid) {
+                Some(pair) => warning!(
+                    "index {} is a copy of index {} built by REINDEX CONCURRENTLY, which is still running or has failed, its contents may be incomplete",
+                    rel_oid,
+                    pair.old
+                ),
+                None => warning!(
+                    "index {} is marked as invalid (probably a failed CREATE INDEX CONCURRENTLY), its contents may be incomplete",
+                    rel_oid
+                ),
+            }
+        }
+        Ok(inspector)
+    }
+
+    /// Returns number of blocks in the main fork of the index.
+    pub fn num_blocks(&self) -> BlockNumber {
+        unsafe { RelationGetNumberOfBlocksInFork(self.relation, ForkNumber_MAIN_FORKNUM) }
+    }
+
+    /// Returns false if index has been marked as invalid in `pg_index.indisvalid`, which is the
+    /// case ie. after failed `CREATE INDEX CONCURRENTLY`. Such indexes can still be inspected,
+    /// but their contents are not guaranteed to be complete.
+    pub fn is_valid(&self) -> bool {
+        unsafe { (*self.relation).rd_index.as_ref() }
+            .map(|index| index.indisvalid)
+            .unwrap_or(false)
+    }
+
+    /// Runs `f` in the memory context accounting this inspection, if there's one.
+    pub(crate) fn in_memory<R>(&self, f: impl FnOnce() -> R) -> R {
+        match &self.memory {
+            Some(memory) => memory.run(f),
+            None => f(),
+        }
+    }
+
+    /// Builds an in-memory tree of index pages, collecting details specified by `options`.
+    pub fn get_tree(&self, options: &TreeOptions) -> IndexTree {
+        let mut builder = TreeBuilder {
+            relation: self.relation,
+            options: options.clone(),
+            stack: Vec::new(),
+            root: None,
+        };
+        self.walk(&mut builder);
+        IndexTree {
+            root: builder.root.unwrap_or_else(|| {
+                Error::CorruptPage {
+                    index: self.name(),
+                    blkno: GIST_ROOT_BLKNO,
+                    message: "GiST index has no root page".to_string(),
+                }
+                .raise()
+            }),
+            is_valid: self.is_valid(),
+        }
+    }
+
+    pub fn stats(&self, max_level: Option<usize>) -> Stats {
+        let mut collector = StatsCollector::new(self.relation, max_level);
+        self.walk(&mut collector);
+        collector.finish(self)
+    }
+
+    /// Returns statistics of the index, one entry per tree level.
+    pub fn stats_by_level(&self) -> Vec<LevelStats> {
+        let mut collector = LevelCollector::default();
+        self.walk(&mut collector);
+        collector.finish(self)
+    }
+
+    /// Returns statistics of NULL values, one entry per key attribute.
+    pub fn attr_stats(&self) -> Vec<AttrStats> {
+        let decoder = KeyDecoder::new(self.relation);
+        let mut collector = AttrCollector {
+            attrs: decoder
+                .attr_names()
+                .into_iter()
+                .map(|name| AttrStats {
+                    name,
+                    ..AttrStats::default()
+                })
+                .collect(),
+            decoder,
+        };
+        self.walk(&mut collector);
+        collector.attrs
+    }
+
+    /// Returns up to `top_n` largest index tuples found across the whole index, ordered from the
+    /// largest to the smallest one. Less tuples are returned if their previews would take more
+    /// than [TOP_K_MAX_BYTES].
+    pub fn key_outliers(&self, top_n: usize) -> Vec<KeyOutlier> {
+        let mut collector = OutlierCollector {
+            decoder: KeyDecoder::new(self.relation),
+            top: TopK::with_memory_cap(top_n, TOP_K_MAX_BYTES, |o| {
+                std::mem::size_of::<KeyOutlier>() + o.preview.len()
+            }),
+        };
+        if top_n > 0 {
+            self.walk(&mut collector);
+        }
+        collector.top.into_sorted_vec()
+    }
+
+    /// Returns saturation statistics of signature-based keys, one entry per tree level. Returns
+    /// empty vector if index doesn't use signature-based keys.
+    pub fn signature_stats(&self) -> Vec<SignatureLevelStats> {
+        let mut collector = SignatureCollector::new(self.relation);
+        if collector.decoder.has_signatures() {
+            self.walk(&mut collector);
+        }
+        collector.levels
+    }
+
+    /// Returns estimated summary of the index. All internal pages are read, but only up to
+    /// `sample_size` leaf pages (picked at even intervals) are visited, and leaf-level numbers
+    /// are extrapolated from them. This bounds the cost of the call regardless of index size.
+    pub fn summary(&self, sample_size: usize) -> Summary {
+        let leaf_level = match self.leaf_level() {
+            Some(level) => level,
+            // there's no page to summarize
+            None => {
+                return Summary::new(0, &Estimate::default(), self.is_valid(), self.fillfactor())
+            }
+        };
+        let mut collector = LeafCollector {
+            leaf_level,
+            estimate: Estimate::default(),
+            leaves: Vec::new(),
+        };
+        if leaf_level == 0 {
+            collector.leaves.push(GIST_ROOT_BLKNO);
+        } else {
+            self.walk(&mut collector);
+        }
+        let LeafCollector {
+            mut estimate,
+            leaves,
+            ..
+        } = collector;
+
+        let sample_size = sample_size.max(1);
+        let step = (leaves.len() as f64 / sample_size as f64).max(1.0);
+        let mut sample = Estimate::default();
+        let mut i = 0.0;
+        while (i as usize) < leaves.len() {
+            check_for_interrupts!();
+            let page = self.cache.get(leaves[i as usize]);
+            i += step;
+            if page.is_new() {
+                continue;
+            }
+            if !GistPage::new(&page).is_leaf() {
+                estimate.imbalanced = true;
+            }
+            sample.add_page(&page);
+        }
+
+        let scale = if sample.pages == 0 {
+            0.0
+        } else {
+            leaves.len() as f64 / sample.pages as f64
+        };
+        estimate.pages += leaves.len() as u64;
+        estimate.capacity += (sample.capacity as f64 * scale) as u64;
+        estimate.free_space += (sample.free_space as f64 * scale) as u64;
+        estimate.invalid_tuples += (sample.invalid_tuples as f64 * scale) as u64;
+        estimate.leaf_tuples += (sample.leaf_tuples as f64 * scale) as u64;
+        estimate.leaf_used += (sample.leaf_used as f64 * scale) as u64;
+
+        let mut summary = Summary::new(
+            leaf_level + 1,
+            &estimate,
+            self.is_valid(),
+            self.fillfactor(),
+        );
+        summary.leaf_pages = leaves.len() as u64;
+        summary
+    }
+
+    /// Returns the fillfactor of the index: percentage of page space filled by index builds.
+    pub fn fillfactor(&self) -> u32 {
+        let rel_oid = self.oid();
+        let query = format!(
+            "SELECT option_value::int FROM pg_options_to_table((SELECT reloptions FROM pg_class WHERE oid = {})) WHERE option_name = 'fillfactor'",
+            rel_oid
         );
+        Spi::get_one::<i32>(&query).map_or(GIST_DEFAULT_FILLFACTOR, |f| f as u32)
+    }
+
+    /// Returns the type keys of the first key column are stored as, ie. `box` for points.
+    pub fn key_type(&self) -> Oid {
+        unsafe { (*TupleDescAttr((*self.relation).rd_att, 0)).atttypid }
+    }
+
+    /// Returns the union of all keys stored on the root page, computed with the opclass union
+    /// support function of the first key column, as a value of [IndexInspector::key_type]. For
+    /// spatial indexes this is the total extent of the indexed data, as seen by the index.
+    /// Returns `None` for empty indexes or when all root keys are NULL.
+    ///
+    /// The union is allocated in the current memory context of the caller.
+    pub fn data_extent(&self) -> Option<Datum> {
+        let decoder = KeyDecoder::new(self.relation);
+        let page = self.cache.get(GIST_ROOT_BLKNO);
+        let mut keys = Vec::new();
+        for (i, which) in page.tuples() {
+            let (values, is_null) = decoder.deform(&which);
+            if !is_null[0] {
+                keys.push((values[0], i));
+            }
+        }
+        if keys.is_empty() {
+            return None;
+        }
+        Some(self.union_keys(&keys, page.0))
+    }
 
-        if !is_leaf {
-            let recurse = match max_level {
-                Some(max) => max > level,
-                None => true,
+    /// Computes the union of non-NULL keys of the first key column, given together with their
+    /// offsets, with the opclass union support function. Keys not stored on a page (ie. copied
+    /// out of it) are passed with a NULL `page`, the same way GiST itself does when
+    /// choosing subtrees. Stored keys are run through the decompress function of the opclass
+    /// first, the same way `gistdentryinit` does.
+    pub(crate) fn union_keys(&self, keys: &[(Datum, OffsetNumber)], page: pg_sys::Page) -> Datum {
+        unsafe {
+            let size = offset_of!(GistEntryVector, vector) + keys.len() * size_of::<GISTENTRY>();
+            let entry_vec = palloc0(size) as *mut GistEntryVector;
+            (*entry_vec).n = keys.len() as i32;
+            let entries = (*entry_vec).vector.as_mut_slice(keys.len());
+            let collation = *(*self.relation).rd_indcollation;
+            let decompress = if index_getprocid(self.relation, 1, GIST_DECOMPRESS_PROC as u16) == 0
+            {
+                None
+            } else {
+                Some(index_getprocinfo(
+                    self.relation,
+                    1,
+                    GIST_DECOMPRESS_PROC as u16,
+                ))
             };
+            for (entry, &(key, offset)) in entries.iter_mut().zip(keys.iter()) {
+                entry.key = key;
+                entry.rel = self.relation;
+                entry.page = page;
+                entry.offset = offset;
+                entry.leafkey = false;
+                if let Some(proc) = decompress {
+                    let decompressed =
+                        FunctionCall1Coll(proc, collation, entry as *mut GISTENTRY as Datum)
+                            as *const GISTENTRY;
+                    if !std::ptr::eq(decompressed, entry) {
+                        entry.key = (*decompressed).key;
+                        entry.rel = (*decompressed).rel;
+                        entry.page = (*decompressed).page;
+                        entry.offset = (*decompressed).offset;
+                        entry.leafkey = (*decompressed).leafkey;
+                    }
+                }
+            }
+
+            let union_proc = index_getprocinfo(self.relation, 1, GIST_UNION_PROC as u16);
+            let mut union_size: i32 = 0;
+            FunctionCall2Coll(
+                union_proc,
+                collation,
+                entry_vec as Datum,
+                &mut union_size as *mut i32 as Datum,
+            )
+        }
+    }
 
-            if recurse {
-                let children = node.children.as_mut().unwrap();
-                for i in FirstOffsetNumber..=max_offset {
-                    let iid = page.item_id(i as usize);
-                    let which = page.get_index_tuple(iid);
-                    let cblk = which.block_num();
-                    let child = self.get_tree_node(level + 1, max_level, cblk, i);
-                    children.push(child);
+    /// Returns all items stored on a page with a given block number.
+    pub fn page_items(&self, blkno: i64) -> Result<Vec<PageItem>> {
+        let blk = self.check_block(blkno)?;
+        let decoder = KeyDecoder::new(self.relation);
+        let page = self.cache.get(blk);
+        let is_leaf = !page.is_new() && GistPage::new(&page).is_leaf();
+        let mut items = Vec::with_capacity(page.max_offset() as usize);
+        for i in page.offsets() {
+            let iid = page.item_id(i as usize);
+            let item_state = item_state(&iid);
+            let (tuple_size, has_nulls, is_invalid, tid, varlena_kinds, key) = if iid.lp_len() == 0
+            {
+                (0, false, false, (InvalidBlockNumber, 0), Vec::new(), None)
+            } else {
+                let which = page.get_index_tuple(iid);
+                (
+                    which.size(),
+                    which.has_nulls(),
+                    which.is_invalid(),
+                    (which.block_num(), which.offset_num()),
+                    decoder.varlena_kinds(&which),
+                    Some(decoder.preview(&which, KEY_PREVIEW_LEN)),
+                )
+            };
+            items.push(PageItem {
+                offset: i,
+                item_state,
+                item_len: iid.lp_len() as usize,
+                tuple_size,
+                is_leaf_key: is_leaf,
+                has_nulls,
+                is_invalid,
+                tid,
+                varlena_kinds,
+                key,
+            });
+        }
+        Ok(items)
+    }
+
+    /// Returns block numbers of all pages of the main fork which have never been initialized
+    /// (are all zeros). A few of them may be left by a crash while the relation was being
+    /// extended, while many of them indicate failed bulk operations.
+    pub fn new_pages(&self) -> Vec<BlockNumber> {
+        (0..self.num_blocks())
+            .filter(|&blk| {
+                check_for_interrupts!();
+                self.cache.get(blk).is_new()
+            })
+            .collect()
+    }
+
+    /// Returns a given block number, if it's within the main fork of the index.
+    pub(crate) fn check_block(&self, blkno: i64) -> Result<BlockNumber> {
+        if blkno < 0 || blkno >= self.num_blocks() as i64 {
+            return Err(Error::BlockOutOfRange { blkno });
+        }
+        Ok(blkno as BlockNumber)
+    }
+
+    /// Compares the number of live leaf tuples found by walking the tree with the number of
+    /// tuples returned by a full bitmap index scan. GiST scans without any scan keys match
+    /// every entry, so both numbers should be equal - otherwise some tuples are physically
+    /// present, but not reachable by scans.
+    ///
+    /// Both numbers are only comparable if the index doesn't change in between, so the index
+    /// should be opened with [IndexInspector::with_table_locked]: otherwise tuples inserted by
+    /// concurrent writes after the walk are reported as a discrepancy.
+    pub fn count_check(&self) -> CountCheck {
+        let mut counter = LiveLeafCounter(0);
+        self.walk(&mut counter);
+
+        let scan_count = unsafe {
+            let scan = index_beginscan_bitmap(self.relation, GetActiveSnapshot(), 0);
+            index_rescan(scan, null_mut(), 0, null_mut(), 0);
+            let bitmap = tbm_create(work_mem as c_long * 1024, null_mut());
+            let count = index_getbitmap(scan, bitmap);
+            tbm_free(bitmap);
+            index_endscan(scan);
+            count as u64
+        };
+
+        CountCheck {
+            walker_count: counter.0,
+            scan_count,
+        }
+    }
+
+    pub fn oid(&self) -> Oid {
+        // the relation is opened for the whole lifetime of the inspector
+        unsafe { (*self.relation).rd_id }
+    }
+
+    /// Returns the name of the index, as used in error messages.
+    pub fn name(&self) -> String {
+        unsafe { CStr::from_ptr((*(*self.relation).rd_rel).relname.data.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Returns the level at which leaf pages reside, by descending the leftmost path of the
+    /// tree. Since GiST is balanced, all leaves are on the same level. When the path leads to
+    /// a block which can't be entered, the page it's leading from is taken as a leaf. Returns
+    /// `None` if even the root page is past the end of the index.
+    pub(crate) fn leaf_level(&self) -> Option<usize> {
+        let mut level: usize = 0;
+        let mut blk = GIST_ROOT_BLKNO;
+        let mut visited = VisitedBlocks::new(self);
+        let mut link = Link::Start;
+        loop {
+            if !visited.enter(self, blk, link) {
+                return level.checked_sub(1);
+            }
+            let page = self.cache.get(blk);
+            if page.is_new() || GistPage::new(&page).is_leaf() {
+                return Some(level);
+            }
+            match page.tuples().next() {
+                Some((offset, downlink)) => {
+                    link = Link::Downlink(blk, offset);
+                    blk = downlink.block_num();
                 }
+                None => return Some(level),
             }
+            level += 1;
         }
+    }
+}
 
-        node
+/// Builds an in-memory [IndexTree] out of visited pages.
+/// Options controlling what is collected by [IndexInspector::get_tree].
+#[derive(Debug, Default, Clone)]
+pub struct TreeOptions {
+    /// Max level of the tree to descend to. `None` means the whole tree.
+    pub max_level: Option<usize>,
+    /// Attach a summary of line pointers to every page.
+    pub include_items: bool,
+    /// Collect fill factors of children of every internal page, including children which are
+    /// below `max_level`, and render them as a sparkline.
+    pub sparkline: bool,
+}
+
+struct TreeBuilder {
+    relation: Relation,
+    options: TreeOptions,
+    /// Nodes of the pages on the path from the root to the currently visited page.
+    stack: Vec<IndexTreeNode>,
+    root: Option<IndexTreeNode>,
+}
+
+impl TreeBuilder {
+    fn descends(&self, level: usize) -> bool {
+        match self.options.max_level {
+            Some(max) => max > level,
+            None => true,
+        }
     }
+}
 
-    pub fn stats(&self, max_level: Option<usize>) -> Stats {
-        let mut stats = Stats::default();
-        self.stats_inner(0, max_level, GIST_ROOT_BLKNO, &mut stats);
+impl Visitor for TreeBuilder {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        let mut node = IndexTreeNode::new(
+            page.page.max_offset(),
+            page.page.free_space(),
+            page.page.capacity(),
+            page.offset,
+            page.block_num,
+            page.gist_page.right_link(),
+            page.is_leaf(),
+        );
+        if self.options.include_items {
+            node.items = Some(ItemsSummary::new(page.page));
+        }
+        if self.options.sparkline && !page.is_leaf() {
+            node.child_fill = Some(Vec::new());
+        }
+        self.stack.push(node);
+        self.descends(page.level)
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) {
+        if self.options.sparkline && !page.is_leaf() && !self.descends(page.level) {
+            // children won't be visited, so we need to peek at their fill factor directly,
+            // unless a corrupted downlink points past the end of the index
+            let num_blocks =
+                unsafe { RelationGetNumberOfBlocksInFork(self.relation, ForkNumber_MAIN_FORKNUM) };
+            if tuple.block_num() >= num_blocks {
+                return;
+            }
+            let child = Page::new(Buffer::new(self.relation, tuple.block_num()));
+            let fill = occupancy(child.free_space(), child.capacity());
+            let node = self.stack.last_mut().unwrap();
+            node.child_fill.as_mut().unwrap().push(fill);
+        }
+    }
+
+    fn on_page_end(&mut self, _page: &PageVisit) {
+        let node = self.stack.pop().unwrap();
+        match self.stack.last_mut() {
+            Some(parent) => {
+                if let Some(child_fill) = parent.child_fill.as_mut() {
+                    child_fill.push(node.occupied());
+                }
+                parent.children.as_mut().unwrap().push(node)
+            }
+            None => self.root = Some(node),
+        }
+    }
+}
+
+pub(crate) struct StatsCollector {
+    relation: Relation,
+    max_level: Option<usize>,
+    stats: Stats,
+    /// Statistics of subtrees under each of the root downlinks.
+    subtrees: Vec<Subtree>,
+    dead_chains: DeadChains,
+    /// Flags of visited blocks, indexed by block number.
+    visited: Vec<bool>,
+}
+
+impl StatsCollector {
+    pub(crate) fn new(relation: Relation, max_level: Option<usize>) -> Self {
+        StatsCollector {
+            relation,
+            max_level,
+            stats: Stats::default(),
+            subtrees: Vec::new(),
+            dead_chains: DeadChains::default(),
+            visited: Vec::new(),
+        }
+    }
+
+    /// Accounts statistics of a subtree under a root downlink gathered separately.
+    pub(crate) fn merge(&mut self, stats: &Stats, subtree: Subtree) {
+        self.stats.merge(stats);
+        self.subtrees.push(subtree);
+    }
+
+    /// Returns the length of the longest chain of consecutive deleted or empty pages following
+    /// any of the visited pages.
+    pub(crate) fn longest_dead_chain(&self, index: &IndexInspector) -> u64 {
+        let longest = self.dead_chains.longest(index);
+        longest.into_iter().max().unwrap_or_default()
+    }
+
+    /// Returns raw statistics gathered during the walk, without the ones computed by
+    /// [StatsCollector::finish].
+    pub(crate) fn into_parts(self) -> (Stats, Vec<Subtree>) {
+        (self.stats, self.subtrees)
+    }
+
+    /// Completes statistics gathered during the walk with the ones which can only be computed
+    /// once all pages have been visited.
+    pub(crate) fn finish(self, index: &IndexInspector) -> Stats {
+        let longest_dead_chain = self.longest_dead_chain(index);
+        let mut stats = self.stats;
+        stats.is_valid = index.is_valid();
+        // merged subtrees bring their own chains
+        stats.max_dead_chain = stats.max_dead_chain.max(longest_dead_chain);
+        if self.max_level.is_none() {
+            // new pages are not reachable from the root, so only the blocks not visited by
+            // the walk have to be read
+            let visited = &self.visited;
+            stats.num_new_pages = (0..index.num_blocks())
+                .filter(|&blk| !visited.get(blk as usize).copied().unwrap_or(false))
+                .filter(|&blk| index.cache.get(blk).is_new())
+                .count() as u64;
+        }
+        let subtrees = self.subtrees;
+        // walks stopped at `max_level` may not reach leaves of some (or any) subtrees
+        let leaf_levels: Vec<_> = subtrees.iter().filter_map(Subtree::leaf_levels).collect();
+        if let (Some(min), Some(max)) = (
+            leaf_levels.iter().map(|&(min, _)| min).min(),
+            leaf_levels.iter().map(|&(_, max)| max).max(),
+        ) {
+            stats.depth_imbalance = max - min;
+        }
+        if !subtrees.is_empty() {
+            let n = subtrees.len() as f64;
+            let mean = subtrees.iter().map(|s| s.pages as f64).sum::<f64>() / n;
+            stats.subtree_size_variance = subtrees
+                .iter()
+                .map(|s| (s.pages as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n;
+        }
+        let rel_oid = index.oid();
+        stats.fsm_size = fork_size(rel_oid, "fsm");
+        stats.vm_size = fork_size(rel_oid, "vm");
         stats
     }
+}
 
-    fn stats_inner(
-        &self,
-        level: usize,
-        max_level: Option<usize>,
-        blk: BlockNumber,
-        stats: &mut Stats,
-    ) {
-        let buf = Buffer::new(self.relation, blk);
-        let page = Page::new(buf);
-        let max_offset = page.max_offset();
-        let tuple_size = PAGE_SIZE as u64 - page.free_space() as u64;
-        let gist_page = GistPage::new(&page);
-        let is_leaf = gist_page.is_leaf();
-
-        stats.num_pages += 1;
-        stats.tuple_size += tuple_size;
-        stats.total_size += BLCKSZ as u64;
-        stats.num_tuple += max_offset as usize;
-        stats.level = stats.level.max(level);
+#[derive(Debug, Clone)]
+pub(crate) struct Subtree {
+    pub(crate) pages: u64,
+    pub(crate) min_leaf_level: usize,
+    pub(crate) max_leaf_level: usize,
+}
 
-        if is_leaf {
-            stats.num_leaf_pages += 1;
-            stats.leaf_tuple_size += tuple_size;
-            stats.num_leaf_tuple += max_offset as usize;
+impl Subtree {
+    /// Returns the min and max level of leaves of the subtree, `None` if the walk hasn't
+    /// reached any of them.
+    fn leaf_levels(&self) -> Option<(usize, usize)> {
+        Some((self.min_leaf_level, self.max_leaf_level)).filter(|&(min, max)| min <= max)
+    }
+}
+
+impl Visitor for StatsCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        let fsm_free_space = unsafe { GetRecordedFreeSpace(self.relation, page.block_num) };
+        self.stats.add_page(
+            page.level,
+            page.is_leaf(),
+            page.page.max_offset(),
+            page.page.free_space() as u64,
+            page.page.capacity() as u64,
+            fsm_free_space as u64,
+        );
+        self.dead_chains.add(page);
+        let blk = page.block_num as usize;
+        if self.visited.len() <= blk {
+            self.visited.resize(blk + 1, false);
+        }
+        self.visited[blk] = true;
+
+        if page.level == 1 {
+            self.subtrees.push(Subtree {
+                pages: 0,
+                min_leaf_level: usize::MAX,
+                max_leaf_level: 0,
+            });
+        }
+        if let Some(subtree) = self.subtrees.last_mut().filter(|_| page.level > 0) {
+            subtree.pages += 1;
+            if page.is_leaf() {
+                subtree.min_leaf_level = subtree.min_leaf_level.min(page.level);
+                subtree.max_leaf_level = subtree.max_leaf_level.max(page.level);
+            }
+        }
+
+        match self.max_level {
+            Some(max) => max > page.level,
+            None => true,
+        }
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        if tuple.has_nulls() {
+            self.stats.num_null_tuple += 1;
+        }
+        match TupleState::of(page.page, offset) {
+            TupleState::Normal => {}
+            TupleState::Invalid => {
+                self.stats.num_invalid_tuple += 1;
+                if page.is_leaf() {
+                    self.stats.num_invalid_leaf_tuple += 1;
+                } else {
+                    self.stats.num_invalid_internal_tuple += 1;
+                }
+            }
+            TupleState::Dead => self.stats.num_dead_tuple += 1,
+        }
+    }
+}
+
+/// Rightlinks of live pages visited by a walk, used to measure chains of consecutive deleted or
+/// empty pages following them. Such chains slow down scans which have to skip them.
+#[derive(Default)]
+pub(crate) struct DeadChains {
+    /// Level and rightlink of every visited live page.
+    starts: Vec<(usize, BlockNumber)>,
+    /// Visited live pages, which are known to end a chain without reading them again.
+    live: HashSet<BlockNumber>,
+}
+
+impl DeadChains {
+    fn add(&mut self, page: &PageVisit) {
+        if is_dead(page.page) {
+            return;
+        }
+        self.live.insert(page.block_num);
+        let right = page.gist_page.right_link();
+        if right != InvalidBlockNumber {
+            self.starts.push((page.level, right));
+        }
+    }
+
+    /// Returns the length of the longest chain of dead pages on each level.
+    fn longest(&self, index: &IndexInspector) -> Vec<u64> {
+        let num_blocks = index.num_blocks();
+        let mut longest = Vec::new();
+        for &(level, right) in self.starts.iter() {
+            let (mut blk, mut len) = (right, 0);
+            // length is bounded in case of rightlink cycles
+            while blk != InvalidBlockNumber
+                && blk < num_blocks
+                && len < num_blocks as u64
+                && !self.live.contains(&blk)
+            {
+                check_for_interrupts!();
+                let page = index.cache.get(blk);
+                if !is_dead(&page) {
+                    break;
+                }
+                len += 1;
+                if page.is_new() {
+                    // new pages have no rightlink
+                    break;
+                }
+                blk = GistPage::new(&page).right_link();
+            }
+            if longest.len() <= level {
+                longest.resize(level + 1, 0);
+            }
+            longest[level] = longest[level].max(len);
+        }
+        longest
+    }
+}
+
+/// Returns true for pages holding no tuples: deleted, empty or never initialized ones.
+fn is_dead(page: &Page) -> bool {
+    page.is_new() || GistPage::new(page).is_deleted() || page.max_offset() == 0
+}
+
+/// Classification of a single tuple stored on an index page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TupleState {
+    Normal,
+    /// Tuple marked as invalid by crash recovery of pre-9.1 PostgreSQL versions. Such tuples
+    /// can only be removed by REINDEX.
+    Invalid,
+    /// Tuple marked as dead (ie. killed by an index scan), but not yet removed by vacuum.
+    Dead,
+}
+
+impl TupleState {
+    pub fn of(page: &Page, offset: OffsetNumber) -> Self {
+        let iid = page.item_id(offset as usize);
+        if iid.lp_flags() == LP_DEAD {
+            TupleState::Dead
+        } else if page.get_index_tuple(iid).is_invalid() {
+            TupleState::Invalid
         } else {
-            for i in FirstOffsetNumber..=max_offset {
-                let iid = page.item_id(i as usize);
-                let which = page.get_index_tuple(iid);
-                if which.is_invalid() {
-                    stats.num_invalid_tuple += 1;
+            TupleState::Normal
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct LevelCollector {
+    levels: Vec<LevelStats>,
+    dead_chains: DeadChains,
+}
+
+impl LevelCollector {
+    /// Completes per-level statistics with the ones which can only be computed once all pages
+    /// have been visited.
+    pub(crate) fn finish(self, index: &IndexInspector) -> Vec<LevelStats> {
+        let mut levels = self.levels;
+        for (level, longest) in self.dead_chains.longest(index).into_iter().enumerate() {
+            levels[level].max_dead_chain = longest;
+        }
+        levels
+    }
+}
+
+impl Visitor for LevelCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if self.levels.len() <= page.level {
+            self.levels.resize_with(page.level + 1, LevelStats::default);
+        }
+        let level = &mut self.levels[page.level];
+        level.num_pages += 1;
+        level.free_space += page.page.free_space() as u64;
+        self.dead_chains.add(page);
+        true
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) {
+        let level = &mut self.levels[page.level];
+        if tuple.has_nulls() {
+            level.null_tuples += 1;
+        }
+        if page.is_leaf() {
+            level.leaf_keys.add(tuple.size());
+        } else {
+            level.internal_keys.add(tuple.size());
+        }
+    }
+}
+
+struct AttrCollector {
+    decoder: KeyDecoder,
+    attrs: Vec<AttrStats>,
+}
+
+impl Visitor for AttrCollector {
+    fn on_tuple(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) {
+        let is_leaf = page.is_leaf();
+        // deforming is only needed to tell which of the attributes are NULL
+        let is_null = if tuple.has_nulls() {
+            Some(self.decoder.deform(tuple).1)
+        } else {
+            None
+        };
+        for (i, attr) in self.attrs.iter_mut().enumerate() {
+            let null = is_null.is_some_and(|is_null| is_null[i]);
+            if is_leaf {
+                attr.leaf_keys += 1;
+                if null {
+                    attr.leaf_nulls += 1;
                 }
-                let cblk = which.block_num();
-                self.stats_inner(level + 1, max_level, cblk, stats);
+            } else if null {
+                attr.internal_nulls += 1;
             }
         }
     }
 }
 
+/// Counts leaf tuples which are not marked as dead.
+struct LiveLeafCounter(u64);
+
+impl Visitor for LiveLeafCounter {
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, _tuple: &IndexTuple) {
+        if page.is_leaf() && TupleState::of(page.page, offset) != TupleState::Dead {
+            self.0 += 1;
+        }
+    }
+}
+
+struct OutlierCollector {
+    decoder: KeyDecoder,
+    top: TopK<KeyOutlier>,
+}
+
+impl Visitor for OutlierCollector {
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        let size = tuple.size();
+        // previews are only rendered for tuples which may make it to the result
+        if self.top.admits(|min| min.size < size) {
+            self.top.push(KeyOutlier {
+                size,
+                level: page.level,
+                block_num: page.block_num,
+                offset,
+                preview: self.decoder.preview(tuple, KEY_PREVIEW_LEN),
+            });
+        }
+    }
+}
+
+pub(crate) struct SignatureCollector {
+    pub(crate) decoder: KeyDecoder,
+    pub(crate) levels: Vec<SignatureLevelStats>,
+}
+
+impl SignatureCollector {
+    pub(crate) fn new(relation: Relation) -> Self {
+        SignatureCollector {
+            decoder: KeyDecoder::new(relation),
+            levels: Vec::new(),
+        }
+    }
+}
+
+impl Visitor for SignatureCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if self.levels.len() <= page.level {
+            self.levels
+                .resize_with(page.level + 1, SignatureLevelStats::default);
+        }
+        true
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) {
+        for signature in self.decoder.signatures(tuple) {
+            self.levels[page.level].add(&signature);
+        }
+    }
+}
+
+/// Gathers estimates from all internal pages and block numbers of all leaf pages, without
+/// reading the leaves themselves.
+struct LeafCollector {
+    leaf_level: usize,
+    estimate: Estimate,
+    leaves: Vec<BlockNumber>,
+}
+
+impl Visitor for LeafCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if page.is_leaf() {
+            // leaf found above the level of the leftmost leaf
+            self.estimate.imbalanced = true;
+        }
+        self.estimate.add_page(page.page);
+        true
+    }
+
+    fn descend(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) -> bool {
+        if page.level + 1 == self.leaf_level {
+            self.leaves.push(tuple.block_num());
+            false
+        } else {
+            true
+        }
+    }
+}
+
 impl Drop for IndexInspector {
     fn drop(&mut self) {
-        unsafe { index_close(self.relation, AccessExclusiveLock as i32) }
+        // release cached pages, which refer to the relation, before it's closed
+        self.cache.clear();
+        unsafe { index_close(self.relation, INDEX_LOCK_MODE as i32) }
+    }
+}
+
+/// Result of [IndexInspector::count_check].
+#[derive(Debug)]
+pub struct CountCheck {
+    /// Number of live leaf tuples found by walking the tree.
+    pub walker_count: u64,
+    /// Number of tuples returned by a full bitmap index scan.
+    pub scan_count: u64,
+}
+
+impl CountCheck {
+    pub fn is_consistent(&self) -> bool {
+        self.walker_count == self.scan_count
+    }
+}
+
+/// Returns the state of a line pointer: normal, dead, redirect or unused.
+pub(crate) fn item_state(iid: &ItemIdData) -> &'static str {
+    match iid.lp_flags() {
+        LP_NORMAL => "normal",
+        LP_DEAD => "dead",
+        LP_REDIRECT => "redirect",
+        _ => "unused",
     }
 }
 
-pub struct IndexTree(IndexTreeNode);
+/// A single item of a page reported by [IndexInspector::page_items].
+#[derive(Debug)]
+pub struct PageItem {
+    pub offset: OffsetNumber,
+    /// State of the line pointer: normal, dead, redirect or unused.
+    pub item_state: &'static str,
+    /// Length of the tuple, as stored in its line pointer.
+    pub item_len: usize,
+    /// Size of the tuple, as stored in its own header.
+    pub tuple_size: usize,
+    /// True if the tuple is stored on a leaf page, so its key is an actual entry rather than
+    /// a union of a subtree.
+    pub is_leaf_key: bool,
+    pub has_nulls: bool,
+    pub is_invalid: bool,
+    /// Item pointer stored in the tuple: heap tuple for leaf keys and child page for
+    /// internal ones.
+    pub tid: (BlockNumber, OffsetNumber),
+    /// Header kinds of variable-length key attributes.
+    pub varlena_kinds: Vec<Option<VarlenaKind>>,
+    pub key: Option<String>,
+}
+
+/// A single index tuple reported by [IndexInspector::key_outliers].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KeyOutlier {
+    /// Size of the index tuple in bytes.
+    pub size: usize,
+    pub level: usize,
+    pub block_num: BlockNumber,
+    pub offset: OffsetNumber,
+    /// Text representation of the key, truncated to a reasonable length.
+    pub preview: String,
+}
+
+/// Saturation statistics of signature-based keys on a single level of the tree.
+#[derive(Debug, Default)]
+pub struct SignatureLevelStats {
+    /// Number of keys storing exact values instead of signatures.
+    pub exact_keys: u64,
+    /// Number of keys storing signature bitmaps.
+    pub signature_keys: u64,
+    /// Number of signature keys with all bits set. Such keys match every query, making the
+    /// subtrees under them useless for filtering.
+    pub all_true_keys: u64,
+    /// Sum of densities of all signature keys.
+    density_sum: f64,
+}
+
+impl SignatureLevelStats {
+    /// Ratio of set bits above which a signature is considered to be saturated.
+    pub const SATURATION_THRESHOLD: f64 = 0.9;
+
+    fn add(&mut self, signature: &Signature) {
+        match signature.density() {
+            None => self.exact_keys += 1,
+            Some(density) => {
+                self.signature_keys += 1;
+                self.density_sum += density;
+                if *signature == Signature::AllTrue {
+                    self.all_true_keys += 1;
+                }
+            }
+        }
+    }
+
+    /// Average ratio of set bits in signature keys.
+    pub fn avg_density(&self) -> f64 {
+        if self.signature_keys == 0 {
+            0.0
+        } else {
+            self.density_sum / self.signature_keys as f64
+        }
+    }
+
+    /// Returns true if signatures on this level are so saturated, that they are no longer
+    /// useful for filtering.
+    pub fn is_saturated(&self) -> bool {
+        self.signature_keys > 0 && self.avg_density() >= Self::SATURATION_THRESHOLD
+    }
+
+    pub fn to_json(&self, level: usize) -> serde_json::Value {
+        json!({
+            "level": level,
+            "exact_keys": self.exact_keys,
+            "signature_keys": self.signature_keys,
+            "all_true_keys": self.all_true_keys,
+            "avg_density": self.avg_density(),
+            "saturated": self.is_saturated(),
+        })
+    }
+}
+
+/// NULL statistics of a single key attribute.
+#[derive(Debug, Default)]
+pub struct AttrStats {
+    /// Name of the index column.
+    pub name: String,
+    /// Number of leaf tuples.
+    pub leaf_keys: u64,
+    /// Number of leaf tuples in which this attribute is NULL.
+    pub leaf_nulls: u64,
+    /// Number of internal tuples in which this attribute is NULL, which means that the whole
+    /// subtree under them contains only NULLs for this attribute.
+    pub internal_nulls: u64,
+}
+
+impl AttrStats {
+    /// Percentage of leaf tuples in which this attribute is NULL.
+    pub fn null_pct(&self) -> f64 {
+        if self.leaf_keys == 0 {
+            0.0
+        } else {
+            self.leaf_nulls as f64 / self.leaf_keys as f64 * 100.0
+        }
+    }
+}
+
+/// Statistics of a single level of the index tree.
+#[derive(Debug, Default)]
+pub struct LevelStats {
+    pub num_pages: u64,
+    /// Sum of free space left on all pages of this level.
+    pub free_space: u64,
+    /// Number of tuples with at least one NULL key attribute.
+    pub null_tuples: u64,
+    /// Sizes of keys stored on internal pages of this level. Internal keys are unions of
+    /// the keys stored in their subtrees.
+    pub internal_keys: KeySizeStats,
+    /// Sizes of keys stored on leaf pages of this level.
+    pub leaf_keys: KeySizeStats,
+    /// Length of the longest chain of consecutive deleted or empty pages linked via
+    /// rightlinks on this level.
+    pub max_dead_chain: u64,
+}
+
+impl LevelStats {
+    pub fn to_json(&self, level: usize) -> serde_json::Value {
+        json!({
+            "level": level,
+            "pages": self.num_pages,
+            "free_bytes": self.free_space,
+            "null_tuples": self.null_tuples,
+            "internal_keys": self.internal_keys.to_json(),
+            "leaf_keys": self.leaf_keys.to_json(),
+            "max_dead_chain": self.max_dead_chain,
+        })
+    }
+}
+
+/// Aggregated sizes (in bytes) of index tuples.
+#[derive(Debug, Default)]
+pub struct KeySizeStats {
+    pub count: u64,
+    pub total: u64,
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+}
+
+impl KeySizeStats {
+    fn add(&mut self, size: usize) {
+        self.count += 1;
+        self.total += size as u64;
+        self.min = Some(self.min.map_or(size, |min| min.min(size)));
+        self.max = Some(self.max.map_or(size, |max| max.max(size)));
+    }
+
+    pub fn avg(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.total as f64 / self.count as f64)
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "count": self.count,
+            "avg_size": self.avg(),
+            "min_size": self.min,
+            "max_size": self.max,
+        })
+    }
+}
+
+pub struct IndexTree {
+    root: IndexTreeNode,
+    is_valid: bool,
+}
 
 struct IndexTreeNode {
     offset: OffsetNumber,
     max_offset: OffsetNumber,
     block_num: BlockNumber,
     free_space: usize,
+    /// Number of bytes available for tuples on this page, see [Page::capacity].
+    capacity: usize,
     right_link: Option<BlockNumber>,
+    items: Option<ItemsSummary>,
+    /// Fill factors of child pages, collected only when sparklines were requested.
+    child_fill: Option<Vec<f64>>,
     children: Option<Vec<IndexTreeNode>>,
 }
 
+/// Summary of line pointer states on a single page.
+#[derive(Debug, Default)]
+pub struct ItemsSummary {
+    pub normal: usize,
+    /// Line pointers of tuples marked as dead (killed by index scans), but not yet removed.
+    pub dead: usize,
+    pub unused: usize,
+    pub redirect: usize,
+    /// Total size of all tuples on a page, as stored in their line pointers.
+    pub total_bytes: usize,
+}
+
+impl ItemsSummary {
+    pub fn new(page: &Page) -> Self {
+        let mut items = ItemsSummary::default();
+        for i in page.offsets() {
+            let iid = page.item_id(i as usize);
+            match iid.lp_flags() {
+                LP_NORMAL => items.normal += 1,
+                LP_DEAD => items.dead += 1,
+                LP_REDIRECT => items.redirect += 1,
+                _ => items.unused += 1,
+            }
+            items.total_bytes += iid.lp_len() as usize;
+        }
+        items
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "normal": self.normal,
+            "dead": self.dead,
+            "unused": self.unused,
+            "redirect": self.redirect,
+            "total_bytes": self.total_bytes,
+        })
+    }
+}
+
 impl IndexTreeNode {
     fn new(
         max_offset: OffsetNumber,
         free_space: usize,
+        capacity: usize,
         offset: OffsetNumber,
         block_num: BlockNumber,
         right: BlockNumber,
@@ -137,6 +1395,7 @@ impl IndexTreeNode {
         IndexTreeNode {
             max_offset,
             free_space,
+            capacity,
             offset,
             block_num,
             right_link: if right == InvalidBlockNumber {
@@ -144,6 +1403,8 @@ impl IndexTreeNode {
             } else {
                 Some(right)
             },
+            items: None,
+            child_fill: None,
             children: if is_leaf { None } else { Some(Vec::new()) },
         }
     }
@@ -155,14 +1416,100 @@ impl IndexTreeNode {
     /// Returns a value from [0.0..1.0] which describes the percentage of space occupied by data
     /// inside of current page.
     fn occupied(&self) -> f64 {
-        (PAGE_SIZE as f64 - self.free_space as f64) / PAGE_SIZE as f64
+        occupancy(self.free_space, self.capacity)
     }
 
-    fn fmt(&self, f: &mut Formatter<'_>, level: usize) -> std::fmt::Result {
+    /// Returns JSON representation of the node and its descendants, as long as their serialized
+    /// size fits into the `budget`. Nodes whose children don't fit are marked as truncated,
+    /// which also sets the `truncated` flag.
+    fn to_json(
+        &self,
+        level: usize,
+        options: &JsonOptions,
+        budget: &mut usize,
+        truncated: &mut bool,
+    ) -> serde_json::Value {
+        let fields = vec![
+            ("offset", json!(self.offset)),
+            ("level", json!(level)),
+            ("blkno", json!(self.block_num)),
+            ("tuples", json!(self.max_offset)),
+            ("free", json!(self.free_space)),
+            (
+                "largest_insertable",
+                json!(largest_insertable(self.free_space)),
+            ),
+            ("occupied", json!(self.occupied())),
+            ("rightlink", json!(self.right_link)),
+            ("is_leaf", json!(self.is_leaf())),
+        ];
+        let mut node = serde_json::Map::new();
+        for (name, value) in fields {
+            if options.includes(name) {
+                node.insert(name.to_string(), value);
+            }
+        }
+        if let Some(items) = self.items.as_ref().filter(|_| options.includes("items")) {
+            node.insert("items".to_string(), items.to_json());
+        }
+        let mut node = serde_json::Value::Object(node);
+        *budget = budget.saturating_sub(node.to_string().len());
+        if let Some(children) = self.children.as_ref() {
+            let leaves = children.first().is_some_and(|c| c.is_leaf());
+            match options.leaves {
+                LeafOutput::Summary if leaves => {
+                    node["leaves"] = json!({
+                        "pages": children.len(),
+                        "tuples": children.iter().map(|c| c.max_offset as usize).sum::<usize>(),
+                        "free": children.iter().map(|c| c.free_space).sum::<usize>(),
+                    });
+                }
+                LeafOutput::None if leaves => {}
+                _ => {
+                    let mut nodes = Vec::with_capacity(children.len());
+                    for child in children.iter() {
+                        if *budget == 0 {
+                            node["truncated"] = json!(true);
+                            *truncated = true;
+                            break;
+                        }
+                        nodes.push(child.to_json(level + 1, options, budget, truncated));
+                    }
+                    node["children"] = serde_json::Value::Array(nodes);
+                }
+            }
+        }
+        node
+    }
+
+    fn fmt_markdown<W: Write>(&self, out: &mut W, level: usize) -> std::fmt::Result {
         writeln!(
-            f,
-            "{}{}(l:{}) blk: {} numTuple: {} free: {}B ({:.2}%) rightlink: {}",
-            format!("{:width$}", "", width = level * 4),
+            out,
+            "| {}{} | {} | {} | {:.2}% | {} |",
+            "&emsp;".repeat(level),
+            level,
+            self.block_num,
+            self.max_offset,
+            self.occupied() * 100.0,
+            match self.right_link {
+                None => "-".to_string(),
+                Some(blk) => blk.to_string(),
+            }
+        )?;
+        if let Some(children) = self.children.as_ref() {
+            for node in children.iter() {
+                node.fmt_markdown(out, level + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the line describing this page in the text report, without its sparkline.
+    fn write_line<W: Write>(&self, out: &mut W, level: usize) -> std::fmt::Result {
+        write!(
+            out,
+            "{:indent$}{}(l:{}) blk: {} numTuple: {} free: {}B ({:.2}%) rightlink: {}",
+            "",
             self.offset,
             level,
             self.block_num,
@@ -172,8 +1519,17 @@ impl IndexTreeNode {
             match self.right_link {
                 None => "Invalid Block".to_string(),
                 Some(blk) => blk.to_string(),
-            }
-        )?;
+            },
+            indent = level * 4
+        )
+    }
+
+    fn fmt(&self, f: &mut Formatter<'_>, level: usize) -> std::fmt::Result {
+        self.write_line(f, level)?;
+        if let Some(child_fill) = self.child_fill.as_ref() {
+            write!(f, " children: {}", sparkline(child_fill))?;
+        }
+        writeln!(f)?;
 
         if let Some(children) = self.children.as_ref() {
             for node in children.iter() {
@@ -185,25 +1541,300 @@ impl IndexTreeNode {
     }
 }
 
+/// Returns a value from [0.0..1.0] which describes the percentage of space occupied by data
+/// inside of a page with a given amount of free space and a total capacity.
+pub(crate) fn occupancy(free_space: usize, capacity: usize) -> f64 {
+    if capacity == 0 {
+        0.0
+    } else {
+        (capacity as f64 - free_space as f64) / capacity as f64
+    }
+}
+
+/// Returns the size of the largest tuple which can be added to a page with a given free space
+/// (as returned by `PageGetFreeSpace`) without splitting it. It's less than the free space, as
+/// GiST (`gistnospace`) counts the line pointer of a new tuple on top of the one already
+/// reserved by `PageGetFreeSpace`, and sizes of index tuples are always MAXALIGNed.
+pub(crate) fn largest_insertable(free_space: usize) -> usize {
+    let available = free_space.saturating_sub(size_of::<ItemIdData>());
+    available & !(MAXIMUM_ALIGNOF as usize - 1)
+}
+
+/// Renders a sequence of values from [0.0..1.0] as a sparkline, one character per value.
+fn sparkline(values: &[f64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    values
+        .iter()
+        .map(|v| BARS[(v.clamp(0.0, 1.0) * (BARS.len() - 1) as f64).round() as usize])
+        .collect()
+}
+
+/// Fields of a page in JSON output of the tree, which can be selected by [JsonOptions].
+pub const JSON_FIELDS: [&str; 10] = [
+    "offset",
+    "level",
+    "blkno",
+    "tuples",
+    "free",
+    "largest_insertable",
+    "occupied",
+    "rightlink",
+    "is_leaf",
+    "items",
+];
+
+/// How leaf pages are represented in JSON output of the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafOutput {
+    /// Every leaf page is a separate node.
+    Full,
+    /// Leaf pages are replaced by a single `leaves` object of their parent, holding the number
+    /// of pages, tuples and free space.
+    Summary,
+    /// Leaf pages are left out.
+    None,
+}
+
+/// Shape of JSON output of the tree, which lets huge indexes be exported compactly.
+#[derive(Debug, Clone)]
+pub struct JsonOptions {
+    /// Fields included in every page node, `None` means all of them. Nested pages are always
+    /// included under `children`.
+    pub fields: Option<HashSet<String>>,
+    /// Max level of pages to include, `None` means the whole tree.
+    pub max_depth: Option<usize>,
+    pub leaves: LeafOutput,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        JsonOptions {
+            fields: None,
+            max_depth: None,
+            leaves: LeafOutput::Full,
+        }
+    }
+}
+
+impl JsonOptions {
+    /// Parses options passed as a JSON object, ie. `{"fields": ["blkno", "free"], "max_depth": 3,
+    /// "leaves": "summary"}`. All of the fields are optional.
+    pub fn from_json(value: &serde_json::Value) -> Result<Self> {
+        let invalid = |msg: &str| Err(Error::InvalidArgument(msg.to_string()));
+        let object = value.as_object().ok_or_else(|| {
+            Error::InvalidArgument("tree JSON options must be a JSON object".to_string())
+        })?;
+        let mut options = JsonOptions::default();
+        for (key, value) in object.iter() {
+            match (key.as_str(), value) {
+                ("fields", serde_json::Value::Array(fields)) => {
+                    let mut selected = HashSet::new();
+                    for field in fields.iter() {
+                        match field.as_str() {
+                            Some(name) if JSON_FIELDS.contains(&name) => {
+                                selected.insert(name.to_string());
+                            }
+                            _ => {
+                                return invalid(&format!(
+                                    "unknown tree JSON field {}, expected one of: {}",
+                                    field,
+                                    JSON_FIELDS.join(", ")
+                                ))
+                            }
+                        }
+                    }
+                    options.fields = Some(selected);
+                }
+                ("max_depth", depth) => match depth.as_u64() {
+                    Some(depth) => options.max_depth = Some(depth as usize),
+                    None => return invalid("max_depth must be a non-negative integer"),
+                },
+                ("leaves", serde_json::Value::String(leaves)) => {
+                    options.leaves = match leaves.as_str() {
+                        "full" => LeafOutput::Full,
+                        "summary" => LeafOutput::Summary,
+                        "none" => LeafOutput::None,
+                        _ => return invalid("leaves must be one of: full, summary, none"),
+                    }
+                }
+                ("fields", _) => return invalid("fields must be an array of field names"),
+                ("leaves", _) => return invalid("leaves must be one of: full, summary, none"),
+                (key, _) => {
+                    return invalid(&format!(
+                        "unknown tree JSON option {}, expected one of: fields, max_depth, leaves",
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    /// Returns true if a given field should be included in page nodes.
+    pub fn includes(&self, field: &str) -> bool {
+        self.fields.as_ref().is_none_or(|f| f.contains(field))
+    }
+}
+
+impl IndexTree {
+    /// Returns JSON representation of the tree, where each page is represented by an object
+    /// with its child pages nested under `children` field.
+    #[cfg(any(test, feature = "pg_test"))]
+    pub fn to_json(&self) -> serde_json::Value {
+        self.to_json_capped(&JsonOptions::default(), usize::MAX).0
+    }
+
+    /// Same as [IndexTree::to_json], but stops adding pages once the serialized size of the
+    /// output reaches `limit` bytes. Returns true as the second value if any page was skipped.
+    /// Page nodes are shaped according to `options`, except for `max_depth`, which has to be
+    /// applied when the tree is built.
+    pub fn to_json_capped(&self, options: &JsonOptions, limit: usize) -> (serde_json::Value, bool) {
+        let (mut budget, mut truncated) = (limit, false);
+        let root = self.root.to_json(0, options, &mut budget, &mut truncated);
+        let mut tree = json!({
+            "is_valid": self.is_valid,
+            "root": root,
+        });
+        if truncated {
+            tree["truncated"] = json!(true);
+        }
+        (tree, truncated)
+    }
+
+    /// Same as [IndexTree::to_json_capped], but instead of one nested document returns an array
+    /// of independent documents, one per subtree rooted at `chunk_level`. Each of them refers
+    /// to the block of its parent page, while pages above `chunk_level` are not included. Trees
+    /// which are not that deep are returned as a single chunk rooted at their deepest level.
+    pub fn to_json_chunks_capped(
+        &self,
+        chunk_level: usize,
+        options: &JsonOptions,
+        limit: usize,
+    ) -> (serde_json::Value, bool) {
+        let (mut budget, mut truncated) = (limit, false);
+        let mut chunks = Vec::new();
+        // (parent block, node, level) of the subtrees left to visit, in depth-first order
+        let mut pending = vec![(None, &self.root, 0)];
+        while let Some((parent, node, level)) = pending.pop() {
+            match node.children.as_ref() {
+                Some(children) if level < chunk_level && !children.is_empty() => {
+                    for child in children.iter().rev() {
+                        pending.push((Some(node.block_num), child, level + 1));
+                    }
+                }
+                _ => {
+                    if budget == 0 {
+                        truncated = true;
+                        break;
+                    }
+                    let root = node.to_json(level, options, &mut budget, &mut truncated);
+                    chunks.push(json!({
+                        "is_valid": self.is_valid,
+                        "parent_blkno": parent,
+                        "root": root,
+                    }));
+                }
+            }
+        }
+        (serde_json::Value::Array(chunks), truncated)
+    }
+
+    /// Writes the tree as a GitHub-flavored markdown table with one row per page into a given
+    /// writer. Nesting of pages is shown by indentation of the level column.
+    pub fn write_markdown<W: Write>(&self, out: &mut W) -> std::fmt::Result {
+        if !self.is_valid {
+            out.write_str("> **WARNING:** index is marked as invalid\n\n")?;
+        }
+        out.write_str("| level | block | tuples | fill | rightlink |\n")?;
+        out.write_str("|:------|------:|-------:|-----:|----------:|\n")?;
+        self.root.fmt_markdown(out, 0)
+    }
+}
+
+/// Lines of the text report of the tree produced one page at a time, see
+/// [IndexInspector::tree_lines].
+pub struct TreeLines {
+    rows: TreeRows,
+    /// Warning about an invalid index, returned before any page.
+    header: Option<String>,
+}
+
+impl Iterator for TreeLines {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if let Some(header) = self.header.take() {
+            return Some(header);
+        }
+        let row = self.rows.next()?;
+        let node = IndexTreeNode::new(
+            row.tuples as OffsetNumber,
+            row.free_space,
+            row.capacity,
+            row.offset,
+            row.block_num,
+            row.right_link.unwrap_or(InvalidBlockNumber),
+            row.is_leaf,
+        );
+        let mut line = String::new();
+        node.write_line(&mut line, row.level).ok()?;
+        Some(line)
+    }
+}
+
+impl IndexInspector {
+    /// Returns the same text report as [IndexTree]'s `Display`, without sparklines, as an
+    /// iterator of lines. Pages are read as lines are requested, so the report is never
+    /// materialized as a whole.
+    pub fn tree_lines(self) -> TreeLines {
+        let header = if self.is_valid() {
+            None
+        } else {
+            Some("WARNING: index is marked as invalid".to_string())
+        };
+        TreeLines {
+            rows: self.into_tree_rows(),
+            header,
+        }
+    }
+}
+
 impl Display for IndexTree {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f, 0)
+        if !self.is_valid {
+            writeln!(f, "WARNING: index is marked as invalid")?;
+        }
+        self.root.fmt(f, 0)
     }
 }
 
 pub struct Stats {
+    /// False if index has been marked as invalid ie. after failed concurrent build.
+    pub is_valid: bool,
     /// Max level of depth of index tree.
     pub level: usize,
     /// How many pages consist on the current index.
-    pub num_pages: usize,
+    pub num_pages: u64,
     /// How many leaf pages consist on the current index.
-    pub num_leaf_pages: usize,
+    pub num_leaf_pages: u64,
+    /// How many pages of the main fork have never been initialized (are all zeros). They are
+    /// left by extending the relation, ie. by interrupted bulk operations, and are not reachable
+    /// from the root. Only counted by walks over the whole tree.
+    pub num_new_pages: u64,
     /// How many leaf tuples consist on the current index.
-    pub num_leaf_tuple: usize,
+    pub num_leaf_tuple: u64,
     /// How many tuples does current index has.
-    pub num_tuple: usize,
+    pub num_tuple: u64,
     /// How many tuples are invalid.
-    pub num_invalid_tuple: usize,
+    pub num_invalid_tuple: u64,
+    /// How many tuples on internal pages are invalid.
+    pub num_invalid_internal_tuple: u64,
+    /// How many tuples on leaf pages are invalid.
+    pub num_invalid_leaf_tuple: u64,
+    /// How many tuples are marked as dead, but were not yet removed by vacuum.
+    pub num_dead_tuple: u64,
+    /// How many tuples have at least one NULL key attribute.
+    pub num_null_tuple: u64,
     /// Total size of memory occupied by tuples in bytes.
     pub tuple_size: u64,
     /// Size of memory occupied by leaf tuples in bytes.
@@ -211,31 +1842,184 @@ pub struct Stats {
     /// Total size of an index (includes both total tuple_size
     /// and total free page space reserved for future use).  
     pub total_size: u64,
+    /// Sum of free space left on all visited pages of the main fork.
+    pub free_space: u64,
+    /// Sum of free space recorded in the free space map for all visited pages. For GiST it
+    /// only accounts for deleted pages available for reuse, so it's usually much smaller than
+    /// `free_space`.
+    pub fsm_free_space: u64,
+    /// Size of the free space map fork in bytes.
+    pub fsm_size: u64,
+    /// Size of the visibility map fork in bytes (indexes usually don't have one).
+    pub vm_size: u64,
+    /// Difference between the deepest and the shallowest leaf level reached under the root
+    /// downlinks. All leaves of a healthy GiST index are on the same level, so any value
+    /// other than 0 indicates corruption.
+    pub depth_imbalance: usize,
+    /// Variance of the number of pages in subtrees under the root downlinks.
+    pub subtree_size_variance: f64,
+    /// Length of the longest chain of consecutive deleted or empty pages linked via
+    /// rightlinks, on any level.
+    pub max_dead_chain: u64,
 }
 
 impl Default for Stats {
     fn default() -> Self {
         Stats {
+            is_valid: true,
             level: 0,
             num_pages: 0,
             num_leaf_pages: 0,
+            num_new_pages: 0,
             num_tuple: 0,
             num_invalid_tuple: 0,
+            num_invalid_internal_tuple: 0,
+            num_invalid_leaf_tuple: 0,
+            num_dead_tuple: 0,
+            num_null_tuple: 0,
             num_leaf_tuple: 0,
             tuple_size: 0,
             leaf_tuple_size: 0,
             total_size: 0,
+            free_space: 0,
+            fsm_free_space: 0,
+            fsm_size: 0,
+            vm_size: 0,
+            depth_imbalance: 0,
+            subtree_size_variance: 0.0,
+            max_dead_chain: 0,
         }
     }
 }
 
+impl Stats {
+    /// Accounts a single visited page into statistics. All the counters are 64-bit, so that
+    /// they don't overflow even for indexes with billions of pages.
+    pub fn add_page(
+        &mut self,
+        level: usize,
+        is_leaf: bool,
+        num_tuples: OffsetNumber,
+        free_space: u64,
+        capacity: u64,
+        fsm_free_space: u64,
+    ) {
+        let tuple_size = capacity.saturating_sub(free_space);
+
+        self.num_pages += 1;
+        self.tuple_size += tuple_size;
+        self.total_size += BLCKSZ as u64;
+        self.free_space += free_space;
+        self.fsm_free_space += fsm_free_space;
+        self.num_tuple += num_tuples as u64;
+        self.level = self.level.max(level);
+
+        if is_leaf {
+            self.num_leaf_pages += 1;
+            self.leaf_tuple_size += tuple_size;
+            self.num_leaf_tuple += num_tuples as u64;
+        }
+    }
+
+    /// Adds counters of another statistics to this one.
+    pub fn merge(&mut self, other: &Stats) {
+        self.level = self.level.max(other.level);
+        self.num_pages += other.num_pages;
+        self.num_leaf_pages += other.num_leaf_pages;
+        self.num_new_pages += other.num_new_pages;
+        self.num_leaf_tuple += other.num_leaf_tuple;
+        self.num_tuple += other.num_tuple;
+        self.num_invalid_tuple += other.num_invalid_tuple;
+        self.num_invalid_internal_tuple += other.num_invalid_internal_tuple;
+        self.num_invalid_leaf_tuple += other.num_invalid_leaf_tuple;
+        self.num_dead_tuple += other.num_dead_tuple;
+        self.num_null_tuple += other.num_null_tuple;
+        self.tuple_size += other.tuple_size;
+        self.leaf_tuple_size += other.leaf_tuple_size;
+        self.total_size += other.total_size;
+        self.free_space += other.free_space;
+        self.fsm_free_space += other.fsm_free_space;
+        self.max_dead_chain = self.max_dead_chain.max(other.max_dead_chain);
+    }
+
+    /// Reads counters from the JSON produced by [Stats::to_json]. Returns `None` if any of
+    /// them is missing.
+    pub fn from_json(value: &serde_json::Value) -> Option<Self> {
+        let get = |name: &str| value[name].as_u64();
+        Some(Stats {
+            is_valid: value["is_valid"].as_bool()?,
+            level: (get("levels")? as usize).saturating_sub(1),
+            num_pages: get("pages")?,
+            num_leaf_pages: get("leaf_pages")?,
+            num_leaf_tuple: get("leaf_tuples")?,
+            num_tuple: get("tuples")?,
+            num_invalid_tuple: get("invalid_tuples")?,
+            num_invalid_internal_tuple: get("invalid_internal_tuples")?,
+            num_invalid_leaf_tuple: get("invalid_leaf_tuples")?,
+            num_dead_tuple: get("dead_tuples")?,
+            num_null_tuple: get("null_tuples")?,
+            tuple_size: get("tuple_size")?,
+            leaf_tuple_size: get("leaf_tuple_size")?,
+            total_size: get("total_size")?,
+            free_space: get("free_space")?,
+            fsm_free_space: get("fsm_free_space")?,
+            max_dead_chain: get("max_dead_chain")?,
+            ..Stats::default()
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "is_valid": self.is_valid,
+            "levels": self.level + 1,
+            "pages": self.num_pages,
+            "leaf_pages": self.num_leaf_pages,
+            "new_pages": self.num_new_pages,
+            "tuples": self.num_tuple,
+            "invalid_tuples": self.num_invalid_tuple,
+            "invalid_internal_tuples": self.num_invalid_internal_tuple,
+            "invalid_leaf_tuples": self.num_invalid_leaf_tuple,
+            "dead_tuples": self.num_dead_tuple,
+            "null_tuples": self.num_null_tuple,
+            "leaf_tuples": self.num_leaf_tuple,
+            "tuple_size": self.tuple_size,
+            "leaf_tuple_size": self.leaf_tuple_size,
+            "total_size": self.total_size,
+            "free_space": self.free_space,
+            "fsm_free_space": self.fsm_free_space,
+            "fsm_size": self.fsm_size,
+            "vm_size": self.vm_size,
+            "depth_imbalance": self.depth_imbalance,
+            "subtree_size_variance": self.subtree_size_variance,
+            "max_dead_chain": self.max_dead_chain,
+        })
+    }
+}
+
 impl Display for Stats {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !self.is_valid {
+            writeln!(f, "WARNING: index is marked as invalid")?;
+        }
+        writeln!(f, "Index is valid:            {}", self.is_valid)?;
         writeln!(f, "Number of levels:          {}", self.level + 1)?;
         writeln!(f, "Number of pages:           {}", self.num_pages)?;
         writeln!(f, "Number of leaf pages:      {}", self.num_leaf_pages)?;
+        writeln!(f, "Number of new pages:       {}", self.num_new_pages)?;
         writeln!(f, "Number of tuples:          {}", self.num_tuple)?;
         writeln!(f, "Number of invalid tuples:  {}", self.num_invalid_tuple)?;
+        writeln!(
+            f,
+            "  on internal pages:       {}",
+            self.num_invalid_internal_tuple
+        )?;
+        writeln!(
+            f,
+            "  on leaf pages:           {}",
+            self.num_invalid_leaf_tuple
+        )?;
+        writeln!(f, "Number of dead tuples:     {}", self.num_dead_tuple)?;
+        writeln!(f, "Number of NULL tuples:     {}", self.num_null_tuple)?;
         writeln!(f, "Number of leaf tuples:     {}", self.num_leaf_tuple)?;
         writeln!(f, "Total size of tuples:      {} bytes", self.tuple_size)?;
         writeln!(
@@ -243,25 +2027,295 @@ impl Display for Stats {
             "Total size of leaf tuples: {} bytes",
             self.leaf_tuple_size
         )?;
-        writeln!(f, "Total size of index:       {} bytes", self.total_size)
+        writeln!(f, "Total size of index:       {} bytes", self.total_size)?;
+        writeln!(f, "Free space in main fork:   {} bytes", self.free_space)?;
+        writeln!(
+            f,
+            "Free space in FSM:         {} bytes",
+            self.fsm_free_space
+        )?;
+        writeln!(f, "Size of FSM fork:          {} bytes", self.fsm_size)?;
+        writeln!(f, "Size of VM fork:           {} bytes", self.vm_size)?;
+        writeln!(
+            f,
+            "Total size on disk:        {} bytes",
+            self.total_size + self.fsm_size + self.vm_size
+        )?;
+        writeln!(f, "Depth imbalance:           {}", self.depth_imbalance)?;
+        writeln!(
+            f,
+            "Subtree size variance:     {:.2}",
+            self.subtree_size_variance
+        )?;
+        writeln!(
+            f,
+            "Longest dead chain:        {} pages",
+            self.max_dead_chain
+        )?;
+        if self.depth_imbalance > 0 {
+            writeln!(
+                f,
+                "WARNING: leaves found at different depths, index is likely corrupted"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Partial counters gathered from pages visited by [IndexInspector::summary].
+#[derive(Debug, Default)]
+struct Estimate {
+    pages: u64,
+    /// Sum of capacities of all visited pages.
+    capacity: u64,
+    free_space: u64,
+    invalid_tuples: u64,
+    leaf_tuples: u64,
+    /// Space occupied by tuples (including their line pointers) on leaf pages.
+    leaf_used: u64,
+    internal_tuples: u64,
+    /// Space occupied by tuples (including their line pointers) on internal pages.
+    internal_used: u64,
+    /// True if leaf pages have been found on different levels.
+    imbalanced: bool,
+}
+
+impl Estimate {
+    fn add_page(&mut self, page: &Page) {
+        let used = (page.capacity() as u64).saturating_sub(page.free_space() as u64);
+        self.pages += 1;
+        self.capacity += page.capacity() as u64;
+        self.free_space += page.free_space() as u64;
+        if GistPage::new(page).is_leaf() {
+            self.leaf_tuples += page.max_offset() as u64;
+            self.leaf_used += used;
+        } else {
+            self.internal_tuples += page.max_offset() as u64;
+            self.internal_used += used;
+        }
+        for (_, tuple) in page.tuples() {
+            if tuple.is_invalid() {
+                self.invalid_tuples += 1;
+            }
+        }
+    }
+}
+
+/// Short, estimated summary of the index health returned by [IndexInspector::summary].
+#[derive(Debug)]
+pub struct Summary {
+    /// False if index has been marked as invalid ie. after failed concurrent build.
+    pub is_valid: bool,
+    /// Number of levels of the index tree.
+    pub levels: usize,
+    /// Number of pages reachable from the root.
+    pub pages: u64,
+    /// Number of leaf pages, which is exact even for sampled summaries, since it's the number
+    /// of downlinks of the level above.
+    pub leaf_pages: u64,
+    /// Percentage of page space which is not occupied by tuples.
+    pub bloat_pct: f64,
+    /// Estimated number of invalid tuples.
+    pub invalid_tuples: u64,
+    /// Score from [0..100], where 100 means a perfectly packed index without invalid tuples.
+    /// It's equal to the percentage of occupied space, halved when any invalid tuples exist
+    /// and halved again when leaves are found at different depths. Invalid indexes always
+    /// score 0.
+    pub health_score: f64,
+    /// Number of pages of a perfectly packed tree holding the same tuples, with pages filled
+    /// up to the fillfactor.
+    pub optimal_pages: u64,
+    /// Number of levels of a perfectly packed tree holding the same tuples.
+    pub optimal_levels: usize,
+    /// Ratio of the actual to the optimal number of pages. 1.0 means a perfectly packed index,
+    /// the higher the value, the more the index has degraded.
+    pub pages_vs_optimal: f64,
+    /// Length of the longest chain of consecutive deleted or empty pages. Only known when the
+    /// summary was computed from exact statistics.
+    pub max_dead_chain: Option<u64>,
+    /// Suggested maintenance actions.
+    pub recommendations: Vec<String>,
+}
+
+impl Summary {
+    /// Length of a chain of dead pages above which maintenance is recommended.
+    pub const DEAD_CHAIN_THRESHOLD: u64 = 8;
+
+    /// Computes the summary from exact statistics of a fully walked index, rather than from
+    /// estimates.
+    pub fn from_stats(stats: &Stats, fillfactor: u32) -> Self {
+        let estimate = Estimate {
+            pages: stats.num_pages,
+            capacity: stats.tuple_size + stats.free_space,
+            free_space: stats.free_space,
+            invalid_tuples: stats.num_invalid_tuple,
+            leaf_tuples: stats.num_leaf_tuple,
+            leaf_used: stats.leaf_tuple_size,
+            internal_tuples: stats.num_tuple - stats.num_leaf_tuple,
+            internal_used: stats.tuple_size - stats.leaf_tuple_size,
+            imbalanced: stats.depth_imbalance > 0,
+        };
+        let mut summary = Summary::new(stats.level + 1, &estimate, stats.is_valid, fillfactor);
+        summary.leaf_pages = stats.num_leaf_pages;
+        summary.max_dead_chain = Some(stats.max_dead_chain);
+        if stats.max_dead_chain > Self::DEAD_CHAIN_THRESHOLD {
+            summary.recommendations.push(format!(
+                "rightlinks lead through up to {} consecutive deleted or empty pages: VACUUM the table to recycle them or REINDEX the index",
+                stats.max_dead_chain
+            ));
+        }
+        summary
+    }
+
+    /// Classifies the index as `healthy`, `degraded` (some maintenance is recommended or the
+    /// health score is below 80) or `critical` (invalid or scoring below 50).
+    pub fn verdict(&self) -> &'static str {
+        if !self.is_valid || self.health_score < 50.0 {
+            "critical"
+        } else if self.health_score < 80.0 || !self.recommendations.is_empty() {
+            "degraded"
+        } else {
+            "healthy"
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "is_valid": self.is_valid,
+            "levels": self.levels,
+            "pages": self.pages,
+            "leaf_pages": self.leaf_pages,
+            "bloat_pct": self.bloat_pct,
+            "invalid_tuples": self.invalid_tuples,
+            "health_score": self.health_score,
+            "optimal_pages": self.optimal_pages,
+            "optimal_levels": self.optimal_levels,
+            "pages_vs_optimal": self.pages_vs_optimal,
+            "max_dead_chain": self.max_dead_chain,
+            "recommendations": self.recommendations,
+            "verdict": self.verdict(),
+        })
+    }
+
+    fn new(levels: usize, estimate: &Estimate, is_valid: bool, fillfactor: u32) -> Self {
+        let capacity = estimate.capacity as f64;
+        let bloat_pct = if capacity == 0.0 {
+            0.0
+        } else {
+            (estimate.free_space as f64 / capacity * 100.0).min(100.0)
+        };
+        let mut health_score = 100.0 - bloat_pct;
+        if estimate.invalid_tuples > 0 {
+            health_score /= 2.0;
+        }
+        if estimate.imbalanced {
+            health_score /= 2.0;
+        }
+        if !is_valid {
+            health_score = 0.0;
+        }
+        let (optimal_pages, optimal_levels) = optimal_tree(estimate, fillfactor);
+        Summary {
+            is_valid,
+            levels,
+            pages: estimate.pages,
+            leaf_pages: 0,
+            bloat_pct,
+            invalid_tuples: estimate.invalid_tuples,
+            health_score,
+            optimal_pages,
+            optimal_levels,
+            pages_vs_optimal: estimate.pages.max(1) as f64 / optimal_pages as f64,
+            max_dead_chain: None,
+            recommendations: Vec::new(),
+        }
     }
 }
 
-struct GistPage<'a> {
+/// Returns the number of pages and levels of a tree holding estimated tuples, if all of its
+/// pages were filled up to a given fillfactor.
+fn optimal_tree(estimate: &Estimate, fillfactor: u32) -> (u64, usize) {
+    if estimate.pages == 0 || estimate.leaf_tuples == 0 {
+        return (1, 1);
+    }
+    let page_space = estimate.capacity as f64 / estimate.pages as f64 * fillfactor as f64 / 100.0;
+    let mut pages = (estimate.leaf_used as f64 / page_space).ceil().max(1.0) as u64;
+    let fanout = if estimate.internal_tuples == 0 {
+        2.0
+    } else {
+        let internal_tuple = estimate.internal_used as f64 / estimate.internal_tuples as f64;
+        (page_space / internal_tuple).floor().max(2.0)
+    };
+    let (mut total, mut levels) = (pages, 1);
+    while pages > 1 {
+        pages = (pages as f64 / fanout).ceil() as u64;
+        total += pages;
+        levels += 1;
+    }
+    (total, levels)
+}
+
+pub struct GistPage<'a> {
     opaque: &'a GISTPageOpaqueData,
 }
 
 impl<'a> GistPage<'a> {
-    fn new(page: &'a Page) -> Self {
+    pub fn new(page: &'a Page) -> Self {
         let opaque = page.as_special();
         GistPage { opaque }
     }
 
-    fn is_leaf(&self) -> bool {
-        self.opaque.flags as u32 == F_LEAF
+    pub fn is_leaf(&self) -> bool {
+        self.opaque.flags as u32 & F_LEAF != 0
+    }
+
+    /// Returns the NSN of the page: LSN of the split of this page which moved some of its
+    /// tuples to its right sibling. Readers which got to this page from a parent older than
+    /// this must follow the rightlink to not miss those tuples.
+    pub fn nsn(&self) -> u64 {
+        let nsn = self.opaque.nsn;
+        ((nsn.xlogid as u64) << 32) | nsn.xrecoff as u64
     }
 
-    fn right_link(&self) -> BlockNumber {
+    pub fn right_link(&self) -> BlockNumber {
         self.opaque.rightlink
     }
+
+    /// Returns raw GiST page flags.
+    pub fn flags(&self) -> u16 {
+        self.opaque.flags
+    }
+
+    /// Returns names of the flags set on the page.
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        const NAMES: [(u32, &str); 5] = [
+            (F_LEAF, "leaf"),
+            (F_DELETED, "deleted"),
+            (F_TUPLES_DELETED, "tuples_deleted"),
+            (F_FOLLOW_RIGHT, "follow_right"),
+            (F_HAS_GARBAGE, "has_garbage"),
+        ];
+        NAMES
+            .iter()
+            .filter(|(flag, _)| self.opaque.flags as u32 & flag != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// Returns the identifier stored in the special space, which is `GIST_PAGE_ID` for all
+    /// GiST pages.
+    pub fn page_id(&self) -> u16 {
+        self.opaque.gist_page_id
+    }
+
+    /// Returns true if page was deleted by VACUUM and is waiting to be recycled.
+    pub fn is_deleted(&self) -> bool {
+        self.opaque.flags as u32 & F_DELETED != 0
+    }
+
+    /// Returns true if page was split, but the downlink to its new right sibling hasn't been
+    /// inserted into the parent yet.
+    pub fn is_follow_right(&self) -> bool {
+        self.opaque.flags as u32 & F_FOLLOW_RIGHT != 0
+    }
 }
@@ -0,0 +1,155 @@
+use crate::error::{raise, ErrorClass};
+use crate::gist::IndexInspector;
+use crate::walker::check_for_interrupts;
+use pgx::pg_sys::BlockNumber;
+
+/// Max number of buckets of a growth history.
+pub const MAX_BUCKETS: usize = 1000;
+
+/// LSN set on pages written by an index build which is not WAL-logged page by page, see
+/// `GistBuildLSN` of Postgres. Like 0, it doesn't tell when a page was written.
+const BUILD_LSN: u64 = 1;
+
+/// Returns an LSN in the usual `XXX/XXX` notation of `pg_lsn`.
+pub(crate) fn format_lsn(lsn: u64) -> String {
+    format!("{:X}/{:X}", lsn >> 32, lsn as u32)
+}
+
+/// Growth phase inferred for a bucket of a [GrowthHistory].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowthPhase {
+    /// Pages were modified, but the index didn't grow.
+    Idle,
+    /// At least half of the index has been written at once, like by `CREATE INDEX`.
+    BulkBuild,
+    /// The index grew more than twice as fast as on average.
+    Burst,
+    /// The index grew at most twice as fast as on average.
+    Trickle,
+}
+
+impl GrowthPhase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GrowthPhase::Idle => "idle",
+            GrowthPhase::BulkBuild => "bulk build",
+            GrowthPhase::Burst => "burst",
+            GrowthPhase::Trickle => "trickle",
+        }
+    }
+}
+
+/// Pages last modified within a range of WAL positions, see [IndexInspector::growth_history].
+#[derive(Debug)]
+pub struct GrowthBucket {
+    pub lsn_from: u64,
+    /// Last LSN of the range (inclusive).
+    pub lsn_to: u64,
+    /// Number of pages last modified within the range.
+    pub pages: u64,
+    /// Number of blocks the index has been extended with within the range, inferred from
+    /// pages stored past all pages modified before.
+    pub extended: u64,
+    /// Smallest possible size of the index in blocks at the end of the range.
+    pub blocks: BlockNumber,
+    pub phase: GrowthPhase,
+}
+
+/// Result of [IndexInspector::growth_history].
+#[derive(Debug, Default)]
+pub struct GrowthHistory {
+    /// Buckets of equal LSN ranges, from the oldest one.
+    pub buckets: Vec<GrowthBucket>,
+    /// Number of pages skipped because their LSN doesn't tell when they were written: new
+    /// pages and pages of indexes which are not WAL-logged.
+    pub skipped: u64,
+}
+
+impl IndexInspector {
+    /// Infers how the index grew over time from LSNs of its pages, with WAL positions standing
+    /// in for time. Pages are ordered by LSN and split into (up to) `buckets` equal LSN ranges.
+    /// Since relations are extended one block after another, a page stored past all pages
+    /// modified before it must have been added to the index by then, so the highest block
+    /// seen so far tells the smallest size the index could have had at the end of each range.
+    ///
+    /// This is only a lower bound: LSN of a page is the one of its last modification, so pages
+    /// modified again later are accounted for in a later range, and blocks of deleted pages
+    /// reused by new ones don't show up as growth at all.
+    pub fn growth_history(&self, buckets: usize) -> GrowthHistory {
+        if buckets == 0 || buckets > MAX_BUCKETS {
+            raise(
+                ErrorClass::InvalidArgument,
+                &format!(
+                    "buckets must be between 1 and {}, got {}",
+                    MAX_BUCKETS, buckets
+                ),
+            );
+        }
+        let num_blocks = self.num_blocks();
+        let mut history = GrowthHistory::default();
+        let mut pages: Vec<(u64, BlockNumber)> = Vec::with_capacity(num_blocks as usize);
+        for blk in 0..num_blocks {
+            check_for_interrupts!();
+            let page = self.cache.get(blk);
+            let lsn = page.lsn();
+            if page.is_new() || lsn <= BUILD_LSN {
+                history.skipped += 1;
+            } else {
+                pages.push((lsn, blk));
+            }
+        }
+        pages.sort_unstable();
+        let (min, max) = match (pages.first(), pages.last()) {
+            (Some(&(min, _)), Some(&(max, _))) => (min, max),
+            _ => return history,
+        };
+        let buckets = (buckets as u64).min(max - min + 1);
+        let width = (max - min) / buckets + 1;
+
+        let mut blocks = 0;
+        let mut pages = pages.into_iter().peekable();
+        for i in 0..buckets {
+            let lsn_from = min + i * width;
+            let lsn_to = if i + 1 == buckets {
+                max
+            } else {
+                lsn_from + width - 1
+            };
+            let mut bucket = GrowthBucket {
+                lsn_from,
+                lsn_to,
+                pages: 0,
+                extended: 0,
+                blocks,
+                phase: GrowthPhase::Idle,
+            };
+            while let Some(&(lsn, blk)) = pages.peek() {
+                if lsn > lsn_to {
+                    break;
+                }
+                pages.next();
+                bucket.pages += 1;
+                if blk >= bucket.blocks {
+                    bucket.extended += (blk + 1 - bucket.blocks) as u64;
+                    bucket.blocks = blk + 1;
+                }
+            }
+            blocks = bucket.blocks;
+            history.buckets.push(bucket);
+        }
+
+        let mean = blocks as f64 / history.buckets.len() as f64;
+        for bucket in history.buckets.iter_mut() {
+            bucket.phase = if bucket.extended == 0 {
+                GrowthPhase::Idle
+            } else if bucket.extended * 2 >= blocks as u64 {
+                GrowthPhase::BulkBuild
+            } else if bucket.extended as f64 > 2.0 * mean {
+                GrowthPhase::Burst
+            } else {
+                GrowthPhase::Trickle
+            };
+        }
+        history
+    }
+}
@@ -0,0 +1,98 @@
+use pgx::*;
+
+/// When enabled, page occupancy is computed against the legacy page size (see
+/// [crate::legacy_page_size]), which ignores space reserved for the GiST special
+/// area. This makes reported numbers comparable with the ones produced by older versions of
+/// gevel.
+pub static LEGACY_OCCUPANCY: GucSetting<bool> = GucSetting::new(false);
+
+/// Max time (in milliseconds) gevel functions try to lock an inspected index for. 0 means
+/// waiting in the lock queue without a timeout.
+pub static LOCK_TIMEOUT_MS: GucSetting<i32> = GucSetting::new(5000);
+
+/// Max size (in bytes) of a single text or JSON output, above which it's truncated. Stays
+/// safely below the 1GB limit of a single value.
+pub static MAX_OUTPUT_BYTES: GucSetting<i32> = GucSetting::new(1_000_000_000);
+
+/// When enabled, EXPLAIN output of plans scanning GiST indexes is followed by a summary of
+/// shape of each of these indexes.
+#[cfg(not(feature = "testing"))]
+pub static EXPLAIN_ANNOTATIONS: GucSetting<bool> = GucSetting::new(false);
+
+/// When enabled, scans of GiST indexes are recorded in `gevel.scan_stats`. Only has an effect
+/// when gevel is loaded via `shared_preload_libraries`.
+#[cfg(not(feature = "testing"))]
+pub static TRACK_SCANS: GucSetting<bool> = GucSetting::new(true);
+
+/// Bloat percentage of an index above which `gevel.scan_all_databases` sends an alert on the
+/// `gevel_alerts` channel. Negative values disable the check.
+#[cfg(not(feature = "testing"))]
+pub static ALERT_MAX_BLOAT_PCT: GucSetting<f64> = GucSetting::new(-1.0);
+
+/// Number of invalid tuples of an index above which `gevel.scan_all_databases` sends an alert on
+/// the `gevel_alerts` channel. Negative values disable the check.
+#[cfg(not(feature = "testing"))]
+pub static ALERT_MAX_INVALID_TUPLES: GucSetting<i32> = GucSetting::new(-1);
+
+/// Registers all gevel configuration parameters. Left out of `testing` builds: extensions which
+/// link gevel as a library don't run its `_PG_init`, so they see default values.
+#[cfg(not(feature = "testing"))]
+pub fn init() {
+    GucRegistry::define_bool_guc(
+        "gevel.legacy_occupancy",
+        "Compute page occupancy the way older gevel versions did.",
+        "When enabled, page fill factors and tuple sizes are computed against a fixed page size which ignores the special space of GiST pages, so that a completely full page is never reported as 100% occupied.",
+        &LEGACY_OCCUPANCY,
+        GucContext::Userset,
+    );
+    GucRegistry::define_int_guc(
+        "gevel.lock_timeout_ms",
+        "Max time in milliseconds gevel functions try to lock an inspected index for.",
+        "Locks are only taken if they can be granted immediately, and attempts are retried until the timeout passes, so that inspection never waits in the lock queue blocking DDL behind it. 0 waits in the lock queue without a timeout.",
+        &LOCK_TIMEOUT_MS,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+    );
+    GucRegistry::define_int_guc(
+        "gevel.max_output_bytes",
+        "Max size in bytes of a single text or JSON output of gevel functions.",
+        "Outputs which would exceed it are truncated with an explicit marker and a NOTICE, instead of failing on the 1GB limit of a single value after the whole index has been traversed.",
+        &MAX_OUTPUT_BYTES,
+        1024,
+        1_000_000_000,
+        GucContext::Userset,
+    );
+    GucRegistry::define_bool_guc(
+        "gevel.explain_annotations",
+        "Append a summary of shape of scanned GiST indexes to EXPLAIN output.",
+        "For every GiST index scanned by the explained plan, its number of levels, leaf pages and estimated bloat are reported after the plan. Leaf pages are sampled the same way gist_summary does, so it adds a bounded cost to every EXPLAIN.",
+        &EXPLAIN_ANNOTATIONS,
+        GucContext::Userset,
+    );
+    GucRegistry::define_bool_guc(
+        "gevel.track_scans",
+        "Record the number of pages visited by scans of GiST indexes.",
+        "Index scan counters are compared before and after every executed query, and the differences are accumulated per index in shared memory, queryable via gevel.scan_stats. Requires gevel to be loaded via shared_preload_libraries.",
+        &TRACK_SCANS,
+        GucContext::Suset,
+    );
+    GucRegistry::define_float_guc(
+        "gevel.alert_max_bloat_pct",
+        "Bloat percentage of a GiST index above which an alert is sent.",
+        "Every index summarized by gevel.scan_all_databases whose estimated bloat exceeds it is reported with a NOTIFY on the gevel_alerts channel, sent when the scanning transaction commits. -1 disables the check.",
+        &ALERT_MAX_BLOAT_PCT,
+        -1.0,
+        100.0,
+        GucContext::Userset,
+    );
+    GucRegistry::define_int_guc(
+        "gevel.alert_max_invalid_tuples",
+        "Number of invalid tuples of a GiST index above which an alert is sent.",
+        "Every index summarized by gevel.scan_all_databases with more invalid tuples, left behind by crashes of pre-9.1 servers, is reported with a NOTIFY on the gevel_alerts channel, sent when the scanning transaction commits. -1 disables the check.",
+        &ALERT_MAX_INVALID_TUPLES,
+        -1,
+        i32::MAX,
+        GucContext::Userset,
+    );
+}
@@ -0,0 +1,128 @@
+use crate::error::{raise, ErrorClass};
+use crate::gist::IndexInspector;
+use crate::walker::{PageVisit, Visitor};
+use crate::Page;
+use memoffset::offset_of;
+use pgx::pg_sys::{BlockNumber, PageHeaderData};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// 64-bit FNV-1a hash. Unlike hashers of the standard library, its output is guaranteed to
+/// stay the same across versions, so hashes can be stored and compared later on.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, &b| (h ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Hashes contents of a page: its line pointers, tuples and special space. The rest of the page
+/// header is skipped, since its LSN and checksum change without the contents being modified
+/// (ie. when hint bits are set), and so is the unused space between line pointers and tuples.
+pub fn page_hash(page: &Page) -> u64 {
+    let bytes = page.as_bytes();
+    let header = page.header();
+    let lower = (header.pd_lower as usize).min(bytes.len());
+    let upper = (header.pd_upper as usize).min(bytes.len());
+    let linp_start = offset_of!(PageHeaderData, pd_linp).min(lower);
+    let hash = fnv1a(FNV_OFFSET_BASIS, &bytes[linp_start..lower]);
+    fnv1a(hash, &bytes[upper.max(lower)..])
+}
+
+/// Content hash of a single page reachable from the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageHash {
+    pub block_num: BlockNumber,
+    pub hash: u64,
+}
+
+#[derive(Default)]
+struct HashCollector {
+    hashes: Vec<PageHash>,
+}
+
+impl Visitor for HashCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        self.hashes.push(PageHash {
+            block_num: page.block_num,
+            hash: page_hash(page.page),
+        });
+        true
+    }
+}
+
+/// Page whose content hash differs between two sets of hashes, see [compare_hashes].
+#[derive(Debug, PartialEq, Eq)]
+pub struct HashChange {
+    pub block_num: BlockNumber,
+    /// One of: `changed`, `added` (page became reachable) or `removed` (page is no longer
+    /// reachable).
+    pub change: &'static str,
+}
+
+/// Converts page hashes into a compact JSON object mapping block numbers to hex-encoded
+/// hashes, which can be stored and later compared with [compare_hashes].
+pub fn hashes_to_json(hashes: &[PageHash]) -> serde_json::Value {
+    let map: serde_json::Map<_, _> = hashes
+        .iter()
+        .map(|h| (h.block_num.to_string(), json!(format!("{:016x}", h.hash))))
+        .collect();
+    serde_json::Value::Object(map)
+}
+
+fn parse_hashes(value: &serde_json::Value) -> BTreeMap<BlockNumber, &str> {
+    let invalid = || -> ! {
+        raise(
+            ErrorClass::InvalidArgument,
+            "page hashes must be a JSON object mapping block numbers to hashes, as returned by gist_page_hashes",
+        )
+    };
+    let map = value.as_object().unwrap_or_else(|| invalid());
+    map.iter()
+        .map(
+            |(blk, hash)| match (blk.parse::<BlockNumber>(), hash.as_str()) {
+                (Ok(blk), Some(hash)) => (blk, hash),
+                _ => invalid(),
+            },
+        )
+        .collect()
+}
+
+/// Compares two sets of page hashes returned by [hashes_to_json] and returns pages which
+/// differ, ordered by block number.
+pub fn compare_hashes(before: &serde_json::Value, after: &serde_json::Value) -> Vec<HashChange> {
+    let (before, after) = (parse_hashes(before), parse_hashes(after));
+    let mut changes = Vec::new();
+    for (&block_num, hash) in before.iter() {
+        match after.get(&block_num) {
+            None => changes.push(HashChange {
+                block_num,
+                change: "removed",
+            }),
+            Some(h) if h != hash => changes.push(HashChange {
+                block_num,
+                change: "changed",
+            }),
+            Some(_) => {}
+        }
+    }
+    for &block_num in after.keys().filter(|blk| !before.contains_key(blk)) {
+        changes.push(HashChange {
+            block_num,
+            change: "added",
+        });
+    }
+    changes.sort_by_key(|c| c.block_num);
+    changes
+}
+
+impl IndexInspector {
+    /// Returns content hashes of all pages reachable from the root, see [page_hash].
+    pub fn page_hashes(&self) -> Vec<PageHash> {
+        let mut collector = HashCollector::default();
+        self.walk(&mut collector);
+        collector.hashes
+    }
+}
@@ -0,0 +1,243 @@
+use crate::error::{raise, ErrorClass};
+use crate::gist::IndexInspector;
+use crate::key::KeyDecoder;
+use crate::sys::TupleDescAttr;
+use crate::walker::{PageVisit, Visitor};
+use crate::{IndexTuple, GIST_ROOT_BLKNO};
+use pgx::pg_sys::{format_type_be, Datum, OffsetNumber, Relation};
+use serde_json::json;
+use std::ffi::CStr;
+
+/// Max number of cells along each axis of a heatmap.
+pub const MAX_GRID: usize = 1000;
+
+/// Layouts of bounding box keys which can be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BoxFormat {
+    /// Core `box`, used as a key by `box_ops`, `point_ops`, `poly_ops` and `circle_ops`: upper
+    /// right and lower left corners as 4 doubles.
+    Box,
+    /// PostGIS `box2df`, used as a key by `gist_geometry_ops_2d`: xmin, xmax, ymin and ymax as
+    /// 4 floats.
+    Box2df,
+}
+
+/// Returns the name of the type of keys stored for a given (0-based) attribute.
+fn key_type_name(relation: Relation, attno: usize) -> String {
+    unsafe {
+        let attr = &*TupleDescAttr((*relation).rd_att, attno);
+        CStr::from_ptr(format_type_be(attr.atttypid))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+impl BoxFormat {
+    fn from_type_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(BoxFormat::Box),
+            "box2df" => Some(BoxFormat::Box2df),
+            _ => None,
+        }
+    }
+
+    /// Returns the layout of keys of a given (0-based) attribute, `None` if they are not
+    /// bounding boxes.
+    pub(crate) fn of_attribute(relation: Relation, attno: usize) -> Option<Self> {
+        let type_name = key_type_name(relation, attno);
+        // skip schema qualification if any
+        let name = type_name.rsplit('.').next().unwrap_or_default();
+        BoxFormat::from_type_name(name)
+    }
+
+    /// Decodes a bounding box key passed by reference.
+    pub(crate) fn decode(&self, datum: Datum) -> BoundingBox {
+        unsafe {
+            match self {
+                BoxFormat::Box => {
+                    let v = std::slice::from_raw_parts(datum as *const f64, 4);
+                    BoundingBox {
+                        xmin: v[2],
+                        ymin: v[3],
+                        xmax: v[0],
+                        ymax: v[1],
+                    }
+                }
+                BoxFormat::Box2df => {
+                    let v = std::slice::from_raw_parts(datum as *const f32, 4);
+                    BoundingBox {
+                        xmin: v[0] as f64,
+                        ymin: v[2] as f64,
+                        xmax: v[1] as f64,
+                        ymax: v[3] as f64,
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+}
+
+impl BoundingBox {
+    fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            xmin: self.xmin.min(other.xmin),
+            ymin: self.ymin.min(other.ymin),
+            xmax: self.xmax.max(other.xmax),
+            ymax: self.ymax.max(other.ymax),
+        }
+    }
+
+    fn centroid(&self) -> (f64, f64) {
+        ((self.xmin + self.xmax) / 2.0, (self.ymin + self.ymax) / 2.0)
+    }
+
+    /// Renders the box as WKT. Boxes degenerated to a point (ie. leaf keys of `point_ops`) or
+    /// a segment are rendered as such, since WKT polygons must have a non-zero area.
+    pub fn to_wkt(self) -> String {
+        let (width, height) = (self.xmax - self.xmin, self.ymax - self.ymin);
+        if width == 0.0 && height == 0.0 {
+            format!("POINT({} {})", self.xmin, self.ymin)
+        } else if width == 0.0 || height == 0.0 {
+            format!(
+                "LINESTRING({} {}, {} {})",
+                self.xmin, self.ymin, self.xmax, self.ymax
+            )
+        } else {
+            format!(
+                "POLYGON(({xmin} {ymin}, {xmax} {ymin}, {xmax} {ymax}, {xmin} {ymax}, {xmin} {ymin}))",
+                xmin = self.xmin,
+                ymin = self.ymin,
+                xmax = self.xmax,
+                ymax = self.ymax
+            )
+        }
+    }
+}
+
+/// Number of leaf keys whose centroids fall into each cell of a `grid`×`grid` matrix spanning
+/// the extent of the index.
+#[derive(Debug)]
+pub struct Heatmap {
+    /// Extent of the indexed data, `None` for empty indexes.
+    pub extent: Option<BoundingBox>,
+    pub grid: usize,
+    /// Rows of cells, starting from the one at `ymin`. Every row starts from the cell at `xmin`.
+    pub cells: Vec<Vec<u64>>,
+}
+
+impl Heatmap {
+    fn new(extent: Option<BoundingBox>, grid: usize) -> Self {
+        Heatmap {
+            extent,
+            grid,
+            cells: vec![vec![0; grid]; grid],
+        }
+    }
+
+    /// Returns position of a given coordinate within a range split into `grid` cells.
+    fn cell(&self, value: f64, min: f64, max: f64) -> usize {
+        if max <= min || !value.is_finite() {
+            return 0;
+        }
+        let cell = ((value - min) / (max - min) * self.grid as f64) as usize;
+        cell.min(self.grid - 1)
+    }
+
+    fn add(&mut self, key: &BoundingBox) {
+        if let Some(extent) = self.extent {
+            let (x, y) = key.centroid();
+            let column = self.cell(x, extent.xmin, extent.xmax);
+            let row = self.cell(y, extent.ymin, extent.ymax);
+            self.cells[row][column] += 1;
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "grid": self.grid,
+            "extent": self.extent.map(|e| json!({
+                "xmin": e.xmin,
+                "ymin": e.ymin,
+                "xmax": e.xmax,
+                "ymax": e.ymax,
+            })),
+            "cells": self.cells,
+        })
+    }
+}
+
+struct HeatmapCollector {
+    decoder: KeyDecoder,
+    format: BoxFormat,
+    heatmap: Heatmap,
+}
+
+impl Visitor for HeatmapCollector {
+    fn on_tuple(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) {
+        if !page.is_leaf() {
+            return;
+        }
+        let (values, is_null) = self.decoder.deform(tuple);
+        if !is_null[0] {
+            let key = self.format.decode(values[0]);
+            self.heatmap.add(&key);
+        }
+    }
+}
+
+/// Returns the layout of bounding box keys of the first key attribute, failing with an error
+/// naming the `feature` which requires them if they are not bounding boxes.
+pub(crate) fn box_format(relation: Relation, feature: &str) -> BoxFormat {
+    BoxFormat::of_attribute(relation, 0).unwrap_or_else(|| {
+        let type_name = key_type_name(relation, 0);
+        raise(
+            ErrorClass::Unsupported,
+            &format!(
+                "{} require bounding box keys (box or box2df), but the first key column stores {}",
+                feature, type_name
+            ),
+        )
+    })
+}
+
+impl IndexInspector {
+    /// Rasterizes centroids of bounding boxes of all leaf keys of the first key attribute onto
+    /// a `grid`×`grid` matrix spanning the extent of the index, which is the union of the keys
+    /// stored on the root page.
+    pub fn heatmap(&self, grid: usize) -> Heatmap {
+        if grid == 0 || grid > MAX_GRID {
+            raise(
+                ErrorClass::InvalidArgument,
+                &format!("grid must be between 1 and {}, got {}", MAX_GRID, grid),
+            );
+        }
+        let format = box_format(self.relation, "heatmaps");
+        let decoder = KeyDecoder::new(self.relation);
+
+        let root = self.cache.get(GIST_ROOT_BLKNO);
+        let mut extent: Option<BoundingBox> = None;
+        for (_, tuple) in root.tuples() {
+            let (values, is_null) = decoder.deform(&tuple);
+            if !is_null[0] {
+                let key = format.decode(values[0]);
+                extent = Some(extent.map_or(key, |e| e.union(&key)));
+            }
+        }
+
+        let mut collector = HeatmapCollector {
+            decoder,
+            format,
+            heatmap: Heatmap::new(extent, grid),
+        };
+        self.walk(&mut collector);
+        collector.heatmap
+    }
+}
@@ -0,0 +1,202 @@
+use crate::gist::{GistPage, IndexInspector, Stats, StatsCollector, Subtree};
+use crate::readonly;
+use crate::walker::{PageVisit, Visitor};
+use crate::GIST_ROOT_BLKNO;
+use pgx::pg_sys::BlockNumber;
+use pgx::{notice, JsonB, Spi};
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Table storing statistics of subtrees computed by previous runs of
+/// [IndexInspector::stats_incremental].
+const SNAPSHOTS_TABLE: &str = "gevel.stat_snapshots";
+
+/// Statistics of a subtree under a single root downlink, as stored by a previous run.
+struct SubtreeSnapshot {
+    /// Max LSN of all pages of the subtree.
+    lsn: u64,
+    stats: Stats,
+    subtree: Subtree,
+}
+
+impl SubtreeSnapshot {
+    fn from_json(value: &serde_json::Value) -> Option<Self> {
+        Some(SubtreeSnapshot {
+            lsn: value["lsn"].as_u64()?,
+            stats: Stats::from_json(&value["stats"])?,
+            subtree: Subtree {
+                pages: value["pages"].as_u64()?,
+                min_leaf_level: value["min_leaf_level"].as_u64()? as usize,
+                max_leaf_level: value["max_leaf_level"].as_u64()? as usize,
+            },
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "lsn": self.lsn,
+            "stats": self.stats.to_json(),
+            "pages": self.subtree.pages,
+            "min_leaf_level": self.subtree.min_leaf_level,
+            "max_leaf_level": self.subtree.max_leaf_level,
+        })
+    }
+}
+
+/// Finds the max LSN of all pages, without decoding any keys.
+#[derive(Default)]
+struct LsnCollector {
+    max_lsn: u64,
+}
+
+impl Visitor for LsnCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        self.max_lsn = self.max_lsn.max(page.page.lsn());
+        true
+    }
+}
+
+/// Result of [IndexInspector::stats_incremental].
+pub struct IncrementalStats {
+    pub stats: Stats,
+    /// Number of subtrees under root downlinks.
+    pub subtrees: usize,
+    /// Number of subtrees which statistics have been reused from the previous run.
+    pub reused: usize,
+}
+
+impl IndexInspector {
+    /// Computes the same statistics as [IndexInspector::stats], but reuses statistics of
+    /// subtrees under root downlinks stored by the previous run when it's safe to do so.
+    ///
+    /// Every modification of a page advances its LSN, so stored statistics of a subtree are
+    /// reused only if the index has not been rewritten, extended or had its root modified
+    /// since then (see [IndexInspector::fingerprint]) and the max LSN of all pages of the
+    /// subtree hasn't advanced. GiST inserts which don't widen any keys only modify leaf pages,
+    /// so leaf pages are read as well, but only their headers are looked at, which is much
+    /// cheaper than decoding keys for mostly-static indexes.
+    ///
+    /// Tuples killed by index scans are marked as dead with a hint, which doesn't advance the
+    /// LSN, so the number of dead tuples of a reused subtree may lag until its pages get
+    /// modified again.
+    pub fn stats_incremental(&self) -> IncrementalStats {
+        let rel_oid = self.oid();
+        let fingerprint = self.fingerprint();
+        let mut snapshots = load_snapshots(rel_oid, &fingerprint);
+
+        // root page alone
+        let mut collector = StatsCollector::new(self.relation, Some(0));
+        self.walk(&mut collector);
+
+        let root = self.cache.get(GIST_ROOT_BLKNO);
+        let mut fresh = Vec::new();
+        let mut result = IncrementalStats {
+            stats: Stats::default(),
+            subtrees: 0,
+            reused: 0,
+        };
+        if !GistPage::new(&root).is_leaf() {
+            for (offset, downlink) in root.tuples() {
+                let blk = downlink.block_num();
+                let mut lsn = LsnCollector::default();
+                self.walk_subtree(1, blk, offset, &mut lsn);
+
+                let snapshot = match snapshots.remove(&blk).filter(|s| s.lsn == lsn.max_lsn) {
+                    Some(snapshot) => {
+                        result.reused += 1;
+                        snapshot
+                    }
+                    None => {
+                        let mut subtree = StatsCollector::new(self.relation, None);
+                        self.walk_subtree(1, blk, offset, &mut subtree);
+                        let longest_dead_chain = subtree.longest_dead_chain(self);
+                        let (mut stats, mut subtrees) = subtree.into_parts();
+                        stats.max_dead_chain = longest_dead_chain;
+                        // the walk skips downlinks pointing to new, missing or already visited
+                        // pages, so there's no subtree to account for
+                        let subtree = match subtrees.pop() {
+                            Some(subtree) => subtree,
+                            None => continue,
+                        };
+                        SubtreeSnapshot {
+                            lsn: lsn.max_lsn,
+                            stats,
+                            subtree,
+                        }
+                    }
+                };
+                collector.merge(&snapshot.stats, snapshot.subtree.clone());
+                fresh.push((blk, snapshot));
+                result.subtrees += 1;
+            }
+        }
+
+        if readonly::is_read_only() {
+            notice!("statistics of subtrees were not stored, because the transaction is read-only");
+        } else {
+            self.read_only
+                .allow_writes(|| save_snapshots(rel_oid, &fingerprint, &fresh));
+        }
+        result.stats = collector.finish(self);
+        result
+    }
+
+    /// Returns a value which changes whenever the index is rewritten, extended or its root page
+    /// is modified: relfilenode and size of the index together with the LSN of the root page.
+    /// It's read from the index itself, so unlike statistics counters of the table, it reflects
+    /// modifications of all transactions as soon as they are made.
+    fn fingerprint(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            unsafe { (*self.relation).rd_node.relNode },
+            self.num_blocks(),
+            self.cache.get(GIST_ROOT_BLKNO).lsn()
+        )
+    }
+}
+
+/// Loads subtree statistics stored for a given index, if they were stored with a given
+/// fingerprint.
+fn load_snapshots(rel_oid: u32, fingerprint: &str) -> HashMap<BlockNumber, SubtreeSnapshot> {
+    let query = format!(
+        "SELECT jsonb_object_agg(subtree_blkno, snapshot) FROM {} WHERE index_oid = {} AND fingerprint = '{}'",
+        SNAPSHOTS_TABLE, rel_oid, fingerprint
+    );
+    let mut snapshots = HashMap::new();
+    if let Some(JsonB(serde_json::Value::Object(map))) = Spi::get_one::<JsonB>(&query) {
+        for (blk, value) in map.iter() {
+            if let (Ok(blk), Some(snapshot)) = (blk.parse(), SubtreeSnapshot::from_json(value)) {
+                snapshots.insert(blk, snapshot);
+            }
+        }
+    }
+    snapshots
+}
+
+/// Replaces subtree statistics stored for a given index.
+fn save_snapshots(rel_oid: u32, fingerprint: &str, snapshots: &[(BlockNumber, SubtreeSnapshot)]) {
+    Spi::run(&format!(
+        "DELETE FROM {} WHERE index_oid = {}",
+        SNAPSHOTS_TABLE, rel_oid
+    ));
+    if snapshots.is_empty() {
+        return;
+    }
+    let values: Vec<String> = snapshots
+        .iter()
+        .map(|(blk, snapshot)| {
+            format!(
+                "({}, {}, '{}', '{}'::jsonb)",
+                rel_oid,
+                blk,
+                fingerprint,
+                snapshot.to_json().to_string().replace('\'', "''")
+            )
+        })
+        .collect();
+    Spi::run(&format!(
+        "INSERT INTO {}(index_oid, subtree_blkno, fingerprint, snapshot) VALUES {}",
+        SNAPSHOTS_TABLE,
+        values.join(", ")
+    ));
+}
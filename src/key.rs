@@ -0,0 +1,358 @@
+use crate::signature::{Signature, SignatureKind};
+use crate::sys::{pg_server_to_any, TupleDescAttr, PG_UTF8};
+use crate::IndexTuple;
+use pgx::pg_sys::{
+    format_type_be, getTypeOutputInfo, index_deform_tuple, Datum, IndexTupleData, Oid,
+    OidOutputFunctionCall, Relation, TupleDesc, INDEX_MAX_KEYS,
+};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+/// Describes how to render a single key attribute.
+enum KeyOutput {
+    /// Use type output function with a given oid.
+    Function(Oid),
+    /// Key is a signature bitmap of known layout. Output functions of these types usually
+    /// refuse to work, so they are rendered by gevel itself.
+    Signature(SignatureKind),
+}
+
+/// Decodes key attributes stored inside of index tuples into their text representation, using
+/// output functions of the types declared by the index tuple descriptor.
+pub struct KeyDecoder {
+    tuple_desc: TupleDesc,
+    outputs: Vec<KeyOutput>,
+    /// Whether attribute at a given position is a variable-length one.
+    is_varlena: Vec<bool>,
+}
+
+/// Kind of a header of the varlena datum stored inline inside of an index tuple.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarlenaKind {
+    /// Regular 4-byte header.
+    Long,
+    /// 4-byte header of a compressed datum.
+    Compressed,
+    /// 1-byte header of a short datum.
+    Short,
+    /// Pointer to a TOASTed datum.
+    External,
+}
+
+/// Converts a string produced by an output function from the server encoding into UTF-8, so
+/// that multi-byte characters of non-UTF-8 databases are not garbled. Conversion into the
+/// client encoding happens when results are sent to the client.
+fn server_to_utf8(cstr: *const c_char) -> String {
+    unsafe {
+        let len = CStr::from_ptr(cstr).to_bytes().len();
+        let converted = pg_server_to_any(cstr, len as i32, PG_UTF8);
+        CStr::from_ptr(converted).to_string_lossy().into_owned()
+    }
+}
+
+/// Escapes ASCII control characters, so that rendered keys are safe to display and don't
+/// break line-oriented outputs. Tabs, newlines and carriage returns are escaped C-style,
+/// all the others as `\xNN`. Backslashes are left as they are.
+pub fn escape_control(s: &str) -> String {
+    if !s.chars().any(|c| c.is_ascii_control()) {
+        return s.to_string();
+    }
+    let mut escaped = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns sizes of the header and of the whole non-compressed, non-TOASTed varlena pointed
+/// by `ptr` (assumes little-endian header layout), or `None` for other kinds of varlenas.
+unsafe fn varlena_layout(ptr: *const u8) -> Option<(usize, usize)> {
+    let first_byte = *ptr;
+    let (header, total) = match VarlenaKind::from_first_byte(first_byte) {
+        VarlenaKind::Short => (1, (first_byte >> 1) as usize),
+        VarlenaKind::Long => {
+            let word = u32::from_le_bytes([*ptr, *ptr.add(1), *ptr.add(2), *ptr.add(3)]);
+            (4, (word >> 2) as usize)
+        }
+        VarlenaKind::Compressed | VarlenaKind::External => return None,
+    };
+    if total < header {
+        None
+    } else {
+        Some((header, total))
+    }
+}
+
+impl VarlenaKind {
+    /// Recognizes the kind of a varlena header from its first byte (assumes little-endian
+    /// header layout).
+    fn from_first_byte(byte: u8) -> Self {
+        if byte == 0x01 {
+            VarlenaKind::External
+        } else if byte & 0x01 == 0x01 {
+            VarlenaKind::Short
+        } else if byte & 0x03 == 0x02 {
+            VarlenaKind::Compressed
+        } else {
+            VarlenaKind::Long
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VarlenaKind::Long => "4B",
+            VarlenaKind::Compressed => "4B compressed",
+            VarlenaKind::Short => "1B",
+            VarlenaKind::External => "external",
+        }
+    }
+}
+
+impl KeyDecoder {
+    pub fn new(relation: Relation) -> Self {
+        let tuple_desc = unsafe { relation.as_ref() }
+            .expect("Relation was NULL")
+            .rd_att;
+        let natts = unsafe { tuple_desc.as_ref() }
+            .expect("TupleDesc was NULL")
+            .natts as usize;
+        let attrs: Vec<_> = (0..natts)
+            .map(|attno| unsafe { &*TupleDescAttr(tuple_desc, attno) })
+            .collect();
+        let is_varlena = attrs.iter().map(|attr| attr.attlen == -1).collect();
+        let outputs = attrs
+            .iter()
+            .map(|attr| {
+                let type_name = unsafe { CStr::from_ptr(format_type_be(attr.atttypid)) };
+                let type_name = type_name.to_string_lossy();
+                // skip schema qualification if any
+                let type_name = type_name.rsplit('.').next().unwrap_or_default();
+                match SignatureKind::from_type_name(type_name) {
+                    Some(kind) => KeyOutput::Signature(kind),
+                    None => {
+                        let mut out_func: Oid = 0;
+                        let mut is_varlena = false;
+                        unsafe { getTypeOutputInfo(attr.atttypid, &mut out_func, &mut is_varlena) };
+                        KeyOutput::Function(out_func)
+                    }
+                }
+            })
+            .collect();
+        KeyDecoder {
+            tuple_desc,
+            outputs,
+            is_varlena,
+        }
+    }
+
+    /// Deforms a given tuple into arrays of key attribute datums and their null flags.
+    pub fn deform(
+        &self,
+        tuple: &IndexTuple,
+    ) -> (
+        [Datum; INDEX_MAX_KEYS as usize],
+        [bool; INDEX_MAX_KEYS as usize],
+    ) {
+        let mut values = [0 as Datum; INDEX_MAX_KEYS as usize];
+        let mut is_null = [false; INDEX_MAX_KEYS as usize];
+        unsafe {
+            index_deform_tuple(
+                tuple.0 as *const IndexTupleData as *mut IndexTupleData,
+                self.tuple_desc,
+                values.as_mut_ptr(),
+                is_null.as_mut_ptr(),
+            )
+        };
+        (values, is_null)
+    }
+
+    /// Returns text representation of all key attributes of a given tuple. NULL attributes are
+    /// returned as `None`.
+    pub fn decode(&self, tuple: &IndexTuple) -> Vec<Option<String>> {
+        let (values, is_null) = self.deform(tuple);
+        self.outputs
+            .iter()
+            .enumerate()
+            .map(|(i, output)| {
+                if is_null[i] {
+                    None
+                } else {
+                    Some(Self::render(output, values[i]))
+                }
+            })
+            .collect()
+    }
+
+    /// Returns text representation of a non-null datum of the key attribute at a given
+    /// (0-based) position.
+    pub fn render_datum(&self, attno: usize, datum: Datum) -> String {
+        Self::render(&self.outputs[attno], datum)
+    }
+
+    fn render(output: &KeyOutput, datum: Datum) -> String {
+        match output {
+            KeyOutput::Function(out_func) => {
+                let cstr = unsafe { OidOutputFunctionCall(*out_func, datum) };
+                escape_control(&server_to_utf8(cstr))
+            }
+            KeyOutput::Signature(kind) => Signature::decode(*kind, datum).to_string(),
+        }
+    }
+
+    /// Returns the number of bytes a non-null datum of the key attribute at a given (0-based)
+    /// position occupies inside of an index tuple, not counting alignment padding.
+    pub fn datum_size(&self, attno: usize, datum: Datum) -> usize {
+        let attlen = unsafe { (*TupleDescAttr(self.tuple_desc, attno)).attlen };
+        match attlen {
+            -1 => unsafe {
+                let ptr = datum as *const u8;
+                match VarlenaKind::from_first_byte(*ptr) {
+                    // TOAST pointer: 2 bytes of a header followed by an on-disk pointer
+                    VarlenaKind::External => 2 + 16,
+                    VarlenaKind::Short => (*ptr >> 1) as usize,
+                    VarlenaKind::Long | VarlenaKind::Compressed => {
+                        let word =
+                            u32::from_le_bytes([*ptr, *ptr.add(1), *ptr.add(2), *ptr.add(3)]);
+                        (word >> 2) as usize
+                    }
+                }
+            },
+            // cstring, including its terminator
+            -2 => unsafe { CStr::from_ptr(datum as *const c_char).to_bytes().len() + 1 },
+            len => len as usize,
+        }
+    }
+
+    /// Returns header kinds of all key attributes of a given tuple. Attributes which are NULL or
+    /// not variable-length are returned as `None`.
+    pub fn varlena_kinds(&self, tuple: &IndexTuple) -> Vec<Option<VarlenaKind>> {
+        let (values, is_null) = self.deform(tuple);
+        self.is_varlena
+            .iter()
+            .enumerate()
+            .map(|(i, &is_varlena)| {
+                if is_varlena && !is_null[i] {
+                    let first_byte = unsafe { *(values[i] as *const u8) };
+                    Some(VarlenaKind::from_first_byte(first_byte))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Returns names of all key attributes.
+    pub fn attr_names(&self) -> Vec<String> {
+        let natts = self.outputs.len();
+        (0..natts)
+            .map(|attno| {
+                let attr = unsafe { &*TupleDescAttr(self.tuple_desc, attno) };
+                let name = unsafe { CStr::from_ptr(attr.attname.data.as_ptr()) };
+                name.to_string_lossy().into_owned()
+            })
+            .collect()
+    }
+
+    /// Returns flags telling which of the key attributes are variable-length ones.
+    pub fn is_varlena(&self) -> &[bool] {
+        &self.is_varlena
+    }
+
+    /// Returns copies of payloads (data following the varlena header) of all variable-length
+    /// key attributes of a given tuple. Attributes which are NULL, not variable-length,
+    /// compressed or TOASTed are returned as `None`. If a payload is itself a single varlena
+    /// spanning all of it - as is the case for leaf keys of btree_gist opclasses - the nested
+    /// payload is returned instead, so that headers storing key lengths don't break shared
+    /// prefixes.
+    pub fn varlena_payloads(&self, tuple: &IndexTuple) -> Vec<Option<Vec<u8>>> {
+        let (values, is_null) = self.deform(tuple);
+        self.is_varlena
+            .iter()
+            .enumerate()
+            .map(|(i, &is_varlena)| {
+                if !is_varlena || is_null[i] {
+                    return None;
+                }
+                let ptr = values[i] as *const u8;
+                let (header, total) = unsafe { varlena_layout(ptr) }?;
+                let payload =
+                    unsafe { std::slice::from_raw_parts(ptr.add(header), total - header) };
+                let is_nested = match payload.first() {
+                    // long header needs 4 bytes to be read
+                    Some(&first_byte) if payload.len() >= 4 || first_byte & 0x01 == 0x01 => {
+                        unsafe { varlena_layout(payload.as_ptr()) }
+                            .is_some_and(|(_, nested_total)| nested_total == payload.len())
+                    }
+                    _ => false,
+                };
+                let payload = if is_nested {
+                    let (nested_header, _) = unsafe { varlena_layout(payload.as_ptr()) }.unwrap();
+                    &payload[nested_header..]
+                } else {
+                    payload
+                };
+                Some(payload.to_vec())
+            })
+            .collect()
+    }
+
+    /// Returns a copy of the payload of a given variable-length key attribute of a tuple, as
+    /// is, without unwrapping nested varlenas. Returns `None` under the same conditions as
+    /// [KeyDecoder::varlena_payloads].
+    pub fn varlena_payload(&self, tuple: &IndexTuple, attno: usize) -> Option<Vec<u8>> {
+        let (values, is_null) = self.deform(tuple);
+        if !self.is_varlena[attno] || is_null[attno] {
+            return None;
+        }
+        let ptr = values[attno] as *const u8;
+        let (header, total) = unsafe { varlena_layout(ptr) }?;
+        Some(unsafe { std::slice::from_raw_parts(ptr.add(header), total - header) }.to_vec())
+    }
+
+    /// Returns true if any of the key attributes is a signature-based key.
+    pub fn has_signatures(&self) -> bool {
+        self.outputs
+            .iter()
+            .any(|o| matches!(o, KeyOutput::Signature(_)))
+    }
+
+    /// Returns decoded signatures of all signature-based key attributes of a given tuple.
+    /// Attributes which are NULL or not signature-based are skipped.
+    pub fn signatures(&self, tuple: &IndexTuple) -> Vec<Signature> {
+        let (values, is_null) = self.deform(tuple);
+        self.outputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, output)| match output {
+                KeyOutput::Signature(kind) if !is_null[i] => {
+                    Some(Signature::decode(*kind, values[i]))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns text representation of all key attributes of a given tuple, joined together and
+    /// truncated to at most `max_len` characters.
+    pub fn preview(&self, tuple: &IndexTuple, max_len: usize) -> String {
+        let keys: Vec<String> = self
+            .decode(tuple)
+            .into_iter()
+            .map(|key| key.unwrap_or_else(|| "NULL".to_string()))
+            .collect();
+        let joined = keys.join(", ");
+        if joined.chars().count() > max_len {
+            let mut truncated: String = joined.chars().take(max_len).collect();
+            truncated.push_str("...");
+            truncated
+        } else {
+            joined
+        }
+    }
+}
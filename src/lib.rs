@@ -1,69 +1,1358 @@
-#![feature(const_maybe_uninit_as_ptr)]
-#![feature(const_raw_ptr_deref)]
-#![feature(const_raw_ptr_to_usize_cast)]
+// set-returning functions declare the columns of their rows in their signatures
+#![allow(clippy::type_complexity)]
 
+mod advisor;
+mod attribution;
+#[cfg(feature = "bench")]
+mod bench;
+mod cache;
+mod capabilities;
+mod check;
+mod clusters;
+mod demo;
+mod diff;
+mod duplicates;
+mod error;
+#[cfg(not(feature = "testing"))]
+mod explain;
+pub mod ffi;
+#[cfg(not(feature = "testing"))]
+mod fleet;
+mod forensics;
+mod geojson;
 mod gist;
+mod growth;
+mod guc;
+mod hashes;
+mod heatmap;
+mod incremental;
+mod key;
+mod locality;
+mod memory;
+mod opclass;
+mod ordering;
+mod output;
+mod prefix;
+mod print;
+mod profile;
+mod readonly;
+mod reindex;
+mod report;
+mod rows;
+#[cfg(not(feature = "testing"))]
+mod scanstats;
+mod signature;
+mod sys;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod visibility;
+mod walker;
+mod workload;
+mod xml;
 
-use crate::gist::IndexInspector;
+use crate::error::{raise, Error, ErrorClass, OrRaise};
+use crate::gist::{IndexInspector, JsonOptions, TreeOptions};
+use crate::sys::stringToQualifiedNameList;
 use memoffset::offset_of;
 use pgx::pg_sys::{
-    BlockNumber, BufferGetPage, IndexTupleData, ItemIdData, OffsetNumber, Oid, PageGetFreeSpace,
-    PageHeaderData, RangeVar, RangeVarGetRelidExtended, ReadBuffer, Relation, ReleaseBuffer,
-    BLCKSZ, MAXIMUM_ALIGNOF,
+    makeRangeVarFromNameList, BlockNumber, BufferGetPage, IndexTupleData, ItemIdData, LockBuffer,
+    NoLock, OffsetNumber, Oid, PageGetFreeSpace, PageHeaderData, RangeVar,
+    RangeVarGetRelidExtended, ReadBuffer, Relation, ReleaseBuffer, BLCKSZ, BUFFER_LOCK_SHARE,
+    BUFFER_LOCK_UNLOCK, INDEX_NULL_MASK, INDEX_SIZE_MASK, LP_DEAD, LP_NORMAL,
 };
 use pgx::*;
+use std::ffi::{CStr, CString};
+use std::fmt::Write;
 use std::mem::size_of;
 use std::ptr::null_mut;
 
+// when linked into another extension as a library, that extension owns the module
+#[cfg(not(feature = "testing"))]
 pg_module_magic!();
 
-#[pg_extern]
-pub fn gist_tree(rel_oid: Oid) -> String {
-    let index = IndexInspector::open(rel_oid);
-    let tree = index.get_tree(None);
-    tree.to_string()
+#[cfg(not(feature = "testing"))]
+#[pg_guard]
+pub extern "C" fn _PG_init() {
+    guc::init();
+    explain::init();
+    scanstats::init();
 }
 
+/// Bytes allocated in the memory context of the last inspection in the current backend. Reads
+/// backend-local state, so it can't be STABLE nor PARALLEL SAFE.
 #[pg_extern]
-pub fn gist_stat(rel_oid: Oid) -> String {
-    let index = IndexInspector::open(rel_oid);
-    let stats = index.stats(None);
+pub fn gevel_memory() -> Option<i64> {
+    memory::last_context_bytes()
+}
+
+/// Describes access methods, output formats and optional features supported by this build of
+/// gevel on the running server.
+#[pg_extern(stable, parallel_safe)]
+pub fn gevel_capabilities() -> JsonB {
+    JsonB(capabilities::capabilities())
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_tree(rel_oid: Oid, sparkline: default!(bool, false)) -> String {
+    tree_text(rel_oid, None, sparkline)
+}
+
+/// Same as `gist_tree`, but takes a possibly schema-qualified index name.
+#[pg_extern(stable, parallel_safe, name = "gist_tree")]
+pub fn gist_tree_by_name(index_name: &str, sparkline: default!(bool, false)) -> String {
+    gist_tree(index_oid(index_name), sparkline)
+}
+
+/// Same as `gist_tree`, but only descends down to `max_level` (the root is at level 0), like
+/// `gist_tree(regclass, int)` of the original gevel.
+#[pg_extern(stable, parallel_safe, name = "gist_tree")]
+pub fn gist_tree_max_level(
+    rel_oid: Oid,
+    max_level: i32,
+    sparkline: default!(bool, false),
+) -> String {
+    tree_text(rel_oid, Some(max_level.max(0) as usize), sparkline)
+}
+
+/// Same as `gist_tree` with `max_level`, but takes a possibly schema-qualified index name.
+#[pg_extern(stable, parallel_safe, name = "gist_tree")]
+pub fn gist_tree_max_level_by_name(
+    index_name: &str,
+    max_level: i32,
+    sparkline: default!(bool, false),
+) -> String {
+    gist_tree_max_level(index_oid(index_name), max_level, sparkline)
+}
+
+/// Same as `gist_tree`, but returns one line per page. Pages are read as rows are fetched, so
+/// the report of a huge index is never materialized as a whole.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_tree_lines(rel_oid: Oid) -> impl std::iter::Iterator<Item = String> {
+    IndexInspector::open_streaming(rel_oid)
+        .or_raise()
+        .tree_lines()
+}
+
+fn tree_text(rel_oid: Oid, max_level: Option<usize>, sparkline: bool) -> String {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let options = TreeOptions {
+        max_level,
+        sparkline,
+        ..TreeOptions::default()
+    };
+    let tree = index.get_tree(&options);
+    output::capped("gist_tree", |out| write!(out, "{}", tree))
+}
+
+/// Returns one row per page of the tree, so that it can be filtered and aggregated with SQL.
+/// Pages are read as rows are fetched, so the tree is never materialized as a whole.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_tree_rows(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(level, i32),
+        name!(blkno, i64),
+        name!(parent_blkno, Option<i64>),
+        name!(offset, i32),
+        name!(ntuples, i32),
+        name!(free_bytes, i64),
+        name!(largest_insertable, i64),
+        name!(occupancy, f64),
+        name!(rightlink, Option<i64>),
+        name!(is_leaf, bool),
+    ),
+> {
+    let rows = IndexInspector::open_streaming(rel_oid)
+        .or_raise()
+        .into_tree_rows();
+    rows.map(|r| {
+        (
+            r.level as i32,
+            r.block_num as i64,
+            r.parent.map(|blk| blk as i64),
+            r.offset as i32,
+            r.tuples as i32,
+            r.free_space as i64,
+            r.largest_insertable as i64,
+            r.occupancy,
+            r.right_link.map(|blk| blk as i64),
+            r.is_leaf,
+        )
+    })
+}
+
+/// Returns one line of CSV per page of the tree, preceded by a header line. Meant to be saved
+/// with `\copy` and loaded into spreadsheets.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_pages_csv(rel_oid: Oid) -> impl std::iter::Iterator<Item = String> {
+    let rows = IndexInspector::open_streaming(rel_oid)
+        .or_raise()
+        .into_tree_rows();
+    std::iter::once(rows::CSV_HEADER.to_string()).chain(rows.map(|r| r.to_csv()))
+}
+
+/// Returns the tree as a GraphViz DOT digraph, which can be rendered with `dot -Tsvg`.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_tree_dot(rel_oid: Oid) -> String {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let rows = index.tree_rows();
+    output::capped("gist_tree_dot", |out| rows::write_dot(&rows, out))
+}
+
+/// Returns pages of the tree down to `max_level` as a Mermaid flowchart, which can be pasted
+/// into markdown documents rendering Mermaid diagrams (ie. GitHub issues).
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_tree_mermaid(rel_oid: Oid, max_level: i32) -> String {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let rows = index.tree_rows_to(Some(max_level.max(0) as usize));
+    output::capped("gist_tree_mermaid", |out| rows::write_mermaid(&rows, out))
+}
+
+/// Returns the tree as a flat stream of JSON documents, one per page in the depth-first order,
+/// each referring to its parent page. Unlike `gist_tree_json` it has no size limit and can be
+/// consumed in batches with a cursor.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_tree_json_stream(rel_oid: Oid) -> impl std::iter::Iterator<Item = JsonB> {
+    let rows = IndexInspector::open_streaming(rel_oid)
+        .or_raise()
+        .into_tree_rows();
+    rows.map(|r| JsonB(r.to_json()))
+}
+
+/// Returns the tree as a single nested JSON document or, with a non-negative `chunk_level`,
+/// as an array of independent documents of subtrees rooted at that level. `options` select
+/// fields and depth of the output, see [gist::JsonOptions].
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_tree_json(
+    rel_oid: Oid,
+    include_items: default!(bool, false),
+    chunk_level: default!(i32, -1),
+    options: default!(JsonB, "'{}'"),
+) -> JsonB {
+    let json_options = JsonOptions::from_json(&options.0).or_raise();
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let options = TreeOptions {
+        include_items: include_items
+            || json_options
+                .fields
+                .as_ref()
+                .is_some_and(|f| f.contains("items")),
+        max_level: json_options.max_depth,
+        ..TreeOptions::default()
+    };
+    let tree = index.get_tree(&options);
+    let limit = output::max_output_bytes();
+    let (json, truncated) = if chunk_level < 0 {
+        tree.to_json_capped(&json_options, limit)
+    } else {
+        tree.to_json_chunks_capped(chunk_level as usize, &json_options, limit)
+    };
+    if truncated {
+        output::notice_truncated("gist_tree_json");
+    }
+    JsonB(json)
+}
+
+/// Same as `gist_tree_json`, but takes a possibly schema-qualified index name.
+#[pg_extern(stable, parallel_safe, name = "gist_tree_json")]
+pub fn gist_tree_json_by_name(
+    index_name: &str,
+    include_items: default!(bool, false),
+    chunk_level: default!(i32, -1),
+    options: default!(JsonB, "'{}'"),
+) -> JsonB {
+    gist_tree_json(index_oid(index_name), include_items, chunk_level, options)
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_tree_markdown(rel_oid: Oid) -> String {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let tree = index.get_tree(&TreeOptions::default());
+    output::capped("gist_tree_markdown", |out| tree.write_markdown(out))
+}
+
+/// Same as `gist_tree_markdown`, but takes a possibly schema-qualified index name.
+#[pg_extern(stable, parallel_safe, name = "gist_tree_markdown")]
+pub fn gist_tree_markdown_by_name(index_name: &str) -> String {
+    gist_tree_markdown(index_oid(index_name))
+}
+
+/// Returns content hashes of all pages reachable from the root as a JSON object mapping block
+/// numbers to hashes, to be compared later with `gist_page_hashes_diff`.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_page_hashes(rel_oid: Oid) -> JsonB {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    output::limited_json(
+        "gist_page_hashes",
+        hashes::hashes_to_json(&index.page_hashes()),
+    )
+}
+
+/// Compares two results of `gist_page_hashes` and returns pages which differ.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_page_hashes_diff(
+    before: JsonB,
+    after: JsonB,
+) -> impl std::iter::Iterator<Item = (name!(blkno, i64), name!(change, String))> {
+    let changes = hashes::compare_hashes(&before.0, &after.0);
+    changes
+        .into_iter()
+        .map(|c| (c.block_num as i64, c.change.to_string()))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_forensics(rel_oid: Oid, redact: default!(bool, false)) -> JsonB {
+    // count checks would report concurrent writes as a discrepancy
+    let bundle =
+        IndexInspector::with_table_locked(rel_oid, |index| index.forensics(redact)).or_raise();
+    output::limited_json("gist_forensics", bundle)
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_check(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(check, String),
+        name!(level, Option<i32>),
+        name!(blkno, i64),
+        name!(message, String),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let findings = index.check();
+    findings.into_iter().map(|f| {
+        (
+            f.check.to_string(),
+            f.level.map(|l| l as i32),
+            f.block_num as i64,
+            f.message,
+        )
+    })
+}
+
+/// Returns statistics of the index as a text report. With a non-negative `max_level` only pages
+/// down to that level (the root is at level 0) are taken into account.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_stat(rel_oid: Oid, max_level: default!(i32, -1)) -> String {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let max_level = if max_level < 0 {
+        None
+    } else {
+        Some(max_level as usize)
+    };
+    let stats = index.stats(max_level);
     stats.to_string()
 }
 
+/// Same as `gist_stat`, but takes a possibly schema-qualified index name.
+#[pg_extern(stable, parallel_safe, name = "gist_stat")]
+pub fn gist_stat_by_name(index_name: &str, max_level: default!(i32, -1)) -> String {
+    gist_stat(index_oid(index_name), max_level)
+}
+
+/// Returns block numbers of pages which have never been initialized (are all zeros).
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_new_pages(rel_oid: Oid) -> impl std::iter::Iterator<Item = i64> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let pages = index.new_pages();
+    pages.into_iter().map(|blk| blk as i64)
+}
+
+/// Same as `gist_stat`, but returns the main counters as a single row, which monitoring
+/// systems can ingest without parsing the report.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_stat_record(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(levels, i32),
+        name!(pages, i64),
+        name!(leaf_pages, i64),
+        name!(tuples, i64),
+        name!(leaf_tuples, i64),
+        name!(invalid_tuples, i64),
+        name!(tuple_bytes, i64),
+        name!(leaf_tuple_bytes, i64),
+        name!(total_bytes, i64),
+    ),
+> {
+    let index = IndexInspector::open_aggregates(rel_oid).or_raise();
+    let s = index.stats(None);
+    std::iter::once((
+        (s.level + 1) as i32,
+        s.num_pages as i64,
+        s.num_leaf_pages as i64,
+        s.num_tuple as i64,
+        s.num_leaf_tuple as i64,
+        s.num_invalid_tuple as i64,
+        s.tuple_size as i64,
+        s.leaf_tuple_size as i64,
+        s.total_size as i64,
+    ))
+}
+
+/// Same as `gist_stat`, but reuses statistics of unchanged subtrees stored by the previous run.
+/// Writes to `gevel.stat_snapshots`, so it can't be STABLE.
+#[pg_extern]
+pub fn gist_stat_incremental(rel_oid: Oid) -> String {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let result = index.stats_incremental();
+    notice!(
+        "reused statistics of {} out of {} subtrees",
+        result.reused,
+        result.subtrees
+    );
+    result.stats.to_string()
+}
+
+/// Returns keys of all index tuples, decompressed by the opclass and rendered by output
+/// functions of their types (or as WKT for geometric keys if `wkt` is set), level by level
+/// starting from the root.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_print(
+    rel_oid: Oid,
+    wkt: default!(bool, false),
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(level, i32),
+        name!(blkno, i64),
+        name!(offset, i32),
+        name!(is_leaf, bool),
+        name!(valid, bool),
+        name!(keys, Vec<Option<String>>),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let tuples = index.print(wkt);
+    tuples.into_iter().map(|t| {
+        (
+            t.level as i32,
+            t.block_num as i64,
+            t.offset as i32,
+            t.is_leaf,
+            t.is_valid,
+            t.keys,
+        )
+    })
+}
+
+/// Returns the union of keys stored on the root page as a value of the type keys are stored
+/// as, which has to be the type of `key_type`. Only the type matters, so it's usually given as
+/// a typed NULL, ie. `gist_data_extent(idx, NULL::box)`, which is why the function isn't
+/// STRICT.
+#[pg_extern(stable, parallel_safe)]
+// fcinfo is passed by Postgres, which calls the function
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn gist_data_extent(
+    rel_oid: Option<Oid>,
+    key_type: Option<AnyElement>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<AnyElement> {
+    let _ = key_type;
+    let requested = unsafe { pg_sys::get_fn_expr_argtype((*fcinfo).flinfo, 1) };
+    // the extent is returned to the caller, so it can't live in memory of the inspection
+    let index = IndexInspector::open_streaming(rel_oid?).or_raise();
+    let stored = index.key_type();
+    if requested != stored {
+        let type_name = |oid: Oid| {
+            Spi::get_one::<String>(&format!("SELECT format_type({}, NULL)", oid))
+                .unwrap_or_default()
+        };
+        raise(
+            ErrorClass::InvalidArgument,
+            &format!(
+                "keys of the index are stored as {}, but {} was requested",
+                type_name(stored),
+                type_name(requested)
+            ),
+        );
+    }
+    let extent = index.data_extent()?;
+    unsafe { AnyElement::from_datum(extent, false, stored) }
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_page_items(
+    rel_oid: Oid,
+    blkno: i64,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(offset, i32),
+        name!(item_state, String),
+        name!(item_len, i32),
+        name!(tuple_size, i32),
+        name!(is_leaf_key, bool),
+        name!(has_nulls, bool),
+        name!(is_invalid, bool),
+        name!(tid, String),
+        name!(varlena_kinds, Vec<Option<String>>),
+        name!(key, Option<String>),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let items = index.page_items(blkno).or_raise();
+    items.into_iter().map(|i| {
+        (
+            i.offset as i32,
+            i.item_state.to_string(),
+            i.item_len as i32,
+            i.tuple_size as i32,
+            i.is_leaf_key,
+            i.has_nulls,
+            i.is_invalid,
+            format!("({},{})", i.tid.0, i.tid.1),
+            i.varlena_kinds
+                .into_iter()
+                .map(|k| k.map(|k| k.name().to_string()))
+                .collect(),
+            i.key,
+        )
+    })
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_count_check(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(walker_count, i64),
+        name!(scan_count, i64),
+        name!(consistent, bool),
+    ),
+> {
+    let check = IndexInspector::with_table_locked(rel_oid, |index| index.count_check()).or_raise();
+    if !check.is_consistent() {
+        warning!(
+            "index {} has {} live leaf tuples, but a full index scan returned {}",
+            rel_oid,
+            check.walker_count,
+            check.scan_count
+        );
+    }
+    std::iter::once((
+        check.walker_count as i64,
+        check.scan_count as i64,
+        check.is_consistent(),
+    ))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_stat_by_level(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(level, i32),
+        name!(pages, i64),
+        name!(free_bytes, i64),
+        name!(null_tuples, i64),
+        name!(internal_tuples, i64),
+        name!(internal_avg_key_size, Option<f64>),
+        name!(internal_min_key_size, Option<i32>),
+        name!(internal_max_key_size, Option<i32>),
+        name!(leaf_tuples, i64),
+        name!(leaf_avg_key_size, Option<f64>),
+        name!(leaf_min_key_size, Option<i32>),
+        name!(leaf_max_key_size, Option<i32>),
+        name!(max_dead_chain, i64),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let levels = index.stats_by_level();
+    levels.into_iter().enumerate().map(|(level, s)| {
+        (
+            level as i32,
+            s.num_pages as i64,
+            s.free_space as i64,
+            s.null_tuples as i64,
+            s.internal_keys.count as i64,
+            s.internal_keys.avg(),
+            s.internal_keys.min.map(|v| v as i32),
+            s.internal_keys.max.map(|v| v as i32),
+            s.leaf_keys.count as i64,
+            s.leaf_keys.avg(),
+            s.leaf_keys.min.map(|v| v as i32),
+            s.leaf_keys.max.map(|v| v as i32),
+            s.max_dead_chain as i64,
+        )
+    })
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_attr_stats(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(attno, i32),
+        name!(attname, String),
+        name!(leaf_keys, i64),
+        name!(leaf_nulls, i64),
+        name!(null_pct, f64),
+        name!(internal_nulls, i64),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let attrs = index.attr_stats();
+    attrs.into_iter().enumerate().map(|(i, a)| {
+        (
+            i as i32 + 1,
+            a.name.clone(),
+            a.leaf_keys as i64,
+            a.leaf_nulls as i64,
+            a.null_pct(),
+            a.internal_nulls as i64,
+        )
+    })
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_column_attribution(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(attno, i32),
+        name!(attname, String),
+        name!(leaf_bytes, i64),
+        name!(internal_bytes, i64),
+        name!(bytes_pct, f64),
+        name!(avg_penalty, Option<f64>),
+        name!(decisive_choices, i64),
+        name!(decisive_pct, Option<f64>),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let attribution = index.column_attribution();
+    (0..attribution.columns.len())
+        .map(|i| {
+            let c = &attribution.columns[i];
+            (
+                i as i32 + 1,
+                c.name.clone(),
+                c.leaf_bytes as i64,
+                c.internal_bytes as i64,
+                attribution.bytes_pct(i),
+                c.avg_penalty(),
+                c.decisive_choices as i64,
+                attribution.decisive_pct(i),
+            )
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// Returns the index being replaced and the index being built by a `REINDEX CONCURRENTLY` of
+/// a given index, which may be either of them. Returns no rows if there's no such rebuild.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_reindex_pair(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<Item = (name!(old_index, Oid), name!(new_index, Oid))> {
+    reindex::reindex_pair(rel_oid)
+        .map(|pair| (pair.old, pair.new))
+        .into_iter()
+}
+
+/// Contrasts the structure of the index being built by `REINDEX CONCURRENTLY` with the one it
+/// replaces, to show how the rebuild is shaping up while it runs.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_reindex_progress(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(metric, String),
+        name!(old_value, f64),
+        name!(new_value, f64),
+        name!(new_pct, Option<f64>),
+    ),
+> {
+    reindex::reindex_progress(rel_oid)
+        .or_raise()
+        .into_iter()
+        .map(|m| (m.name.to_string(), m.old, m.new, m.new_pct()))
+}
+
+/// Groups a sample of leaf keys into up to `k` clusters, using the opclass penalty function
+/// as a distance.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_key_clusters(
+    rel_oid: Oid,
+    k: i32,
+    sample: default!(i32, 1000),
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(cluster, i32),
+        name!(keys, i64),
+        name!(keys_pct, f64),
+        name!(medoid, String),
+        name!(extent, String),
+        name!(avg_distance, f64),
+        name!(nearest_distance, Option<f64>),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let clusters = index.key_clusters(k.max(0) as usize, sample.max(0) as usize);
+    (0..clusters.clusters.len())
+        .map(|i| {
+            let c = &clusters.clusters[i];
+            (
+                i as i32 + 1,
+                c.keys as i64,
+                clusters.keys_pct(i),
+                c.medoid.clone(),
+                c.extent.clone(),
+                c.avg_distance,
+                c.nearest_distance,
+            )
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_key_outliers(
+    rel_oid: Oid,
+    top_n: i32,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(size, i32),
+        name!(level, i32),
+        name!(blkno, i64),
+        name!(offset, i32),
+        name!(preview, String),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let outliers = index.key_outliers(top_n.max(0) as usize);
+    outliers.into_iter().map(|o| {
+        (
+            o.size as i32,
+            o.level as i32,
+            o.block_num as i64,
+            o.offset as i32,
+            o.preview,
+        )
+    })
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_signature_stats(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(level, i32),
+        name!(exact_keys, i64),
+        name!(signature_keys, i64),
+        name!(all_true_keys, i64),
+        name!(avg_density, f64),
+        name!(saturated, bool),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let levels = index.signature_stats();
+    levels.into_iter().enumerate().map(|(level, s)| {
+        (
+            level as i32,
+            s.exact_keys as i64,
+            s.signature_keys as i64,
+            s.all_true_keys as i64,
+            s.avg_density(),
+            s.is_saturated(),
+        )
+    })
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_prefix_compression(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(attno, i32),
+        name!(keys, i64),
+        name!(key_bytes, i64),
+        name!(page_prefix_savings, i64),
+        name!(front_coding_savings, i64),
+        name!(savings_pct, f64),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let stats = index.prefix_stats();
+    stats.into_iter().map(|s| {
+        (
+            s.attno as i32 + 1,
+            s.keys as i64,
+            s.key_bytes as i64,
+            s.page_prefix_savings as i64,
+            s.front_coding_savings as i64,
+            s.savings_pct(),
+        )
+    })
+}
+
+/// Returns a `grid`×`grid` matrix of numbers of leaf keys whose bounding box centroids fall
+/// into each cell, together with the extent it spans.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_heatmap(rel_oid: Oid, grid: default!(i32, 64)) -> JsonB {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    JsonB(index.heatmap(grid.max(0) as usize).to_json())
+}
+
+/// Returns bounding box keys stored on pages of a given level as a GeoJSON FeatureCollection.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_keys_geojson(rel_oid: Oid, level: i32) -> JsonB {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    output::limited_json("gist_keys_geojson", index.keys_geojson(level))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_heap_locality(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(blkno, i64),
+        name!(tuples, i64),
+        name!(min_heap_block, i64),
+        name!(max_heap_block, i64),
+        name!(heap_block_spread, i64),
+        name!(heap_block_stddev, f64),
+        name!(distinct_heap_blocks, i64),
+        name!(correlation, f64),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let locality = index.heap_locality();
+    let correlation = locality.correlation;
+    locality.pages.into_iter().map(move |p| {
+        (
+            p.block_num as i64,
+            p.tuples as i64,
+            p.min_heap_block as i64,
+            p.max_heap_block as i64,
+            p.spread() as i64,
+            p.heap_block_stddev,
+            p.distinct_heap_blocks as i64,
+            correlation,
+        )
+    })
+}
+
+/// Infers growth phases of the index from LSNs and block numbers of its pages, one row per
+/// LSN range, see [IndexInspector::growth_history].
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_growth_history(
+    rel_oid: Oid,
+    buckets: default!(i32, 20),
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(bucket, i32),
+        name!(lsn_from, String),
+        name!(lsn_to, String),
+        name!(pages, i64),
+        name!(extended_pages, i64),
+        name!(blocks, i64),
+        name!(phase, String),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let history = index.growth_history(buckets.max(0) as usize);
+    if history.skipped > 0 {
+        notice!(
+            "skipped {} pages whose LSN doesn't tell when they were written",
+            history.skipped
+        );
+    }
+    history.buckets.into_iter().enumerate().map(|(i, b)| {
+        (
+            i as i32,
+            crate::growth::format_lsn(b.lsn_from),
+            crate::growth::format_lsn(b.lsn_to),
+            b.pages as i64,
+            b.extended as i64,
+            b.blocks as i64,
+            b.phase.name().to_string(),
+        )
+    })
+}
+
+/// Counts leaf tuples pointing to heap pages marked as all-visible in the visibility map, which
+/// are known to reference live rows without fetching them.
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_leaf_visibility(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(leaf_tuples, i64),
+        name!(all_visible_tuples, i64),
+        name!(all_visible_pct, f64),
+        name!(heap_pages, i64),
+        name!(all_visible_heap_pages, i64),
+    ),
+> {
+    let index = IndexInspector::open(rel_oid).or_raise();
+    let v = index.leaf_visibility();
+    std::iter::once((
+        v.leaf_tuples as i64,
+        v.all_visible_tuples as i64,
+        v.all_visible_pct(),
+        v.heap_pages as i64,
+        v.all_visible_heap_pages as i64,
+    ))
+}
+
+/// Max number of leaf pages visited by `gist_summary`.
+const SUMMARY_SAMPLE_SIZE: usize = 1000;
+
+#[pg_extern(stable, parallel_safe)]
+pub fn gist_summary(
+    rel_oid: Oid,
+) -> impl std::iter::Iterator<
+    Item = (
+        name!(is_valid, bool),
+        name!(levels, i32),
+        name!(pages, i64),
+        name!(bloat_pct, f64),
+        name!(invalid_tuples, i64),
+        name!(health_score, f64),
+        name!(optimal_pages, i64),
+        name!(optimal_levels, i32),
+        name!(pages_vs_optimal, f64),
+    ),
+> {
+    let index = IndexInspector::open_aggregates(rel_oid).or_raise();
+    let s = index.summary(SUMMARY_SAMPLE_SIZE);
+    std::iter::once((
+        s.is_valid,
+        s.levels as i32,
+        s.pages as i64,
+        s.bloat_pct,
+        s.invalid_tuples as i64,
+        s.health_score,
+        s.optimal_pages as i64,
+        s.optimal_levels as i32,
+        s.pages_vs_optimal,
+    ))
+}
+
+// functions of this module are created in the `gevel` schema
+mod gevel {
+    use crate::error::OrRaise;
+    use pgx::*;
+
+    /// Builds demo indexes of given sizes and measures throughput of available traversal
+    /// strategies over each of them.
+    #[cfg(feature = "bench")]
+    #[pg_extern]
+    fn bench(
+        sizes: default!(Vec<i32>, "ARRAY[1000, 10000, 100000]"),
+    ) -> impl std::iter::Iterator<
+        Item = (
+            name!(rows, i32),
+            name!(strategy, String),
+            name!(pages, i64),
+            name!(elapsed_ms, f64),
+            name!(pages_per_sec, f64),
+        ),
+    > {
+        crate::bench::run(&sizes).into_iter().map(|r| {
+            (
+                r.rows,
+                r.strategy.name().to_string(),
+                r.pages as i64,
+                r.elapsed_ms,
+                r.pages_per_sec(),
+            )
+        })
+    }
+
+    /// Runs several analyses selected by a comma-separated `spec` (ie. `'stats,levels,health'`)
+    /// in a single walk over the index and returns their results combined in one JSON object.
+    #[pg_extern(stable, parallel_safe)]
+    fn report(rel_oid: pg_sys::Oid, spec: &str) -> JsonB {
+        let sections = crate::report::parse_spec(spec);
+        let index = crate::gist::IndexInspector::open(rel_oid).or_raise();
+        JsonB(index.report(&sections))
+    }
+
+    /// Compares a single-column btree_gist index with an equivalent B-tree and recommends
+    /// whether to convert it, with the numbers behind the recommendation.
+    #[pg_extern(stable, parallel_safe)]
+    fn index_advisor(rel_oid: pg_sys::Oid) -> JsonB {
+        let index = crate::gist::IndexInspector::open(rel_oid).or_raise();
+        JsonB(index.index_advice().to_json())
+    }
+
+    /// Ranks all GiST indexes of the current database by how urgently they need maintenance:
+    /// their estimated bloat, weighted by how heavily they are used according to
+    /// `pg_stat_user_indexes`. Health of each index is estimated from `sample_size` leaf pages.
+    #[pg_extern(stable)]
+    fn prioritized_maintenance(
+        sample_size: default!(i32, 100),
+    ) -> impl std::iter::Iterator<
+        Item = (
+            name!(rank, i32),
+            name!(index_oid, pg_sys::Oid),
+            name!(index_name, String),
+            name!(table_name, String),
+            name!(verdict, String),
+            name!(health_score, f64),
+            name!(bloat_pct, f64),
+            name!(pages, i64),
+            name!(pages_vs_optimal, f64),
+            name!(idx_scan, i64),
+            name!(idx_tup_read, i64),
+            name!(usage_weight, f64),
+            name!(maintenance_score, f64),
+        ),
+    > {
+        let items = crate::workload::prioritized_maintenance(sample_size.max(0) as usize);
+        items.into_iter().enumerate().map(|(i, item)| {
+            (
+                i as i32 + 1,
+                item.oid,
+                item.index,
+                item.table,
+                item.summary.verdict().to_string(),
+                item.summary.health_score,
+                item.summary.bloat_pct,
+                item.summary.pages as i64,
+                item.summary.pages_vs_optimal,
+                item.usage.idx_scan as i64,
+                item.usage.idx_tup_read as i64,
+                item.usage.weight(),
+                item.score,
+            )
+        })
+    }
+
+    /// Validates an inspection profile and saves it under a given name, replacing the previous
+    /// one, see [crate::profile::Profile].
+    #[pg_extern]
+    fn save_profile(name: &str, profile: JsonB) {
+        crate::profile::save(name, &profile.0)
+    }
+
+    /// Runs analyses of a profile saved with `gevel.save_profile` and returns their results in
+    /// the format chosen by the profile.
+    #[pg_extern(stable, parallel_safe)]
+    fn run_profile(rel_oid: pg_sys::Oid, name: &str) -> String {
+        let profile = crate::profile::load(name);
+        let index = crate::gist::IndexInspector::open(rel_oid).or_raise();
+        index.run_profile(&profile)
+    }
+
+    /// Runs analyses named the same way as in profiles (ie. `'{stats,tree,check}'`) while
+    /// writes to the table of the index are blocked, so that all of them describe the very
+    /// same structure, and returns their results combined in one JSON object.
+    #[pg_extern]
+    fn with_lock(rel_oid: pg_sys::Oid, analyses: Vec<String>) -> JsonB {
+        JsonB(crate::profile::with_lock(rel_oid, &analyses).or_raise())
+    }
+
+    /// Returns the same tree as `gist_tree_json` as an XML document in text form, which is
+    /// parsed into the `xml` type by `gist_tree_xml`.
+    #[pg_extern(stable, parallel_safe)]
+    fn tree_xml(rel_oid: pg_sys::Oid, include_items: default!(bool, false)) -> String {
+        let index = crate::gist::IndexInspector::open(rel_oid).or_raise();
+        let tree = index.get_tree(&crate::gist::TreeOptions {
+            include_items,
+            ..crate::gist::TreeOptions::default()
+        });
+        let options = crate::gist::JsonOptions::default();
+        let (json, truncated) = tree.to_json_capped(&options, crate::output::max_output_bytes());
+        if truncated {
+            crate::output::notice_truncated("gist_tree_xml");
+        }
+        crate::xml::tree_to_xml(&json)
+    }
+
+    /// Returns statistics, per-level statistics, the occupancy histogram, the worst pages and
+    /// the health verdict of an index as a single JSON object, computed in one walk.
+    #[pg_extern(stable, parallel_safe)]
+    fn dashboard(rel_oid: pg_sys::Oid) -> JsonB {
+        let index = crate::gist::IndexInspector::open(rel_oid).or_raise();
+        JsonB(index.dashboard())
+    }
+
+    /// Returns the number of pages visited by scans of GiST indexes of the current database,
+    /// recorded since the server start or the last reset. Reads shared memory updated by other
+    /// backends, so it can't be STABLE.
+    #[cfg(not(feature = "testing"))]
+    #[pg_extern]
+    fn scan_stats() -> impl std::iter::Iterator<
+        Item = (
+            name!(index_oid, pg_sys::Oid),
+            name!(executions, i64),
+            name!(scans, i64),
+            name!(pages, i64),
+            name!(avg_pages_per_scan, f64),
+            name!(histogram, JsonB),
+        ),
+    > {
+        crate::scanstats::scan_stats().into_iter().map(|s| {
+            (
+                s.index,
+                s.executions as i64,
+                s.scans as i64,
+                s.pages as i64,
+                s.avg_pages_per_scan(),
+                JsonB(s.histogram_json()),
+            )
+        })
+    }
+
+    /// Discards scan statistics of all databases.
+    #[cfg(not(feature = "testing"))]
+    #[pg_extern]
+    fn scan_stats_reset() {
+        crate::scanstats::reset()
+    }
+
+    /// Summarizes GiST indexes of all databases with background workers and stores the
+    /// summaries in `gevel.fleet_stats`, see [crate::fleet::scan_all_databases]. Superuser only.
+    #[cfg(not(feature = "testing"))]
+    #[pg_extern]
+    fn scan_all_databases(
+        sample_size: default!(i32, 100),
+    ) -> impl std::iter::Iterator<
+        Item = (
+            name!(database, String),
+            name!(index_name, String),
+            name!(table_name, String),
+            name!(verdict, String),
+            name!(health_score, f64),
+            name!(bloat_pct, f64),
+            name!(pages, i64),
+            name!(invalid_tuples, i64),
+            name!(maintenance_score, f64),
+        ),
+    > {
+        let entries = crate::fleet::scan_all_databases(sample_size.max(0) as usize);
+        entries.into_iter().map(|e| {
+            (
+                e.database,
+                e.index,
+                e.table,
+                e.verdict,
+                e.health_score,
+                e.bloat_pct,
+                e.pages as i64,
+                e.invalid_tuples as i64,
+                e.maintenance_score,
+            )
+        })
+    }
+
+    /// Compares a previously saved raw image of a page (ie. obtained with pageinspect's
+    /// `get_raw_page`) with the current contents of that page.
+    #[pg_extern(stable, parallel_safe)]
+    fn page_diff(
+        rel_oid: pg_sys::Oid,
+        blkno: i64,
+        before: &[u8],
+    ) -> impl std::iter::Iterator<
+        Item = (
+            name!(kind, String),
+            name!(field, String),
+            name!(before, Option<String>),
+            name!(after, Option<String>),
+        ),
+    > {
+        let index = crate::gist::IndexInspector::open(rel_oid).or_raise();
+        let diffs = index.page_diff(blkno, before).or_raise();
+        diffs
+            .into_iter()
+            .map(|d| (d.kind.to_string(), d.field, d.before, d.after))
+    }
+
+    /// Creates `gevel.demo_points` table with a given number of points and a GiST index over
+    /// them. Points are random, unless one of adversarial datasets is chosen. Returns the oid
+    /// of that index.
+    #[pg_extern]
+    fn demo_setup(rows: i32, dataset: default!(&str, "'random'")) -> pg_sys::Oid {
+        crate::demo::setup_dataset(rows, crate::demo::parse_dataset(dataset))
+    }
+}
+
 /// Wrapper around PostgreSQL page buffer.
-struct Buffer(pg_sys::Buffer);
+struct Buffer {
+    id: pg_sys::Buffer,
+    /// Relation and block the buffer has been read from, to tell where errors come from.
+    relation: Relation,
+    block_num: BlockNumber,
+}
 
 impl Buffer {
     fn new(rel: Relation, blk: BlockNumber) -> Self {
-        Buffer(unsafe { ReadBuffer(rel, blk) })
+        Buffer {
+            id: unsafe { ReadBuffer(rel, blk) },
+            relation: rel,
+            block_num: blk,
+        }
+    }
+
+    /// Copies contents of the buffer into local memory while holding a shared content lock on
+    /// it, so that the copy is never torn by a concurrent modification of the page. The lock is
+    /// only held for the copy, so writers are never blocked while the page is inspected.
+    fn copy_page(&self) -> Vec<u64> {
+        let block_size = BLCKSZ as usize;
+        let mut words = vec![0u64; block_size / size_of::<u64>()];
+        unsafe {
+            LockBuffer(self.id, BUFFER_LOCK_SHARE as i32);
+            std::ptr::copy_nonoverlapping(
+                BufferGetPage(self.id) as *const u8,
+                words.as_mut_ptr() as *mut u8,
+                block_size,
+            );
+            LockBuffer(self.id, BUFFER_LOCK_UNLOCK as i32);
+        }
+        words
     }
 }
 
 impl Drop for Buffer {
     fn drop(&mut self) {
-        unsafe { ReleaseBuffer(self.0) }
+        unsafe { ReleaseBuffer(self.id) }
     }
 }
 
-pub static PAGE_SIZE: u32 = BLCKSZ
-    - ((((size_of::<PageHeaderData>() + size_of::<ItemIdData>()) as u32) + (MAXIMUM_ALIGNOF - 1))
-        & !(MAXIMUM_ALIGNOF - 1));
-
 pub const GIST_ROOT_BLKNO: BlockNumber = 0;
 pub const TUPLE_IS_INVALID: OffsetNumber = 0xfffe;
 
+/// Memory holding the contents of a [Page]. Both variants store the page as words, so that
+/// page structures are properly aligned. Words are only kept alive and measured here, they
+/// are accessed through the page pointer.
+enum PageMemory {
+    /// Copy of a block of an index, see [Buffer::copy_page]. The relation and the block it
+    /// has been read from tell where errors come from.
+    Block {
+        words: Vec<u64>,
+        relation: Relation,
+        block_num: BlockNumber,
+    },
+    /// Page image in local memory.
+    #[cfg_attr(not(any(test, feature = "pg_test")), allow(dead_code))]
+    Image(Vec<u64>),
+}
+
+impl PageMemory {
+    /// Returns the size of the page in bytes.
+    fn size(&self) -> usize {
+        match self {
+            PageMemory::Block { words, .. } | PageMemory::Image(words) => {
+                words.len() * size_of::<u64>()
+            }
+        }
+    }
+}
+
 /// Wrapper around PostgreSQL Page, equipped with convenient safe API for common operations.
-struct Page(pg_sys::Page, Buffer); // keep the buffer around, so it's not prematurely released
+struct Page(pg_sys::Page, PageMemory);
+
+/// Iterator over offsets of all line pointers of a page, starting from `FirstOffsetNumber`.
+/// Empty pages yield no offsets, and neither do line pointers which wouldn't fit into the page
+/// (ie. because of a corrupted `pd_lower`).
+pub struct OffsetRange {
+    next: OffsetNumber,
+    max: OffsetNumber,
+}
+
+impl OffsetRange {
+    fn new(max_offset: OffsetNumber, page_size: usize) -> Self {
+        OffsetRange {
+            next: pg_sys::FirstOffsetNumber,
+            max: max_offset.min(max_items(page_size) as OffsetNumber),
+        }
+    }
+}
+
+impl Iterator for OffsetRange {
+    type Item = OffsetNumber;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next > self.max {
+            return None;
+        }
+        let offset = self.next;
+        self.next += 1;
+        Some(offset)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.max + 1).saturating_sub(self.next) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for OffsetRange {}
 
 impl Page {
+    /// Creates a page from a copy of a buffer's contents. The buffer is released right after,
+    /// so pages can be kept around for as long as needed.
     pub fn new(buf: Buffer) -> Self {
-        let page_ptr = unsafe { BufferGetPage(buf.0) };
-        Page(page_ptr, buf)
+        let mut words = buf.copy_page();
+        let page_ptr = words.as_mut_ptr() as pg_sys::Page;
+        Page(
+            page_ptr,
+            PageMemory::Block {
+                words,
+                relation: buf.relation,
+                block_num: buf.block_num,
+            },
+        )
+    }
+
+    /// Creates a page from a copy of a raw page image, which must be as long as a page of one of
+    /// the block sizes Postgres can be built with (1KB to 32KB). It doesn't have to match
+    /// `BLCKSZ`, so that pages of other block sizes can be tested.
+    #[cfg(any(test, feature = "pg_test"))]
+    pub fn from_image(bytes: &[u8]) -> Self {
+        assert!(
+            bytes.len().is_power_of_two() && (1024..=32768).contains(&bytes.len()),
+            "invalid page image size"
+        );
+        let mut words: Vec<u64> = bytes
+            .chunks(size_of::<u64>())
+            .map(|chunk| {
+                let mut word = [0u8; 8];
+                word.copy_from_slice(chunk);
+                u64::from_ne_bytes(word)
+            })
+            .collect();
+        let page_ptr = words.as_mut_ptr() as pg_sys::Page;
+        Page(page_ptr, PageMemory::Image(words))
+    }
+
+    /// Fails with a corruption error naming the page, ie. `block 5 of index "foo"`. Pages are
+    /// read deep inside of traversals, so errors are raised right away instead of being
+    /// returned through every visitor.
+    fn corrupted(&self, message: &str) -> ! {
+        let message = message.to_string();
+        match &self.1 {
+            PageMemory::Block {
+                relation,
+                block_num,
+                ..
+            } => {
+                let name = unsafe { CStr::from_ptr((*(**relation).rd_rel).relname.data.as_ptr()) };
+                Error::CorruptPage {
+                    index: name.to_string_lossy().into_owned(),
+                    blkno: *block_num,
+                    message,
+                }
+            }
+            PageMemory::Image(_) => Error::CorruptPageImage { message },
+        }
+        .raise()
     }
 
     fn header(&self) -> &PageHeaderData {
-        unsafe { (self.0 as *mut PageHeaderData).as_ref() }.expect("PageHeader was NULL")
+        unsafe { (self.0 as *mut PageHeaderData).as_ref() }
+            .unwrap_or_else(|| self.corrupted("page is not mapped into memory"))
+    }
+
+    /// Returns the LSN of the last WAL record which modified this page.
+    pub fn lsn(&self) -> u64 {
+        let lsn = self.header().pd_lsn;
+        ((lsn.xlogid as u64) << 32) | lsn.xrecoff as u64
     }
 
     pub fn max_offset(&self) -> u16 {
@@ -77,47 +1366,117 @@ impl Page {
         }
     }
 
+    /// Returns true for pages which have never been initialized, ie. left all zeros by extending
+    /// the relation. Such pages have no header, line pointers nor special space, same as in
+    /// `PageIsNew`.
+    pub fn is_new(&self) -> bool {
+        self.header().pd_upper == 0
+    }
+
+    /// Returns the size of the page in bytes, the block size of the server for pages read
+    /// from indexes.
+    pub fn size(&self) -> usize {
+        self.1.size()
+    }
+
     pub fn is_special(&self) -> bool {
         match unsafe { (self.0 as *mut PageHeaderData).as_ref() } {
             None => false,
             Some(p) => {
                 let i = p.pd_special;
-                if i <= BLCKSZ as u16 && i >= offset_of!(PageHeaderData, pd_linp) as u16 {
-                    true
-                } else {
-                    false
-                }
+                i as usize <= self.size() && i >= offset_of!(PageHeaderData, pd_linp) as u16
             }
         }
     }
 
+    /// Returns the special space of the page interpreted as `T`. Fails with a corruption error
+    /// if the page has never been initialized or `T` doesn't fit into its special space.
     pub fn as_special<T>(&self) -> &T {
-        assert!(self.is_special());
-        unsafe {
-            let ptr = self.0 as *mut u8;
-            let header = self.header();
-            let special = ptr.offset(header.pd_special as isize) as *mut T;
-            special.as_ref().expect("Couldn't map page to special")
+        if self.is_new() {
+            self.corrupted("page is new and has never been initialized");
+        }
+        let special = self.header().pd_special as usize;
+        if !self.is_special() || special + size_of::<T>() > self.size() {
+            self.corrupted(&format!(
+                "special space offset {} is out of bounds",
+                special
+            ));
         }
+        unsafe { &*((self.0 as *const u8).add(special) as *const T) }
     }
 
+    /// Returns the line pointer at a given offset. Fails with a corruption error if the offset
+    /// lies outside of the page, see [OffsetRange].
     pub fn item_id(&self, offset: usize) -> ItemIdData {
+        if offset == 0 || offset > max_items(self.size()) {
+            self.corrupted(&format!("line pointer {} is out of bounds", offset));
+        }
         let pd_linp = unsafe { self.header().pd_linp.as_slice(offset) };
         pd_linp[offset - 1]
     }
 
-    pub fn get_index_tuple(&self, item_id: ItemIdData) -> IndexTuple {
-        let data = unsafe {
-            let ptr = (self.0 as *mut u8).offset(item_id.lp_off() as isize) as *mut IndexTupleData;
-            ptr.as_ref()
+    /// Returns offsets of all line pointers of the page, see [OffsetRange].
+    pub fn offsets(&self) -> OffsetRange {
+        OffsetRange::new(self.max_offset(), self.size())
+    }
+
+    /// Returns all tuples of the page together with their offsets. Line pointers which don't
+    /// point to any tuple (unused or redirect ones) and the ones pointing outside of the page
+    /// are skipped, while dead ones are returned, since their tuples are still there.
+    pub fn tuples(&self) -> impl Iterator<Item = (OffsetNumber, IndexTuple<'_>)> + '_ {
+        self.offsets()
+            .filter_map(move |offset| Some((offset, self.tuple_at(offset)?)))
+    }
+
+    /// Returns the tuple pointed by the line pointer at a given offset, `None` if there's no
+    /// tuple there, see [Page::tuples].
+    pub fn tuple_at(&self, offset: OffsetNumber) -> Option<IndexTuple<'_>> {
+        let iid = self.item_id(offset as usize);
+        let has_storage = matches!(iid.lp_flags(), LP_NORMAL | LP_DEAD) && iid.lp_len() > 0;
+        let end = iid.lp_off() as usize + (iid.lp_len() as usize).max(size_of::<IndexTupleData>());
+        if has_storage && end <= self.size() {
+            Some(self.get_index_tuple(iid))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the tuple pointed by a line pointer. Fails with a corruption error if the tuple
+    /// would lie outside of the page.
+    pub fn get_index_tuple(&self, item_id: ItemIdData) -> IndexTuple<'_> {
+        let (offset, len) = (item_id.lp_off() as usize, item_id.lp_len() as usize);
+        if offset + len.max(size_of::<IndexTupleData>()) > self.size() {
+            self.corrupted(&format!(
+                "tuple of {} bytes at offset {} is out of bounds",
+                len, offset
+            ));
         }
-        .expect("PageGetItem failed");
+        let data = unsafe { &*((self.0 as *const u8).add(offset) as *const IndexTupleData) };
         IndexTuple(data)
     }
 
+    /// Returns raw bytes of the whole page.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.0 as *const u8, self.size()) }
+    }
+
     pub fn free_space(&self) -> usize {
         unsafe { PageGetFreeSpace(self.0) }
     }
+
+    /// Returns the number of bytes available for tuples on this page, which is the space
+    /// between the page header and the special space, minus the line pointer which
+    /// [Page::free_space] always reserves. A completely full page has `free_space() == 0`
+    /// and occupies the whole capacity. With `gevel.legacy_occupancy` enabled, the fixed
+    /// legacy page size (see [legacy_page_size]) is returned instead.
+    pub fn capacity(&self) -> usize {
+        if guc::LEGACY_OCCUPANCY.get() {
+            return legacy_page_size(self.size());
+        }
+        let header = self.header();
+        let header_size = offset_of!(PageHeaderData, pd_linp);
+        (header.pd_special as usize).saturating_sub(header_size + size_of::<ItemIdData>())
+    }
 }
 
 pub struct IndexTuple<'a>(&'a IndexTupleData);
@@ -129,44 +1488,2653 @@ impl<'a> IndexTuple<'a> {
         (((block_id.bi_hi as u32) << 16) | (block_id.bi_lo as u32)) as BlockNumber
     }
 
+    /// Returns an offset number stored in the tuple's item pointer. For leaf tuples, together
+    /// with [IndexTuple::block_num] it points to a heap tuple.
+    pub fn offset_num(&self) -> OffsetNumber {
+        self.0.t_tid.ip_posid
+    }
+
+    /// Returns true if any of the tuple's key attributes is NULL.
+    pub fn has_nulls(&self) -> bool {
+        self.0.t_info as u32 & INDEX_NULL_MASK != 0
+    }
+
+    /// Returns a total size of the tuple in bytes, including its header.
+    pub fn size(&self) -> usize {
+        (self.0.t_info as u32 & INDEX_SIZE_MASK) as usize
+    }
+
     pub fn is_invalid(&self) -> bool {
         let ptr = self.0.t_tid;
         ptr.ip_posid == TUPLE_IS_INVALID
     }
 }
 
+/// Max number of line pointers which fit into a page of a given size.
+fn max_items(page_size: usize) -> usize {
+    (page_size - offset_of!(PageHeaderData, pd_linp)) / size_of::<ItemIdData>()
+}
+
+/// Page size used by the legacy occupancy math for pages of a given size: the page minus its
+/// header and a single line pointer, ignoring the special space.
+fn legacy_page_size(page_size: usize) -> usize {
+    let align = pg_sys::MAXIMUM_ALIGNOF as usize;
+    let header = size_of::<PageHeaderData>() + size_of::<ItemIdData>();
+    page_size - ((header + align - 1) & !(align - 1))
+}
+
+/// Returns size (in bytes) of a given relation fork ("main", "fsm", "vm" or "init"). Forks
+/// that don't exist have size 0.
+fn fork_size(rel_oid: Oid, fork: &str) -> u64 {
+    let query = format!("SELECT pg_relation_size({}, '{}')", rel_oid, fork);
+    Spi::get_one::<i64>(&query).unwrap_or(0) as u64
+}
+
+#[cfg(not(feature = "pg10"))]
 #[inline(always)]
 fn range_var_get_rel_id(rel_var: &RangeVar, lock_mode: u32) -> Oid {
     unsafe { RangeVarGetRelidExtended(rel_var, lock_mode as i32, 0, None, null_mut()) }
 }
 
-//#[pg_extern]
-//pub fn gist_print() {
-//    todo!()
-//}
-//
-//#[pg_extern]
-//pub fn gist_stat() {
-//    todo!()
-//}
+// flags replaced missing_ok and nowait arguments in Postgres 11
+#[cfg(feature = "pg10")]
+#[inline(always)]
+fn range_var_get_rel_id(rel_var: &RangeVar, lock_mode: u32) -> Oid {
+    unsafe { RangeVarGetRelidExtended(rel_var, lock_mode as i32, false, false, None, null_mut()) }
+}
+
+/// Resolves a possibly schema-qualified (and quoted) relation name, following `search_path`
+/// for unqualified ones. Fails if there's no such relation. The relation is not locked, it's
+/// up to [IndexInspector::open] to do so.
+fn index_oid(index_name: &str) -> Oid {
+    let name = CString::new(index_name).unwrap_or_else(|_| {
+        raise(
+            ErrorClass::InvalidArgument,
+            "index name contains a NUL byte",
+        )
+    });
+    let rel_var =
+        unsafe { makeRangeVarFromNameList(stringToQualifiedNameList(name.as_ptr())).as_ref() }
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!("invalid index name: \"{}\"", index_name))
+            })
+            .or_raise();
+    range_var_get_rel_id(rel_var, NoLock)
+}
 
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
+    use crate::demo::Dataset;
+    use crate::error::OrRaise;
+    use crate::gist::{IndexInspector, Stats, TreeOptions};
+    use pgx::*;
 
-    //#[pg_test]
-    //fn test_hello_gevel() {
-    //    assert_eq!("Hello, gevel", crate::hello_gevel());
-    //}
-}
+    #[pg_test]
+    fn test_invalid_tuples_counted_per_page_kind() {
+        let oid = crate::demo::setup(10_000);
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert_eq!(
+            stats.num_invalid_tuple,
+            stats.num_invalid_internal_tuple + stats.num_invalid_leaf_tuple
+        );
+        // invalid tuples can only be produced by crash recovery of pre-9.1 versions
+        assert_eq!(stats.num_invalid_tuple, 0);
+        assert_eq!(stats.num_dead_tuple, 0);
 
-#[cfg(test)]
-pub mod pg_test {
-    //pub fn setup(_options: Vec<&str>) {
-    //    // perform one-off initialization when the pg_test framework starts
-    //}
-    //
-    //pub fn postgresql_conf_options() -> Vec<&'static str> {
-    //    // return any postgresql.conf settings that are required for your tests
-    //    vec![]
-    //}
+        use crate::walker::{PageVisit, Visitor};
+        let index = IndexInspector::open(oid).unwrap();
+        let mut collector = crate::gist::StatsCollector::new(index.relation, None);
+        let (normal, dead) = (pg_sys::LP_NORMAL, pg_sys::LP_DEAD);
+        let leaf = gist_page_image(
+            true,
+            &[
+                (normal, true),
+                (normal, true),
+                (dead, false),
+                (normal, false),
+            ],
+        );
+        let internal = gist_page_image(false, &[(normal, true), (dead, false), (dead, true)]);
+        for (level, image) in [(1, leaf), (0, internal)].iter() {
+            let page = crate::Page::from_image(image);
+            let gist_page = crate::gist::GistPage::new(&page);
+            let visit = PageVisit {
+                level: *level,
+                block_num: *level as u32,
+                offset: 0,
+                page: &page,
+                gist_page: &gist_page,
+            };
+            for (offset, tuple) in page.tuples() {
+                collector.on_tuple(&visit, offset, &tuple);
+            }
+        }
+        let (stats, _) = collector.into_parts();
+        assert_eq!(stats.num_invalid_leaf_tuple, 2);
+        // dead tuples are counted as dead, even if they're invalid too
+        assert_eq!(stats.num_invalid_internal_tuple, 1);
+        assert_eq!(stats.num_invalid_tuple, 3);
+        assert_eq!(stats.num_dead_tuple, 3);
+    }
+
+    #[cfg(feature = "bench")]
+    #[pg_test]
+    fn bench_traversal_strategies() {
+        let results = crate::bench::run(&[1_000, 10_000, 100_000]);
+        for r in results.iter() {
+            info!(
+                "{} rows, {}: {} pages in {:.3}ms ({:.0} pages/s)",
+                r.rows,
+                r.strategy.name(),
+                r.pages,
+                r.elapsed_ms,
+                r.pages_per_sec()
+            );
+            assert!(r.pages > 0);
+        }
+    }
+
+    #[pg_test]
+    fn test_stats_counters_dont_overflow() {
+        let mut stats = Stats::default();
+        // more pages than fit into 32-bit block numbers
+        stats.num_pages = u32::MAX as u64 + 10;
+        stats.num_tuple = u32::MAX as u64 * 100;
+        stats.total_size = (u32::MAX as u64 + 10) * pg_sys::BLCKSZ as u64;
+        stats.tuple_size = stats.total_size - 1;
+        stats.add_page(3, true, u16::MAX, 100, 8000, 0);
+
+        assert_eq!(stats.num_pages, u32::MAX as u64 + 11);
+        assert_eq!(stats.num_tuple, u32::MAX as u64 * 100 + u16::MAX as u64);
+        assert_eq!(
+            stats.total_size,
+            (u32::MAX as u64 + 11) * pg_sys::BLCKSZ as u64
+        );
+        assert_eq!(
+            stats.tuple_size,
+            stats.total_size - pg_sys::BLCKSZ as u64 - 1 + 7900
+        );
+        assert!(stats.to_string().contains(&format!(
+            "Number of pages:           {}",
+            u32::MAX as u64 + 11
+        )));
+    }
+
+    #[pg_test]
+    fn test_usage_weight() {
+        use crate::workload::IndexUsage;
+
+        let unused = IndexUsage::default();
+        assert_eq!(unused.weight(), 1.0);
+        let busy = IndexUsage {
+            idx_scan: 999,
+            idx_tup_read: 99_000,
+        };
+        assert!((busy.weight() - 6.0).abs() < 1e-9);
+
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let summary = index.summary(100);
+        assert!(busy.maintenance_score(&summary) > unused.maintenance_score(&summary));
+        let report = index.report(&[crate::report::Section::Health]);
+        assert!(
+            report["health"]["workload"]["usage_weight"]
+                .as_f64()
+                .unwrap()
+                >= 1.0
+        );
+    }
+
+    #[pg_test]
+    fn test_prioritized_maintenance() {
+        let oid = crate::demo::setup(10_000);
+        Spi::run("CREATE INDEX demo_points_p_idx2 ON gevel.demo_points USING gist(p)");
+        let items = crate::workload::prioritized_maintenance(100);
+        assert!(items.iter().any(|i| i.oid == oid));
+        assert!(items
+            .iter()
+            .any(|i| i.index == "gevel.demo_points_p_idx2" && i.table == "gevel.demo_points"));
+        assert!(items.windows(2).all(|w| w[0].score >= w[1].score));
+        let ranks = Spi::get_one::<i64>(
+            "SELECT count(*) FROM gevel.prioritized_maintenance() WHERE rank = 1",
+        );
+        assert_eq!(ranks, Some(1));
+    }
+
+    #[pg_test]
+    fn test_report_composes_sections() {
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let sections = crate::report::parse_spec(" Stats, levels,histogram,health,stats");
+        let report = index.report(&sections);
+        let stats = index.stats(None);
+
+        assert_eq!(report["stats"]["pages"], stats.num_pages);
+        let level_pages: u64 = report["levels"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l["pages"].as_u64().unwrap())
+            .sum();
+        assert_eq!(level_pages, stats.num_pages);
+        let histogram_pages: u64 = report["histogram"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b["pages"].as_u64().unwrap())
+            .sum();
+        assert_eq!(histogram_pages, stats.num_pages);
+        assert!(report["health"]["health_score"].as_f64().unwrap() > 0.0);
+        assert!(report.get("signatures").is_none());
+    }
+
+    #[pg_test]
+    fn test_dashboard() {
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let dashboard = index.dashboard();
+        let stats = index.stats(None);
+        assert_eq!(dashboard["stats"]["pages"], stats.num_pages);
+        assert_eq!(
+            dashboard["levels"].as_array().unwrap().len(),
+            stats.level + 1
+        );
+        assert_eq!(dashboard["histogram"].as_array().unwrap().len(), 10);
+        assert_eq!(dashboard["verdict"], dashboard["health"]["verdict"]);
+
+        let worst = dashboard["worst_pages"].as_array().unwrap();
+        assert_eq!(worst.len(), crate::report::WORST_PAGES);
+        let free: Vec<u64> = worst.iter().map(|p| p["free"].as_u64().unwrap()).collect();
+        assert!(free.windows(2).all(|w| w[0] >= w[1]), "{:?}", free);
+    }
+
+    #[pg_test]
+    fn test_top_k() {
+        let mut top = crate::walker::TopK::new(3);
+        for i in [5, 1, 9, 7, 3, 8].iter() {
+            top.push(*i);
+        }
+        assert_eq!(top.len(), 3);
+        assert_eq!(top.into_sorted_vec(), vec![9, 8, 7]);
+
+        // every item takes 10 bytes, so only 2 of them fit into 25 bytes
+        let mut capped = crate::walker::TopK::with_memory_cap(5, 25, |_: &i32| 10);
+        for i in 0..10 {
+            capped.push(i);
+        }
+        assert_eq!(capped.into_sorted_vec(), vec![9, 8]);
+
+        let mut none = crate::walker::TopK::new(0);
+        none.push(1);
+        assert!(none.is_empty());
+    }
+
+    #[pg_test]
+    fn test_btree_estimate() {
+        let empty = crate::advisor::btree_shape(8192, 0, 4.0);
+        assert_eq!((empty.pages, empty.levels), (2, 1));
+        // int4 tuples take 20 bytes with line pointers, 366 of them fit into a leaf page
+        // filled up to 90%
+        let small = crate::advisor::btree_shape(8192, 366, 4.0);
+        assert_eq!((small.pages, small.levels), (2, 1));
+        let split = crate::advisor::btree_shape(8192, 367, 4.0);
+        assert_eq!((split.pages, split.levels), (4, 2));
+        let large = crate::advisor::btree_shape(8192, 1_000_000, 4.0);
+        assert_eq!(large.levels, 3);
+        assert!(large.pages > 1_000_000 / 366);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "index advisor only supports btree_gist opclasses")]
+    fn test_index_advisor_rejects_other_opclasses() {
+        let oid = crate::demo::setup(100);
+        IndexInspector::open(oid).unwrap().index_advice();
+    }
+
+    #[pg_test]
+    fn test_growth_history() {
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let history = index.growth_history(10);
+        let pages: u64 = history.buckets.iter().map(|b| b.pages).sum();
+        assert_eq!(pages + history.skipped, index.num_blocks() as u64);
+        let extended: u64 = history.buckets.iter().map(|b| b.extended).sum();
+        let blocks = history.buckets.last().map_or(0, |b| b.blocks);
+        assert_eq!(extended, blocks as u64);
+        assert!(blocks <= index.num_blocks());
+        assert!(history.buckets.len() <= 10);
+        assert!(history
+            .buckets
+            .windows(2)
+            .all(|w| w[0].lsn_to < w[1].lsn_from));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "buckets must be between 1 and 1000")]
+    fn test_growth_history_rejects_no_buckets() {
+        let oid = crate::demo::setup(100);
+        IndexInspector::open(oid).unwrap().growth_history(0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "canceling statement due to user request")]
+    fn test_walk_can_be_cancelled() {
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        // as set by the SIGINT handler when the query is cancelled, flags are a bool before
+        // Postgres 12 and sig_atomic_t since then
+        #[allow(clippy::useless_conversion)]
+        unsafe {
+            pg_sys::QueryCancelPending = true.into();
+            pg_sys::InterruptPending = true.into();
+        }
+        index.stats(None);
+    }
+
+    #[pg_test]
+    fn test_walk_visits_pages_depth_first() {
+        use crate::walker::{PageVisit, Visitor};
+        use crate::IndexTuple;
+        use pgx::pg_sys::{BlockNumber, OffsetNumber};
+
+        /// Mirrors the call stack of a recursive traversal: pages are entered in the order of
+        /// downlinks of their parent and left before the next sibling is entered.
+        #[derive(Default)]
+        struct PathChecker {
+            path: Vec<BlockNumber>,
+            downlinks: Vec<BlockNumber>,
+            pages: u64,
+            max_depth: usize,
+        }
+        impl Visitor for PathChecker {
+            fn on_page(&mut self, page: &PageVisit) -> bool {
+                assert_eq!(page.level, self.path.len());
+                if page.level > 0 {
+                    assert_eq!(self.downlinks.pop(), Some(page.block_num));
+                }
+                self.path.push(page.block_num);
+                self.pages += 1;
+                self.max_depth = self.max_depth.max(self.path.len());
+                true
+            }
+
+            fn descend(
+                &mut self,
+                _page: &PageVisit,
+                _offset: OffsetNumber,
+                tuple: &IndexTuple,
+            ) -> bool {
+                // children are entered right after their downlink
+                self.downlinks.push(tuple.block_num());
+                true
+            }
+
+            fn on_page_end(&mut self, page: &PageVisit) {
+                assert_eq!(self.path.pop(), Some(page.block_num));
+            }
+        }
+
+        let oid = crate::demo::setup(100_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let mut checker = PathChecker::default();
+        index.walk(&mut checker);
+        let stats = index.stats(None);
+        assert!(checker.path.is_empty() && checker.downlinks.is_empty());
+        assert_eq!(checker.pages, stats.num_pages);
+        assert_eq!(checker.max_depth, stats.level + 1);
+    }
+
+    #[pg_test]
+    fn test_blocks_are_visited_once() {
+        use crate::walker::{Link, VisitedBlocks};
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let last = index.num_blocks() - 1;
+
+        let mut visited = VisitedBlocks::new(&index);
+        assert!(visited.enter(&index, 0, Link::Start));
+        assert!(visited.enter(&index, last, Link::Downlink(0, 1)));
+        assert!(!visited.enter(&index, 0, Link::Downlink(last, 1)));
+        assert!(!visited.enter(&index, last, Link::Rightlink(0)));
+        assert!(!visited.enter(&index, last + 1, Link::Downlink(0, 2)));
+        assert!(!visited.enter(&index, pg_sys::InvalidBlockNumber - 1, Link::Rightlink(0)));
+
+        let rows = index.tree_rows();
+        let mut blocks: Vec<_> = rows.iter().map(|r| r.block_num).collect();
+        blocks.sort_unstable();
+        blocks.dedup();
+        assert_eq!(blocks.len(), rows.len());
+    }
+
+    #[pg_test]
+    fn test_walk_follows_pages_split_during_walk() {
+        use crate::walker::{PageVisit, Visitor};
+        use std::collections::HashSet;
+
+        /// Collects heap pointers of leaf tuples, splitting pages with inserts as soon as the
+        /// first leaf page is entered.
+        struct SplittingVisitor<'a> {
+            read_only: &'a crate::readonly::ReadOnlyCheck,
+            inserted: bool,
+            tids: HashSet<(u32, u16)>,
+        }
+
+        impl<'a> Visitor for SplittingVisitor<'a> {
+            fn on_page(&mut self, page: &PageVisit) -> bool {
+                if page.is_leaf() && !self.inserted {
+                    self.inserted = true;
+                    self.read_only.allow_writes(|| Spi::run("INSERT INTO gevel.demo_points(p) SELECT point(random() * 1000, random() * 1000) FROM generate_series(1, 10000)"));
+                }
+                true
+            }
+
+            fn on_tuple(
+                &mut self,
+                page: &PageVisit,
+                _offset: pg_sys::OffsetNumber,
+                tuple: &crate::IndexTuple,
+            ) {
+                if page.is_leaf() {
+                    self.tids.insert((tuple.block_num(), tuple.offset_num()));
+                }
+            }
+        }
+
+        let oid = crate::demo::setup(10_000);
+        let before = IndexInspector::open(oid).unwrap().num_blocks();
+        let ctids =
+            Spi::get_one::<String>("SELECT string_agg(ctid::text, ' ') FROM gevel.demo_points")
+                .unwrap();
+        let index = IndexInspector::open(oid).unwrap();
+        let mut visitor = SplittingVisitor {
+            read_only: &index.read_only,
+            inserted: false,
+            tids: HashSet::new(),
+        };
+        index.walk(&mut visitor);
+        assert!(index.num_blocks() > before);
+
+        // tuples moved to right halves of pages split after their parents were read are
+        // still visited
+        for ctid in ctids.split(' ') {
+            let mut parts = ctid.trim_matches(|c| c == '(' || c == ')').split(',');
+            let blk: u32 = parts.next().unwrap().parse().unwrap();
+            let offset: u16 = parts.next().unwrap().parse().unwrap();
+            assert!(
+                visitor.tids.contains(&(blk, offset)),
+                "{} not visited",
+                ctid
+            );
+        }
+    }
+
+    #[pg_test]
+    fn test_summary_of_index_truncated_below_root() {
+        let oid = crate::demo::setup(100);
+        let index = IndexInspector::open(oid).unwrap();
+        unsafe { crate::sys::RelationTruncate(index.relation, 0) };
+        assert_eq!(index.leaf_level(), None);
+        let summary = index.summary(10);
+        assert_eq!(
+            (summary.levels, summary.pages, summary.leaf_pages),
+            (0, 0, 0)
+        );
+    }
+
+    #[pg_test]
+    fn test_key_outliers_are_sorted() {
+        let oid = crate::demo::setup(10_000);
+        let outliers = IndexInspector::open(oid).unwrap().key_outliers(5);
+        assert_eq!(outliers.len(), 5);
+        assert!(outliers.windows(2).all(|w| w[0].size >= w[1].size));
+    }
+
+    #[pg_test]
+    fn test_identical_siblings_are_grouped() {
+        Spi::run("CREATE TABLE gevel.same_points(p point)");
+        Spi::run("INSERT INTO gevel.same_points SELECT point(1, 1) FROM generate_series(1, 10000)");
+        Spi::run("CREATE INDEX same_points_idx ON gevel.same_points USING gist(p)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.same_points_idx'::regclass::oid::bigint")
+            .unwrap() as pg_sys::Oid;
+
+        let index = IndexInspector::open(oid).unwrap();
+        let duplicates = index.sibling_duplicates();
+        let root_children = index.tree_rows().iter().filter(|r| r.level == 1).count();
+        assert!(duplicates[0].groups.is_empty());
+        assert_eq!(duplicates[1].groups.len(), 1);
+        assert_eq!(duplicates[1].pages(), root_children);
+
+        let report = index.report(&crate::report::parse_spec("duplicates"));
+        assert_eq!(report["duplicates"][0]["level"], 1);
+        assert_eq!(report["duplicates"][0]["groups"], 1);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "unknown report section")]
+    fn test_report_rejects_unknown_section() {
+        crate::report::parse_spec("stats,bogus");
+    }
+
+    #[pg_test]
+    fn test_page_cache_avoids_rereads() {
+        let oid = crate::demo::setup(1_000);
+        let index = IndexInspector::open(oid).unwrap();
+        index.stats(None);
+        let misses = index.cache.misses();
+        assert_eq!(index.cache.hits(), 0);
+
+        // small index fits into the cache, so the second walk doesn't read anything
+        index.stats_by_level();
+        assert_eq!(index.cache.misses(), misses);
+        assert_eq!(index.cache.hits(), misses);
+    }
+
+    #[pg_test]
+    fn test_tree_markdown_has_row_per_page() {
+        let oid = crate::demo::setup(10_000);
+        let markdown = crate::gist_tree_markdown(oid);
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        let mut lines = markdown.lines();
+        assert_eq!(
+            lines.next(),
+            Some("| level | block | tuples | fill | rightlink |")
+        );
+        lines.next();
+        assert!(lines.next().unwrap().starts_with("| 0 | 0 |"));
+        // header and separator lines are not pages
+        assert_eq!(markdown.lines().count() as u64 - 2, stats.num_pages);
+    }
+
+    #[pg_test]
+    fn test_single_page_index_is_a_single_leaf() {
+        for &rows in [0, 10].iter() {
+            let oid = crate::demo::setup(rows);
+            let index = IndexInspector::open(oid).unwrap();
+
+            let json = index.get_tree(&TreeOptions::default()).to_json();
+            let root = &json["root"];
+            assert_eq!(root["level"], 0);
+            assert_eq!(root["blkno"], 0);
+            assert_eq!(root["tuples"], rows);
+            assert_eq!(root["is_leaf"], true);
+            assert!(root.get("children").is_none());
+
+            let text = index.get_tree(&TreeOptions::default()).to_string();
+            assert_eq!(text.lines().count(), 1);
+
+            let stats = index.stats(None);
+            assert_eq!(stats.level, 0);
+            assert_eq!(stats.num_pages, 1);
+            assert_eq!(stats.num_leaf_pages, 1);
+            assert_eq!(stats.num_leaf_tuple, rows as u64);
+            assert_eq!(stats.depth_imbalance, 0);
+
+            let summary = index.summary(crate::SUMMARY_SAMPLE_SIZE);
+            assert_eq!(summary.levels, 1);
+            assert_eq!(summary.pages, 1);
+            assert_eq!(summary.optimal_pages, 1);
+            assert_eq!(summary.optimal_levels, 1);
+        }
+    }
+
+    #[pg_test]
+    fn test_page_diff_reports_added_items() {
+        let oid = crate::demo::setup(10);
+        let before = {
+            let index = IndexInspector::open(oid).unwrap();
+            assert!(index
+                .page_diff(0, index.cache.get(0).as_bytes())
+                .unwrap()
+                .is_empty());
+            index.cache.get(0).as_bytes().to_vec()
+        };
+        Spi::run("INSERT INTO gevel.demo_points(p) VALUES (point(1, 1))");
+
+        let diffs = IndexInspector::open(oid)
+            .unwrap()
+            .page_diff(0, &before)
+            .unwrap();
+        let added: Vec<_> = diffs.iter().filter(|d| d.kind == "item_added").collect();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].field, "offset 11");
+        assert!(diffs
+            .iter()
+            .any(|d| d.kind == "header" && d.field == "lower"));
+        assert!(diffs.iter().all(|d| d.kind != "item_removed"));
+    }
+
+    #[pg_test]
+    fn test_pages_are_copied_out_of_buffers() {
+        let oid = crate::demo::setup(10);
+        let index = IndexInspector::open(oid).unwrap();
+        let page = index.cache.get(0);
+        let before = page.as_bytes().to_vec();
+        index
+            .read_only
+            .allow_writes(|| Spi::run("INSERT INTO gevel.demo_points(p) VALUES (point(1, 1))"));
+        // pages already read don't change under the inspection
+        assert_eq!(page.as_bytes(), &before[..]);
+        let after = IndexInspector::open(oid).unwrap().cache.get(0);
+        assert_ne!(after.as_bytes(), &before[..]);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "page image must be exactly")]
+    fn test_page_diff_rejects_truncated_image() {
+        let oid = crate::demo::setup(10);
+        IndexInspector::open(oid)
+            .unwrap()
+            .page_diff(0, &[0u8; 16])
+            .or_raise();
+    }
+
+    #[pg_test]
+    fn test_column_attribution() {
+        Spi::run("CREATE TABLE gevel.attribution(p point, b box)");
+        Spi::run(
+            "INSERT INTO gevel.attribution SELECT point(i % 100, i / 100), box(point(0, 0), point(1, 1)) FROM generate_series(1, 10000) i",
+        );
+        Spi::run("CREATE INDEX attribution_idx ON gevel.attribution USING gist(p, b)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.attribution_idx'::regclass::oid::bigint")
+            .unwrap() as pg_sys::Oid;
+
+        let attribution = IndexInspector::open(oid).unwrap().column_attribution();
+        let (p, b) = (&attribution.columns[0], &attribution.columns[1]);
+        assert_eq!(p.name, "p");
+        // both attributes store boxes of 4 doubles
+        assert_eq!(p.leaf_bytes, 10_000 * 32);
+        assert_eq!(b.leaf_bytes, p.leaf_bytes);
+        assert_eq!(attribution.bytes_pct(0), 50.0);
+        // the constant box never changes the choice of a subtree
+        assert!(attribution.choices > 0);
+        assert!(p.decisive_choices > 0);
+        assert_eq!(b.decisive_choices, 0);
+        assert_eq!(b.avg_penalty(), Some(0.0));
+    }
+
+    #[pg_test]
+    fn test_reindex_progress() {
+        let old = crate::demo::setup(10_000);
+        assert_eq!(crate::reindex::reindex_pair(old), None);
+        // same as the index created by the first phase of REINDEX CONCURRENTLY
+        Spi::run("CREATE INDEX demo_points_p_idx_ccnew ON gevel.demo_points USING gist(p)");
+        let new =
+            Spi::get_one::<i64>("SELECT 'gevel.demo_points_p_idx_ccnew'::regclass::oid::bigint")
+                .unwrap() as pg_sys::Oid;
+        let pair = crate::reindex::ReindexPair { old, new };
+        assert_eq!(crate::reindex::reindex_pair(old), Some(pair));
+        assert_eq!(crate::reindex::reindex_pair(new), Some(pair));
+
+        let progress = crate::reindex::reindex_progress(new).unwrap();
+        let leaf_tuples = progress.iter().find(|m| m.name == "leaf_tuples").unwrap();
+        assert_eq!(leaf_tuples.old, 10_000.0);
+        assert_eq!(leaf_tuples.new_pct(), Some(100.0));
+
+        // after the swap the old index is renamed, right before it's dropped
+        Spi::run("ALTER INDEX gevel.demo_points_p_idx RENAME TO demo_points_p_idx_ccold");
+        Spi::run("ALTER INDEX gevel.demo_points_p_idx_ccnew RENAME TO demo_points_p_idx");
+        assert_eq!(crate::reindex::reindex_pair(new), Some(pair));
+        let rows = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM gist_reindex_progress({}) WHERE metric = 'leaf_tuples' AND new_pct = 100",
+            new
+        ));
+        assert_eq!(rows, Some(1));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "is not being rebuilt by REINDEX CONCURRENTLY")]
+    fn test_reindex_progress_without_rebuild() {
+        let oid = crate::demo::setup(1000);
+        crate::reindex::reindex_progress(oid).or_raise();
+    }
+
+    #[pg_test]
+    fn test_key_clusters() {
+        Spi::run("CREATE TABLE gevel.clusters(b box)");
+        Spi::run(
+            "INSERT INTO gevel.clusters SELECT box(point(x, y), point(x + 1, y + 1)) FROM (SELECT i % 50 + 1e9 * (i % 2) x, i / 50 y FROM generate_series(1, 10000) i) t",
+        );
+        Spi::run("CREATE INDEX clusters_idx ON gevel.clusters USING gist(b)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.clusters_idx'::regclass::oid::bigint").unwrap()
+            as pg_sys::Oid;
+
+        let result = IndexInspector::open(oid).unwrap().key_clusters(2, 500);
+        assert_eq!(result.sampled, 500);
+        assert_eq!(result.clusters.len(), 2);
+        for cluster in result.clusters.iter() {
+            assert!(cluster.keys > 100, "{:?}", cluster);
+            let nearest = cluster.nearest_distance.unwrap();
+            assert!(nearest > cluster.avg_distance * 1000.0, "{:?}", cluster);
+        }
+        assert!((result.keys_pct(0) + result.keys_pct(1) - 100.0).abs() < 1e-9);
+
+        // identical keys can't be split
+        Spi::run("TRUNCATE gevel.clusters");
+        Spi::run("INSERT INTO gevel.clusters SELECT box(point(1, 1), point(2, 2)) FROM generate_series(1, 1000)");
+        Spi::run("REINDEX INDEX gevel.clusters_idx");
+        let result = IndexInspector::open(oid).unwrap().key_clusters(3, 100);
+        assert_eq!(result.clusters.len(), 1);
+        assert_eq!(result.clusters[0].keys, result.sampled);
+        assert_eq!(result.clusters[0].avg_distance, 0.0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "sample must be between k (10) and 2000, got 5")]
+    fn test_key_clusters_rejects_small_sample() {
+        let oid = crate::demo::setup(1000);
+        IndexInspector::open(oid).unwrap().key_clusters(10, 5);
+    }
+
+    #[pg_test]
+    fn test_profiles() {
+        let oid = crate::demo::setup(10_000);
+        let ping = Spi::get_one::<JsonB>(&format!(
+            "SELECT gevel.run_profile({}, 'health_ping')::jsonb",
+            oid
+        ))
+        .unwrap()
+        .0;
+        assert_eq!(
+            ping["summary"]["leaf_pages"],
+            serde_json::json!(
+                IndexInspector::open(oid)
+                    .unwrap()
+                    .stats(None)
+                    .num_leaf_pages
+            )
+        );
+
+        Spi::run(
+            r#"SELECT gevel.save_profile('shape', '{"analyses": ["Tree", "levels", "summary"], "max_level": 1, "format": "text"}')"#,
+        );
+        let saved =
+            Spi::get_one::<JsonB>("SELECT profile FROM gevel.profiles WHERE name = 'shape'")
+                .unwrap()
+                .0;
+        assert_eq!(
+            saved,
+            serde_json::json!({
+                "analyses": ["tree", "levels", "summary"],
+                "max_level": 1,
+                "sample_size": 1000,
+                "format": "text",
+            })
+        );
+        let text =
+            Spi::get_one::<String>(&format!("SELECT gevel.run_profile({}, 'shape')", oid)).unwrap();
+        let tree = text.find("tree:\n").unwrap();
+        let levels = text.find("\nlevels:\n").unwrap();
+        let summary = text.find("\nsummary:\n").unwrap();
+        assert!(tree < levels && levels < summary, "{}", text);
+        // only the root and its children are printed
+        assert!(text[tree..levels].contains("(l:1)"), "{}", text);
+        assert!(!text[tree..levels].contains("(l:2)"), "{}", text);
+
+        // saving under an existing name replaces the profile
+        Spi::run(r#"SELECT gevel.save_profile('shape', '{"analyses": ["stats"]}')"#);
+        let json = Spi::get_one::<JsonB>(&format!(
+            "SELECT gevel.run_profile({}, 'shape')::jsonb",
+            oid
+        ))
+        .unwrap()
+        .0;
+        assert_eq!(
+            json.as_object().unwrap().keys().collect::<Vec<_>>(),
+            vec!["stats"]
+        );
+    }
+
+    #[pg_test]
+    fn test_count_check_releases_table_lock() {
+        let oid = crate::demo::setup(10_000);
+        let consistent =
+            Spi::get_one::<bool>(&format!("SELECT consistent FROM gist_count_check({})", oid))
+                .unwrap();
+        assert!(consistent);
+        let held = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_locks WHERE relation = 'gevel.demo_points'::regclass AND mode = 'ShareLock' AND pid = pg_backend_pid()",
+        )
+        .unwrap();
+        assert_eq!(held, 0);
+    }
+
+    #[pg_test]
+    fn test_with_lock() {
+        let oid = crate::demo::setup(10_000);
+        let result = Spi::get_one::<JsonB>(&format!(
+            "SELECT gevel.with_lock({}, '{{stats,tree,check}}')",
+            oid
+        ))
+        .unwrap()
+        .0;
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert_eq!(result["stats"]["pages"], serde_json::json!(stats.num_pages));
+        assert_eq!(result["tree"]["blkno"], serde_json::json!(0));
+        assert_eq!(result["check"], serde_json::json!([]));
+
+        // the table lock is released before returning
+        let table = Spi::get_one::<i64>(&format!(
+            "SELECT indrelid::bigint FROM pg_index WHERE indexrelid = {}",
+            oid
+        ))
+        .unwrap();
+        let held = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM pg_locks WHERE relation = {} AND mode = 'ShareLock' AND pid = pg_backend_pid()",
+            table
+        ))
+        .unwrap();
+        assert_eq!(held, 0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "unknown profile analysis: \"walk\"")]
+    fn test_with_lock_rejects_unknown_analyses() {
+        let oid = crate::demo::setup(1000);
+        Spi::run(&format!(
+            "SELECT gevel.with_lock({}, '{{stats,walk}}')",
+            oid
+        ));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "invalid profile: unknown field \"max_depth\"")]
+    fn test_profile_with_unknown_field_is_rejected() {
+        Spi::run(r#"SELECT gevel.save_profile('typo', '{"analyses": ["tree"], "max_depth": 2}')"#);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "profile \"missing\" does not exist")]
+    fn test_missing_profile_is_rejected() {
+        let oid = crate::demo::setup(1000);
+        Spi::run(&format!("SELECT gevel.run_profile({}, 'missing')", oid));
+    }
+
+    #[pg_test]
+    fn test_new_pages_are_skipped() {
+        let oid = crate::demo::setup(1000);
+        let before = IndexInspector::open(oid).unwrap().stats(None);
+        unsafe {
+            // extends the index with zeroed pages, same as a crash in the middle of a bulk
+            // operation would leave behind (InvalidBlockNumber is P_NEW)
+            let rel = pg_sys::index_open(oid, pg_sys::RowExclusiveLock as i32);
+            for _ in 0..3 {
+                pg_sys::ReleaseBuffer(pg_sys::ReadBuffer(rel, pg_sys::InvalidBlockNumber));
+            }
+            pg_sys::index_close(rel, pg_sys::RowExclusiveLock as i32);
+        }
+
+        let index = IndexInspector::open(oid).unwrap();
+        let new_pages = index.new_pages();
+        assert_eq!(new_pages.len(), 3);
+        assert!(index.page_items(new_pages[0] as i64).unwrap().is_empty());
+        let stats = index.stats(None);
+        assert_eq!(stats.num_new_pages, 3);
+        assert_eq!(stats.num_pages, before.num_pages);
+        assert!(stats.to_string().contains("Number of new pages:       3"));
+        let listed = Spi::get_one::<i64>(&format!("SELECT count(*) FROM gist_new_pages({})", oid));
+        assert_eq!(listed, Some(3));
+    }
+
+    #[pg_test]
+    fn test_stats_account_for_all_forks() {
+        let oid = crate::demo::setup(10_000);
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        let fork = |name: &str| {
+            Spi::get_one::<i64>(&format!("SELECT pg_relation_size({}, '{}')", oid, name)).unwrap()
+                as u64
+        };
+        assert_eq!(stats.total_size, fork("main"));
+        assert_eq!(stats.fsm_size, fork("fsm"));
+        // indexes have no visibility map
+        assert_eq!(stats.vm_size, 0);
+        // pages of a fresh index haven't been recorded in the FSM yet
+        assert!(stats.fsm_free_space <= stats.free_space);
+        let report = stats.to_string();
+        assert!(report.contains(&format!(
+            "Total size on disk:        {} bytes",
+            stats.total_size + stats.fsm_size + stats.vm_size
+        )));
+    }
+
+    #[pg_test]
+    fn test_summary_matches_exact_stats() {
+        let oid = crate::demo::setup(100_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let stats = index.stats(None);
+        // all internal pages are read and leaves are counted by their downlinks, so the shape
+        // of the tree is exact even when a single leaf is sampled
+        for &sample_size in [1, crate::SUMMARY_SAMPLE_SIZE].iter() {
+            let summary = index.summary(sample_size);
+            assert!(summary.is_valid);
+            assert_eq!(summary.levels, stats.level + 1);
+            assert_eq!(summary.pages, stats.num_pages);
+            assert_eq!(summary.leaf_pages, stats.num_leaf_pages);
+            assert_eq!(summary.invalid_tuples, 0);
+            assert!((0.0..100.0).contains(&summary.bloat_pct));
+            assert!((summary.health_score - (100.0 - summary.bloat_pct)).abs() < 1e-9);
+        }
+        let row = Spi::get_one::<bool>(&format!(
+            "SELECT is_valid AND levels = {} AND pages = {} FROM gist_summary({})",
+            stats.level + 1,
+            stats.num_pages,
+            oid
+        ));
+        assert_eq!(row, Some(true));
+    }
+
+    #[pg_test]
+    fn test_invalid_index_is_reported() {
+        let oid = crate::demo::setup(10_000);
+        // as left behind by a failed CREATE INDEX CONCURRENTLY
+        Spi::run(&format!(
+            "UPDATE pg_index SET indisvalid = false WHERE indexrelid = {}",
+            oid
+        ));
+        let index = IndexInspector::open(oid).unwrap();
+        assert!(!index.is_valid());
+        let stats = index.stats(None);
+        assert!(!stats.is_valid);
+        let report = stats.to_string();
+        assert!(report.starts_with("WARNING: index is marked as invalid\n"));
+        assert!(report.contains("Index is valid:            false"));
+        assert!(crate::gist_tree(oid, false).starts_with("WARNING: index is marked as invalid"));
+
+        let summary = index.summary(crate::SUMMARY_SAMPLE_SIZE);
+        assert!(!summary.is_valid);
+        assert_eq!(summary.health_score, 0.0);
+        assert_eq!(summary.verdict(), "critical");
+        let row = Spi::get_one::<bool>(&format!("SELECT is_valid FROM gist_summary({})", oid));
+        assert_eq!(row, Some(false));
+    }
+
+    #[pg_test]
+    fn test_stat_by_level_separates_internal_and_leaf_keys() {
+        let oid = crate::demo::setup(100_000);
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        let levels =
+            Spi::get_one::<i64>(&format!("SELECT count(*) FROM gist_stat_by_level({})", oid));
+        assert_eq!(levels, Some(stats.level as i64 + 1));
+        // levels above leaves only hold unions of their children
+        let internal = Spi::get_one::<bool>(&format!(
+            "SELECT bool_and(leaf_tuples = 0 AND leaf_avg_key_size IS NULL \
+                 AND internal_min_key_size <= internal_avg_key_size \
+                 AND internal_avg_key_size <= internal_max_key_size) \
+             FROM gist_stat_by_level({}) WHERE level < {}",
+            oid, stats.level
+        ));
+        assert_eq!(internal, Some(true));
+        let leaves = Spi::get_one::<bool>(&format!(
+            "SELECT leaf_tuples = {} AND internal_tuples = 0 AND internal_avg_key_size IS NULL \
+                 AND leaf_min_key_size <= leaf_avg_key_size AND leaf_avg_key_size <= leaf_max_key_size \
+             FROM gist_stat_by_level({}) WHERE level = {}",
+            stats.num_leaf_tuple, oid, stats.level
+        ));
+        assert_eq!(leaves, Some(true));
+    }
+
+    #[pg_test]
+    fn test_page_items_decode_storage_flags() {
+        let oid = crate::demo::setup(10_000);
+        Spi::run("INSERT INTO gevel.demo_points(p) SELECT NULL FROM generate_series(1, 10)");
+        // keys of the root are unions of their children
+        let root = Spi::get_one::<bool>(&format!(
+            "SELECT bool_and(item_state = 'normal' AND NOT is_leaf_key AND NOT is_invalid \
+                 AND tuple_size = item_len AND varlena_kinds = ARRAY[NULL]::text[]) \
+             FROM gist_page_items({}, 0)",
+            oid
+        ));
+        assert_eq!(root, Some(true));
+        let leaves = Spi::get_one::<JsonB>(&format!(
+            "SELECT jsonb_build_object('keys', count(*), 'nulls', count(*) FILTER (WHERE has_nulls), \
+                 'leaf_keys', count(*) FILTER (WHERE is_leaf_key)) \
+             FROM gist_tree_rows({0}) r, gist_page_items({0}, r.blkno) i \
+             WHERE r.level = (SELECT max(level) FROM gist_tree_rows({0}))",
+            oid
+        ))
+        .unwrap()
+        .0;
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert_eq!(leaves["keys"], stats.num_leaf_tuple);
+        assert_eq!(leaves["leaf_keys"], stats.num_leaf_tuple);
+        assert_eq!(leaves["nulls"], 10);
+    }
+
+    #[pg_test]
+    fn test_stat_record_matches_stats() {
+        let oid = crate::demo::setup(1000);
+        let s = IndexInspector::open(oid).unwrap().stats(None);
+        let row = crate::gist_stat_record(oid).next().unwrap();
+        assert_eq!(row.0, s.level as i32 + 1);
+        assert_eq!(row.1, s.num_pages as i64);
+        assert_eq!(row.2, s.num_leaf_pages as i64);
+        assert_eq!(row.3, s.num_tuple as i64);
+        assert_eq!(row.4, s.num_leaf_tuple as i64);
+        assert_eq!(row.6, s.tuple_size as i64);
+        assert_eq!(row.8, s.total_size as i64);
+    }
+
+    /// Switches to a new role which can't read any table, optionally as a member of
+    /// `pg_monitor`.
+    fn set_unprivileged_role(monitoring: bool) {
+        let member_of = if monitoring {
+            " IN ROLE pg_monitor"
+        } else {
+            ""
+        };
+        Spi::run(&format!("CREATE ROLE gevel_unprivileged{}", member_of));
+        Spi::run("SET LOCAL ROLE gevel_unprivileged");
+    }
+
+    #[pg_test]
+    fn test_monitoring_role_can_call_aggregated_functions() {
+        let oid = crate::demo::setup(1_000);
+        set_unprivileged_role(true);
+        let pages = Spi::get_one::<i64>(&format!("SELECT pages FROM gist_summary({})", oid));
+        assert!(pages.unwrap() > 0);
+        let pages = Spi::get_one::<i64>(&format!("SELECT pages FROM gist_stat_record({})", oid));
+        assert!(pages.unwrap() > 0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "permission denied for table demo_points")]
+    fn test_monitoring_role_cant_read_keys() {
+        let oid = crate::demo::setup(1_000);
+        set_unprivileged_role(true);
+        Spi::run(&format!("SELECT * FROM gist_print({})", oid));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "permission denied for function gist_summary")]
+    fn test_aggregated_functions_are_revoked_from_public() {
+        let oid = crate::demo::setup(1_000);
+        set_unprivileged_role(false);
+        Spi::run(&format!("SELECT * FROM gist_summary({})", oid));
+    }
+
+    /// Returns the data extent of an index, rendered as text.
+    fn extent_text(oid: pg_sys::Oid) -> String {
+        let index = IndexInspector::open(oid).unwrap();
+        let extent = index.data_extent().unwrap();
+        crate::key::KeyDecoder::new(index.relation).render_datum(0, extent)
+    }
+
+    /// Parses `(x1,y1),(x2,y2)` box into its width and height.
+    fn box_size(extent: &str) -> (f64, f64) {
+        let coords: Vec<f64> = extent
+            .split(['(', ')', ','])
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().unwrap())
+            .collect();
+        ((coords[0] - coords[2]).abs(), (coords[1] - coords[3]).abs())
+    }
+
+    #[pg_test]
+    fn test_identical_keys_have_degenerate_extent() {
+        let oid = crate::demo::setup_dataset(10_000, Dataset::Identical);
+        let extent = extent_text(oid);
+        assert_eq!(box_size(&extent), (0.0, 0.0));
+    }
+
+    #[pg_test]
+    fn test_clustered_keys_have_tiny_extent() {
+        let oid = crate::demo::setup_dataset(10_000, Dataset::Clustered);
+        let (width, height) = box_size(&extent_text(oid));
+        assert!(width <= 0.001 && height <= 0.001);
+    }
+
+    #[pg_test]
+    fn test_outliers_blow_up_extent() {
+        let oid = crate::demo::setup_dataset(10_000, Dataset::Random);
+        let (width, _) = box_size(&extent_text(oid));
+        assert!(width <= 1000.0);
+
+        let oid = crate::demo::setup_dataset(10_000, Dataset::Outliers);
+        let (width, _) = box_size(&extent_text(oid));
+        assert!(width > 1_000_000.0);
+    }
+
+    #[pg_test]
+    fn test_data_extent_is_typed() {
+        let oid = crate::demo::setup(10_000);
+        let width = Spi::get_one::<f64>(&format!(
+            "SELECT width(gist_data_extent({}, NULL::box))",
+            oid
+        ))
+        .unwrap();
+        assert!(width > 0.0 && width <= 1000.0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "keys of the index are stored as box, but text was requested")]
+    fn test_data_extent_rejects_other_types() {
+        let oid = crate::demo::setup(100);
+        Spi::get_one::<String>(&format!("SELECT gist_data_extent({}, NULL::text)", oid));
+    }
+
+    #[pg_test]
+    fn test_data_extent_decompresses_keys() {
+        // tsvector keys are stored compressed as signatures
+        Spi::run("CREATE TABLE gevel.docs(d tsvector)");
+        Spi::run(
+            "INSERT INTO gevel.docs SELECT to_tsvector('simple', 'word' || i || ' common') FROM generate_series(1, 2000) i",
+        );
+        Spi::run("CREATE INDEX docs_d_idx ON gevel.docs USING gist(d)");
+        let extent = Spi::get_one::<String>(
+            "SELECT gist_data_extent('gevel.docs_d_idx'::regclass, NULL::gtsvector)::text",
+        );
+        assert!(extent.is_some());
+    }
+
+    #[pg_test]
+    fn test_optimal_tree_follows_fillfactor() {
+        let oid = crate::demo::setup(100_000);
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        let packed = crate::gist::Summary::from_stats(&stats, 100);
+        let half_full = crate::gist::Summary::from_stats(&stats, 50);
+        // leaves filled up to half of their space need about twice as many pages
+        assert!(packed.optimal_pages <= stats.num_pages);
+        assert!(half_full.optimal_pages >= 2 * packed.optimal_pages - 1);
+        assert!(half_full.optimal_levels >= packed.optimal_levels);
+        assert!(half_full.pages_vs_optimal < packed.pages_vs_optimal);
+        assert!(
+            (packed.pages_vs_optimal - stats.num_pages as f64 / packed.optimal_pages as f64).abs()
+                < 1e-9
+        );
+
+        // the index's own fillfactor is used by gist_summary
+        Spi::run(&format!(
+            "ALTER INDEX {} SET (fillfactor = 50)",
+            crate::demo::DEMO_INDEX
+        ));
+        let optimal =
+            Spi::get_one::<i64>(&format!("SELECT optimal_pages FROM gist_summary({})", oid));
+        let sampled = IndexInspector::open(oid)
+            .unwrap()
+            .summary(crate::SUMMARY_SAMPLE_SIZE);
+        assert_eq!(optimal, Some(sampled.optimal_pages as i64));
+        assert!(sampled.optimal_pages > packed.optimal_pages);
+    }
+
+    #[pg_test]
+    fn test_sequential_inserts_leave_pages_half_empty() {
+        let oid = crate::demo::setup_dataset(10_000, Dataset::Sequential);
+        let summary = IndexInspector::open(oid)
+            .unwrap()
+            .summary(crate::SUMMARY_SAMPLE_SIZE);
+        assert!(summary.bloat_pct > 30.0, "bloat: {}", summary.bloat_pct);
+        assert!(summary.pages_vs_optimal > 1.0);
+        assert!(summary.optimal_pages < summary.pages);
+        assert!(summary.optimal_levels <= summary.levels);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "unknown demo dataset")]
+    fn test_unknown_dataset_is_rejected() {
+        crate::demo::parse_dataset("skewed");
+    }
+
+    #[pg_test]
+    fn test_prefix_compression_of_shared_prefixes() {
+        Spi::run("CREATE TABLE gevel.prefixes(a inet)");
+        Spi::run(
+            "INSERT INTO gevel.prefixes SELECT ('10.1.' || (i / 256) || '.' || (i % 256))::inet FROM generate_series(0, 9999) i",
+        );
+        Spi::run("CREATE INDEX prefixes_a_idx ON gevel.prefixes USING gist(a inet_ops)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.prefixes_a_idx'::regclass::oid::bigint")
+            .unwrap() as pg_sys::Oid;
+
+        let stats = IndexInspector::open(oid).unwrap().prefix_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].keys, 10_000);
+        // all addresses share at least the family, netmask and the first two octets
+        assert!(stats[0].page_prefix_savings > 0);
+        assert!(stats[0].front_coding_savings >= stats[0].page_prefix_savings);
+        assert!(stats[0].savings_pct() > 0.0);
+    }
+
+    #[pg_test]
+    fn test_prefix_compression_skips_fixed_size_keys() {
+        let oid = crate::demo::setup(1_000);
+        assert!(IndexInspector::open(oid).unwrap().prefix_stats().is_empty());
+    }
+
+    #[pg_test]
+    fn test_control_characters_are_escaped() {
+        assert_eq!(crate::key::escape_control("zażółć"), "zażółć");
+        assert_eq!(
+            crate::key::escape_control("a\tb\nc\u{1}\u{7f}"),
+            "a\\tb\\nc\\x01\\x7f"
+        );
+    }
+
+    #[pg_test]
+    fn test_multibyte_keys_are_rendered_intact() {
+        Spi::run("CREATE TYPE gevel.textrange AS RANGE (subtype = text)");
+        Spi::run("CREATE TABLE gevel.words(r gevel.textrange)");
+        Spi::run(
+            "INSERT INTO gevel.words VALUES (gevel.textrange('zażółć', 'źdźbło')), (gevel.textrange(E'gęś\\tja', 'żółw'))",
+        );
+        Spi::run("CREATE INDEX words_r_idx ON gevel.words USING gist(r)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.words_r_idx'::regclass::oid::bigint").unwrap()
+            as pg_sys::Oid;
+
+        let keys: Vec<String> = IndexInspector::open(oid)
+            .unwrap()
+            .page_items(0)
+            .unwrap()
+            .into_iter()
+            .filter_map(|i| i.key)
+            .collect();
+        assert!(keys
+            .iter()
+            .any(|k| k.contains("zażółć") && k.contains("źdźbło")));
+        assert!(keys.iter().any(|k| k.contains("gęś\\tja")));
+        assert!(keys.iter().all(|k| !k.chars().any(|c| c.is_control())));
+    }
+
+    #[pg_test]
+    fn test_inspection_functions_are_parallel_safe() {
+        let unsafe_functions = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_proc WHERE proname LIKE 'gist\\_%' AND proname <> 'gist_stat_incremental' AND (provolatile <> 's' OR proparallel <> 's')",
+        );
+        assert_eq!(unsafe_functions, Some(0));
+
+        let oid = crate::demo::setup(10_000);
+        Spi::run("SET LOCAL force_parallel_mode = on");
+        let pages = Spi::get_one::<i64>(&format!("SELECT pages FROM gist_summary({})", oid));
+        let expected = IndexInspector::open(oid)
+            .unwrap()
+            .summary(crate::SUMMARY_SAMPLE_SIZE)
+            .pages;
+        assert_eq!(pages, Some(expected as i64));
+    }
+
+    #[pg_test]
+    fn test_signature_stats_of_tsvector_index() {
+        Spi::run("CREATE TABLE gevel.docs(d tsvector)");
+        Spi::run(
+            "INSERT INTO gevel.docs SELECT to_tsvector('simple', md5(i::text) || ' ' || md5((i + 1)::text)) FROM generate_series(1, 5000) i",
+        );
+        Spi::run("CREATE INDEX docs_d_idx ON gevel.docs USING gist(d)");
+        let levels = Spi::get_one::<i64>(
+            "SELECT count(*) FROM gist_signature_stats('gevel.docs_d_idx'::regclass)",
+        )
+        .unwrap();
+        assert!(levels >= 2);
+        // internal keys are unions of their children, so they're always signatures
+        let root = Spi::get_one::<bool>(
+            "SELECT exact_keys = 0 AND signature_keys + all_true_keys > 0 AND avg_density > 0 \
+             FROM gist_signature_stats('gevel.docs_d_idx'::regclass) WHERE level = 0",
+        );
+        assert_eq!(root, Some(true));
+        // while leaf keys of short documents store their lexemes
+        let leaves = Spi::get_one::<bool>(&format!(
+            "SELECT exact_keys = 5000 AND signature_keys = 0 \
+             FROM gist_signature_stats('gevel.docs_d_idx'::regclass) WHERE level = {}",
+            levels - 1
+        ));
+        assert_eq!(leaves, Some(true));
+
+        // indexes without signature keys have no levels to report
+        let oid = crate::demo::setup(1_000);
+        let levels = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM gist_signature_stats({})",
+            oid
+        ));
+        assert_eq!(levels, Some(0));
+    }
+
+    #[pg_test]
+    fn test_null_keys_are_counted() {
+        Spi::run("CREATE TABLE gevel.sparse(p point, b box)");
+        Spi::run(
+            "INSERT INTO gevel.sparse SELECT CASE WHEN i % 4 = 0 THEN point(i, i) END, box(point(i, i), point(i + 1, i + 1)) FROM generate_series(1, 10000) i",
+        );
+        Spi::run("CREATE INDEX sparse_idx ON gevel.sparse USING gist(p, b)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.sparse_idx'::regclass::oid::bigint").unwrap()
+            as pg_sys::Oid;
+        let index = IndexInspector::open(oid).unwrap();
+
+        let attrs = index.attr_stats();
+        assert_eq!(attrs.len(), 2);
+        assert_eq!(attrs[0].name, "p");
+        assert_eq!(attrs[0].leaf_keys, 10_000);
+        assert_eq!(attrs[0].leaf_nulls, 7_500);
+        assert_eq!(attrs[0].null_pct(), 75.0);
+        assert_eq!(attrs[1].leaf_nulls, 0);
+
+        let stats = index.stats(None);
+        let levels = index.stats_by_level();
+        assert!(stats.num_null_tuple >= 7_500);
+        assert_eq!(
+            levels.iter().map(|l| l.null_tuples).sum::<u64>(),
+            stats.num_null_tuple
+        );
+    }
+
+    #[pg_test]
+    fn test_lock_timeout_is_configurable() {
+        assert_eq!(
+            Spi::get_one::<String>("SHOW gevel.lock_timeout_ms"),
+            Some("5000".to_string())
+        );
+        let oid = crate::demo::setup(1_000);
+        for timeout in ["0", "10"].iter() {
+            Spi::run(&format!("SET LOCAL gevel.lock_timeout_ms = {}", timeout));
+            assert!(IndexInspector::open(oid).unwrap().stats(None).num_pages > 0);
+        }
+    }
+
+    #[pg_test]
+    fn test_forensics_of_healthy_index() {
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let bundle = index.forensics(false);
+        assert_eq!(bundle["index"], "gevel.demo_points_p_idx");
+        assert_eq!(bundle["table"], "gevel.demo_points");
+        assert_eq!(bundle["findings"], serde_json::json!([]));
+        assert_eq!(bundle["suspicious_pages"], serde_json::json!([]));
+        assert_eq!(bundle["stats"]["pages"], index.stats(None).num_pages);
+        assert_eq!(bundle["server"]["block_size"], pg_sys::BLCKSZ);
+    }
+
+    #[pg_test]
+    fn test_redacted_page_keeps_structure() {
+        let oid = crate::demo::setup(1_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let page = index.cache.get(0);
+        let redacted = crate::forensics::redact_page(&page);
+        let original = page.as_bytes();
+        assert_eq!(redacted.len(), original.len());
+        assert_ne!(redacted.as_slice(), original);
+
+        let header = std::mem::size_of::<pg_sys::IndexTupleData>();
+        let iid = page.item_id(1);
+        let (start, end) = (
+            iid.lp_off() as usize,
+            (iid.lp_off() + iid.lp_len()) as usize,
+        );
+        // line pointers and tuple headers are intact, key data is zeroed
+        assert_eq!(&redacted[..start + header], &original[..start + header]);
+        assert!(redacted[start + header..end].iter().all(|&b| b == 0));
+    }
+
+    #[pg_test]
+    fn test_incremental_stats_reuse_unchanged_subtrees() {
+        let oid = crate::demo::setup(10_000);
+        let expected = IndexInspector::open(oid).unwrap().stats(None);
+
+        let first = IndexInspector::open(oid).unwrap().stats_incremental();
+        assert!(first.subtrees > 0);
+        assert_eq!(first.reused, 0);
+        assert_eq!(first.stats.to_string(), expected.to_string());
+
+        let second = IndexInspector::open(oid).unwrap().stats_incremental();
+        assert_eq!(second.reused, second.subtrees);
+        assert_eq!(second.stats.to_string(), expected.to_string());
+
+        // an insert modifies pages of a single subtree, the others are still reused
+        Spi::run("INSERT INTO gevel.demo_points(p) VALUES (point(1, 1))");
+        let third = IndexInspector::open(oid).unwrap().stats_incremental();
+        assert!(third.reused < third.subtrees);
+        assert_eq!(third.stats.num_leaf_tuple, expected.num_leaf_tuple + 1);
+    }
+
+    #[pg_test]
+    fn test_incremental_stats_notice_untracked_modifications() {
+        let oid = crate::demo::setup(10_000);
+        IndexInspector::open(oid).unwrap().stats_incremental();
+
+        // modifications are noticed by their pages, not by statistics counters of the table
+        Spi::run("SET LOCAL track_counts = off");
+        Spi::run("DELETE FROM gevel.demo_points WHERE ctid IN (SELECT ctid FROM gevel.demo_points LIMIT 100)");
+        Spi::run(
+            "INSERT INTO gevel.demo_points(p) SELECT point(i, i) FROM generate_series(1, 500) i",
+        );
+        let expected = IndexInspector::open(oid).unwrap().stats(None);
+        let incremental = IndexInspector::open(oid).unwrap().stats_incremental();
+        assert!(incremental.reused < incremental.subtrees);
+        assert_eq!(incremental.stats.to_string(), expected.to_string());
+    }
+
+    #[pg_test]
+    fn test_heap_locality_follows_insertion_order() {
+        let oid = crate::demo::setup_dataset(10_000, Dataset::Sequential);
+        let locality = IndexInspector::open(oid).unwrap().heap_locality();
+        assert_eq!(locality.pages.iter().map(|p| p.tuples).sum::<u64>(), 10_000);
+        assert!(locality.correlation > 0.5, "{}", locality.correlation);
+        for page in locality.pages.iter() {
+            assert!(page.distinct_heap_blocks <= page.tuples);
+            assert!(page.distinct_heap_blocks <= page.spread() + 1);
+        }
+    }
+
+    #[pg_test]
+    fn test_heap_locality_of_random_data() {
+        let oid = crate::demo::setup_dataset(10_000, Dataset::Random);
+        let locality = IndexInspector::open(oid).unwrap().heap_locality();
+        assert!(locality.correlation.abs() < 0.5, "{}", locality.correlation);
+    }
+
+    #[pg_test]
+    fn test_leaf_visibility_of_fresh_table() {
+        let oid = crate::demo::setup(10_000);
+        let visibility = IndexInspector::open(oid).unwrap().leaf_visibility();
+        assert_eq!(visibility.leaf_tuples, 10_000);
+        // pages are only marked as all-visible by vacuum, which can't run inside of a test
+        assert_eq!(visibility.all_visible_tuples, 0);
+        assert_eq!(visibility.all_visible_heap_pages, 0);
+        assert!(visibility.heap_pages > 0);
+    }
+
+    #[pg_test]
+    fn test_build_leftovers_are_classified() {
+        use crate::check::BuildLeftover;
+        use memoffset::offset_of;
+        use pgx::pg_sys::{GISTPageOpaqueData, PageHeaderData, F_DELETED, GIST_PAGE_ID};
+
+        fn put_u16(bytes: &mut [u8], at: usize, value: u16) {
+            bytes[at..at + 2].copy_from_slice(&value.to_ne_bytes());
+        }
+        let special = 8192 - std::mem::size_of::<GISTPageOpaqueData>();
+        let classify = |bytes: &[u8], reachable: bool, logged: bool| {
+            BuildLeftover::classify(&crate::Page::from_image(bytes), reachable, logged)
+        };
+
+        let mut image = gist_page_image(true, &[(pg_sys::LP_NORMAL, false)]);
+        // temporary pages of a build don't carry the GiST page id
+        assert_eq!(
+            classify(&image, false, true),
+            vec![BuildLeftover::ForeignPage(0)]
+        );
+        let page_id = special + offset_of!(GISTPageOpaqueData, gist_page_id);
+        put_u16(&mut image, page_id, GIST_PAGE_ID as u16);
+        assert_eq!(classify(&image, true, true), vec![]);
+
+        // LSN assigned during the build, which only matters for WAL-logged indexes
+        let lsn = offset_of!(PageHeaderData, pd_lsn) + 4;
+        image[lsn..lsn + 4].copy_from_slice(&1u32.to_ne_bytes());
+        assert_eq!(classify(&image, true, true), vec![BuildLeftover::BuildLsn]);
+        assert_eq!(classify(&image, true, false), vec![]);
+        image[lsn..lsn + 4].copy_from_slice(&0u32.to_ne_bytes());
+
+        let flags = special + offset_of!(GISTPageOpaqueData, flags);
+        put_u16(&mut image, flags, 0x8000 | F_DELETED as u16);
+        assert_eq!(
+            classify(&image, true, true),
+            vec![
+                BuildLeftover::UnknownFlags(0x8000 | F_DELETED as u16),
+                BuildLeftover::ReachableUnused
+            ]
+        );
+        put_u16(&mut image, flags, F_DELETED as u16);
+        assert_eq!(classify(&image, false, true), vec![]);
+
+        // pages which have never been initialized, ie. after extending the relation
+        let new_page = vec![0u8; 8192];
+        assert_eq!(
+            classify(&new_page, true, true),
+            vec![BuildLeftover::ReachableUnused]
+        );
+        assert_eq!(classify(&new_page, false, true), vec![]);
+    }
+
+    #[pg_test]
+    fn test_check_of_healthy_index() {
+        let oid = crate::demo::setup(100_000);
+        let index = IndexInspector::open(oid).unwrap();
+        // at least two levels below the root, so that rightlinks are checked on both
+        assert!(index.stats(None).level >= 3);
+        assert_eq!(index.check(), vec![]);
+    }
+
+    // MemoryContextMemAllocated is only available since Postgres 13
+    #[cfg(feature = "pg13")]
+    #[pg_test]
+    fn test_memory_of_last_inspection() {
+        let oid = crate::demo::setup(10_000);
+        let before = unsafe { pg_sys::CurrentMemoryContext };
+        let index = IndexInspector::open(oid).unwrap();
+        let stats = index.stats(None);
+        // the walk runs in the inspection context, but doesn't leave it switched to
+        assert_eq!(unsafe { pg_sys::CurrentMemoryContext }, before);
+        drop(index);
+        assert!(stats.num_pages > 0);
+        let bytes = Spi::get_one::<i64>("SELECT gevel_memory()").unwrap();
+        assert!(bytes > 0);
+    }
+
+    #[pg_test]
+    fn test_capabilities() {
+        let capabilities = Spi::get_one::<JsonB>("SELECT gevel_capabilities()")
+            .unwrap()
+            .0;
+        assert_eq!(capabilities["access_methods"], serde_json::json!(["gist"]));
+        let formats = capabilities["formats"].as_array().unwrap();
+        assert!(formats.contains(&serde_json::json!("json")));
+        assert!(formats.contains(&serde_json::json!("mermaid")));
+        let functions = capabilities["functions"].as_array().unwrap();
+        assert!(functions.contains(&serde_json::json!("gist_tree")));
+        assert!(functions.contains(&serde_json::json!("gevel.report")));
+        assert_eq!(
+            capabilities["features"]["parallel_traversal"],
+            serde_json::json!(false)
+        );
+        assert!(capabilities["server_version_num"].as_u64().unwrap() >= 100000);
+        assert_eq!(capabilities["block_size"], pg_sys::BLCKSZ);
+    }
+
+    #[pg_test]
+    fn test_c_abi_index_stats() {
+        use crate::ffi::*;
+        use std::mem::size_of;
+
+        assert_eq!(size_of::<GevelIndexStats>(), GEVEL_INDEX_STATS_V1_SIZE);
+        let oid = crate::demo::setup(10_000);
+        let expected = IndexInspector::open(oid).unwrap().stats(None);
+        let mut stats = GevelIndexStats::default();
+        let rc = unsafe { gevel_index_stats(oid, &mut stats, size_of::<GevelIndexStats>()) };
+        assert_eq!(rc, GEVEL_OK);
+        assert_eq!(stats.abi_version, gevel_abi_version());
+        assert_eq!(stats.size as usize, size_of::<GevelIndexStats>());
+        assert_eq!(stats.levels as usize, expected.level + 1);
+        assert_eq!(stats.pages, expected.num_pages);
+        assert_eq!(stats.leaf_tuples, expected.num_leaf_tuple);
+        assert_eq!(stats.free_bytes, expected.free_space);
+
+        let mut untouched = GevelIndexStats::default();
+        let rc = unsafe { gevel_index_stats(oid, &mut untouched, 8) };
+        assert_eq!(rc, GEVEL_ERR_BUFFER);
+        let rc = unsafe { gevel_index_stats(0, &mut untouched, size_of::<GevelIndexStats>()) };
+        assert_eq!(rc, GEVEL_ERR_INVALID_RELATION);
+        let table = Spi::get_one::<i64>("SELECT 'gevel.demo_points'::regclass::oid::bigint")
+            .unwrap() as pg_sys::Oid;
+        let rc = unsafe { gevel_index_stats(table, &mut untouched, size_of::<GevelIndexStats>()) };
+        assert_eq!(rc, GEVEL_ERR_NOT_GIST_INDEX);
+        assert_eq!(untouched, GevelIndexStats::default());
+    }
+
+    #[pg_test]
+    fn test_ordering_of_sequential_inserts() {
+        let sequential = crate::demo::setup_dataset(10_000, Dataset::Sequential);
+        let sequential = IndexInspector::open(sequential).unwrap().ordering_stats();
+        let random = crate::demo::setup_dataset(10_000, Dataset::Random);
+        let random = IndexInspector::open(random).unwrap().ordering_stats();
+
+        assert!(sequential.pages > 0 && random.pages > 0);
+        // points inserted in order end up next to each other within pages
+        assert!(sequential.clustering().unwrap() < random.clustering().unwrap());
+    }
+
+    #[pg_test]
+    fn test_outputs_are_truncated_at_limit() {
+        let oid = crate::demo::setup(10_000);
+        Spi::run("SET LOCAL gevel.max_output_bytes = 1024");
+        let text = crate::gist_tree(oid, false);
+        assert!(text.len() <= 1024 + 64);
+        assert!(text.ends_with("[output truncated]\n"));
+
+        let tree = IndexInspector::open(oid)
+            .unwrap()
+            .get_tree(&TreeOptions::default());
+        let (json, truncated) = tree.to_json_capped(&Default::default(), 1024);
+        assert!(truncated);
+        assert_eq!(json["truncated"], true);
+        assert!(json.to_string().len() < tree.to_json().to_string().len());
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "output of gist_forensics exceeds 1024 bytes")]
+    fn test_untruncatable_outputs_fail_at_limit() {
+        let oid = crate::demo::setup(10_000);
+        Spi::run("SET LOCAL gevel.max_output_bytes = 1024");
+        crate::gist_forensics(oid, false);
+    }
+
+    #[pg_test]
+    fn test_health_report_of_range_index() {
+        Spi::run("CREATE TABLE gevel.ranges(r int4range)");
+        Spi::run(
+            "INSERT INTO gevel.ranges SELECT CASE WHEN i % 10 = 0 THEN 'empty'::int4range ELSE int4range(i, i + 10) END FROM generate_series(1, 1000) i",
+        );
+        Spi::run("CREATE INDEX ranges_r_idx ON gevel.ranges USING gist(r)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.ranges_r_idx'::regclass::oid::bigint").unwrap()
+            as pg_sys::Oid;
+
+        let index = IndexInspector::open(oid).unwrap();
+        assert_eq!(
+            index.known_opclass(),
+            Some(crate::opclass::KnownOpclass::Range)
+        );
+        let report = index.report(&crate::report::parse_spec("health"));
+        assert_eq!(report["opclass"]["opfamily"], "range_ops");
+        assert_eq!(report["opclass"]["leaf_keys"], 1000);
+        assert_eq!(report["opclass"]["empty_ranges"], 100);
+
+        // opclass section is only a part of health reports
+        let report = index.report(&crate::report::parse_spec("stats"));
+        assert!(report.get("opclass").is_none());
+    }
+
+    #[pg_test]
+    fn test_dead_chains_recommend_maintenance() {
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let stats = index.stats(None);
+        assert_eq!(stats.max_dead_chain, 0);
+        assert!(index.stats_by_level().iter().all(|l| l.max_dead_chain == 0));
+        let summary = crate::gist::Summary::from_stats(&stats, 90);
+        assert_eq!(summary.max_dead_chain, Some(0));
+        assert!(summary.recommendations.is_empty());
+
+        let degraded = Stats {
+            max_dead_chain: crate::gist::Summary::DEAD_CHAIN_THRESHOLD + 1,
+            ..stats
+        };
+        let summary = crate::gist::Summary::from_stats(&degraded, 90);
+        assert_eq!(summary.recommendations.len(), 1);
+    }
+
+    #[cfg(feature = "testing")]
+    #[pg_test]
+    fn test_public_testing_api() {
+        use crate::testing::{self, fixtures};
+
+        let index = fixtures::points(10_000, fixtures::Dataset::Random);
+        let props = testing::inspect(index);
+        assert!(props.levels > 1);
+        assert!(props.min_fanout.unwrap() <= props.max_fanout.unwrap());
+        testing::assert_max_levels(index, props.levels);
+        testing::assert_fanout_between(index, 1, props.max_fanout.unwrap());
+        testing::assert_health_score_at_least(index, 0.0);
+
+        let index = fixtures::keys(
+            "gevel.spans",
+            "int4range",
+            "int4range(i, i + 10)",
+            1_000,
+            Some("range_ops"),
+        );
+        assert_eq!(index, "gevel.spans_k_idx");
+        assert!(testing::inspect(&index).leaf_pages > 0);
+    }
+
+    #[pg_test]
+    fn test_functions_accept_index_names() {
+        let oid = crate::demo::setup(1_000);
+        let by_oid = Spi::get_one::<String>(&format!("SELECT gist_tree({})", oid));
+        let by_regclass = Spi::get_one::<String>(&format!(
+            "SELECT gist_tree('{}'::regclass)",
+            crate::demo::DEMO_INDEX
+        ));
+        let by_name =
+            Spi::get_one::<String>(&format!("SELECT gist_tree('{}')", crate::demo::DEMO_INDEX));
+        assert!(by_oid.is_some());
+        assert_eq!(by_oid, by_regclass);
+        assert_eq!(by_oid, by_name);
+
+        Spi::run("SET LOCAL search_path = gevel, public");
+        let unqualified = Spi::get_one::<String>("SELECT gist_stat('demo_points_p_idx')");
+        assert_eq!(
+            unqualified,
+            Some(IndexInspector::open(oid).unwrap().stats(None).to_string())
+        );
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "does not exist")]
+    fn test_unknown_index_name_is_rejected() {
+        Spi::get_one::<String>("SELECT gist_tree('gevel.no_such_idx')");
+    }
+
+    #[pg_test]
+    fn test_tree_json_chunks() {
+        let oid = crate::demo::setup(10_000);
+        let tree = crate::gist_tree_json(oid, false, -1, JsonB(serde_json::json!({}))).0;
+        let root = &tree["root"];
+        assert!(!root["is_leaf"].as_bool().unwrap());
+
+        let chunks = crate::gist_tree_json(oid, false, 1, JsonB(serde_json::json!({}))).0;
+        let chunks = chunks.as_array().unwrap();
+        let children = root["children"].as_array().unwrap();
+        assert_eq!(chunks.len(), children.len());
+        for (chunk, child) in chunks.iter().zip(children.iter()) {
+            assert_eq!(chunk["parent_blkno"], root["blkno"]);
+            assert_eq!(&chunk["root"], child);
+        }
+
+        // trees which are not deep enough end up in a chunk rooted at their leaves
+        let deep = crate::gist_tree_json(oid, false, 100, JsonB(serde_json::json!({}))).0;
+        let deep = deep.as_array().unwrap();
+        assert!(deep.iter().all(|c| c["root"]["is_leaf"].as_bool().unwrap()));
+
+        let whole = crate::gist_tree_json(oid, false, 0, JsonB(serde_json::json!({}))).0;
+        assert_eq!(whole.as_array().unwrap().len(), 1);
+        assert_eq!(whole[0]["parent_blkno"], serde_json::Value::Null);
+        assert_eq!(&whole[0]["root"], root);
+    }
+
+    #[pg_test]
+    fn test_tree_json_items() {
+        let oid = crate::demo::setup(10_000);
+        let tree = crate::gist_tree_json(oid, false, -1, JsonB(serde_json::json!({}))).0;
+        assert!(tree["root"].get("items").is_none());
+
+        /// Sums line pointers of leaf pages of a JSON tree, checking that every page has its
+        /// items summarized.
+        fn leaf_items(node: &serde_json::Value) -> (u64, u64) {
+            let items = &node["items"];
+            assert_eq!(items["normal"], node["tuples"]);
+            assert_eq!(items["dead"], 0);
+            assert!(items["total_bytes"].as_u64().unwrap() > 0);
+            match node["children"].as_array() {
+                Some(children) if !children.is_empty() => children
+                    .iter()
+                    .map(leaf_items)
+                    .fold((0, 0), |(n, b), (cn, cb)| (n + cn, b + cb)),
+                _ => (
+                    items["normal"].as_u64().unwrap(),
+                    items["total_bytes"].as_u64().unwrap(),
+                ),
+            }
+        }
+        let tree = crate::gist_tree_json(oid, true, -1, JsonB(serde_json::json!({}))).0;
+        let (normal, total_bytes) = leaf_items(&tree["root"]);
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert_eq!(normal, stats.num_leaf_tuple);
+        // occupied space also counts line pointers and alignment of tuples
+        assert!(total_bytes > 0 && total_bytes <= stats.leaf_tuple_size);
+
+        // selecting the field includes items as well
+        let options = serde_json::json!({"fields": ["blkno", "items"]});
+        let tree = crate::gist_tree_json(oid, false, -1, JsonB(options)).0;
+        assert!(tree["root"]["items"]["normal"].as_u64().unwrap() > 0);
+    }
+
+    #[pg_test]
+    fn test_largest_insertable_tuple() {
+        use crate::gist::largest_insertable;
+        // a new tuple needs its own line pointer, and its size is MAXALIGNed
+        assert_eq!(largest_insertable(0), 0);
+        assert_eq!(largest_insertable(4), 0);
+        assert_eq!(largest_insertable(11), 0);
+        assert_eq!(largest_insertable(12), 8);
+        assert_eq!(largest_insertable(8148), 8144);
+
+        let oid = crate::demo::setup(10_000);
+        let options = serde_json::json!({"fields": ["free", "largest_insertable"]});
+        let tree = crate::gist_tree_json(oid, false, -1, JsonB(options)).0;
+        let root = &tree["root"];
+        let free = root["free"].as_u64().unwrap() as usize;
+        assert_eq!(root["largest_insertable"], largest_insertable(free));
+        assert!(largest_insertable(free) < free);
+    }
+
+    #[pg_test]
+    fn test_tree_json_options() {
+        let oid = crate::demo::setup(10_000);
+        let options =
+            serde_json::json!({"fields": ["blkno", "free"], "max_depth": 1, "leaves": "summary"});
+        let tree = crate::gist_tree_json(oid, false, -1, JsonB(options)).0;
+        let root = &tree["root"];
+        assert!(root.get("is_leaf").is_none());
+        assert!(root.get("blkno").is_some() && root.get("free").is_some());
+        for child in root["children"].as_array().into_iter().flatten() {
+            let mut fields = child.as_object().unwrap().keys();
+            assert!(fields.all(|f| ["blkno", "free", "children", "leaves"].contains(&f.as_str())));
+            let grandchildren = child.get("children").and_then(|c| c.as_array());
+            assert!(grandchildren.is_none_or(|c| c.is_empty()));
+        }
+
+        /// Sums numbers of leaf pages summarized by all nodes of a JSON tree.
+        fn summarized_leaves(node: &serde_json::Value) -> u64 {
+            assert_ne!(node["is_leaf"], true);
+            node["leaves"]["pages"].as_u64().unwrap_or(0)
+                + node["children"]
+                    .as_array()
+                    .map_or(0, |c| c.iter().map(summarized_leaves).sum())
+        }
+        let summary = serde_json::json!({"leaves": "summary"});
+        let summary = crate::gist_tree_json(oid, false, -1, JsonB(summary)).0;
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert_eq!(summarized_leaves(&summary["root"]), stats.num_leaf_pages);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "unknown tree JSON field")]
+    fn test_tree_json_rejects_unknown_fields() {
+        let oid = crate::demo::setup(10);
+        crate::gist_tree_json(
+            oid,
+            false,
+            -1,
+            JsonB(serde_json::json!({"fields": ["color"]})),
+        );
+    }
+
+    #[pg_test]
+    fn test_heatmap_counts_all_leaf_keys() {
+        let oid = crate::demo::setup(10_000);
+        let heatmap = IndexInspector::open(oid).unwrap().heatmap(16);
+        assert_eq!(heatmap.cells.len(), 16);
+        assert!(heatmap.cells.iter().all(|row| row.len() == 16));
+        let total: u64 = heatmap.cells.iter().flatten().sum();
+        assert_eq!(total, 10_000);
+        let extent = heatmap.extent.unwrap();
+        assert!(extent.xmin <= extent.xmax && extent.ymin <= extent.ymax);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "grid must be between")]
+    fn test_heatmap_rejects_empty_grid() {
+        let oid = crate::demo::setup(100);
+        IndexInspector::open(oid).unwrap().heatmap(0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "heatmaps require bounding box keys")]
+    fn test_heatmap_rejects_non_spatial_keys() {
+        Spi::run("CREATE TABLE gevel.heat_ranges(r int4range)");
+        Spi::run("CREATE INDEX heat_ranges_r_idx ON gevel.heat_ranges USING gist(r)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.heat_ranges_r_idx'::regclass::oid::bigint")
+            .unwrap() as pg_sys::Oid;
+        IndexInspector::open(oid).unwrap().heatmap(16);
+    }
+
+    #[pg_test]
+    fn test_keys_geojson() {
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let stats = index.stats(None);
+        let root = index.keys_geojson(0);
+        assert_eq!(root["type"], "FeatureCollection");
+        let root_features = root["features"].as_array().unwrap();
+        // the root holds one key per downlink
+        assert_eq!(
+            root_features.len(),
+            Spi::get_one::<i64>(&format!("SELECT count(*) FROM gist_page_items({}, 0)", oid))
+                .unwrap() as usize
+        );
+        let ring = root_features[0]["geometry"]["coordinates"][0]
+            .as_array()
+            .unwrap();
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring[0], ring[4]);
+
+        let leaves = index.keys_geojson(stats.level as i32);
+        assert_eq!(
+            leaves["features"].as_array().unwrap().len() as u64,
+            stats.num_leaf_tuple
+        );
+        let below = index.keys_geojson(stats.level as i32 + 1);
+        assert!(below["features"].as_array().unwrap().is_empty());
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "GeoJSON exports require bounding box keys")]
+    fn test_keys_geojson_rejects_non_spatial_keys() {
+        Spi::run("CREATE TABLE gevel.geojson_ranges(r int4range)");
+        Spi::run("CREATE INDEX geojson_ranges_r_idx ON gevel.geojson_ranges USING gist(r)");
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.geojson_ranges_r_idx'::regclass::oid::bigint")
+            .unwrap() as pg_sys::Oid;
+        IndexInspector::open(oid).unwrap().keys_geojson(0);
+    }
+
+    /// Creates `gevel.explain_lines(query)`, returning lines of EXPLAIN output of a query.
+    fn create_explain_lines() {
+        Spi::run(
+            "CREATE FUNCTION gevel.explain_lines(query text) RETURNS SETOF text LANGUAGE plpgsql AS $$ \
+             DECLARE line text; \
+             BEGIN FOR line IN EXECUTE 'EXPLAIN ' || query LOOP RETURN NEXT line; END LOOP; END $$",
+        );
+    }
+
+    #[pg_test]
+    fn test_explain_annotations() {
+        crate::demo::setup(10_000);
+        create_explain_lines();
+        Spi::run("SET enable_seqscan = off");
+        let explain = || {
+            Spi::get_one::<String>(
+                "SELECT string_agg(l, chr(10)) FROM gevel.explain_lines($q$SELECT * FROM gevel.demo_points WHERE p <@ box '((0,0),(0.1,0.1))'$q$) l",
+            )
+            .unwrap()
+        };
+        let plain = explain();
+        assert!(plain.contains("demo_points_p_idx"), "{}", plain);
+        assert!(!plain.contains("GiST Index:"), "{}", plain);
+
+        Spi::run("SET gevel.explain_annotations = on");
+        let annotated = explain();
+        assert!(
+            annotated.contains("GiST Index: gevel.demo_points_p_idx (levels="),
+            "{}",
+            annotated
+        );
+        assert!(annotated.contains("bloat="), "{}", annotated);
+    }
+
+    #[pg_test]
+    fn test_explain_annotations_skip_unreadable_indexes() {
+        crate::demo::setup(10_000);
+        create_explain_lines();
+        Spi::run("CREATE ROLE gevel_explain_reader");
+        Spi::run("GRANT USAGE ON SCHEMA gevel TO gevel_explain_reader");
+        // a column grant is enough to run the query, but not to read keys of its index
+        Spi::run("GRANT SELECT (p) ON gevel.demo_points TO gevel_explain_reader");
+        Spi::run("SET enable_seqscan = off");
+        Spi::run("SET gevel.explain_annotations = on");
+        Spi::run("SET ROLE gevel_explain_reader");
+        let explained = Spi::get_one::<String>(
+            "SELECT string_agg(l, chr(10)) FROM gevel.explain_lines($q$SELECT p FROM gevel.demo_points WHERE p <@ box '((0,0),(0.1,0.1))'$q$) l",
+        )
+        .unwrap();
+        Spi::run("RESET ROLE");
+        assert!(explained.contains("demo_points_p_idx"), "{}", explained);
+        assert!(!explained.contains("GiST Index:"), "{}", explained);
+    }
+
+    #[pg_test]
+    fn test_scan_all_databases_stores_summaries() {
+        let scanned = Spi::get_one::<i64>(
+            "SELECT count(*) FROM gevel.scan_all_databases(10) \
+             WHERE database IN (SELECT datname FROM pg_database)",
+        );
+        let stored = Spi::get_one::<i64>("SELECT count(*) FROM gevel.fleet_stats");
+        assert_eq!(scanned, stored);
+        // summaries of a database are replaced by the next scan instead of being appended
+        Spi::run("SELECT count(*) FROM gevel.scan_all_databases(10)");
+        let stored_again = Spi::get_one::<i64>("SELECT count(*) FROM gevel.fleet_stats");
+        assert_eq!(stored, stored_again);
+    }
+
+    #[cfg(not(feature = "testing"))]
+    #[pg_test]
+    fn test_alerts_report_exceeded_thresholds() {
+        let entry = |index: &str, bloat_pct: f64, invalid_tuples: u64| crate::fleet::FleetEntry {
+            database: "postgres".into(),
+            index: index.into(),
+            table: "public.t".into(),
+            verdict: "ok".into(),
+            health_score: 1.0,
+            bloat_pct,
+            pages: 10,
+            invalid_tuples,
+            maintenance_score: 0.0,
+        };
+        let entries = [
+            entry("public.healthy", 5.0, 0),
+            entry("public.bloated", 50.0, 0),
+            entry("public.broken", 5.0, 3),
+        ];
+        // thresholds are disabled by default
+        assert!(crate::fleet::alerts(&entries).is_empty());
+
+        Spi::run("SET LOCAL gevel.alert_max_bloat_pct = 20");
+        Spi::run("SET LOCAL gevel.alert_max_invalid_tuples = 0");
+        let alerts = crate::fleet::alerts(&entries);
+        assert_eq!(alerts.len(), 2, "{:?}", alerts);
+        assert_eq!(alerts[0]["index"], "public.bloated");
+        assert_eq!(
+            alerts[0]["exceeded"],
+            serde_json::json!({ "max_bloat_pct": 20.0 })
+        );
+        assert_eq!(alerts[1]["index"], "public.broken");
+        assert_eq!(
+            alerts[1]["exceeded"],
+            serde_json::json!({ "max_invalid_tuples": 0 })
+        );
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "must be superuser to scan all databases")]
+    fn test_scan_all_databases_requires_superuser() {
+        set_unprivileged_role(true);
+        Spi::run("SELECT count(*) FROM gevel.scan_all_databases()");
+    }
+
+    #[pg_test]
+    fn test_scan_stats() {
+        crate::demo::setup(10_000);
+        Spi::run("SELECT gevel.scan_stats_reset()");
+        Spi::run("SET enable_seqscan = off");
+        let query = "SELECT count(*) FROM gevel.demo_points WHERE p <@ box '((0,0),(0.1,0.1))'";
+        Spi::run("SET gevel.track_scans = off");
+        Spi::run(query);
+        let tracked = "SELECT count(*) FROM gevel.scan_stats() WHERE index_oid = 'gevel.demo_points_p_idx'::regclass";
+        assert_eq!(Spi::get_one::<i64>(tracked), Some(0));
+
+        Spi::run("SET gevel.track_scans = on");
+        Spi::run(query);
+        Spi::run(query);
+        let stats = Spi::get_one::<JsonB>(
+            "SELECT to_jsonb(s) FROM gevel.scan_stats() s WHERE index_oid = 'gevel.demo_points_p_idx'::regclass",
+        )
+        .unwrap()
+        .0;
+        assert_eq!(stats["executions"], 2);
+        assert_eq!(stats["scans"], 2);
+        let pages = stats["pages"].as_i64().unwrap();
+        assert!(pages >= 2, "{}", stats);
+        let histogram = stats["histogram"].as_array().unwrap();
+        let scans: i64 = histogram.iter().map(|b| b["scans"].as_i64().unwrap()).sum();
+        assert_eq!(scans, 2);
+
+        Spi::run("SELECT gevel.scan_stats_reset()");
+        assert_eq!(Spi::get_one::<i64>(tracked), Some(0));
+    }
+
+    #[pg_test]
+    fn test_stat_max_level() {
+        let oid = crate::demo::setup(10_000);
+        let whole = IndexInspector::open(oid).unwrap().stats(None);
+        assert!(whole.num_pages > 1);
+        assert_eq!(crate::gist_stat(oid, -1), whole.to_string());
+
+        let root_only = IndexInspector::open(oid).unwrap().stats(Some(0));
+        assert_eq!(root_only.num_pages, 1);
+        assert_eq!(crate::gist_stat(oid, 0), root_only.to_string());
+    }
+
+    #[pg_test]
+    fn test_stat_above_leaves_has_no_depth_imbalance() {
+        let oid = crate::demo::setup(100_000);
+        let whole = IndexInspector::open(oid).unwrap().stats(None);
+        assert!(
+            whole.level >= 2,
+            "expected 3 levels, got {}",
+            whole.level + 1
+        );
+
+        let upper = IndexInspector::open(oid).unwrap().stats(Some(1));
+        assert_eq!(upper.num_leaf_pages, 0);
+        assert_eq!(upper.depth_imbalance, 0);
+        let text = crate::gist_stat(oid, 1);
+        assert_eq!(text, upper.to_string());
+        assert!(!text.contains("likely corrupted"), "{}", text);
+    }
+
+    #[pg_test]
+    fn test_print_returns_keys_level_by_level() {
+        let oid = crate::demo::setup(1_000);
+        let tuples = IndexInspector::open(oid).unwrap().print(false);
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert_eq!(tuples.len() as u64, stats.num_tuple);
+        assert!(tuples.windows(2).all(|w| w[0].level <= w[1].level));
+        assert_eq!(tuples[0].level, 0);
+
+        let leaves: Vec<_> = tuples.iter().filter(|t| t.is_leaf).collect();
+        assert_eq!(leaves.len(), 1_000);
+        // points are stored as degenerated boxes
+        let key = leaves[0].keys[0].as_ref().unwrap();
+        assert!(key.starts_with('('), "unexpected key: {}", key);
+        assert!(tuples.iter().all(|t| t.is_valid));
+    }
+
+    #[pg_test]
+    fn test_print_geometric_keys_as_wkt() {
+        let oid = crate::demo::setup(1_000);
+        let tuples = IndexInspector::open(oid).unwrap().print(true);
+        for t in tuples.iter() {
+            let key = t.keys[0].as_ref().unwrap();
+            if t.is_leaf {
+                assert!(key.starts_with("POINT("), "unexpected key: {}", key);
+            } else {
+                assert!(key.starts_with("POLYGON(("), "unexpected key: {}", key);
+            }
+        }
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "cannot execute INSERT in a read-only transaction")]
+    fn test_inspection_rejects_writes() {
+        let oid = crate::demo::setup(10);
+        let _index = IndexInspector::open(oid).unwrap();
+        Spi::run("INSERT INTO gevel.demo_points(p) VALUES (point(1, 1))");
+    }
+
+    #[pg_test]
+    fn test_writes_allowed_outside_of_inspections() {
+        let oid = crate::demo::setup(10);
+        {
+            let outer = IndexInspector::open(oid).unwrap();
+            let inner = IndexInspector::open(oid).unwrap();
+            // inspections may be dropped in any order
+            drop(outer);
+            assert!(!crate::readonly::is_read_only());
+            inner
+                .read_only
+                .allow_writes(|| Spi::run("INSERT INTO gevel.demo_points(p) VALUES (point(1, 1))"));
+        }
+        Spi::run("INSERT INTO gevel.demo_points(p) VALUES (point(2, 2))");
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM gevel.demo_points"),
+            Some(12)
+        );
+    }
+
+    #[pg_test]
+    fn test_functions_run_in_read_only_transactions() {
+        let oid = crate::demo::setup(10_000);
+        // seed snapshots, so that the read-only run has something to reuse
+        crate::gist_stat_incremental(oid);
+        Spi::run("SET LOCAL transaction_read_only = on");
+        assert!(crate::readonly::is_read_only());
+
+        // all functions taking just an index oid, together with the ones needing more arguments
+        let functions = Spi::get_one::<Vec<String>>(
+            "SELECT array_agg(format('%I.%I', n.nspname, p.proname)) \
+             FROM pg_proc p JOIN pg_namespace n ON n.oid = p.pronamespace \
+             WHERE (p.proname LIKE 'gist\\_%' OR n.nspname = 'gevel') \
+             AND p.pronargs > 0 AND p.proargtypes[0] = 'oid'::regtype AND p.pronargs - p.pronargdefaults = 1",
+        )
+        .unwrap();
+        assert!(functions.len() > 10);
+        let mut queries: Vec<_> = functions
+            .iter()
+            .map(|f| format!("SELECT count(*) FROM {}({})", f, oid))
+            .collect();
+        queries.push(format!("SELECT count(*) FROM gist_page_items({}, 0)", oid));
+        queries.push(format!(
+            "SELECT count(*) FROM gist_key_outliers({}, 10)",
+            oid
+        ));
+        queries.push(format!(
+            "SELECT count(*) FROM gevel.report({}, 'stats,levels,histogram,health,signatures,ordering,duplicates')",
+            oid
+        ));
+        for query in queries.iter() {
+            let rows = Spi::get_one::<i64>(query);
+            assert!(rows.is_some(), "{} returned no rows", query);
+        }
+    }
+
+    #[pg_test]
+    fn test_tree_max_level() {
+        let oid = crate::demo::setup(10_000);
+        let whole = crate::gist_tree(oid, false);
+        let root_only = Spi::get_one::<String>(&format!("SELECT gist_tree({}, 0)", oid)).unwrap();
+        assert_eq!(root_only.lines().count(), 1);
+        assert!(whole.lines().count() > 1);
+
+        let levels = IndexInspector::open(oid).unwrap().stats(None).level;
+        let deepest = crate::gist_tree_max_level(oid, levels as i32, false);
+        assert_eq!(deepest, whole);
+        let by_name = Spi::get_one::<String>(&format!(
+            "SELECT gist_tree('{}', 0)",
+            crate::demo::DEMO_INDEX
+        ));
+        assert_eq!(by_name, Some(root_only));
+    }
+
+    #[pg_test]
+    fn test_tree_sparklines_of_child_fill() {
+        let oid = crate::demo::setup(10_000);
+        let plain = crate::gist_tree(oid, false);
+        let with_sparklines = crate::gist_tree(oid, true);
+        assert!(!plain.contains("children:"));
+        assert_eq!(plain.lines().count(), with_sparklines.lines().count());
+
+        let mut internal_pages = 0;
+        for (plain, line) in plain.lines().zip(with_sparklines.lines()) {
+            match line.split_once(" children: ") {
+                Some((page, bars)) => {
+                    internal_pages += 1;
+                    assert_eq!(page, plain);
+                    // one bar per downlink
+                    let tuples = page.split("numTuple: ").nth(1).unwrap();
+                    let tuples: usize = tuples.split(' ').next().unwrap().parse().unwrap();
+                    assert_eq!(bars.chars().count(), tuples, "{}", line);
+                    assert!(bars.chars().all(|c| ('▁'..='█').contains(&c)), "{}", line);
+                }
+                None => assert_eq!(line, plain),
+            }
+        }
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert_eq!(internal_pages, stats.num_pages - stats.num_leaf_pages);
+
+        // children are read to render the sparkline of the root, even if they're not printed
+        let root_only = crate::gist_tree_max_level(oid, 0, true);
+        assert_eq!(root_only.lines().count(), 1);
+        assert!(root_only.contains(" children: "));
+    }
+
+    #[pg_test]
+    fn test_page_hashes_detect_changes() {
+        let oid = crate::demo::setup(10_000);
+        let before = crate::gist_page_hashes(oid).0;
+        assert_eq!(crate::gist_page_hashes(oid).0, before);
+        assert_eq!(crate::hashes::compare_hashes(&before, &before), Vec::new());
+
+        Spi::run("INSERT INTO gevel.demo_points(p) SELECT point(1e6 + i, 1e6 + i) FROM generate_series(1, 2000) i");
+        let after = crate::gist_page_hashes(oid).0;
+        let changes = crate::hashes::compare_hashes(&before, &after);
+        assert!(changes.iter().any(|c| c.change == "changed"));
+        assert!(changes.iter().any(|c| c.change == "added"));
+        assert!(changes.iter().all(|c| c.change != "removed"));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "page hashes must be a JSON object")]
+    fn test_page_hashes_diff_rejects_invalid_input() {
+        crate::hashes::compare_hashes(&serde_json::json!([1, 2]), &serde_json::json!({}));
+    }
+
+    /// Builds a page image of `block_size` bytes with a given `pd_lower` and line pointers
+    /// described by their `(lp_off, lp_flags, lp_len)`.
+    fn page_image(block_size: usize, pd_lower: usize, items: &[(u32, u32, u32)]) -> Vec<u8> {
+        use memoffset::offset_of;
+        use pgx::pg_sys::PageHeaderData;
+
+        let mut bytes = vec![0u8; block_size];
+        let lower = offset_of!(PageHeaderData, pd_lower);
+        bytes[lower..lower + 2].copy_from_slice(&(pd_lower as u16).to_ne_bytes());
+        let linp = offset_of!(PageHeaderData, pd_linp);
+        for (i, (off, flags, len)) in items.iter().enumerate() {
+            let iid = off | (flags << 15) | (len << 17);
+            bytes[linp + i * 4..linp + i * 4 + 4].copy_from_slice(&iid.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// Builds an 8KB GiST page image holding 16-byte tuples described by their
+    /// `(lp_flags, is_invalid)`.
+    fn gist_page_image(is_leaf: bool, tuples: &[(u32, bool)]) -> Vec<u8> {
+        use memoffset::offset_of;
+        use pgx::pg_sys::{GISTPageOpaqueData, PageHeaderData, F_LEAF};
+
+        fn put_u16(bytes: &mut [u8], at: usize, value: u16) {
+            bytes[at..at + 2].copy_from_slice(&value.to_ne_bytes());
+        }
+        let special = 8192 - std::mem::size_of::<GISTPageOpaqueData>();
+        let upper = special - 16 * tuples.len();
+        let items: Vec<_> = tuples
+            .iter()
+            .enumerate()
+            .map(|(i, &(flags, _))| ((special - 16 * (i + 1)) as u32, flags, 16))
+            .collect();
+        let header = offset_of!(PageHeaderData, pd_linp);
+        let mut bytes = page_image(8192, header + items.len() * 4, &items);
+        put_u16(
+            &mut bytes,
+            offset_of!(PageHeaderData, pd_upper),
+            upper as u16,
+        );
+        put_u16(
+            &mut bytes,
+            offset_of!(PageHeaderData, pd_special),
+            special as u16,
+        );
+        if is_leaf {
+            let flags = special + offset_of!(GISTPageOpaqueData, flags);
+            put_u16(&mut bytes, flags, F_LEAF as u16);
+        }
+        for (i, &(_, invalid)) in tuples.iter().enumerate() {
+            let tuple = special - 16 * (i + 1);
+            // ip_posid of t_tid, followed by t_info holding the size of the tuple
+            let posid = if invalid { crate::TUPLE_IS_INVALID } else { 1 };
+            put_u16(&mut bytes, tuple + 4, posid);
+            put_u16(&mut bytes, tuple + 6, 16);
+        }
+        bytes
+    }
+
+    #[pg_test]
+    fn test_offset_range_of_empty_pages() {
+        let header = memoffset::offset_of!(pg_sys::PageHeaderData, pd_linp);
+        for pd_lower in [0, header].iter() {
+            let page = crate::Page::from_image(&page_image(8192, *pd_lower, &[]));
+            assert_eq!(page.offsets().count(), 0);
+            assert_eq!(page.tuples().count(), 0);
+        }
+    }
+
+    #[pg_test]
+    fn test_offset_range_skips_items_without_tuples() {
+        let header = memoffset::offset_of!(pg_sys::PageHeaderData, pd_linp);
+        let items = [
+            (8000, pg_sys::LP_NORMAL, 16),
+            (0, pg_sys::LP_UNUSED, 0),
+            (7984, pg_sys::LP_DEAD, 16),
+            (5, pg_sys::LP_REDIRECT, 0),
+            (8190, pg_sys::LP_NORMAL, 16),
+        ];
+        let page = crate::Page::from_image(&page_image(8192, header + items.len() * 4, &items));
+        assert_eq!(page.offsets().len(), 5);
+        assert_eq!(page.offsets().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        let offsets: Vec<_> = page.tuples().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, vec![1, 3]);
+    }
+
+    #[pg_test]
+    #[should_panic(
+        expected = "page image is corrupted: special space offset 8190 is out of bounds"
+    )]
+    fn test_special_space_out_of_bounds_is_rejected() {
+        use memoffset::offset_of;
+        use pgx::pg_sys::PageHeaderData;
+
+        let mut bytes = page_image(8192, offset_of!(PageHeaderData, pd_linp), &[]);
+        let upper = offset_of!(PageHeaderData, pd_upper);
+        bytes[upper..upper + 2].copy_from_slice(&8000u16.to_ne_bytes());
+        let special = offset_of!(PageHeaderData, pd_special);
+        bytes[special..special + 2].copy_from_slice(&8190u16.to_ne_bytes());
+        let page = crate::Page::from_image(&bytes);
+        crate::gist::GistPage::new(&page);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "page image is corrupted: line pointer 0 is out of bounds")]
+    fn test_line_pointer_out_of_bounds_is_rejected() {
+        let page = crate::Page::from_image(&page_image(8192, u16::MAX as usize, &[]));
+        page.item_id(0);
+    }
+
+    #[pg_test]
+    fn test_offset_range_is_capped_at_page_size() {
+        let page = crate::Page::from_image(&page_image(8192, u16::MAX as usize, &[]));
+        assert_eq!(page.offsets().len(), 2042);
+        assert_eq!(page.offsets().last(), Some(2042));
+    }
+
+    #[pg_test]
+    fn test_block_size_matches_server() {
+        let setting = Spi::get_one::<i64>("SELECT current_setting('block_size')::bigint");
+        assert_eq!(Some(pg_sys::BLCKSZ as i64), setting);
+    }
+
+    #[pg_test]
+    fn test_page_bounds_follow_block_size() {
+        use memoffset::offset_of;
+        use pgx::pg_sys::PageHeaderData;
+
+        for &block_size in [8192usize, 16384, 32768].iter() {
+            let max_items = (block_size - 24) / 4;
+            assert_eq!(crate::max_items(block_size), max_items);
+            let page = crate::Page::from_image(&page_image(block_size, u16::MAX as usize, &[]));
+            assert_eq!(page.size(), block_size);
+            assert_eq!(page.offsets().len(), max_items);
+            assert_eq!(page.offsets().last(), Some(max_items as u16));
+
+            // a tuple at the end of the page is only in bounds of pages large enough
+            let header = offset_of!(PageHeaderData, pd_linp);
+            let items = [
+                (block_size as u32 - 16, pg_sys::LP_NORMAL, 16),
+                (16368, pg_sys::LP_NORMAL, 16),
+            ];
+            let page = crate::Page::from_image(&page_image(block_size, header + 8, &items));
+            let in_bounds = if block_size > 8192 { 2 } else { 1 };
+            assert_eq!(page.tuples().count(), in_bounds);
+            assert_eq!(page.as_bytes().len(), block_size);
+        }
+    }
+
+    #[pg_test]
+    fn test_occupancy_excludes_special_space() {
+        use pgx::pg_sys::{GISTPageOpaqueData, ItemIdData, PageHeaderData};
+        use std::mem::size_of;
+
+        let empty = crate::Page::from_image(&gist_page_image(true, &[]));
+        let header = memoffset::offset_of!(PageHeaderData, pd_linp);
+        let capacity = 8192 - size_of::<GISTPageOpaqueData>() - header - size_of::<ItemIdData>();
+        assert_eq!(empty.capacity(), capacity);
+        // so that an empty page is entirely free, and a page is full when nothing fits anymore
+        assert_eq!(empty.free_space(), capacity);
+        assert_eq!(
+            crate::gist::occupancy(empty.free_space(), empty.capacity()),
+            0.0
+        );
+        assert_eq!(crate::gist::occupancy(0, empty.capacity()), 1.0);
+
+        let full =
+            crate::Page::from_image(&gist_page_image(true, &[(pg_sys::LP_NORMAL, false); 4]));
+        assert_eq!(full.capacity(), capacity);
+        assert_eq!(
+            full.free_space(),
+            capacity - 4 * (16 + size_of::<ItemIdData>())
+        );
+
+        // the legacy page size ignores the special space
+        Spi::run("SET LOCAL gevel.legacy_occupancy = on");
+        assert_eq!(empty.capacity(), crate::legacy_page_size(8192));
+        assert!(empty.capacity() > empty.free_space());
+    }
+
+    #[pg_test]
+    fn test_size_math_follows_block_size() {
+        // the page minus a maxaligned header with a single line pointer
+        for &(block_size, legacy) in [(8192, 8160), (16384, 16352), (32768, 32736)].iter() {
+            assert_eq!(crate::legacy_page_size(block_size), legacy);
+        }
+        // int4 B-tree leaf pages filled up to 90% fit twice as many tuples on twice as large
+        // pages, minus rounding
+        for &(block_size, per_leaf) in [(8192, 366), (16384, 735), (32768, 1472)].iter() {
+            let leaf = crate::advisor::btree_shape(block_size, per_leaf, 4.0);
+            assert_eq!((leaf.pages, leaf.levels), (2, 1));
+            let split = crate::advisor::btree_shape(block_size, per_leaf + 1, 4.0);
+            assert_eq!((split.pages, split.levels), (4, 2));
+        }
+    }
+
+    #[pg_test]
+    fn test_pages_csv() {
+        let oid = crate::demo::setup(10_000);
+        let lines: Vec<String> = crate::gist_pages_csv(oid).collect();
+        let rows = IndexInspector::open(oid).unwrap().tree_rows();
+        assert_eq!(lines.len(), rows.len() + 1);
+        let columns = lines[0].split(',').count();
+        assert!(lines.iter().all(|l| l.split(',').count() == columns));
+        // root has no parent nor rightlink
+        assert!(lines[1].starts_with(&format!("0,{},,0,", crate::GIST_ROOT_BLKNO)));
+        let leaves = lines.iter().filter(|l| l.ends_with(",leaf")).count();
+        assert_eq!(leaves, rows.iter().filter(|r| r.is_leaf).count());
+    }
+
+    #[pg_test]
+    fn test_tree_xml() {
+        let oid = crate::demo::setup(10_000);
+        let xml = Spi::get_one::<String>(&format!("SELECT gevel.tree_xml({}, true)", oid)).unwrap();
+        let pages = IndexInspector::open(oid).unwrap().stats(None).num_pages as usize;
+        assert!(
+            xml.starts_with("<index is_valid=\"true\"><page "),
+            "{}",
+            xml
+        );
+        assert!(xml.ends_with("</page></index>"));
+        assert_eq!(xml.matches("<page ").count(), pages);
+        assert_eq!(xml.matches("<items ").count(), pages);
+        assert!(xml.contains(" level=\"0\""));
+    }
+
+    #[pg_test]
+    fn test_tree_lines_match_tree() {
+        let oid = crate::demo::setup(10_000);
+        let lines: Vec<String> = IndexInspector::open_streaming(oid)
+            .unwrap()
+            .tree_lines()
+            .collect();
+        let tree = crate::gist_tree(oid, false);
+        assert_eq!(lines, tree.lines().collect::<Vec<_>>());
+
+        let rows =
+            Spi::get_one::<i64>(&format!("SELECT count(*) FROM gist_tree_lines({})", oid)).unwrap();
+        assert_eq!(rows as usize, lines.len());
+    }
+
+    #[pg_test]
+    fn test_tree_dot() {
+        let oid = crate::demo::setup(10_000);
+        let dot = crate::gist_tree_dot(oid);
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert!(dot.starts_with("digraph gist {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(
+            dot.lines().filter(|l| l.contains("[label=\"blk ")).count() as u64,
+            stats.num_pages
+        );
+        let downlinks = dot
+            .lines()
+            .filter(|l| l.contains(" -> ") && !l.contains("dashed"))
+            .count() as u64;
+        assert_eq!(downlinks, stats.num_pages - 1);
+        assert!(dot.contains("    p0 [label=\"blk 0\\n"));
+    }
+
+    #[pg_test]
+    fn test_tree_mermaid() {
+        let oid = crate::demo::setup(10_000);
+        let root_only = crate::gist_tree_mermaid(oid, 0);
+        assert_eq!(root_only.lines().collect::<Vec<_>>().len(), 2);
+        assert!(root_only.starts_with("flowchart TD\n    p0[\"blk 0<br/>"));
+
+        let rows = IndexInspector::open(oid).unwrap().tree_rows_to(Some(1));
+        let upper = crate::gist_tree_mermaid(oid, 1);
+        let downlinks = upper.lines().filter(|l| l.contains(" -->|")).count();
+        assert_eq!(downlinks, rows.len() - 1);
+        for row in rows.iter() {
+            assert!(upper.contains(&format!("    p{}", row.block_num)));
+        }
+    }
+
+    #[pg_test]
+    fn test_tree_json_stream() {
+        let oid = crate::demo::setup(10_000);
+        let pages: Vec<serde_json::Value> =
+            crate::gist_tree_json_stream(oid).map(|p| p.0).collect();
+        let tree = crate::gist_tree_json(oid, false, -1, JsonB(serde_json::json!({}))).0;
+        assert_eq!(pages[0]["blkno"], tree["root"]["blkno"]);
+        assert_eq!(pages[0]["parent_blkno"], serde_json::Value::Null);
+        assert_eq!(pages[0]["occupied"], tree["root"]["occupied"]);
+        // parents are streamed before their children
+        for (i, page) in pages.iter().enumerate().skip(1) {
+            assert!(pages[..i]
+                .iter()
+                .any(|p| p["blkno"] == page["parent_blkno"]));
+        }
+        let streamed = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM gist_tree_json_stream({}) p WHERE (p->>'is_leaf')::bool",
+            oid
+        ));
+        let stats = IndexInspector::open(oid).unwrap().stats(None);
+        assert_eq!(streamed, Some(stats.num_leaf_pages as i64));
+    }
+
+    #[pg_test]
+    fn test_tree_rows() {
+        let oid = crate::demo::setup(10_000);
+        let index = IndexInspector::open(oid).unwrap();
+        let rows = index.tree_rows();
+        let stats = index.stats(None);
+        assert_eq!(rows.len() as u64, stats.num_pages);
+        assert_eq!(
+            rows.iter().filter(|r| r.is_leaf).count() as u64,
+            stats.num_leaf_pages
+        );
+        assert_eq!(rows[0].parent, None);
+        assert_eq!(rows[0].level, 0);
+        for row in rows.iter().skip(1) {
+            let parent = rows
+                .iter()
+                .find(|p| Some(p.block_num) == row.parent)
+                .unwrap();
+            assert_eq!(parent.level + 1, row.level);
+        }
+
+        for row in rows.iter() {
+            assert!(row.largest_insertable + 4 <= row.free_space.max(4));
+            assert_eq!(row.largest_insertable % 8, 0);
+        }
+        let fits = Spi::get_one::<bool>(&format!(
+            "SELECT bool_and(largest_insertable < free_bytes OR free_bytes < 4) FROM gist_tree_rows({})",
+            oid
+        ));
+        assert_eq!(fits, Some(true));
+
+        let leaves = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM gist_tree_rows({}) WHERE is_leaf",
+            oid
+        ));
+        assert_eq!(leaves, Some(stats.num_leaf_pages as i64));
+    }
+
+    #[pg_test]
+    fn test_streamed_tree_rows_match_collected_ones() {
+        let oid = crate::demo::setup(10_000);
+        let collected = IndexInspector::open(oid).unwrap().tree_rows();
+        let streamed: Vec<_> = IndexInspector::open_streaming(oid)
+            .unwrap()
+            .into_tree_rows()
+            .collect();
+        assert_eq!(streamed.len(), collected.len());
+        for (s, c) in streamed.iter().zip(collected.iter()) {
+            assert_eq!(s.to_csv(), c.to_csv());
+        }
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "invalid index oid")]
+    fn test_zero_oid_is_rejected() {
+        crate::gist_stat(0, -1);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "invalid index oid")]
+    fn test_nonexistent_oid_is_rejected() {
+        crate::gist_tree(u32::MAX - 1, false);
+    }
+
+    #[pg_test]
+    fn test_open_returns_structured_errors() {
+        use crate::error::{Error, ErrorClass};
+
+        let error = IndexInspector::open(0).err().unwrap();
+        assert_eq!(error, Error::InvalidRelation { oid: 0 });
+        assert_eq!(error.class(), ErrorClass::InvalidArgument);
+
+        crate::demo::setup(100);
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.demo_points'::regclass::oid::bigint").unwrap();
+        let error = IndexInspector::open(oid as pg_sys::Oid).err().unwrap();
+        assert_eq!(error.class(), ErrorClass::Unsupported);
+        assert_eq!(
+            error.to_string(),
+            "relation \"demo_points\" is not a GiST index, but a table"
+        );
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "relation \"demo_points\" is not a GiST index, but a table")]
+    fn test_table_is_rejected() {
+        crate::demo::setup(100);
+        let oid = Spi::get_one::<i64>("SELECT 'gevel.demo_points'::regclass::oid::bigint").unwrap();
+        crate::gist_stat(oid as pg_sys::Oid, -1);
+    }
+
+    #[pg_test]
+    #[should_panic(
+        expected = "relation \"demo_points_pkey\" is not a GiST index, but a btree index"
+    )]
+    fn test_btree_index_is_rejected() {
+        crate::demo::setup(100);
+        let oid =
+            Spi::get_one::<i64>("SELECT 'gevel.demo_points_pkey'::regclass::oid::bigint").unwrap();
+        crate::gist_tree(oid as pg_sys::Oid, false);
+    }
+
+    #[pg_test]
+    fn test_null_input_returns_null() {
+        assert_eq!(Spi::get_one::<String>("SELECT gist_tree(NULL)"), None);
+        assert_eq!(Spi::get_one::<String>("SELECT gist_stat(NULL)"), None);
+    }
+}
+
+#[cfg(test)]
+pub mod pg_test {
+    pub fn setup(_options: Vec<&str>) {
+        // perform one-off initialization when the pg_test framework starts
+    }
+
+    pub fn postgresql_conf_options() -> Vec<&'static str> {
+        // return any postgresql.conf settings that are required for your tests
+        vec!["shared_preload_libraries = 'gevel'"]
+    }
 }
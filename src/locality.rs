@@ -0,0 +1,125 @@
+use crate::gist::IndexInspector;
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys::{BlockNumber, OffsetNumber};
+
+/// Spread of heap blocks referenced by tuples of a single leaf page.
+#[derive(Debug)]
+pub struct PageLocality {
+    pub block_num: BlockNumber,
+    /// Number of leaf tuples on the page.
+    pub tuples: u64,
+    pub min_heap_block: BlockNumber,
+    pub max_heap_block: BlockNumber,
+    /// Standard deviation of referenced heap block numbers.
+    pub heap_block_stddev: f64,
+    /// Number of distinct heap blocks a bitmap heap scan would have to read to fetch all
+    /// tuples of the page.
+    pub distinct_heap_blocks: u64,
+}
+
+impl PageLocality {
+    /// Distance between the first and the last heap block referenced by the page.
+    pub fn spread(&self) -> u64 {
+        (self.max_heap_block - self.min_heap_block) as u64
+    }
+}
+
+/// Result of [IndexInspector::heap_locality].
+#[derive(Debug, Default)]
+pub struct HeapLocality {
+    /// Leaf pages in depth-first order. Empty leaf pages are skipped.
+    pub pages: Vec<PageLocality>,
+    /// Pearson correlation between the position of leaf tuples in the depth-first order of the
+    /// tree and heap blocks they point to, ranging from -1 to 1. Values close to 0 mean that
+    /// neighbouring index entries are scattered all over the heap.
+    pub correlation: f64,
+}
+
+#[derive(Default)]
+struct LocalityCollector {
+    pages: Vec<PageLocality>,
+    /// Heap blocks referenced by the currently visited leaf page.
+    page_blocks: Vec<BlockNumber>,
+    // running sums of the correlation between leaf tuple rank (x) and heap block (y)
+    n: f64,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_yy: f64,
+    sum_xy: f64,
+}
+
+impl LocalityCollector {
+    fn correlation(&self) -> f64 {
+        let cov = self.n * self.sum_xy - self.sum_x * self.sum_y;
+        let var_x = self.n * self.sum_xx - self.sum_x * self.sum_x;
+        let var_y = self.n * self.sum_yy - self.sum_y * self.sum_y;
+        if var_x <= 0.0 || var_y <= 0.0 {
+            // all tuples point to the same heap block, which is perfect locality
+            if self.n > 0.0 {
+                1.0
+            } else {
+                0.0
+            }
+        } else {
+            cov / (var_x.sqrt() * var_y.sqrt())
+        }
+    }
+}
+
+impl Visitor for LocalityCollector {
+    fn on_tuple(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) {
+        if !page.is_leaf() {
+            return;
+        }
+        let x = self.n;
+        let y = tuple.block_num() as f64;
+        self.n += 1.0;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_yy += y * y;
+        self.sum_xy += x * y;
+        self.page_blocks.push(tuple.block_num());
+    }
+
+    fn on_page_end(&mut self, page: &PageVisit) {
+        if !page.is_leaf() || self.page_blocks.is_empty() {
+            return;
+        }
+        let blocks = &mut self.page_blocks;
+        let n = blocks.len() as f64;
+        let mean = blocks.iter().map(|&b| b as f64).sum::<f64>() / n;
+        let variance = blocks
+            .iter()
+            .map(|&b| (b as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        blocks.sort_unstable();
+        let distinct = 1 + blocks.windows(2).filter(|pair| pair[0] != pair[1]).count();
+        self.pages.push(PageLocality {
+            block_num: page.block_num,
+            tuples: blocks.len() as u64,
+            min_heap_block: blocks[0],
+            max_heap_block: blocks[blocks.len() - 1],
+            heap_block_stddev: variance.sqrt(),
+            distinct_heap_blocks: distinct as u64,
+        });
+        blocks.clear();
+    }
+}
+
+impl IndexInspector {
+    /// Measures how well the order of leaf tuples matches the physical order of heap tuples
+    /// they point to. Poor locality makes bitmap heap scans read many more heap pages than
+    /// the number of matching index entries would suggest.
+    pub fn heap_locality(&self) -> HeapLocality {
+        let mut collector = LocalityCollector::default();
+        self.walk(&mut collector);
+        HeapLocality {
+            correlation: collector.correlation(),
+            pages: collector.pages,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use pgx::PgMemoryContexts;
+use std::cell::Cell;
+use std::panic::AssertUnwindSafe;
+
+thread_local! {
+    // bytes allocated in the memory context of the last finished inspection, -1 if there was none
+    static LAST_CONTEXT: Cell<i64> = const { Cell::new(-1) };
+}
+
+/// Bytes allocated by Postgres functions called during the last finished inspection, as
+/// returned by `gevel_memory()`. Only available on Postgres 13 and newer.
+pub fn last_context_bytes() -> Option<i64> {
+    let bytes = LAST_CONTEXT.with(|last| last.get());
+    if bytes < 0 {
+        None
+    } else {
+        Some(bytes)
+    }
+}
+
+/// Accounts memory used during the lifetime of an [crate::gist::IndexInspector]. Allocations
+/// made with palloc while walking the index are redirected into a dedicated memory context,
+/// which is deleted when the inspection finishes.
+///
+/// The context is a child of the one current when the inspection started, so when an ERROR
+/// aborts the inspection it's released by Postgres together with its parent.
+pub struct InspectionMemory {
+    context: PgMemoryContexts,
+}
+
+impl InspectionMemory {
+    pub fn start() -> Self {
+        InspectionMemory {
+            context: PgMemoryContexts::new("gevel inspection"),
+        }
+    }
+
+    /// Runs `f` with the inspection context switched to, and switches back to the previous one
+    /// when it returns. When `f` raises an ERROR, it's the error recovery of Postgres which
+    /// switches back to a context of the caller, as it does for C code.
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        // switch_to takes a Fn, but calls it exactly once
+        let f = AssertUnwindSafe(Cell::new(Some(f)));
+        PgMemoryContexts::For(self.context.value()).switch_to(move |_| match f.take() {
+            Some(f) => f(),
+            None => unreachable!("inspection memory closure called twice"),
+        })
+    }
+
+    #[cfg(feature = "pg13")]
+    fn context_bytes(&self) -> i64 {
+        unsafe { pgx::pg_sys::MemoryContextMemAllocated(self.context.value(), true) as i64 }
+    }
+
+    #[cfg(not(feature = "pg13"))]
+    fn context_bytes(&self) -> i64 {
+        -1
+    }
+}
+
+impl Drop for InspectionMemory {
+    fn drop(&mut self) {
+        let bytes = self.context_bytes();
+        LAST_CONTEXT.with(|last| last.set(bytes));
+        // the context itself is deleted when dropped
+    }
+}
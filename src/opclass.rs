@@ -0,0 +1,187 @@
+use crate::gist::{IndexInspector, SignatureCollector};
+use crate::key::KeyDecoder;
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys::{OffsetNumber, Relation};
+use pgx::{JsonB, Spi};
+use serde_json::json;
+
+/// Range flag set on empty ranges, stored in the last byte of a range.
+const RANGE_EMPTY: u8 = 0x01;
+/// Range flag set by GiST on internal keys whose subtrees contain empty ranges.
+const RANGE_CONTAIN_EMPTY: u8 = 0x80;
+/// Address families stored in inet_ops keys. Internal keys covering both families store 0.
+const PGSQL_AF_INET: u8 = 2;
+const PGSQL_AF_INET6: u8 = 3;
+
+/// Well-known opclass families, for which reports include specialized sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownOpclass {
+    /// PostGIS `gist_geometry_ops_2d`: SRIDs of indexed geometries.
+    Geometry2d,
+    /// `range_ops`: counts of empty ranges.
+    Range,
+    /// `inet_ops`: mix of address families.
+    Inet,
+    /// `tsvector_ops`: saturation of signatures.
+    Tsvector,
+}
+
+/// Maps names of opclass families to the specialized sections reported for them.
+const REGISTRY: &[(&str, KnownOpclass)] = &[
+    ("gist_geometry_ops_2d", KnownOpclass::Geometry2d),
+    ("range_ops", KnownOpclass::Range),
+    ("inet_ops", KnownOpclass::Inet),
+    ("tsvector_ops", KnownOpclass::Tsvector),
+];
+
+impl KnownOpclass {
+    pub fn from_opfamily(name: &str) -> Option<Self> {
+        REGISTRY
+            .iter()
+            .find(|(opfamily, _)| *opfamily == name)
+            .map(|(_, opclass)| *opclass)
+    }
+}
+
+/// Counters gathered over keys of the first attribute of indexes with a known opclass.
+#[derive(Debug, Default)]
+struct KeyCounters {
+    leaf_keys: u64,
+    internal_keys: u64,
+    empty_ranges: u64,
+    /// Internal range keys whose subtrees contain empty ranges.
+    containing_empty: u64,
+    ipv4: u64,
+    ipv6: u64,
+    /// Internal inet keys covering both address families.
+    mixed_family: u64,
+}
+
+pub(crate) struct OpclassCollector {
+    opclass: KnownOpclass,
+    decoder: KeyDecoder,
+    counters: KeyCounters,
+    signatures: SignatureCollector,
+}
+
+impl OpclassCollector {
+    pub(crate) fn new(relation: Relation, opclass: KnownOpclass) -> Self {
+        OpclassCollector {
+            opclass,
+            decoder: KeyDecoder::new(relation),
+            counters: KeyCounters::default(),
+            signatures: SignatureCollector::new(relation),
+        }
+    }
+}
+
+impl Visitor for OpclassCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if self.opclass == KnownOpclass::Tsvector {
+            self.signatures.on_page(page);
+        }
+        true
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        match self.opclass {
+            KnownOpclass::Tsvector => return self.signatures.on_tuple(page, offset, tuple),
+            KnownOpclass::Geometry2d => return,
+            KnownOpclass::Range | KnownOpclass::Inet => {}
+        }
+        let payload = match self.decoder.varlena_payload(tuple, 0) {
+            Some(payload) if !payload.is_empty() => payload,
+            _ => return,
+        };
+        let c = &mut self.counters;
+        let is_leaf = page.is_leaf();
+        if is_leaf {
+            c.leaf_keys += 1;
+        } else {
+            c.internal_keys += 1;
+        }
+        if self.opclass == KnownOpclass::Range {
+            let flags = payload[payload.len() - 1];
+            if is_leaf && flags & RANGE_EMPTY != 0 {
+                c.empty_ranges += 1;
+            } else if !is_leaf && flags & RANGE_CONTAIN_EMPTY != 0 {
+                c.containing_empty += 1;
+            }
+        } else {
+            match payload[0] {
+                PGSQL_AF_INET if is_leaf => c.ipv4 += 1,
+                PGSQL_AF_INET6 if is_leaf => c.ipv6 += 1,
+                PGSQL_AF_INET | PGSQL_AF_INET6 => {}
+                _ if !is_leaf => c.mixed_family += 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+impl IndexInspector {
+    /// Returns the known opclass family of the first key attribute, if there's one.
+    pub fn known_opclass(&self) -> Option<KnownOpclass> {
+        let opfamily = unsafe { *(*self.relation).rd_opfamily };
+        let name = Spi::get_one::<String>(&format!(
+            "SELECT opfname::text FROM pg_opfamily WHERE oid = {}",
+            opfamily
+        ))?;
+        KnownOpclass::from_opfamily(&name)
+    }
+
+    /// Returns SRIDs of geometries indexed by the first key attribute together with the number
+    /// of rows using them. Reads the whole indexed table. Returns NULL for expression indexes.
+    fn srids(&self) -> serde_json::Value {
+        let query = format!(
+            "SELECT format('SELECT coalesce(jsonb_agg(jsonb_build_object(''srid'', srid, ''rows'', n) ORDER BY srid), ''[]'') FROM (SELECT ST_SRID(%I) AS srid, count(*) AS n FROM %s WHERE %I IS NOT NULL GROUP BY 1) s', a.attname, i.indrelid::regclass, a.attname) \
+             FROM pg_index i JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = i.indkey[0] \
+             WHERE i.indexrelid = {}",
+            self.oid()
+        );
+        Spi::get_one::<String>(&query)
+            .and_then(|srid_query| Spi::get_one::<JsonB>(&srid_query))
+            .map_or(serde_json::Value::Null, |srids| srids.0)
+    }
+
+    /// Finishes specialized report section of a known opclass.
+    pub(crate) fn opclass_report(&self, collector: &OpclassCollector) -> serde_json::Value {
+        let c = &collector.counters;
+        let (opfamily, _) = REGISTRY
+            .iter()
+            .find(|(_, opclass)| *opclass == collector.opclass)
+            .unwrap();
+        let mut report = json!({ "opfamily": opfamily });
+        match collector.opclass {
+            KnownOpclass::Geometry2d => {
+                let srids = self.srids();
+                report["mixed_srids"] = json!(srids.as_array().map(|s| s.len() > 1));
+                report["srids"] = srids;
+            }
+            KnownOpclass::Range => {
+                report["leaf_keys"] = json!(c.leaf_keys);
+                report["empty_ranges"] = json!(c.empty_ranges);
+                report["internal_keys"] = json!(c.internal_keys);
+                report["internal_keys_containing_empty"] = json!(c.containing_empty);
+            }
+            KnownOpclass::Inet => {
+                report["leaf_keys"] = json!(c.leaf_keys);
+                report["ipv4"] = json!(c.ipv4);
+                report["ipv6"] = json!(c.ipv6);
+                report["internal_keys"] = json!(c.internal_keys);
+                report["mixed_family_keys"] = json!(c.mixed_family);
+            }
+            KnownOpclass::Tsvector => {
+                report["signatures"] = json!(collector
+                    .signatures
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .map(|(level, s)| s.to_json(level))
+                    .collect::<Vec<_>>());
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,167 @@
+use crate::gist::IndexInspector;
+use crate::key::KeyDecoder;
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys::{
+    index_getprocinfo, Datum, FmgrInfo, FunctionCall3Coll, OffsetNumber, Oid, Relation, GISTENTRY,
+    GIST_PENALTY_PROC,
+};
+use serde_json::json;
+
+/// Tells how well tuples within leaf pages are clustered by key proximity. Proximity of two
+/// keys is measured with the opclass penalty function - the cost of extending one key to cover
+/// the other - so it works for any opclass: area enlargement for geometric keys, range growth
+/// for orderable ones.
+#[derive(Debug, Default)]
+pub struct OrderingStats {
+    /// Number of analyzed leaf pages with at least 2 non-NULL keys.
+    pub pages: u64,
+    /// Number of adjacent tuple pairs analyzed.
+    pub pairs: u64,
+    /// Sum of penalties between tuples at adjacent offsets.
+    adjacent_penalty: f64,
+    /// Sum of penalties between tuples half a page apart, used as a baseline of what an
+    /// arbitrary order of tuples within the page would give.
+    distant_penalty: f64,
+}
+
+impl OrderingStats {
+    /// Average penalty between tuples at adjacent offsets.
+    pub fn adjacent_penalty(&self) -> f64 {
+        if self.pairs == 0 {
+            0.0
+        } else {
+            self.adjacent_penalty / self.pairs as f64
+        }
+    }
+
+    /// Average penalty between tuples half a page apart.
+    pub fn distant_penalty(&self) -> f64 {
+        if self.pairs == 0 {
+            0.0
+        } else {
+            self.distant_penalty / self.pairs as f64
+        }
+    }
+
+    /// Ratio of the adjacent to the distant penalty. Values well below 1.0 mean that
+    /// neighbouring tuples hold close keys, while values around 1.0 mean that tuples within
+    /// pages are not ordered at all.
+    pub fn clustering(&self) -> Option<f64> {
+        if self.distant_penalty > 0.0 {
+            Some(self.adjacent_penalty / self.distant_penalty)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "pages": self.pages,
+            "pairs": self.pairs,
+            "adjacent_penalty": self.adjacent_penalty(),
+            "distant_penalty": self.distant_penalty(),
+            "clustering": self.clustering(),
+        })
+    }
+}
+
+pub(crate) struct OrderingCollector {
+    relation: Relation,
+    decoder: KeyDecoder,
+    penalty_proc: *mut FmgrInfo,
+    collation: Oid,
+    /// Non-NULL keys of the first attribute of the currently visited leaf page, together with
+    /// their offsets. Datums point into the page, which stays in memory until the page is left.
+    keys: Vec<(Datum, OffsetNumber)>,
+    pub(crate) stats: OrderingStats,
+}
+
+impl OrderingCollector {
+    pub(crate) fn new(relation: Relation) -> Self {
+        let (penalty_proc, collation) = unsafe {
+            (
+                index_getprocinfo(relation, 1, GIST_PENALTY_PROC as u16),
+                *(*relation).rd_indcollation,
+            )
+        };
+        OrderingCollector {
+            relation,
+            decoder: KeyDecoder::new(relation),
+            penalty_proc,
+            collation,
+            keys: Vec::new(),
+            stats: OrderingStats::default(),
+        }
+    }
+
+    fn penalty(&self, page: &PageVisit, a: (Datum, OffsetNumber), b: (Datum, OffsetNumber)) -> f64 {
+        let entry = |(key, offset)| GISTENTRY {
+            key,
+            rel: self.relation,
+            page: page.page.0,
+            offset,
+            leafkey: false,
+        };
+        let (orig, new) = (entry(a), entry(b));
+        let mut penalty: f32 = 0.0;
+        unsafe {
+            FunctionCall3Coll(
+                self.penalty_proc,
+                self.collation,
+                &orig as *const GISTENTRY as Datum,
+                &new as *const GISTENTRY as Datum,
+                &mut penalty as *mut f32 as Datum,
+            )
+        };
+        penalty as f64
+    }
+}
+
+impl Visitor for OrderingCollector {
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        if !page.is_leaf() {
+            return;
+        }
+        let (values, is_null) = self.decoder.deform(tuple);
+        if !is_null[0] {
+            self.keys.push((values[0], offset));
+        }
+    }
+
+    fn on_page_end(&mut self, page: &PageVisit) {
+        let n = self.keys.len();
+        if !page.is_leaf() || n < 2 {
+            self.keys.clear();
+            return;
+        }
+        let mut pairs = 0;
+        let (mut adjacent, mut distant) = (0.0, 0.0);
+        for i in 0..n - 1 {
+            let a = self.penalty(page, self.keys[i], self.keys[i + 1]);
+            let d = self.penalty(page, self.keys[i], self.keys[(i + n / 2) % n]);
+            // some opclasses return infinity for keys they can't compare
+            if a.is_finite() && d.is_finite() {
+                adjacent += a;
+                distant += d;
+                pairs += 1;
+            }
+        }
+        self.stats.pages += 1;
+        self.stats.pairs += pairs;
+        self.stats.adjacent_penalty += adjacent;
+        self.stats.distant_penalty += distant;
+        self.keys.clear();
+    }
+}
+
+impl IndexInspector {
+    /// Measures how well tuples within leaf pages are clustered by key proximity. Only the
+    /// first key attribute is taken into account.
+    #[cfg(any(test, feature = "pg_test"))]
+    pub fn ordering_stats(&self) -> OrderingStats {
+        let mut collector = OrderingCollector::new(self.relation);
+        self.walk(&mut collector);
+        collector.stats
+    }
+}
@@ -0,0 +1,100 @@
+use crate::error::{raise, ErrorClass};
+use crate::guc;
+use pgx::{notice, JsonB};
+use std::fmt::{Result, Write};
+
+/// Text buffer refusing writes which would make it grow beyond a given limit. Refused writes
+/// return an error, so that formatting of a huge tree stops as soon as the limit is reached
+/// instead of after the whole traversal.
+pub struct CappedString {
+    out: String,
+    limit: usize,
+    truncated: bool,
+}
+
+impl CappedString {
+    pub fn new(limit: usize) -> Self {
+        CappedString {
+            out: String::new(),
+            limit,
+            truncated: false,
+        }
+    }
+}
+
+impl Write for CappedString {
+    fn write_str(&mut self, s: &str) -> Result {
+        if self.out.len() + s.len() > self.limit {
+            self.truncated = true;
+            return Err(std::fmt::Error);
+        }
+        self.out.push_str(s);
+        Ok(())
+    }
+}
+
+/// Returns the max size of a single text or JSON output, as set by `gevel.max_output_bytes`.
+pub fn max_output_bytes() -> usize {
+    guc::MAX_OUTPUT_BYTES.get().max(0) as usize
+}
+
+/// Emits a notice about output of a given function being truncated.
+pub fn notice_truncated(function: &str) {
+    notice!(
+        "output of {} was truncated at {} bytes (gevel.max_output_bytes), use set-returning functions like gist_tree_rows, gist_page_items or gist_stat_by_level to inspect large indexes",
+        function,
+        max_output_bytes()
+    );
+}
+
+/// Formats output of a given function with `format`, truncating it with an explicit marker
+/// when it would exceed `gevel.max_output_bytes`.
+pub fn capped<F>(function: &str, format: F) -> String
+where
+    F: FnOnce(&mut CappedString) -> Result,
+{
+    let mut out = CappedString::new(max_output_bytes());
+    if format(&mut out).is_err() && out.truncated {
+        notice_truncated(function);
+        out.out.push_str("\n[output truncated]\n");
+    }
+    out.out
+}
+
+/// Counts bytes of serialized JSON, failing as soon as they exceed a given limit.
+struct ByteCounter {
+    bytes: usize,
+    limit: usize,
+}
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes += buf.len();
+        if self.bytes > self.limit {
+            return Err(std::io::Error::other("output limit exceeded"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns JSON output of a given function, failing with a resource limit error when it
+/// would exceed `gevel.max_output_bytes`. Used for documents which can't be truncated without
+/// becoming misleading, like page dumps of `gist_forensics`.
+pub fn limited_json(function: &str, value: serde_json::Value) -> JsonB {
+    let limit = max_output_bytes();
+    let mut counter = ByteCounter { bytes: 0, limit };
+    if serde_json::to_writer(&mut counter, &value).is_err() {
+        raise(
+            ErrorClass::ResourceLimit,
+            &format!(
+                "output of {} exceeds {} bytes (gevel.max_output_bytes)",
+                function, limit
+            ),
+        );
+    }
+    JsonB(value)
+}
@@ -0,0 +1,115 @@
+use crate::gist::IndexInspector;
+use crate::key::KeyDecoder;
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys::OffsetNumber;
+
+/// Potential savings of prefix-compressed storage of a single variable-length key attribute,
+/// computed over leaf keys.
+#[derive(Debug, Default)]
+pub struct PrefixStats {
+    /// 0-based position of the key attribute.
+    pub attno: usize,
+    /// Number of analyzed leaf keys. Compressed and TOASTed keys are skipped.
+    pub keys: u64,
+    /// Total size of analyzed key payloads in bytes, not counting varlena headers.
+    pub key_bytes: u64,
+    /// Bytes saved by storing the prefix shared by all keys of a page only once per page.
+    pub page_prefix_savings: u64,
+    /// Bytes saved by front coding of keys of each page sorted bytewise, where every key
+    /// stores only the suffix following the prefix shared with its predecessor, plus one byte
+    /// for the length of that prefix.
+    pub front_coding_savings: u64,
+}
+
+impl PrefixStats {
+    fn add_page(&mut self, keys: &mut [Vec<u8>]) {
+        if keys.is_empty() {
+            return;
+        }
+        self.keys += keys.len() as u64;
+        self.key_bytes += keys.iter().map(|k| k.len() as u64).sum::<u64>();
+
+        let page_prefix = keys.iter().skip(1).fold(keys[0].len(), |len, key| {
+            len.min(common_prefix(&keys[0], key))
+        });
+        self.page_prefix_savings += (page_prefix * (keys.len() - 1)) as u64;
+
+        keys.sort();
+        for pair in keys.windows(2) {
+            let shared = common_prefix(&pair[0], &pair[1]);
+            self.front_coding_savings += shared.saturating_sub(1) as u64;
+        }
+    }
+
+    /// Percentage of key bytes which front coding would save.
+    pub fn savings_pct(&self) -> f64 {
+        if self.key_bytes == 0 {
+            0.0
+        } else {
+            self.front_coding_savings as f64 / self.key_bytes as f64 * 100.0
+        }
+    }
+}
+
+/// Returns the length of the prefix shared by two byte strings.
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+}
+
+struct PrefixCollector {
+    decoder: KeyDecoder,
+    stats: Vec<PrefixStats>,
+    /// Payloads of keys of the currently visited leaf page, one vector per key attribute.
+    page_keys: Vec<Vec<Vec<u8>>>,
+}
+
+impl Visitor for PrefixCollector {
+    fn on_tuple(&mut self, page: &PageVisit, _offset: OffsetNumber, tuple: &IndexTuple) {
+        if !page.is_leaf() {
+            return;
+        }
+        for (i, payload) in self.decoder.varlena_payloads(tuple).into_iter().enumerate() {
+            if let Some(payload) = payload {
+                self.page_keys[i].push(payload);
+            }
+        }
+    }
+
+    fn on_page_end(&mut self, page: &PageVisit) {
+        if page.is_leaf() {
+            for (stats, keys) in self.stats.iter_mut().zip(self.page_keys.iter_mut()) {
+                stats.add_page(keys);
+                keys.clear();
+            }
+        }
+    }
+}
+
+impl IndexInspector {
+    /// Estimates how much space prefix compression of leaf keys would save, reported for each
+    /// variable-length key attribute. Returns empty vector if index has no such attributes.
+    pub fn prefix_stats(&self) -> Vec<PrefixStats> {
+        let decoder = KeyDecoder::new(self.relation);
+        let natts = decoder.is_varlena().len();
+        let mut collector = PrefixCollector {
+            decoder,
+            stats: (0..natts)
+                .map(|attno| PrefixStats {
+                    attno,
+                    ..PrefixStats::default()
+                })
+                .collect(),
+            page_keys: vec![Vec::new(); natts],
+        };
+        if collector.decoder.is_varlena().iter().any(|&v| v) {
+            self.walk(&mut collector);
+        }
+        let is_varlena = collector.decoder.is_varlena().to_vec();
+        collector
+            .stats
+            .into_iter()
+            .filter(|s| is_varlena[s.attno])
+            .collect()
+    }
+}
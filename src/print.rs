@@ -0,0 +1,150 @@
+use crate::gist::IndexInspector;
+use crate::heatmap::BoxFormat;
+use crate::key::KeyDecoder;
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys::{
+    index_getprocid, index_getprocinfo, BlockNumber, Datum, FmgrInfo, FunctionCall1Coll,
+    OffsetNumber, Oid, Relation, GISTENTRY, GIST_DECOMPRESS_PROC,
+};
+
+/// Keys of a single index tuple, as printed by `gist_print`.
+#[derive(Debug)]
+pub struct PrintedTuple {
+    pub level: usize,
+    pub block_num: BlockNumber,
+    pub offset: OffsetNumber,
+    pub is_leaf: bool,
+    /// False for tuples left invalid by crash recovery of pre-9.1 versions.
+    pub is_valid: bool,
+    /// Rendered key attributes, `None` for NULL ones.
+    pub keys: Vec<Option<String>>,
+}
+
+/// Decompress support function of a single key attribute.
+struct Decompress {
+    proc: *mut FmgrInfo,
+    collation: Oid,
+}
+
+struct KeyPrinter {
+    relation: Relation,
+    decoder: KeyDecoder,
+    /// Decompress functions of key attributes, `None` for attributes whose opclass doesn't
+    /// define one (keys are stored as they are).
+    decompress: Vec<Option<Decompress>>,
+    /// Layouts of key attributes rendered as WKT, `None` for attributes rendered by output
+    /// functions of their types.
+    wkt: Vec<Option<BoxFormat>>,
+    tuples: Vec<PrintedTuple>,
+}
+
+impl KeyPrinter {
+    fn new(relation: Relation, wkt: bool) -> Self {
+        let decoder = KeyDecoder::new(relation);
+        let natts = decoder.attr_names().len();
+        let decompress = (0..natts)
+            .map(|i| unsafe {
+                let attno = (i + 1) as i16;
+                if index_getprocid(relation, attno, GIST_DECOMPRESS_PROC as u16) == 0 {
+                    None
+                } else {
+                    Some(Decompress {
+                        proc: index_getprocinfo(relation, attno, GIST_DECOMPRESS_PROC as u16),
+                        collation: *(*relation).rd_indcollation.add(i),
+                    })
+                }
+            })
+            .collect();
+        let wkt = (0..natts)
+            .map(|i| {
+                Some(i)
+                    .filter(|_| wkt)
+                    .and_then(|i| BoxFormat::of_attribute(relation, i))
+            })
+            .collect();
+        KeyPrinter {
+            relation,
+            decoder,
+            decompress,
+            wkt,
+            tuples: Vec::new(),
+        }
+    }
+
+    /// Runs a stored key through the decompress function of its opclass, the same way index
+    /// scans do before passing keys to other support functions.
+    fn decompress(
+        &self,
+        page: &PageVisit,
+        offset: OffsetNumber,
+        attno: usize,
+        key: Datum,
+    ) -> Datum {
+        match self.decompress[attno].as_ref() {
+            None => key,
+            Some(d) => {
+                let entry = GISTENTRY {
+                    key,
+                    rel: self.relation,
+                    page: page.page.0,
+                    offset,
+                    leafkey: false,
+                };
+                unsafe {
+                    let decompressed =
+                        FunctionCall1Coll(d.proc, d.collation, &entry as *const GISTENTRY as Datum)
+                            as *const GISTENTRY;
+                    (*decompressed).key
+                }
+            }
+        }
+    }
+}
+
+impl Visitor for KeyPrinter {
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        let is_valid = !tuple.is_invalid();
+        let keys = if is_valid {
+            let (values, is_null) = self.decoder.deform(tuple);
+            (0..self.decompress.len())
+                .map(|i| {
+                    if is_null[i] {
+                        None
+                    } else {
+                        let key = self.decompress(page, offset, i, values[i]);
+                        Some(match self.wkt[i] {
+                            Some(format) => format.decode(key).to_wkt(),
+                            None => self.decoder.render_datum(i, key),
+                        })
+                    }
+                })
+                .collect()
+        } else {
+            // invalid tuples carry no keys
+            vec![None; self.decompress.len()]
+        };
+        self.tuples.push(PrintedTuple {
+            level: page.level,
+            block_num: page.block_num,
+            offset,
+            is_leaf: page.is_leaf(),
+            is_valid,
+            keys,
+        });
+    }
+}
+
+impl IndexInspector {
+    /// Returns decompressed and rendered keys of all tuples of the index, level by level
+    /// starting from the root. Tuples of the same level are returned in the order of the
+    /// depth-first traversal. With `wkt` set, bounding box keys (`box` and PostGIS `box2df`,
+    /// used by all geometric opclasses) are rendered as WKT instead.
+    pub fn print(&self, wkt: bool) -> Vec<PrintedTuple> {
+        let mut printer = KeyPrinter::new(self.relation, wkt);
+        self.walk(&mut printer);
+        let mut tuples = printer.tuples;
+        tuples.sort_by_key(|t| t.level);
+        tuples
+    }
+}
@@ -0,0 +1,293 @@
+use crate::error::{raise, ErrorClass, Result};
+use crate::gist::{IndexInspector, IndexTree, JsonOptions, TreeOptions};
+use crate::output;
+use crate::report::Section;
+use pgx::pg_sys::Oid;
+use pgx::{JsonB, Spi};
+use serde_json::json;
+use std::fmt::Write;
+
+/// Table storing named profiles saved by `gevel.save_profile`.
+const PROFILES_TABLE: &str = "gevel.profiles";
+
+/// Analysis which can be requested by a [Profile].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Analysis {
+    /// One of sections of [IndexInspector::report]. All of them are computed in a single walk.
+    Section(Section),
+    /// Health summary estimated from a sample of leaf pages, see [IndexInspector::summary].
+    Summary,
+    /// The tree itself, down to the profile's `max_level`.
+    Tree,
+    /// Structural checks, see [IndexInspector::check].
+    Check,
+}
+
+impl Analysis {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Analysis::Section(section) => section.name(),
+            Analysis::Summary => "summary",
+            Analysis::Tree => "tree",
+            Analysis::Check => "check",
+        }
+    }
+
+    fn parse(name: &str) -> Self {
+        match name {
+            "summary" => Analysis::Summary,
+            "tree" => Analysis::Tree,
+            "check" => Analysis::Check,
+            other => Analysis::Section(other.parse::<Section>().unwrap_or_else(|_| {
+                raise(
+                    ErrorClass::InvalidArgument,
+                    &format!(
+                        "unknown profile analysis: \"{}\" (expected summary, tree, check or one of report sections: stats, levels, histogram, health, signatures, ordering, duplicates, worst_pages)",
+                        other
+                    ),
+                )
+            })),
+        }
+    }
+}
+
+/// Format of the output of [IndexInspector::run_profile].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// A single JSON object with results of all analyses keyed by their names.
+    Json,
+    /// Results of all analyses one after another, under their names. The tree is printed the
+    /// same way as by `gist_tree`, other analyses as indented JSON.
+    Text,
+}
+
+/// Named, reusable configuration of an inspection: which analyses to run and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Analyses to run, in the order of the output.
+    pub analyses: Vec<Analysis>,
+    /// Max level of the tree printed by the `tree` analysis, `None` means the whole tree.
+    pub max_level: Option<usize>,
+    /// Number of leaf pages sampled by the `summary` analysis.
+    pub sample_size: usize,
+    pub format: ProfileFormat,
+}
+
+impl Profile {
+    /// Parses a profile from JSON, ie. `{"analyses": ["summary"], "sample_size": 100}`. Only
+    /// `analyses` is required, unknown fields are rejected so that typos don't go unnoticed.
+    pub fn from_json(value: &serde_json::Value) -> Self {
+        let invalid = |msg: &str| -> ! {
+            raise(
+                ErrorClass::InvalidArgument,
+                &format!("invalid profile: {}", msg),
+            )
+        };
+        let object = value
+            .as_object()
+            .unwrap_or_else(|| invalid("expected a JSON object"));
+        if let Some(unknown) = object
+            .keys()
+            .find(|k| !["analyses", "max_level", "sample_size", "format"].contains(&k.as_str()))
+        {
+            invalid(&format!(
+                "unknown field \"{}\" (expected analyses, max_level, sample_size or format)",
+                unknown
+            ));
+        }
+        let names = object
+            .get("analyses")
+            .and_then(|a| a.as_array())
+            .unwrap_or_else(|| invalid("\"analyses\" must be an array of analysis names"));
+        let mut analyses = Vec::new();
+        for name in names.iter() {
+            let name = name
+                .as_str()
+                .unwrap_or_else(|| invalid("\"analyses\" must be an array of analysis names"));
+            let analysis = Analysis::parse(&name.trim().to_lowercase());
+            if !analyses.contains(&analysis) {
+                analyses.push(analysis);
+            }
+        }
+        if analyses.is_empty() {
+            invalid("\"analyses\" is empty");
+        }
+        let max_level = match object.get("max_level") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(level) => Some(
+                level
+                    .as_u64()
+                    .unwrap_or_else(|| invalid("\"max_level\" must be a non-negative integer"))
+                    as usize,
+            ),
+        };
+        let sample_size = match object.get("sample_size") {
+            None | Some(serde_json::Value::Null) => crate::SUMMARY_SAMPLE_SIZE,
+            Some(size) => size
+                .as_u64()
+                .filter(|&s| s > 0)
+                .unwrap_or_else(|| invalid("\"sample_size\" must be a positive integer"))
+                as usize,
+        };
+        let format = match object.get("format").map(|f| f.as_str()) {
+            None | Some(Some("json")) => ProfileFormat::Json,
+            Some(Some("text")) => ProfileFormat::Text,
+            _ => invalid("\"format\" must be either \"json\" or \"text\""),
+        };
+        Profile {
+            analyses,
+            max_level,
+            sample_size,
+            format,
+        }
+    }
+
+    /// Returns JSON representation of the profile, with defaults filled in.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "analyses": self.analyses.iter().map(|a| a.name()).collect::<Vec<_>>(),
+            "max_level": self.max_level,
+            "sample_size": self.sample_size,
+            "format": match self.format {
+                ProfileFormat::Json => "json",
+                ProfileFormat::Text => "text",
+            },
+        })
+    }
+}
+
+/// Validates a profile and stores it under a given name, replacing the previous one if any.
+pub fn save(name: &str, profile: &serde_json::Value) {
+    if name.trim().is_empty() {
+        raise(
+            ErrorClass::InvalidArgument,
+            "profile name must not be empty",
+        );
+    }
+    let profile = Profile::from_json(profile);
+    Spi::run(&format!(
+        "INSERT INTO {}(name, profile) VALUES ('{}', '{}'::jsonb) ON CONFLICT (name) DO UPDATE SET profile = excluded.profile, updated_at = now()",
+        PROFILES_TABLE,
+        name.replace('\'', "''"),
+        profile.to_json().to_string().replace('\'', "''")
+    ));
+}
+
+/// Loads a profile stored under a given name.
+pub fn load(name: &str) -> Profile {
+    let query = format!(
+        "SELECT profile FROM {} WHERE name = '{}'",
+        PROFILES_TABLE,
+        name.replace('\'', "''")
+    );
+    match Spi::get_one::<JsonB>(&query) {
+        Some(JsonB(profile)) => Profile::from_json(&profile),
+        None => raise(
+            ErrorClass::InvalidArgument,
+            &format!("profile \"{}\" does not exist", name),
+        ),
+    }
+}
+
+/// Runs analyses (named the same way as in profiles) of a given index while writes to its
+/// table are blocked, so that all of them see the very same structure, and returns their
+/// results in a single JSON object. The table lock is released right after.
+pub fn with_lock(rel_oid: Oid, analyses: &[String]) -> Result<serde_json::Value> {
+    let profile = Profile::from_json(&json!({ "analyses": analyses }));
+    IndexInspector::with_table_locked(rel_oid, |index| index.profile_json(&profile))
+}
+
+impl IndexInspector {
+    /// Computes report sections requested by a profile in a single walk over the index, and
+    /// builds the tree if it's requested.
+    fn profile_inputs(&self, profile: &Profile) -> (serde_json::Value, Option<IndexTree>) {
+        let sections: Vec<Section> = profile
+            .analyses
+            .iter()
+            .filter_map(|a| match a {
+                Analysis::Section(section) => Some(*section),
+                _ => None,
+            })
+            .collect();
+        let report = if sections.is_empty() {
+            json!({})
+        } else {
+            self.report(&sections)
+        };
+        let tree = if profile.analyses.contains(&Analysis::Tree) {
+            Some(self.get_tree(&TreeOptions {
+                max_level: profile.max_level,
+                ..TreeOptions::default()
+            }))
+        } else {
+            None
+        };
+        (report, tree)
+    }
+
+    /// Runs all analyses of a profile and returns their results in a single JSON object keyed
+    /// by their names, regardless of the profile's format.
+    pub fn profile_json(&self, profile: &Profile) -> serde_json::Value {
+        let (report, tree) = self.profile_inputs(profile);
+        let mut result = json!({});
+        for analysis in profile.analyses.iter() {
+            result[analysis.name()] = match analysis {
+                Analysis::Section(section) => report[section.name()].clone(),
+                Analysis::Summary => self.summary(profile.sample_size).to_json(),
+                Analysis::Tree => {
+                    let tree = tree.as_ref().expect("tree was not built");
+                    let (json, truncated) =
+                        tree.to_json_capped(&JsonOptions::default(), output::max_output_bytes());
+                    if truncated {
+                        output::notice_truncated("run_profile");
+                    }
+                    json
+                }
+                Analysis::Check => self.check_json(),
+            };
+        }
+        if let Some(opclass) = report.get("opclass") {
+            result["opclass"] = opclass.clone();
+        }
+        result
+    }
+
+    fn check_json(&self) -> serde_json::Value {
+        json!(self.check().iter().map(|f| f.to_json()).collect::<Vec<_>>())
+    }
+
+    /// Runs all analyses of a profile and returns their results in the profile's format.
+    /// Report sections are computed together in a single walk over the index.
+    pub fn run_profile(&self, profile: &Profile) -> String {
+        match profile.format {
+            ProfileFormat::Json => self.profile_json(profile).to_string(),
+            ProfileFormat::Text => output::capped("run_profile", |out| {
+                let (report, tree) = self.profile_inputs(profile);
+                for (i, analysis) in profile.analyses.iter().enumerate() {
+                    if i > 0 {
+                        out.write_str("\n")?;
+                    }
+                    writeln!(out, "{}:", analysis.name())?;
+                    match analysis {
+                        Analysis::Section(section) => {
+                            let value = &report[section.name()];
+                            writeln!(out, "{}", serde_json::to_string_pretty(value).unwrap())?
+                        }
+                        Analysis::Summary => {
+                            let summary = self.summary(profile.sample_size).to_json();
+                            writeln!(out, "{}", serde_json::to_string_pretty(&summary).unwrap())?
+                        }
+                        Analysis::Tree => {
+                            write!(out, "{}", tree.as_ref().expect("tree was not built"))?
+                        }
+                        Analysis::Check => {
+                            let findings = self.check_json();
+                            writeln!(out, "{}", serde_json::to_string_pretty(&findings).unwrap())?
+                        }
+                    }
+                }
+                Ok(())
+            }),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use pgx::pg_sys::{RecoveryInProgress, XactReadOnly};
+use std::cell::Cell;
+
+thread_local! {
+    // number of inspections in progress, and whether the transaction was read-only before the
+    // first of them started
+    static ACTIVE: Cell<(usize, bool)> = const { Cell::new((0, false)) };
+}
+
+/// Returns true if the current transaction can't write, either because it's been declared
+/// read-only (ie. with `default_transaction_read_only`) or because it runs on a hot standby.
+/// Inspections in progress, which make the transaction read-only for their duration, don't
+/// count.
+pub fn is_read_only() -> bool {
+    let (active, declared) = ACTIVE.with(Cell::get);
+    let declared = if active > 0 {
+        declared
+    } else {
+        unsafe { XactReadOnly }
+    };
+    declared || unsafe { RecoveryInProgress() }
+}
+
+/// Makes the transaction read-only for the duration of an inspection, so that any write
+/// attempted by it fails right away with the same error as in a transaction declared read-only.
+/// This guarantees that inspections can run inside of read-only transactions and on hot
+/// standbys. Functions which do write on purpose must say so with [ReadOnlyCheck::allow_writes].
+/// The transaction becomes writable again once all inspections in progress are dropped.
+pub struct ReadOnlyCheck {
+    // only created by ReadOnlyCheck::start, so that every drop is paired with a start
+    _started: (),
+}
+
+impl ReadOnlyCheck {
+    pub fn start() -> Self {
+        ACTIVE.with(|active| {
+            let (count, declared) = active.get();
+            let declared = if count == 0 {
+                unsafe { XactReadOnly }
+            } else {
+                declared
+            };
+            active.set((count + 1, declared));
+        });
+        unsafe { XactReadOnly = true };
+        ReadOnlyCheck { _started: () }
+    }
+
+    /// Runs `f`, which writes on purpose, with the transaction as read-only as it was before
+    /// the inspection started.
+    pub fn allow_writes<R>(&self, f: impl FnOnce() -> R) -> R {
+        unsafe { XactReadOnly = ACTIVE.with(Cell::get).1 };
+        let result = f();
+        unsafe { XactReadOnly = true };
+        result
+    }
+}
+
+impl Drop for ReadOnlyCheck {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| {
+            let (count, declared) = active.get();
+            active.set((count - 1, declared));
+            if count == 1 {
+                unsafe { XactReadOnly = declared };
+            }
+        });
+    }
+}
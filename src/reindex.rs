@@ -0,0 +1,112 @@
+use crate::error::{Error, Result};
+use crate::gist::{IndexInspector, Stats};
+use pgx::pg_sys::Oid;
+use pgx::{JsonB, Spi};
+
+/// Indexes taking part in a `REINDEX CONCURRENTLY` of a single index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReindexPair {
+    /// The index being replaced. It's renamed with a `_ccold` suffix when the new one is swapped
+    /// in, right before it's dropped.
+    pub old: Oid,
+    /// The index being built, named with a `_ccnew` suffix until it's swapped in.
+    pub new: Oid,
+}
+
+/// Returns true if `name` is `base` followed by a given `REINDEX CONCURRENTLY` suffix, which
+/// may be followed by a number when the plain name was already taken.
+fn has_suffix(name: &str, base: &str, suffix: &str) -> bool {
+    name.strip_prefix(base)
+        .and_then(|rest| rest.strip_prefix(suffix))
+        .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Returns the `REINDEX CONCURRENTLY` counterpart of a given index: the `_ccnew` index being
+/// built from it or the original index of a `_ccnew` one (and the same for the `_ccold` index
+/// left after the swap). Counterparts are found by name among other indexes of the same table,
+/// so names truncated to fit `NAMEDATALEN` are not recognized.
+pub fn reindex_pair(rel_oid: Oid) -> Option<ReindexPair> {
+    let name = Spi::get_one::<String>(&format!(
+        "SELECT relname::text FROM pg_class WHERE oid = {}",
+        rel_oid
+    ))?;
+    let siblings = Spi::get_one::<JsonB>(&format!(
+        "SELECT jsonb_agg(jsonb_build_array(s.indexrelid::bigint, c.relname::text)) \
+         FROM pg_index i JOIN pg_index s ON s.indrelid = i.indrelid AND s.indexrelid <> i.indexrelid \
+         JOIN pg_class c ON c.oid = s.indexrelid WHERE i.indexrelid = {}",
+        rel_oid
+    ))?;
+    let siblings = siblings.0.as_array()?.clone();
+    siblings.iter().find_map(|sibling| {
+        let oid = sibling[0].as_u64()? as Oid;
+        let sibling_name = sibling[1].as_str()?;
+        if has_suffix(sibling_name, &name, "_ccnew") || has_suffix(&name, sibling_name, "_ccold") {
+            Some(ReindexPair {
+                old: rel_oid,
+                new: oid,
+            })
+        } else if has_suffix(&name, sibling_name, "_ccnew")
+            || has_suffix(sibling_name, &name, "_ccold")
+        {
+            Some(ReindexPair {
+                old: oid,
+                new: rel_oid,
+            })
+        } else {
+            None
+        }
+    })
+}
+
+/// A single measure of the old and the new index of a `REINDEX CONCURRENTLY`.
+#[derive(Debug)]
+pub struct ReindexMetric {
+    pub name: &'static str,
+    pub old: f64,
+    pub new: f64,
+}
+
+impl ReindexMetric {
+    /// Value of the new index relative to the old one, in percents. `None` if the old value is
+    /// zero.
+    pub fn new_pct(&self) -> Option<f64> {
+        if self.old == 0.0 {
+            None
+        } else {
+            Some(self.new * 100.0 / self.old)
+        }
+    }
+}
+
+fn metrics(old: &Stats, new: &Stats) -> Vec<ReindexMetric> {
+    let metric = |name, get: fn(&Stats) -> f64| ReindexMetric {
+        name,
+        old: get(old),
+        new: get(new),
+    };
+    vec![
+        metric("levels", |s| (s.level + 1) as f64),
+        metric("pages", |s| s.num_pages as f64),
+        metric("leaf_pages", |s| s.num_leaf_pages as f64),
+        metric("leaf_tuples", |s| s.num_leaf_tuple as f64),
+        metric("tuples", |s| s.num_tuple as f64),
+        metric("invalid_tuples", |s| s.num_invalid_tuple as f64),
+        metric("tuple_bytes", |s| s.tuple_size as f64),
+        metric("free_bytes", |s| s.free_space as f64),
+    ]
+}
+
+/// Compares the structure of the index being built by `REINDEX CONCURRENTLY` of a given index
+/// (which may be either of the two) with the one it replaces. While the new index is being
+/// built, its numbers reflect pages written so far.
+pub fn reindex_progress(rel_oid: Oid) -> Result<Vec<ReindexMetric>> {
+    let pair = reindex_pair(rel_oid).ok_or_else(|| {
+        Error::InvalidArgument(format!(
+            "index {} is not being rebuilt by REINDEX CONCURRENTLY (no _ccnew or _ccold index found next to it)",
+            rel_oid
+        ))
+    })?;
+    let old = IndexInspector::open(pair.old)?.stats(None);
+    let new = IndexInspector::open(pair.new)?.stats(None);
+    Ok(metrics(&old, &new))
+}
@@ -0,0 +1,287 @@
+use crate::duplicates::DuplicateCollector;
+use crate::error::{raise, ErrorClass};
+use crate::gist::{
+    occupancy, IndexInspector, LevelCollector, SignatureCollector, StatsCollector, Summary,
+};
+use crate::opclass::OpclassCollector;
+use crate::ordering::OrderingCollector;
+use crate::walker::{Fanout, PageVisit, TopK, Visitor};
+use pgx::pg_sys::BlockNumber;
+use serde_json::json;
+use std::str::FromStr;
+
+/// Number of pages listed by the `worst_pages` section.
+pub const WORST_PAGES: usize = 10;
+
+/// Analysis which can be requested as a part of [IndexInspector::report].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// Index-wide statistics, same as reported by `gist_stat`.
+    Stats,
+    /// Per-level statistics, same as reported by `gist_stat_by_level`.
+    Levels,
+    /// Number of pages in each 10% bucket of occupancy.
+    Histogram,
+    /// Health summary, same as reported by `gist_summary`, but computed from exact numbers.
+    Health,
+    /// Per-level saturation of signature-based keys, same as reported by `gist_signature_stats`.
+    Signatures,
+    /// Clustering of tuples within leaf pages by key proximity.
+    Ordering,
+    /// Groups of sibling pages with identical or near-identical keys, per level.
+    Duplicates,
+    /// Pages with the most free space, which are the first candidates for bloat.
+    WorstPages,
+}
+
+impl Section {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Section::Stats => "stats",
+            Section::Levels => "levels",
+            Section::Histogram => "histogram",
+            Section::Health => "health",
+            Section::Signatures => "signatures",
+            Section::Ordering => "ordering",
+            Section::Duplicates => "duplicates",
+            Section::WorstPages => "worst_pages",
+        }
+    }
+}
+
+impl FromStr for Section {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stats" => Ok(Section::Stats),
+            "levels" => Ok(Section::Levels),
+            "histogram" => Ok(Section::Histogram),
+            "health" => Ok(Section::Health),
+            "signatures" => Ok(Section::Signatures),
+            "ordering" => Ok(Section::Ordering),
+            "duplicates" => Ok(Section::Duplicates),
+            "worst_pages" => Ok(Section::WorstPages),
+            other => Err(format!(
+                "unknown report section: \"{}\" (expected one of: stats, levels, histogram, health, signatures, ordering, duplicates, worst_pages)",
+                other
+            )),
+        }
+    }
+}
+
+/// Parses a comma-separated list of section names, ie. `"stats,levels,health"`. Names are
+/// case-insensitive, surrounding whitespace and duplicates are ignored.
+pub fn parse_spec(spec: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    for name in spec.split(',').map(|s| s.trim().to_lowercase()) {
+        if name.is_empty() {
+            continue;
+        }
+        let section = name
+            .parse::<Section>()
+            .unwrap_or_else(|e| raise(ErrorClass::InvalidArgument, &e));
+        if !sections.contains(&section) {
+            sections.push(section);
+        }
+    }
+    if sections.is_empty() {
+        raise(ErrorClass::InvalidArgument, "report spec has no sections");
+    }
+    sections
+}
+
+/// Counts pages by their occupancy, in 10% wide buckets.
+#[derive(Default)]
+struct FillHistogram {
+    buckets: [u64; 10],
+}
+
+impl Visitor for FillHistogram {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        let occupied = occupancy(page.page.free_space(), page.page.capacity());
+        let bucket = ((occupied.max(0.0) * 10.0) as usize).min(9);
+        self.buckets[bucket] += 1;
+        true
+    }
+}
+
+impl FillHistogram {
+    fn to_json(&self) -> serde_json::Value {
+        let buckets: Vec<_> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, pages)| {
+                json!({
+                    "occupied_from": i as f64 / 10.0,
+                    "occupied_to": (i + 1) as f64 / 10.0,
+                    "pages": pages,
+                })
+            })
+            .collect();
+        serde_json::Value::Array(buckets)
+    }
+}
+
+/// A page with a lot of free space. Pages are ordered by free space first, so that the ones
+/// kept by [WorstPages] can be selected by the derived order.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct WorstPage {
+    free_space: usize,
+    block_num: BlockNumber,
+    level: usize,
+    tuples: usize,
+    capacity: usize,
+    is_leaf: bool,
+}
+
+/// Keeps [WORST_PAGES] pages with the most free space.
+struct WorstPages(TopK<WorstPage>);
+
+impl Default for WorstPages {
+    fn default() -> Self {
+        WorstPages(TopK::new(WORST_PAGES))
+    }
+}
+
+impl Visitor for WorstPages {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        self.0.push(WorstPage {
+            free_space: page.page.free_space(),
+            block_num: page.block_num,
+            level: page.level,
+            tuples: page.page.max_offset() as usize,
+            capacity: page.page.capacity(),
+            is_leaf: page.is_leaf(),
+        });
+        true
+    }
+}
+
+impl WorstPages {
+    fn into_json(self) -> serde_json::Value {
+        let pages: Vec<_> = self
+            .0
+            .into_sorted_vec()
+            .into_iter()
+            .map(|p| {
+                json!({
+                    "blkno": p.block_num,
+                    "level": p.level,
+                    "is_leaf": p.is_leaf,
+                    "tuples": p.tuples,
+                    "free": p.free_space,
+                    "occupied": occupancy(p.free_space, p.capacity),
+                })
+            })
+            .collect();
+        serde_json::Value::Array(pages)
+    }
+}
+
+impl IndexInspector {
+    /// Runs all requested analyses in a single walk over the index and returns their results
+    /// as a JSON object with one field per section.
+    pub fn report(&self, sections: &[Section]) -> serde_json::Value {
+        let wants = |s: Section| sections.contains(&s);
+        let mut stats = StatsCollector::new(self.relation, None);
+        let mut levels = LevelCollector::default();
+        let mut histogram = FillHistogram::default();
+        let mut signatures = SignatureCollector::new(self.relation);
+        let mut ordering = OrderingCollector::new(self.relation);
+        let mut duplicates = DuplicateCollector::new(self.relation);
+        let mut worst_pages = WorstPages::default();
+        // health reports of well-known opclasses get a specialized section
+        let mut opclass = if wants(Section::Health) {
+            self.known_opclass()
+                .map(|known| OpclassCollector::new(self.relation, known))
+        } else {
+            None
+        };
+
+        {
+            let mut fanout = Fanout(Vec::new());
+            if wants(Section::Stats) || wants(Section::Health) {
+                fanout.0.push(&mut stats);
+            }
+            if wants(Section::Levels) {
+                fanout.0.push(&mut levels);
+            }
+            if wants(Section::Histogram) {
+                fanout.0.push(&mut histogram);
+            }
+            if wants(Section::Signatures) && signatures.decoder.has_signatures() {
+                fanout.0.push(&mut signatures);
+            }
+            if wants(Section::Ordering) {
+                fanout.0.push(&mut ordering);
+            }
+            if wants(Section::Duplicates) {
+                fanout.0.push(&mut duplicates);
+            }
+            if wants(Section::WorstPages) {
+                fanout.0.push(&mut worst_pages);
+            }
+            if let Some(opclass) = opclass.as_mut() {
+                fanout.0.push(opclass);
+            }
+            self.walk(&mut fanout);
+        }
+
+        let stats = stats.finish(self);
+        let levels = levels.finish(self);
+        let mut report = json!({});
+        for section in sections {
+            report[section.name()] = match section {
+                Section::Stats => stats.to_json(),
+                Section::Levels => json!(levels
+                    .iter()
+                    .enumerate()
+                    .map(|(level, s)| s.to_json(level))
+                    .collect::<Vec<_>>()),
+                Section::Histogram => histogram.to_json(),
+                Section::Health => {
+                    let summary = Summary::from_stats(&stats, self.fillfactor());
+                    let mut health = summary.to_json();
+                    health["workload"] = self.usage().to_json(&summary);
+                    health
+                }
+                Section::Signatures => json!(signatures
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .map(|(level, s)| s.to_json(level))
+                    .collect::<Vec<_>>()),
+                Section::Ordering => ordering.stats.to_json(),
+                Section::Duplicates => json!(duplicates
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .skip(1)
+                    .map(|(level, d)| d.to_json(level))
+                    .collect::<Vec<_>>()),
+                Section::WorstPages => std::mem::take(&mut worst_pages).into_json(),
+            };
+        }
+        if let Some(opclass) = opclass.as_ref() {
+            report["opclass"] = self.opclass_report(opclass);
+        }
+        report
+    }
+
+    /// Returns everything an admin UI shows about an index, computed in a single walk: index-wide
+    /// statistics, per-level statistics, the occupancy histogram, pages with the most free
+    /// space and the health summary together with its verdict.
+    pub fn dashboard(&self) -> serde_json::Value {
+        let mut dashboard = self.report(&[
+            Section::Stats,
+            Section::Levels,
+            Section::Histogram,
+            Section::WorstPages,
+            Section::Health,
+        ]);
+        dashboard["verdict"] = dashboard["health"]["verdict"].clone();
+        dashboard
+    }
+}
@@ -0,0 +1,300 @@
+use crate::gist::{largest_insertable, occupancy, GistPage, IndexInspector};
+use crate::walker::{check_for_interrupts, Link, PageVisit, VisitedBlocks, Visitor};
+use crate::GIST_ROOT_BLKNO;
+use pgx::pg_sys::{BlockNumber, InvalidBlockNumber, OffsetNumber};
+use serde_json::json;
+use std::fmt::Write;
+
+/// A single page of the tree, as returned by `gist_tree_rows`.
+#[derive(Debug)]
+pub struct TreeRow {
+    pub level: usize,
+    pub block_num: BlockNumber,
+    /// Block of the page holding the downlink to this one, `None` for the root.
+    pub parent: Option<BlockNumber>,
+    /// Offset of the downlink inside of the parent page, 0 for the root.
+    pub offset: OffsetNumber,
+    pub tuples: usize,
+    pub free_space: usize,
+    /// Size of the largest tuple which fits into the page without splitting it.
+    pub largest_insertable: usize,
+    pub occupancy: f64,
+    /// Space available for tuples on an empty page.
+    pub capacity: usize,
+    pub right_link: Option<BlockNumber>,
+    pub is_leaf: bool,
+    /// Names of GiST flags set on the page.
+    pub flags: Vec<&'static str>,
+}
+
+impl TreeRow {
+    fn new(page: &PageVisit, parent: Option<BlockNumber>) -> Self {
+        let right_link = page.gist_page.right_link();
+        let free_space = page.page.free_space();
+        TreeRow {
+            level: page.level,
+            block_num: page.block_num,
+            parent,
+            offset: page.offset,
+            tuples: page.page.max_offset() as usize,
+            free_space,
+            largest_insertable: largest_insertable(free_space),
+            occupancy: occupancy(free_space, page.page.capacity()),
+            capacity: page.page.capacity(),
+            right_link: Some(right_link).filter(|&blk| blk != InvalidBlockNumber),
+            is_leaf: page.is_leaf(),
+            flags: page.gist_page.flag_names(),
+        }
+    }
+
+    /// Returns JSON representation of the page, with the same fields as pages of
+    /// `gist_tree_json` and a reference to the parent page instead of nested children.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "offset": self.offset,
+            "level": self.level,
+            "blkno": self.block_num,
+            "parent_blkno": self.parent,
+            "tuples": self.tuples,
+            "free": self.free_space,
+            "largest_insertable": self.largest_insertable,
+            "occupied": self.occupancy,
+            "rightlink": self.right_link,
+            "is_leaf": self.is_leaf,
+        })
+    }
+}
+
+#[derive(Default)]
+struct RowCollector {
+    /// Max level of pages to collect, `None` means the whole tree.
+    max_level: Option<usize>,
+    /// Blocks of the pages on the path from the root to the currently visited page.
+    path: Vec<BlockNumber>,
+    rows: Vec<TreeRow>,
+}
+
+impl Visitor for RowCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        self.rows
+            .push(TreeRow::new(page, self.path.last().copied()));
+        self.path.push(page.block_num);
+        self.max_level.is_none_or(|max| page.level < max)
+    }
+
+    fn on_page_end(&mut self, _page: &PageVisit) {
+        self.path.pop();
+    }
+}
+
+/// Header line of [TreeRow::to_csv].
+pub const CSV_HEADER: &str =
+    "level,blkno,parent_blkno,offset,tuples,free_bytes,largest_insertable,occupancy,rightlink,flags";
+
+impl TreeRow {
+    /// Returns the page as a line of CSV (without a line terminator), with columns described
+    /// by [CSV_HEADER]. Missing parents and rightlinks are empty, flags are separated by `|`.
+    pub fn to_csv(&self) -> String {
+        let optional = |blk: Option<BlockNumber>| blk.map(|b| b.to_string()).unwrap_or_default();
+        format!(
+            "{},{},{},{},{},{},{},{:.4},{},{}",
+            self.level,
+            self.block_num,
+            optional(self.parent),
+            self.offset,
+            self.tuples,
+            self.free_space,
+            self.largest_insertable,
+            self.occupancy,
+            optional(self.right_link),
+            self.flags.join("|")
+        )
+    }
+}
+
+/// Writes rows of the tree as a GraphViz DOT digraph with one node per page, labelled with its
+/// block number, number of tuples and fill, solid edges for downlinks (labelled with their
+/// offsets) and dashed edges for rightlinks. Pages of the same level are ranked together.
+pub fn write_dot<W: Write>(rows: &[TreeRow], out: &mut W) -> std::fmt::Result {
+    out.write_str("digraph gist {\n    node [shape=box];\n")?;
+    for row in rows.iter() {
+        writeln!(
+            out,
+            "    p{} [label=\"blk {}\\n{} tuples\\n{:.2}%\"{}];",
+            row.block_num,
+            row.block_num,
+            row.tuples,
+            row.occupancy * 100.0,
+            if row.is_leaf { ", style=rounded" } else { "" }
+        )?;
+    }
+    for row in rows.iter() {
+        if let Some(parent) = row.parent {
+            writeln!(
+                out,
+                "    p{} -> p{} [label=\"{}\"];",
+                parent, row.block_num, row.offset
+            )?;
+        }
+        if let Some(right) = row.right_link {
+            writeln!(
+                out,
+                "    p{} -> p{} [style=dashed, constraint=false];",
+                row.block_num, right
+            )?;
+        }
+    }
+    let max_level = rows.iter().map(|r| r.level).max().unwrap_or(0);
+    for level in 0..=max_level {
+        out.write_str("    { rank=same;")?;
+        for row in rows.iter().filter(|r| r.level == level) {
+            write!(out, " p{};", row.block_num)?;
+        }
+        out.write_str(" }\n")?;
+    }
+    out.write_str("}\n")
+}
+
+/// Writes rows of the tree as a Mermaid flowchart, with the same nodes and edges as
+/// [write_dot]. Leaf pages are drawn with rounded corners.
+pub fn write_mermaid<W: Write>(rows: &[TreeRow], out: &mut W) -> std::fmt::Result {
+    out.write_str("flowchart TD\n")?;
+    for row in rows.iter() {
+        let (open, close) = if row.is_leaf { ("(", ")") } else { ("[", "]") };
+        writeln!(
+            out,
+            "    p{}{}\"blk {}<br/>{} tuples<br/>{:.2}%\"{}",
+            row.block_num,
+            open,
+            row.block_num,
+            row.tuples,
+            row.occupancy * 100.0,
+            close
+        )?;
+    }
+    for row in rows.iter() {
+        if let Some(parent) = row.parent {
+            writeln!(
+                out,
+                "    p{} -->|{}| p{}",
+                parent, row.offset, row.block_num
+            )?;
+        }
+        if let Some(right) = row.right_link {
+            writeln!(out, "    p{} -.-> p{}", row.block_num, right)?;
+        }
+    }
+    Ok(())
+}
+
+/// Page of the tree left to visit by [TreeRows].
+struct PendingPage {
+    level: usize,
+    block_num: BlockNumber,
+    offset: OffsetNumber,
+    parent: Option<BlockNumber>,
+    /// LSN of the parent page at the time the downlink was read, `None` for the root.
+    parent_lsn: Option<u64>,
+    link: Link,
+}
+
+/// Rows of the tree produced one page at a time, see [IndexInspector::into_tree_rows].
+pub struct TreeRows {
+    index: IndexInspector,
+    /// Pages left to visit. The next page to visit is on top.
+    pending: Vec<PendingPage>,
+    visited: VisitedBlocks,
+}
+
+impl Iterator for TreeRows {
+    type Item = TreeRow;
+
+    fn next(&mut self) -> Option<TreeRow> {
+        loop {
+            check_for_interrupts!();
+            let next = self.pending.pop()?;
+            if !self.visited.enter(&self.index, next.block_num, next.link) {
+                continue;
+            }
+            let page = self.index.cache.get(next.block_num);
+            if page.is_new() {
+                continue;
+            }
+            let gist_page = GistPage::new(&page);
+            // pages are visited in the same order as by IndexInspector::walk, including right
+            // halves of pages split concurrently, which come after the subtree of the page
+            let right_link = gist_page.right_link();
+            if let Some(parent_lsn) = next.parent_lsn {
+                if (gist_page.is_follow_right() || parent_lsn < gist_page.nsn())
+                    && right_link != InvalidBlockNumber
+                {
+                    self.pending.push(PendingPage {
+                        block_num: right_link,
+                        link: Link::Rightlink(next.block_num),
+                        ..next
+                    });
+                }
+            }
+            if !gist_page.is_leaf() {
+                let lsn = page.lsn();
+                let children: Vec<_> = page
+                    .tuples()
+                    .map(|(offset, tuple)| PendingPage {
+                        level: next.level + 1,
+                        block_num: tuple.block_num(),
+                        offset,
+                        parent: Some(next.block_num),
+                        parent_lsn: Some(lsn),
+                        link: Link::Downlink(next.block_num, offset),
+                    })
+                    .collect();
+                self.pending.extend(children.into_iter().rev());
+            }
+            let visit = PageVisit {
+                level: next.level,
+                block_num: next.block_num,
+                offset: next.offset,
+                page: &page,
+                gist_page: &gist_page,
+            };
+            return Some(TreeRow::new(&visit, next.parent));
+        }
+    }
+}
+
+impl IndexInspector {
+    /// Returns the same rows as [IndexInspector::tree_rows], but reads pages as rows are
+    /// requested, so that the tree is never materialized as a whole. Only a stack of downlinks
+    /// left to visit is kept, whose size is bounded by the height and fanout of the tree, together
+    /// with a bitmap of visited blocks.
+    pub fn into_tree_rows(self) -> TreeRows {
+        TreeRows {
+            visited: VisitedBlocks::new(&self),
+            index: self,
+            pending: vec![PendingPage {
+                level: 0,
+                block_num: GIST_ROOT_BLKNO,
+                offset: 0,
+                parent: None,
+                parent_lsn: None,
+                link: Link::Start,
+            }],
+        }
+    }
+
+    /// Returns one row per page of the tree, in the depth-first order of [IndexInspector::walk].
+    pub fn tree_rows(&self) -> Vec<TreeRow> {
+        self.tree_rows_to(None)
+    }
+
+    /// Same as [IndexInspector::tree_rows], but only returns pages down to `max_level` (the root
+    /// is at level 0). Pages below are not read at all.
+    pub fn tree_rows_to(&self, max_level: Option<usize>) -> Vec<TreeRow> {
+        let mut collector = RowCollector {
+            max_level,
+            ..RowCollector::default()
+        };
+        self.walk(&mut collector);
+        collector.rows
+    }
+}
@@ -0,0 +1,303 @@
+use crate::error::{raise, ErrorClass};
+use crate::guc;
+use crate::sys::GIST_AM_OID;
+use pgx::pg_sys::{
+    planstate_tree_walker, standard_ExecutorEnd, standard_ExecutorStart, BitmapIndexScanState,
+    ExecutorEnd_hook_type, ExecutorStart_hook_type, IndexOnlyScanState, IndexScanState,
+    MyDatabaseId, NodeTag, Oid, PlanState, QueryDesc, Relation, XactEvent,
+    XactEvent_XACT_EVENT_ABORT, EXEC_FLAG_EXPLAIN_ONLY,
+};
+use pgx::*;
+use serde_json::json;
+use std::cell::RefCell;
+use std::os::raw::{c_int, c_void};
+use std::ptr::null_mut;
+
+/// Max number of indexes (across all databases) whose scans are tracked. Scans of indexes
+/// seen after that are not recorded until the statistics are reset.
+pub const MAX_TRACKED_INDEXES: usize = 1000;
+
+/// Number of buckets of the histogram of pages visited per scan. Bucket `i` counts scans which
+/// visited up to `2^i` pages (and more than `2^(i-1)`), the last one counts all longer scans.
+pub const HISTOGRAM_BUCKETS: usize = 16;
+
+/// Scans of a single GiST index, as returned by `gevel.scan_stats`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanStats {
+    pub database: Oid,
+    pub index: Oid,
+    /// Number of executed queries which scanned the index.
+    pub executions: u64,
+    pub scans: u64,
+    /// Number of index pages read by all scans.
+    pub pages: u64,
+    /// Number of scans by the average number of pages visited by scans of the same query
+    /// execution, see [HISTOGRAM_BUCKETS].
+    pub histogram: [u64; HISTOGRAM_BUCKETS],
+}
+
+impl ScanStats {
+    pub fn avg_pages_per_scan(&self) -> f64 {
+        if self.scans == 0 {
+            0.0
+        } else {
+            self.pages as f64 / self.scans as f64
+        }
+    }
+
+    /// Returns non-empty buckets of the histogram as `[{pages_from, pages_to, scans}]`, where
+    /// `pages_to` of the last bucket is NULL.
+    pub fn histogram_json(&self) -> serde_json::Value {
+        let buckets: Vec<_> = self
+            .histogram
+            .iter()
+            .enumerate()
+            .filter(|(_, &scans)| scans > 0)
+            .map(|(i, scans)| {
+                let from = if i == 0 { 1 } else { (1u64 << (i - 1)) + 1 };
+                let to = Some(1u64 << i).filter(|_| i < HISTOGRAM_BUCKETS - 1);
+                json!({ "pages_from": from, "pages_to": to, "scans": scans })
+            })
+            .collect();
+        serde_json::Value::Array(buckets)
+    }
+}
+
+/// Returns the histogram bucket of a given number of pages per scan.
+fn bucket(pages_per_scan: u64) -> usize {
+    if pages_per_scan <= 1 {
+        0
+    } else {
+        ((64 - (pages_per_scan - 1).leading_zeros()) as usize).min(HISTOGRAM_BUCKETS - 1)
+    }
+}
+
+/// Statistics shared by all backends.
+#[derive(Clone, Copy)]
+struct SharedState {
+    used: usize,
+    /// Number of query executions which couldn't be recorded, because all entries were used.
+    dropped: u64,
+    entries: [ScanStats; MAX_TRACKED_INDEXES],
+}
+
+impl Default for SharedState {
+    fn default() -> Self {
+        SharedState {
+            used: 0,
+            dropped: 0,
+            entries: [ScanStats::default(); MAX_TRACKED_INDEXES],
+        }
+    }
+}
+
+// plain data without any pointers, so it's valid in every backend
+unsafe impl PGXSharedMemory for SharedState {}
+
+static SHARED: PgLwLock<SharedState> = PgLwLock::new();
+/// Set when shared memory is reserved, which is inherited by backends forked afterwards.
+static mut ENABLED: bool = false;
+static mut PREV_EXECUTOR_START: ExecutorStart_hook_type = None;
+static mut PREV_EXECUTOR_END: ExecutorEnd_hook_type = None;
+
+/// Scan counters of a GiST index at some point of a query execution.
+#[derive(Debug, Clone, Copy)]
+struct Counters {
+    index: Oid,
+    scans: i64,
+    pages: i64,
+}
+
+thread_local! {
+    /// Counters read when execution of each of running queries started, identified by the
+    /// address of their query descriptors.
+    static BASELINES: RefCell<Vec<(usize, Vec<Counters>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Reserves shared memory and installs hooks. Scans are only tracked when gevel is loaded via
+/// `shared_preload_libraries`, since shared memory can't be reserved later on.
+pub fn init() {
+    if unsafe { !pg_sys::process_shared_preload_libraries_in_progress } {
+        return;
+    }
+    pg_shmem_init!(SHARED);
+    unsafe {
+        ENABLED = true;
+        PREV_EXECUTOR_START = pg_sys::ExecutorStart_hook;
+        pg_sys::ExecutorStart_hook = Some(executor_start);
+        PREV_EXECUTOR_END = pg_sys::ExecutorEnd_hook;
+        pg_sys::ExecutorEnd_hook = Some(executor_end);
+        pg_sys::RegisterXactCallback(Some(xact_callback), null_mut());
+    }
+}
+
+/// Returns true if scans are tracked, ie. gevel has been loaded via `shared_preload_libraries`.
+pub fn is_enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+/// Returns the shared state, which has to be locked before it's accessed. Its lock is released
+/// when the guard returned by locking it is dropped, or by elog on error.
+fn shared() -> &'static PgLwLock<SharedState> {
+    if !is_enabled() {
+        raise(
+            ErrorClass::Unsupported,
+            "scan statistics require gevel to be loaded via shared_preload_libraries",
+        );
+    }
+    &SHARED
+}
+
+/// Returns the index scanned by a plan node, if it's an index scan of any kind.
+unsafe fn scanned_index(planstate: *mut PlanState) -> Relation {
+    match (*planstate).type_ as NodeTag {
+        pg_sys::NodeTag_T_IndexScanState => (*(planstate as *mut IndexScanState)).iss_RelationDesc,
+        pg_sys::NodeTag_T_IndexOnlyScanState => {
+            (*(planstate as *mut IndexOnlyScanState)).ioss_RelationDesc
+        }
+        pg_sys::NodeTag_T_BitmapIndexScanState => {
+            (*(planstate as *mut BitmapIndexScanState)).biss_RelationDesc
+        }
+        _ => null_mut(),
+    }
+}
+
+/// Walker collecting scan counters of GiST indexes of a plan state tree into a
+/// `Vec<Counters>` passed as the context.
+unsafe extern "C" fn collect_counters(planstate: *mut PlanState, context: *mut c_void) -> bool {
+    if planstate.is_null() {
+        return false;
+    }
+    let counters = &mut *(context as *mut Vec<Counters>);
+    let index = scanned_index(planstate);
+    // pgstat_info is missing when track_counts is off
+    if !index.is_null()
+        && (*(*index).rd_rel).relam == GIST_AM_OID
+        && !(*index).pgstat_info.is_null()
+        && !counters.iter().any(|c| c.index == (*index).rd_id)
+    {
+        let stats = &(*(*index).pgstat_info).t_counts;
+        counters.push(Counters {
+            index: (*index).rd_id,
+            scans: stats.t_numscans,
+            pages: stats.t_blocks_fetched,
+        });
+    }
+    // planstate_tree_walker declares the walker without arguments, as C does
+    let walker = std::mem::transmute::<
+        unsafe extern "C" fn(*mut PlanState, *mut c_void) -> bool,
+        unsafe extern "C" fn() -> bool,
+    >(collect_counters);
+    planstate_tree_walker(planstate, Some(walker), context)
+}
+
+/// Returns current scan counters of all GiST indexes scanned by a query. Counters are kept
+/// per relation, so indexes scanned by several nodes are listed once.
+unsafe fn gist_counters(query_desc: *mut QueryDesc) -> Vec<Counters> {
+    let mut counters = Vec::new();
+    collect_counters(
+        (*query_desc).planstate,
+        &mut counters as *mut Vec<Counters> as *mut c_void,
+    );
+    counters
+}
+
+/// Adds scans performed by a single query execution to the statistics of an index.
+fn record(index: Oid, scans: u64, pages: u64) {
+    let database = unsafe { MyDatabaseId };
+    let mut shared = shared().exclusive();
+    let used = shared.used;
+    let entry = match shared.entries[..used]
+        .iter()
+        .position(|e| e.database == database && e.index == index)
+    {
+        Some(i) => &mut shared.entries[i],
+        None if used < MAX_TRACKED_INDEXES => {
+            shared.used += 1;
+            let entry = &mut shared.entries[used];
+            *entry = ScanStats {
+                database,
+                index,
+                ..ScanStats::default()
+            };
+            entry
+        }
+        None => {
+            shared.dropped += 1;
+            return;
+        }
+    };
+    entry.executions += 1;
+    entry.scans += scans;
+    entry.pages += pages;
+    entry.histogram[bucket(pages.div_ceil(scans))] += scans;
+}
+
+#[pg_guard]
+unsafe extern "C" fn executor_start(query_desc: *mut QueryDesc, eflags: c_int) {
+    match PREV_EXECUTOR_START {
+        Some(prev) => prev(query_desc, eflags),
+        None => standard_ExecutorStart(query_desc, eflags),
+    }
+    if guc::TRACK_SCANS.get() && eflags & EXEC_FLAG_EXPLAIN_ONLY as c_int == 0 {
+        let counters = gist_counters(query_desc);
+        if !counters.is_empty() {
+            BASELINES.with(|b| b.borrow_mut().push((query_desc as usize, counters)));
+        }
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn executor_end(query_desc: *mut QueryDesc) {
+    let baseline = BASELINES.with(|b| {
+        let mut baselines = b.borrow_mut();
+        let i = baselines
+            .iter()
+            .rposition(|(desc, _)| *desc == query_desc as usize)?;
+        Some(baselines.remove(i).1)
+    });
+    if let Some(baseline) = baseline {
+        // scans are still open, they are closed by the standard ExecutorEnd
+        for now in gist_counters(query_desc) {
+            if let Some(before) = baseline.iter().find(|c| c.index == now.index) {
+                let scans = (now.scans - before.scans).max(0) as u64;
+                let pages = (now.pages - before.pages).max(0) as u64;
+                if scans > 0 {
+                    record(now.index, scans, pages);
+                }
+            }
+        }
+    }
+    match PREV_EXECUTOR_END {
+        Some(prev) => prev(query_desc),
+        None => standard_ExecutorEnd(query_desc),
+    }
+}
+
+/// Forgets baselines of queries whose execution has been aborted, so that their query
+/// descriptors' addresses can be reused.
+#[pg_guard]
+unsafe extern "C" fn xact_callback(event: XactEvent, _arg: *mut c_void) {
+    if event == XactEvent_XACT_EVENT_ABORT {
+        BASELINES.with(|b| b.borrow_mut().clear());
+    }
+}
+
+/// Returns statistics of all tracked GiST indexes of the current database.
+pub fn scan_stats() -> Vec<ScanStats> {
+    let database = unsafe { MyDatabaseId };
+    let shared = shared().share();
+    shared.entries[..shared.used]
+        .iter()
+        .filter(|e| e.database == database)
+        .copied()
+        .collect()
+}
+
+/// Discards statistics of all databases.
+pub fn reset() {
+    let mut shared = shared().exclusive();
+    shared.used = 0;
+    shared.dropped = 0;
+}
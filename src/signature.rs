@@ -0,0 +1,129 @@
+use pgx::pg_sys::{pg_detoast_datum, varlena, Datum};
+use std::fmt::{Display, Formatter};
+
+/// Size of a varlena header followed by a 32-bit flags field, which all signature-based keys
+/// start with.
+const SIGNATURE_HEADER_SIZE: usize = 8;
+
+/// Default signature length of `gist_ltree_ops` (`LTREE_SIGLEN_DEFAULT`). Unlike other
+/// signature keys, ltree signature is followed by the bounds of the subtree, so it cannot be
+/// inferred from the key size alone.
+const LTREE_SIGLEN_DEFAULT: usize = 8;
+
+/// Contrib key types which store a bitmap signature instead of the actual value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureKind {
+    /// `gtsvector` used by `tsvector_ops`.
+    TsVector,
+    /// `intbig_gkey` used by intarray's `gist__intbig_ops`.
+    IntArray,
+    /// `ghstore` used by hstore's `gist_hstore_ops`.
+    Hstore,
+    /// `ltree_gist` used by ltree's `gist_ltree_ops`.
+    Ltree,
+}
+
+impl SignatureKind {
+    /// Recognizes signature key type by its type name.
+    pub fn from_type_name(name: &str) -> Option<Self> {
+        match name {
+            "gtsvector" => Some(SignatureKind::TsVector),
+            "intbig_gkey" => Some(SignatureKind::IntArray),
+            "ghstore" => Some(SignatureKind::Hstore),
+            "ltree_gist" => Some(SignatureKind::Ltree),
+            _ => None,
+        }
+    }
+}
+
+/// Decoded content of a signature-based key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signature {
+    /// Key stores an actual value (eg. array of lexeme hashes or a single ltree) rather than
+    /// a signature. This is the case for most of the leaf keys.
+    Exact,
+    /// All bits of the signature are set, which happens when a signature has been saturated.
+    AllTrue,
+    /// Bitmap signature.
+    Bits { set: usize, total: usize },
+}
+
+impl Signature {
+    /// Decodes a signature of a given kind from the datum stored in the index tuple.
+    pub fn decode(kind: SignatureKind, datum: Datum) -> Self {
+        let bytes = unsafe {
+            let ptr = pg_detoast_datum(datum as *mut varlena) as *const u8;
+            let header = (ptr as *const u32).read_unaligned();
+            let size = ((header >> 2) & 0x3FFFFFFF) as usize;
+            std::slice::from_raw_parts(ptr, size)
+        };
+        let flags = u32::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let data = &bytes[SIGNATURE_HEADER_SIZE..];
+
+        match kind {
+            SignatureKind::TsVector => {
+                // ARRKEY = 0x01, SIGNKEY = 0x02, ALLISTRUE = 0x04
+                if flags & 0x04 != 0 {
+                    Signature::AllTrue
+                } else if flags & 0x02 != 0 {
+                    Self::bits(data)
+                } else {
+                    Signature::Exact
+                }
+            }
+            SignatureKind::IntArray | SignatureKind::Hstore => {
+                // ALLISTRUE = 0x04, otherwise always a signature
+                if flags & 0x04 != 0 {
+                    Signature::AllTrue
+                } else {
+                    Self::bits(data)
+                }
+            }
+            SignatureKind::Ltree => {
+                // LTG_ONENODE = 0x01, LTG_ALLTRUE = 0x02
+                if flags & 0x01 != 0 {
+                    Signature::Exact
+                } else if flags & 0x02 != 0 {
+                    Signature::AllTrue
+                } else {
+                    Self::bits(&data[..LTREE_SIGLEN_DEFAULT.min(data.len())])
+                }
+            }
+        }
+    }
+
+    fn bits(data: &[u8]) -> Self {
+        let set = data.iter().map(|b| b.count_ones() as usize).sum();
+        Signature::Bits {
+            set,
+            total: data.len() * 8,
+        }
+    }
+
+    /// Returns a value from [0.0..1.0] describing which fraction of signature bits is set.
+    /// Returns `None` for keys that are not signatures.
+    pub fn density(&self) -> Option<f64> {
+        match self {
+            Signature::Exact => None,
+            Signature::AllTrue => Some(1.0),
+            Signature::Bits { total: 0, .. } => Some(0.0),
+            Signature::Bits { set, total } => Some(*set as f64 / *total as f64),
+        }
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Signature::Exact => write!(f, "exact key"),
+            Signature::AllTrue => write!(f, "signature: all bits set"),
+            Signature::Bits { set, total } => write!(
+                f,
+                "signature: {}/{} bits set ({:.2}%)",
+                set,
+                total,
+                self.density().unwrap_or(0.0) * 100.0
+            ),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Postgres functions and constants used by gevel, which are not covered by pgx bindings.
+
+// keep the names of Postgres, so they can be looked up in its sources
+#![allow(non_snake_case, non_upper_case_globals)]
+
+use pgx::pg_sys::{self, AclMode, BlockNumber, Buffer, Datum, Oid, Relation, Size, LOCKMODE};
+use std::os::raw::{c_char, c_int};
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    #[link_name = "__sigsetjmp"]
+    fn sigsetjmp(env: *mut pg_sys::sigjmp_buf, savemask: c_int) -> c_int;
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+extern "C" {
+    fn sigsetjmp(env: *mut pg_sys::sigjmp_buf, savemask: c_int) -> c_int;
+}
+
+/// Declares Postgres functions wrapped the same way `#[pg_guard]` wraps pgx bindings: an ERROR
+/// raised by a function is turned into a panic, which unwinds Rust frames before it's rethrown
+/// to Postgres, instead of jumping over them.
+macro_rules! guarded {
+    ($(pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+        $(
+            #[allow(clippy::missing_safety_doc)]
+            pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+                extern "C" {
+                    fn $name($($arg: $ty),*) $(-> $ret)?;
+                }
+                let prev_exception_stack = pg_sys::PG_exception_stack;
+                let prev_error_context_stack = pg_sys::error_context_stack;
+                let mut jmp_buf = std::mem::MaybeUninit::uninit();
+                if sigsetjmp(jmp_buf.as_mut_ptr(), 0) != 0 {
+                    pg_sys::PG_exception_stack = prev_exception_stack;
+                    pg_sys::error_context_stack = prev_error_context_stack;
+                    std::panic::panic_any(pg_sys::JumpContext {});
+                }
+                pg_sys::PG_exception_stack = jmp_buf.as_mut_ptr();
+                let result = $name($($arg),*);
+                pg_sys::PG_exception_stack = prev_exception_stack;
+                pg_sys::error_context_stack = prev_error_context_stack;
+                result
+            }
+        )*
+    };
+}
+
+/// `TupleDescAttr` of `access/tupdesc.h`: returns a given (0-based) attribute of a tuple
+/// descriptor. Attributes are stored inline since Postgres 11, and as pointers before.
+#[cfg(feature = "pg10")]
+pub unsafe fn TupleDescAttr(
+    desc: pg_sys::TupleDesc,
+    attno: usize,
+) -> *mut pg_sys::FormData_pg_attribute {
+    *(*desc).attrs.add(attno)
+}
+
+#[cfg(not(feature = "pg10"))]
+pub unsafe fn TupleDescAttr(
+    desc: pg_sys::TupleDesc,
+    attno: usize,
+) -> *mut pg_sys::FormData_pg_attribute {
+    (*desc).attrs.as_ptr().add(attno) as *mut _
+}
+
+/// Result of ACL checks (`AclResult` of `utils/acl.h`).
+pub type AclResult = std::os::raw::c_uint;
+pub const AclResult_ACLCHECK_OK: AclResult = 0;
+
+/// Oid of the `gist` access method in `pg_am`.
+pub const GIST_AM_OID: Oid = 783;
+
+/// Flag of heap pages whose tuples are all visible, see `access/visibilitymapdefs.h`.
+pub const VISIBILITYMAP_ALL_VISIBLE: u8 = 0x01;
+
+/// `PG_UTF8` of the `pg_enc` enum of `mb/pg_wchar.h`.
+pub const PG_UTF8: c_int = 6;
+
+guarded! {
+    pub fn LockRelationOid(relid: Oid, lockmode: LOCKMODE);
+    pub fn ConditionalLockRelationOid(relid: Oid, lockmode: LOCKMODE) -> bool;
+    pub fn UnlockRelationOid(relid: Oid, lockmode: LOCKMODE);
+    pub fn datumCopy(value: Datum, typ_by_val: bool, typ_len: c_int) -> Datum;
+    pub fn visibilitymap_get_status(rel: Relation, heap_blk: BlockNumber, vmbuf: *mut Buffer) -> u8;
+    pub fn GetRecordedFreeSpace(rel: Relation, heap_blk: BlockNumber) -> Size;
+    pub fn pg_class_aclcheck(table_oid: Oid, roleid: Oid, mode: AclMode) -> AclResult;
+    pub fn stringToQualifiedNameList(string: *const c_char) -> *mut pg_sys::List;
+    pub fn pg_server_to_any(s: *const c_char, len: c_int, encoding: c_int) -> *mut c_char;
+}
+
+// lets tests simulate indexes truncated below their root page
+#[cfg(any(test, feature = "pg_test"))]
+guarded! {
+    pub fn RelationTruncate(rel: Relation, nblocks: BlockNumber);
+}
@@ -0,0 +1,169 @@
+//! Public API for test suites of other extensions, ie. authors of custom GiST opclasses who
+//! want to assert properties of the indexes their opclasses build. All functions must be
+//! called from within a running backend, typically from `#[pg_test]` functions of
+//! `pgx-tests`. Failed assertions panic, which fails the test.
+
+use crate::error::OrRaise;
+use crate::gist::{IndexInspector, StatsCollector, Summary};
+use crate::walker::{Fanout, PageVisit, Visitor};
+use pgx::pg_sys::Oid;
+use pgx::Spi;
+
+/// Structural properties of an index, gathered in a single walk.
+#[derive(Debug, Clone)]
+pub struct IndexProperties {
+    /// Number of levels of the tree, including the root.
+    pub levels: usize,
+    pub pages: u64,
+    pub leaf_pages: u64,
+    /// Min number of downlinks on any internal page, `None` if the root is a leaf.
+    pub min_fanout: Option<u64>,
+    /// Max number of downlinks on any internal page, `None` if the root is a leaf.
+    pub max_fanout: Option<u64>,
+    /// Average number of downlinks of internal pages.
+    pub avg_fanout: Option<f64>,
+    /// Same as `health_score` of `gist_summary`, but computed from exact numbers.
+    pub health_score: f64,
+    /// Ratio of the actual to the optimal number of pages.
+    pub pages_vs_optimal: f64,
+}
+
+#[derive(Default)]
+struct FanoutCollector {
+    internal_pages: u64,
+    downlinks: u64,
+    min: Option<u64>,
+    max: Option<u64>,
+}
+
+impl Visitor for FanoutCollector {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        if !page.is_leaf() {
+            let fanout = page.page.max_offset() as u64;
+            self.internal_pages += 1;
+            self.downlinks += fanout;
+            self.min = Some(self.min.map_or(fanout, |min| min.min(fanout)));
+            self.max = Some(self.max.map_or(fanout, |max| max.max(fanout)));
+        }
+        true
+    }
+}
+
+/// Resolves a possibly schema-qualified index name into its oid.
+pub fn index_oid(index_name: &str) -> Oid {
+    let query = format!(
+        "SELECT '{}'::regclass::oid::bigint",
+        index_name.replace('\'', "''")
+    );
+    Spi::get_one::<i64>(&query).unwrap_or_else(|| panic!("index {} not found", index_name)) as Oid
+}
+
+/// Walks an index with a given name and returns its structural properties.
+pub fn inspect(index_name: &str) -> IndexProperties {
+    let index = IndexInspector::open(index_oid(index_name)).or_raise();
+    let mut stats = StatsCollector::new(index.relation, None);
+    let mut fanout = FanoutCollector::default();
+    index.walk(&mut Fanout(vec![&mut stats, &mut fanout]));
+    let stats = stats.finish(&index);
+    let summary = Summary::from_stats(&stats, index.fillfactor());
+    IndexProperties {
+        levels: stats.level + 1,
+        pages: stats.num_pages,
+        leaf_pages: stats.num_leaf_pages,
+        min_fanout: fanout.min,
+        max_fanout: fanout.max,
+        avg_fanout: if fanout.internal_pages == 0 {
+            None
+        } else {
+            Some(fanout.downlinks as f64 / fanout.internal_pages as f64)
+        },
+        health_score: summary.health_score,
+        pages_vs_optimal: summary.pages_vs_optimal,
+    }
+}
+
+/// Asserts that an index has at most `max_levels` levels, including the root.
+pub fn assert_max_levels(index_name: &str, max_levels: usize) {
+    let levels = inspect(index_name).levels;
+    assert!(
+        levels <= max_levels,
+        "index {} has {} levels, expected at most {}",
+        index_name,
+        levels,
+        max_levels
+    );
+}
+
+/// Asserts that every internal page of an index has between `min` and `max` downlinks.
+/// Indexes whose root is a leaf pass trivially.
+pub fn assert_fanout_between(index_name: &str, min: u64, max: u64) {
+    let props = inspect(index_name);
+    if let (Some(actual_min), Some(actual_max)) = (props.min_fanout, props.max_fanout) {
+        assert!(
+            actual_min >= min && actual_max <= max,
+            "index {} has fanout between {} and {}, expected between {} and {}",
+            index_name,
+            actual_min,
+            actual_max,
+            min,
+            max
+        );
+    }
+}
+
+/// Asserts that the health score (0-100) of an index is at least `min_score`.
+pub fn assert_health_score_at_least(index_name: &str, min_score: f64) {
+    let score = inspect(index_name).health_score;
+    assert!(
+        score >= min_score,
+        "index {} has health score {:.2}, expected at least {:.2}",
+        index_name,
+        score,
+        min_score
+    );
+}
+
+/// Fixtures creating indexes to run assertions against.
+pub mod fixtures {
+    use pgx::Spi;
+
+    pub use crate::demo::Dataset;
+
+    /// (Re)creates `gevel.demo_points` table filled with `rows` points of a given distribution
+    /// and a GiST index over it. Returns the name of the index.
+    pub fn points(rows: i32, dataset: Dataset) -> &'static str {
+        crate::demo::setup_dataset(rows, dataset);
+        crate::demo::DEMO_INDEX
+    }
+
+    /// (Re)creates a table `name` with a single column `k` of a given type filled with `rows`
+    /// values of `value_expr` (which may refer to the row number as `i`), and a GiST index
+    /// `<name>_k_idx` over it using a given opclass (or the default one). Returns the name of
+    /// the index.
+    pub fn keys(
+        name: &str,
+        column_type: &str,
+        value_expr: &str,
+        rows: i32,
+        opclass: Option<&str>,
+    ) -> String {
+        Spi::run(&format!("DROP TABLE IF EXISTS {}", name));
+        Spi::run(&format!("CREATE TABLE {}(k {})", name, column_type));
+        Spi::run(&format!(
+            "INSERT INTO {} SELECT {} FROM generate_series(1, {}) AS i",
+            name,
+            value_expr,
+            rows.max(0)
+        ));
+        let index = format!("{}_k_idx", name);
+        // indexes are created in the schema of their table
+        let index_name = index.rsplit('.').next().unwrap_or_default().to_string();
+        Spi::run(&format!(
+            "CREATE INDEX {} ON {} USING gist(k {})",
+            index_name,
+            name,
+            opclass.unwrap_or_default()
+        ));
+        index
+    }
+}
@@ -0,0 +1,95 @@
+use crate::gist::{IndexInspector, TupleState};
+use crate::sys::{visibilitymap_get_status, VISIBILITYMAP_ALL_VISIBLE};
+use crate::walker::{PageVisit, Visitor};
+use crate::IndexTuple;
+use pgx::pg_sys::{
+    relation_close, relation_open, AccessShareLock, BlockNumber, Buffer, InvalidBuffer,
+    OffsetNumber, Relation, ReleaseBuffer,
+};
+use std::collections::HashMap;
+
+/// Result of [IndexInspector::leaf_visibility].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LeafVisibility {
+    /// Number of valid leaf tuples not marked as dead.
+    pub leaf_tuples: u64,
+    /// Number of leaf tuples pointing to heap pages marked as all-visible in the visibility map.
+    pub all_visible_tuples: u64,
+    /// Number of distinct heap pages referenced by leaf tuples.
+    pub heap_pages: u64,
+    /// Number of distinct referenced heap pages marked as all-visible.
+    pub all_visible_heap_pages: u64,
+}
+
+impl LeafVisibility {
+    /// Share of leaf tuples known to point to live rows, in percents.
+    pub fn all_visible_pct(&self) -> f64 {
+        if self.leaf_tuples == 0 {
+            0.0
+        } else {
+            self.all_visible_tuples as f64 * 100.0 / self.leaf_tuples as f64
+        }
+    }
+}
+
+struct VisibilityCollector {
+    heap: Relation,
+    /// Visibility map page of the last looked up heap page, kept pinned between lookups.
+    vm_buffer: Buffer,
+    /// All-visible status of already looked up heap pages.
+    heap_pages: HashMap<BlockNumber, bool>,
+    result: LeafVisibility,
+}
+
+impl VisibilityCollector {
+    fn is_all_visible(&mut self, heap_block: BlockNumber) -> bool {
+        let (heap, vm_buffer) = (self.heap, &mut self.vm_buffer);
+        *self.heap_pages.entry(heap_block).or_insert_with(|| {
+            let status = unsafe { visibilitymap_get_status(heap, heap_block, vm_buffer) };
+            status & VISIBILITYMAP_ALL_VISIBLE != 0
+        })
+    }
+}
+
+impl Visitor for VisibilityCollector {
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        if !page.is_leaf() || TupleState::of(page.page, offset) != TupleState::Normal {
+            return;
+        }
+        self.result.leaf_tuples += 1;
+        if self.is_all_visible(tuple.block_num()) {
+            self.result.all_visible_tuples += 1;
+        }
+    }
+}
+
+impl IndexInspector {
+    /// Classifies leaf tuples by whether they point to heap pages marked as all-visible in the
+    /// visibility map of the heap. Rows stored on such pages are visible to all transactions,
+    /// so the number of their index entries is a lower bound of live entries, obtained without
+    /// fetching any heap tuples. Only the visibility map is read, and each of its pages at most
+    /// once per distinct heap page.
+    pub fn leaf_visibility(&self) -> LeafVisibility {
+        unsafe {
+            let heap_oid = (*(*self.relation).rd_index).indrelid;
+            let heap = relation_open(heap_oid, AccessShareLock as i32);
+            let mut collector = VisibilityCollector {
+                heap,
+                vm_buffer: InvalidBuffer as Buffer,
+                heap_pages: HashMap::new(),
+                result: LeafVisibility::default(),
+            };
+            self.walk(&mut collector);
+            if collector.vm_buffer != InvalidBuffer as Buffer {
+                ReleaseBuffer(collector.vm_buffer);
+            }
+            relation_close(heap, AccessShareLock as i32);
+
+            let mut result = collector.result;
+            result.heap_pages = collector.heap_pages.len() as u64;
+            result.all_visible_heap_pages =
+                collector.heap_pages.values().filter(|&&v| v).count() as u64;
+            result
+        }
+    }
+}
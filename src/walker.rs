@@ -0,0 +1,423 @@
+use crate::error::Error;
+use crate::gist::{GistPage, IndexInspector};
+use crate::{IndexTuple, OffsetRange, Page, GIST_ROOT_BLKNO};
+use pgx::pg_sys::{BlockNumber, InvalidBlockNumber, OffsetNumber};
+use pgx::warning;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// Default cap of memory taken by items kept by a [TopK].
+pub const TOP_K_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// Same as `CHECK_FOR_INTERRUPTS()` of Postgres: processes pending interrupts, which raises an
+/// error when the query has been cancelled or `statement_timeout` has passed. Pinned buffers
+/// and the index lock are released while the error unwinds the inspection.
+macro_rules! check_for_interrupts {
+    () => {
+        #[allow(unused_unsafe)]
+        unsafe {
+            // a bool before Postgres 12, sig_atomic_t since then
+            #[cfg(any(feature = "pg10", feature = "pg11"))]
+            let pending = pgx::pg_sys::InterruptPending;
+            #[cfg(any(feature = "pg12", feature = "pg13"))]
+            let pending = pgx::pg_sys::InterruptPending != 0;
+            if pending {
+                pgx::pg_sys::ProcessInterrupts();
+            }
+        }
+    };
+}
+
+pub(crate) use check_for_interrupts;
+
+/// Page currently visited by the [IndexInspector::walk].
+pub struct PageVisit<'a> {
+    /// Depth of the page inside of the tree. Root page is at level 0.
+    pub level: usize,
+    pub block_num: BlockNumber,
+    /// Offset of the downlink inside of the parent page, which led to the current page.
+    /// For the root page it's 0.
+    pub offset: OffsetNumber,
+    pub page: &'a Page,
+    pub gist_page: &'a GistPage<'a>,
+}
+
+impl<'a> PageVisit<'a> {
+    pub fn is_leaf(&self) -> bool {
+        self.gist_page.is_leaf()
+    }
+}
+
+/// Callbacks invoked by [IndexInspector::walk] while traversing an index tree in depth-first
+/// order. Right halves of pages split concurrently with the walk are visited right after the
+/// pages they were split from, as if they were reached by the same downlink. Pages deleted by
+/// VACUUM after their downlinks were read are skipped. All of them have
+/// default no-op implementations, so that visitors only need to implement the ones they care
+/// about.
+pub trait Visitor {
+    /// Called when a page is entered, before any of its tuples is visited. Returning false
+    /// prevents walker from descending into any of the page's children.
+    fn on_page(&mut self, _page: &PageVisit) -> bool {
+        true
+    }
+
+    /// Called for every tuple of a visited page, in offset order. Line pointers which don't
+    /// point to any tuple are skipped, see [Page::tuples].
+    fn on_tuple(&mut self, _page: &PageVisit, _offset: OffsetNumber, _tuple: &IndexTuple) {}
+
+    /// Called for every downlink of an internal page right after [Visitor::on_tuple]. Returning
+    /// false skips the child page pointed by this downlink.
+    fn descend(&mut self, _page: &PageVisit, _offset: OffsetNumber, _tuple: &IndexTuple) -> bool {
+        true
+    }
+
+    /// Called after all tuples and children of a page have been visited.
+    fn on_page_end(&mut self, _page: &PageVisit) {}
+}
+
+/// Visitor dispatching all callbacks to multiple visitors, so that several analyses can share
+/// a single traversal. Children of a page are visited if any of the visitors asks for them.
+pub struct Fanout<'a>(pub Vec<&'a mut dyn Visitor>);
+
+impl<'a> Visitor for Fanout<'a> {
+    fn on_page(&mut self, page: &PageVisit) -> bool {
+        let mut recurse = false;
+        for visitor in self.0.iter_mut() {
+            recurse |= visitor.on_page(page);
+        }
+        recurse
+    }
+
+    fn on_tuple(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) {
+        for visitor in self.0.iter_mut() {
+            visitor.on_tuple(page, offset, tuple);
+        }
+    }
+
+    fn descend(&mut self, page: &PageVisit, offset: OffsetNumber, tuple: &IndexTuple) -> bool {
+        let mut descend = false;
+        for visitor in self.0.iter_mut() {
+            descend |= visitor.descend(page, offset, tuple);
+        }
+        descend
+    }
+
+    fn on_page_end(&mut self, page: &PageVisit) {
+        for visitor in self.0.iter_mut() {
+            visitor.on_page_end(page);
+        }
+    }
+}
+
+/// Keeps the `k` greatest items offered to it, so that visitors reporting the largest keys,
+/// the worst pages and the like can select them during a single walk instead of collecting all
+/// candidates first. Memory taken by kept items is capped as well: once their total size exceeds
+/// the cap, the smallest ones are evicted, even if less than `k` items are left.
+pub struct TopK<T: Ord> {
+    k: usize,
+    max_bytes: usize,
+    bytes: usize,
+    /// Returns the number of bytes taken by an item.
+    weigh: fn(&T) -> usize,
+    /// Min-heap of kept items, the smallest one is on top.
+    heap: BinaryHeap<Reverse<T>>,
+}
+
+impl<T: Ord> TopK<T> {
+    /// Creates an accumulator of `k` items of a fixed size, capped at [TOP_K_MAX_BYTES].
+    pub fn new(k: usize) -> Self {
+        TopK::with_memory_cap(k, TOP_K_MAX_BYTES, |_| std::mem::size_of::<T>())
+    }
+
+    /// Creates an accumulator of `k` items, which evicts the smallest ones when the total size
+    /// of kept items, as returned by `weigh`, exceeds `max_bytes`.
+    pub fn with_memory_cap(k: usize, max_bytes: usize, weigh: fn(&T) -> usize) -> Self {
+        TopK {
+            k,
+            max_bytes,
+            bytes: 0,
+            weigh,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    #[cfg(any(test, feature = "pg_test"))]
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    #[cfg(any(test, feature = "pg_test"))]
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Returns true if an item ordered by `is_greater` relative to the smallest kept one would
+    /// be kept. Lets callers skip building items which would be discarded right away.
+    pub fn admits<F: FnOnce(&T) -> bool>(&self, is_greater: F) -> bool {
+        match self.heap.peek() {
+            _ if self.k == 0 => false,
+            Some(Reverse(min)) if self.heap.len() >= self.k => is_greater(min),
+            _ => true,
+        }
+    }
+
+    /// Offers an item, which is kept if it's one of the `k` greatest ones seen so far.
+    pub fn push(&mut self, item: T) {
+        if !self.admits(|min| &item > min) {
+            return;
+        }
+        self.bytes += (self.weigh)(&item);
+        self.heap.push(Reverse(item));
+        while self.heap.len() > self.k || self.bytes > self.max_bytes {
+            match self.heap.pop() {
+                Some(Reverse(evicted)) => self.bytes -= (self.weigh)(&evicted),
+                None => break,
+            }
+        }
+    }
+
+    /// Returns kept items ordered from the greatest to the smallest one.
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        // ascending order of reversed items is the descending order of items
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(item)| item)
+            .collect()
+    }
+}
+
+/// Link by which a page has been reached during a traversal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Link {
+    /// The page the traversal started from.
+    Start,
+    /// Downlink at a given offset of a given parent page.
+    Downlink(BlockNumber, OffsetNumber),
+    /// Rightlink of a given left sibling.
+    Rightlink(BlockNumber),
+}
+
+impl Display for Link {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Link::Start => write!(f, "the start of the traversal"),
+            Link::Downlink(parent, offset) => {
+                write!(f, "the downlink at offset {} of block {}", offset, parent)
+            }
+            Link::Rightlink(left) => write!(f, "the rightlink of block {}", left),
+        }
+    }
+}
+
+/// Bitmap of blocks visited by a traversal, which guards it against links of corrupted indexes.
+/// In a healthy index every page is reached exactly once, so reaching a block again means that
+/// downlinks or rightlinks form a cycle, which would otherwise make the traversal loop forever.
+/// Links pointing past the end of the relation would fail deep inside of `ReadBuffer`.
+pub(crate) struct VisitedBlocks {
+    bits: Vec<u64>,
+    /// Size of the relation the last time it was checked.
+    num_blocks: BlockNumber,
+}
+
+impl VisitedBlocks {
+    pub(crate) fn new(index: &IndexInspector) -> Self {
+        let num_blocks = index.num_blocks();
+        VisitedBlocks {
+            bits: vec![0; (num_blocks as usize).div_ceil(64)],
+            num_blocks,
+        }
+    }
+
+    /// Marks a block reached by a given link as visited. Returns false and warns about the
+    /// offending link if the block doesn't exist or has been visited before, in which case it
+    /// must be skipped.
+    pub(crate) fn enter(&mut self, index: &IndexInspector, blk: BlockNumber, link: Link) -> bool {
+        if blk >= self.num_blocks {
+            // the index may have been extended by page splits since the traversal started
+            self.num_blocks = index.num_blocks();
+            if blk >= self.num_blocks {
+                let error = Error::OutOfRangeDownlink {
+                    link: link.to_string(),
+                    blkno: blk,
+                    num_blocks: self.num_blocks,
+                };
+                warning!("{}: the index is likely corrupted, skipping it", error);
+                return false;
+            }
+            self.bits.resize((self.num_blocks as usize).div_ceil(64), 0);
+        }
+        let (word, bit) = (blk as usize / 64, blk % 64);
+        if self.bits[word] & (1 << bit) != 0 {
+            warning!(
+                "block {} has already been visited, but is referenced again by {}: the index is likely corrupted, skipping it",
+                blk,
+                link
+            );
+            return false;
+        }
+        self.bits[word] |= 1 << bit;
+        true
+    }
+}
+
+impl IndexInspector {
+    /// Traverses the whole index tree starting from its root, calling visitor callbacks
+    /// for every visited page and tuple.
+    pub fn walk<V: Visitor>(&self, visitor: &mut V) {
+        self.in_memory(|| self.walk_from(0, GIST_ROOT_BLKNO, 0, visitor))
+    }
+
+    /// Traverses a subtree starting from a given page, as if it was reached at a given `level`
+    /// by a downlink at a given `offset` of its parent.
+    pub fn walk_subtree<V: Visitor>(
+        &self,
+        level: usize,
+        blk: BlockNumber,
+        offset: OffsetNumber,
+        visitor: &mut V,
+    ) {
+        self.in_memory(|| self.walk_from(level, blk, offset, visitor))
+    }
+
+    /// Visits a page and its subtree. Pages on the path from it to the currently visited one
+    /// are kept on an explicit stack instead of the call stack, so that deep trees can't
+    /// overflow the latter. Links of corrupted trees pointing to blocks visited before or past
+    /// the end of the index are skipped, see [VisitedBlocks], and so are deleted pages.
+    fn walk_from<V: Visitor>(
+        &self,
+        level: usize,
+        blk: BlockNumber,
+        offset: OffsetNumber,
+        visitor: &mut V,
+    ) {
+        let mut visited = VisitedBlocks::new(self);
+        let mut stack: Vec<Frame> = Vec::new();
+        // page to enter before resuming the page on top of the stack
+        let mut next = Some(Entry {
+            level,
+            blk,
+            offset,
+            parent_lsn: None,
+            link: Link::Start,
+        });
+        loop {
+            if let Some(entry) = next.take() {
+                check_for_interrupts!();
+                if !visited.enter(self, entry.blk, entry.link) {
+                    continue;
+                }
+                let page = self.cache.get(entry.blk);
+                if page.is_new() {
+                    // never initialized page holds no tuples and has no rightlink to follow,
+                    // see IndexInspector::new_pages
+                    continue;
+                }
+                let gist_page = GistPage::new(&page);
+                if gist_page.is_deleted() {
+                    // Same as in gistScanPage: there's nothing of interest on a deleted page,
+                    // but it may have been split before all of its tuples were removed, so
+                    // its right half must be visited anyway.
+                    next = entry.right_half(&gist_page);
+                    continue;
+                }
+                let visit = entry.visit(&page, &gist_page);
+                let recurse = visitor.on_page(&visit);
+                let (is_leaf, lsn, offsets) = (visit.is_leaf(), page.lsn(), page.offsets());
+                stack.push(Frame {
+                    entry,
+                    page,
+                    descend: recurse && !is_leaf,
+                    lsn,
+                    offsets,
+                });
+                continue;
+            }
+
+            let frame = match stack.last_mut() {
+                Some(frame) => frame,
+                None => break,
+            };
+            let page = frame.page.clone();
+            let gist_page = GistPage::new(&page);
+            let visit = frame.entry.visit(&page, &gist_page);
+            let tuple = frame
+                .offsets
+                .by_ref()
+                .find_map(|i| Some((i, page.tuple_at(i)?)));
+            if let Some((i, which)) = tuple {
+                visitor.on_tuple(&visit, i, &which);
+                if frame.descend && visitor.descend(&visit, i, &which) {
+                    next = Some(Entry {
+                        level: frame.entry.level + 1,
+                        blk: which.block_num(),
+                        offset: i,
+                        parent_lsn: Some(frame.lsn),
+                        link: Link::Downlink(frame.entry.blk, i),
+                    });
+                }
+                continue;
+            }
+
+            visitor.on_page_end(&visit);
+            let entry = stack.pop().expect("visited page is not on the stack").entry;
+            next = entry.right_half(&gist_page);
+        }
+    }
+}
+
+/// Page to be visited by [IndexInspector::walk].
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    level: usize,
+    blk: BlockNumber,
+    offset: OffsetNumber,
+    /// LSN of the parent page at the time the downlink was read, which tells if the page has
+    /// been split since then. `None` for the page the walk started from.
+    parent_lsn: Option<u64>,
+    link: Link,
+}
+
+impl Entry {
+    fn visit<'a>(&self, page: &'a Page, gist_page: &'a GistPage<'a>) -> PageVisit<'a> {
+        PageVisit {
+            level: self.level,
+            block_num: self.blk,
+            offset: self.offset,
+            page,
+            gist_page,
+        }
+    }
+
+    /// Same check as in gistScanPage: a page split after its parent was read moved some of its
+    /// tuples to right siblings, which have no downlinks in the parent we've read. Returns the
+    /// right sibling to be visited right after the page, as if it was reached by its downlink.
+    fn right_half(&self, gist_page: &GistPage) -> Option<Entry> {
+        let parent_lsn = self.parent_lsn?;
+        let right_link = gist_page.right_link();
+        if (gist_page.is_follow_right() || parent_lsn < gist_page.nsn())
+            && right_link != InvalidBlockNumber
+        {
+            Some(Entry {
+                blk: right_link,
+                link: Link::Rightlink(self.blk),
+                ..*self
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Page of [IndexInspector::walk] whose tuples are being visited.
+struct Frame {
+    entry: Entry,
+    page: Rc<Page>,
+    /// True if children of the page are visited, as requested by [Visitor::on_page].
+    descend: bool,
+    lsn: u64,
+    /// Offsets of line pointers which haven't been visited yet.
+    offsets: OffsetRange,
+}
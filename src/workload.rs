@@ -0,0 +1,129 @@
+use crate::error::{raise, Error, ErrorClass};
+use crate::gist::{IndexInspector, Summary};
+use crate::walker::check_for_interrupts;
+use pgx::pg_sys::Oid;
+use pgx::{warning, JsonB, Spi};
+use serde_json::json;
+
+/// Usage counters of an index, as reported by `pg_stat_user_indexes` since the last reset of
+/// statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IndexUsage {
+    /// Number of scans initiated on the index.
+    pub idx_scan: u64,
+    /// Number of index entries returned by scans of the index.
+    pub idx_tup_read: u64,
+}
+
+impl IndexUsage {
+    /// Returns how much the usage of the index amplifies its bloat: 1 for unused indexes, plus
+    /// 1 for every tenfold increase of scans and entries read. Bloat costs every scan extra
+    /// pages to read, so the same bloat matters more on busier indexes, but a logarithmic
+    /// scale keeps a single hot index from hiding badly degraded ones.
+    pub fn weight(&self) -> f64 {
+        1.0 + (1.0 + self.idx_scan as f64 + self.idx_tup_read as f64).log10()
+    }
+
+    /// Returns the "fix me first" score of an index: how far it is from perfect health,
+    /// weighted by its usage. Scores are only meaningful relative to each other.
+    pub fn maintenance_score(&self, summary: &Summary) -> f64 {
+        (100.0 - summary.health_score) * self.weight()
+    }
+
+    pub fn to_json(self, summary: &Summary) -> serde_json::Value {
+        json!({
+            "idx_scan": self.idx_scan,
+            "idx_tup_read": self.idx_tup_read,
+            "usage_weight": self.weight(),
+            "maintenance_score": self.maintenance_score(summary),
+        })
+    }
+}
+
+impl IndexInspector {
+    /// Returns usage counters of the index. Indexes without statistics (ie. of temporary
+    /// tables of other sessions) are treated as unused.
+    pub fn usage(&self) -> IndexUsage {
+        let query = format!(
+            "SELECT jsonb_build_object('idx_scan', idx_scan, 'idx_tup_read', idx_tup_read) \
+             FROM pg_stat_user_indexes WHERE indexrelid = {}",
+            self.oid()
+        );
+        Spi::get_one::<JsonB>(&query).map_or_else(IndexUsage::default, |usage| IndexUsage {
+            idx_scan: usage.0["idx_scan"].as_u64().unwrap_or(0),
+            idx_tup_read: usage.0["idx_tup_read"].as_u64().unwrap_or(0),
+        })
+    }
+}
+
+/// A GiST index ranked by [prioritized_maintenance].
+#[derive(Debug)]
+pub struct MaintenanceItem {
+    pub oid: Oid,
+    /// Schema-qualified name of the index.
+    pub index: String,
+    /// Schema-qualified name of the indexed table.
+    pub table: String,
+    pub summary: Summary,
+    pub usage: IndexUsage,
+    pub score: f64,
+}
+
+/// Ranks all GiST indexes of the current database, which the current user can see statistics
+/// of, by [IndexUsage::maintenance_score], the most urgent first. Health of each index is
+/// estimated by [IndexInspector::summary] from a sample of `sample_size` leaf pages, so that
+/// the whole database can be ranked in bounded time. Indexes which can't be locked within
+/// `gevel.lock_timeout_ms` are skipped with a warning.
+pub fn prioritized_maintenance(sample_size: usize) -> Vec<MaintenanceItem> {
+    if sample_size == 0 {
+        raise(
+            ErrorClass::InvalidArgument,
+            "sample_size must be a positive number of leaf pages",
+        );
+    }
+    let indexes = Spi::get_one::<JsonB>(
+        "SELECT jsonb_agg(jsonb_build_array(s.indexrelid::bigint, \
+             format('%I.%I', s.schemaname, s.indexrelname), format('%I.%I', s.schemaname, s.relname), \
+             s.idx_scan, s.idx_tup_read) ORDER BY s.indexrelid) \
+         FROM pg_stat_user_indexes s JOIN pg_class c ON c.oid = s.indexrelid \
+         JOIN pg_am a ON a.oid = c.relam WHERE a.amname = 'gist' AND c.relkind = 'i'",
+    )
+    .and_then(|indexes| indexes.0.as_array().cloned())
+    .unwrap_or_default();
+
+    let mut items = Vec::with_capacity(indexes.len());
+    for index in indexes.iter() {
+        check_for_interrupts!();
+        let oid = index[0].as_u64().unwrap_or(0) as Oid;
+        let name = index[1].as_str().unwrap_or_default().to_string();
+        let inspector = match IndexInspector::open_aggregates(oid) {
+            Ok(inspector) => inspector,
+            Err(e @ Error::LockTimeout { .. }) => {
+                warning!("{}, skipping index {}", e, name);
+                continue;
+            }
+            // dropped since the list was taken
+            Err(Error::InvalidRelation { .. }) => continue,
+            Err(e) => e.raise(),
+        };
+        let summary = inspector.summary(sample_size);
+        let usage = IndexUsage {
+            idx_scan: index[3].as_u64().unwrap_or(0),
+            idx_tup_read: index[4].as_u64().unwrap_or(0),
+        };
+        items.push(MaintenanceItem {
+            oid,
+            index: name,
+            table: index[2].as_str().unwrap_or_default().to_string(),
+            score: usage.maintenance_score(&summary),
+            summary,
+            usage,
+        });
+    }
+    items.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    items
+}
@@ -0,0 +1,68 @@
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Escapes characters which can't appear inside of XML attribute values.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes scalar fields of a JSON object as XML attributes. NULLs are left out.
+fn write_attributes(object: &serde_json::Map<String, Value>, out: &mut String) {
+    for (name, value) in object.iter() {
+        match value {
+            Value::Bool(_) | Value::Number(_) => {
+                write!(out, " {}=\"{}\"", name, value).unwrap();
+            }
+            Value::String(s) => write!(out, " {}=\"{}\"", name, escape(s)).unwrap(),
+            _ => {}
+        }
+    }
+}
+
+/// Writes a page object of `gist_tree_json` as a `page` element. Nested objects (`items` and
+/// `leaves`) become child elements of the same name, followed by `page` elements of children.
+fn write_page(page: &Value, out: &mut String) {
+    let page = match page.as_object() {
+        Some(page) => page,
+        None => return,
+    };
+    out.push_str("<page");
+    write_attributes(page, out);
+    let nested: Vec<(&String, &serde_json::Map<String, Value>)> = page
+        .iter()
+        .filter_map(|(name, value)| value.as_object().map(|o| (name, o)))
+        .collect();
+    let children = page.get("children").and_then(|c| c.as_array());
+    if nested.is_empty() && children.is_none_or(|c| c.is_empty()) {
+        out.push_str("/>");
+        return;
+    }
+    out.push('>');
+    for (name, object) in nested {
+        write!(out, "<{}", name).unwrap();
+        write_attributes(object, out);
+        out.push_str("/>");
+    }
+    for child in children.into_iter().flatten() {
+        write_page(child, out);
+    }
+    out.push_str("</page>");
+}
+
+/// Converts the tree returned by [IndexTree::to_json_capped](crate::gist::IndexTree::to_json_capped)
+/// into an XML document with the same structure: an `index` element with `is_valid` (and `truncated`)
+/// attributes, holding nested `page` elements whose attributes are fields of page objects.
+pub fn tree_to_xml(tree: &Value) -> String {
+    let mut out = String::from("<index");
+    if let Some(tree) = tree.as_object() {
+        write_attributes(tree, &mut out);
+    }
+    out.push('>');
+    write_page(&tree["root"], &mut out);
+    out.push_str("</index>");
+    out
+}